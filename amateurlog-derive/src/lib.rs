@@ -0,0 +1,215 @@
+//! Procedural macros for amateurlog, re-exported by it with the `derive`
+//! feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as Tokens;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Data, DeriveInput, Fields, FnArg, Index, ItemFn, LitStr, ReturnType, Type,
+};
+
+/// Implements `ToFact` for a struct: the fact is named after the struct in
+/// snake case, or as `#[fact(name = "...")]` says, and its arguments are the
+/// fields in order, each converted with `ToTerm`.
+#[proc_macro_derive(ToFact, attributes(fact))]
+pub fn derive_to_fact(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    to_fact(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn to_fact(input: &DeriveInput) -> syn::Result<Tokens> {
+    let Data::Struct(data) = &input.data else {
+        let message = "ToFact can only be derived for structs";
+        return Err(syn::Error::new_spanned(&input.ident, message));
+    };
+    let mut name = snake_case(&input.ident.to_string());
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("fact"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"`"))
+            }
+        })?;
+    }
+    let fields: Vec<Tokens> = match &data.fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| {
+                let ident = &field.ident;
+                quote!(&self.#ident)
+            })
+            .collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|n| {
+                let index = Index::from(n);
+                quote!(&self.#index)
+            })
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::amateurlog::ToFact for #ident #ty_generics #where_clause {
+            fn name(&self) -> &str {
+                #name
+            }
+            fn args(&self) -> ::amateurlog::__private::Vec<::amateurlog::Term> {
+                ::amateurlog::__private::vec![#(::amateurlog::ToTerm::to_term(#fields)),*]
+            }
+        }
+    })
+}
+
+// `HttpRequest2` as `http_request2`
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (n, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && n > 0 {
+            let after_lower = !chars[n - 1].is_uppercase() && chars[n - 1] != '_';
+            let ends_acronym =
+                chars[n - 1].is_uppercase() && chars.get(n + 1).is_some_and(|c| c.is_lowercase());
+            if after_lower || ends_acronym {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+/// Makes a Rust function a predicate: `#[predicate] fn f(...)` also writes
+/// `fn f_predicate() -> amateurlog::Predicate`, for `Database::add_predicate`.
+/// Name the predicate otherwise than the function with
+/// `#[predicate(name = "...")]`.
+///
+/// The function's parameters are the predicate's first arguments, read from
+/// the call with `FromTerm`; the call fails if one can't be. What it returns
+/// decides the rest: a `bool` whether the call succeeds, `()` that it does,
+/// and a value one more argument, converted with `ToTerm`, to unify with what
+/// it's called with. An `Option` of a value is a solution or none, and a
+/// `Vec` or an `impl Iterator` of values a solution for each, found as the
+/// goal backtracks.
+#[proc_macro_attribute]
+pub fn predicate(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as ItemFn);
+    let mut name = function.sig.ident.to_string();
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = meta.value()?.parse::<LitStr>()?.value();
+            Ok(())
+        } else {
+            Err(meta.error("expected `name = \"...\"`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    let glue = predicate_glue(&function, &name).unwrap_or_else(syn::Error::into_compile_error);
+    quote!(#function #glue).into()
+}
+
+// what a function's result says of a call
+enum Returns {
+    Truth,
+    Nothing,
+    Value,
+    Maybe,
+    Many,
+}
+
+fn returns(output: &ReturnType) -> Returns {
+    let ReturnType::Type(_, ty) = output else {
+        return Returns::Nothing;
+    };
+    match &**ty {
+        Type::Tuple(tuple) if tuple.elems.is_empty() => Returns::Nothing,
+        Type::ImplTrait(_) => Returns::Many,
+        Type::Path(path) => match path.path.segments.last() {
+            Some(last) if last.ident == "bool" => Returns::Truth,
+            Some(last) if last.ident == "Option" => Returns::Maybe,
+            Some(last) if last.ident == "Vec" => Returns::Many,
+            _ => Returns::Value,
+        },
+        _ => Returns::Value,
+    }
+}
+
+fn predicate_glue(function: &ItemFn, name: &str) -> syn::Result<Tokens> {
+    let sig = &function.sig;
+    if !sig.generics.params.is_empty() || sig.asyncness.is_some() {
+        let message = "a predicate can't be generic or async";
+        return Err(syn::Error::new_spanned(sig, message));
+    }
+    let mut types = Vec::new();
+    for input in &sig.inputs {
+        match input {
+            FnArg::Typed(typed) => types.push(&typed.ty),
+            FnArg::Receiver(receiver) => {
+                let message = "a predicate can't take `self`";
+                return Err(syn::Error::new_spanned(receiver, message));
+            }
+        }
+    }
+    let inputs = types.len();
+    let values: Vec<_> = (0..inputs).map(|n| format_ident!("arg{}", n)).collect();
+    let indexes = 0..inputs;
+    let ident = &sig.ident;
+    let call = quote!(#ident(#(#values),*));
+    let returns = returns(&sig.output);
+    let arity = match returns {
+        Returns::Truth | Returns::Nothing => inputs,
+        _ => inputs + 1,
+    };
+    let answer = match returns {
+        Returns::Truth => quote! {
+            ::amateurlog::__private::Box::new(#call.then_some(given).into_iter())
+        },
+        Returns::Nothing => quote! {
+            #call;
+            ::amateurlog::__private::Box::new(::core::iter::once(given))
+        },
+        Returns::Value => quote! {
+            let mut row = given;
+            row.push(::amateurlog::ToTerm::to_term(&#call));
+            ::amateurlog::__private::Box::new(::core::iter::once(row))
+        },
+        Returns::Maybe | Returns::Many => quote! {
+            ::amateurlog::__private::Box::new(::core::iter::IntoIterator::into_iter(#call).map(move |value| {
+                let mut row = given.clone();
+                row.push(::amateurlog::ToTerm::to_term(&value));
+                row
+            }))
+        },
+    };
+    let vis = &function.vis;
+    let constructor = format_ident!("{}_predicate", ident);
+    Ok(quote! {
+        #vis fn #constructor() -> ::amateurlog::Predicate {
+            fn rows(args: &[::amateurlog::Term]) -> ::amateurlog::Rows {
+                #(
+                    let ::core::option::Option::Some(#values) =
+                        <#types as ::amateurlog::FromTerm>::from_term(&args[#indexes])
+                    else {
+                        return ::amateurlog::__private::Box::new(::core::iter::empty());
+                    };
+                )*
+                let given = args[..#inputs].to_vec();
+                #answer
+            }
+            ::amateurlog::Predicate {
+                name: #name,
+                arity: #arity,
+                rows,
+            }
+        }
+    })
+}
@@ -0,0 +1,113 @@
+//! Python bindings, built into a wheel with `maturin build --release`:
+//!
+//! ```python
+//! from amateurlog import Database
+//! db = Database()
+//! db.consult("parent(tom, bob). parent(bob, ann).")
+//! db.query("parent(X, Y)")  # [{"X": "tom", "Y": "bob"}, ...]
+//! ```
+//!
+//! Values come back as Python ones: atoms and strings as `str`, integers as
+//! `int`, lists as `list`, unbound variables as `None`, and other compound
+//! terms as `{"functor": name, "args": [...]}`, as in `Solution::to_json`.
+
+use ::amateurlog::{parse_query, Bindings, ParseErrors, SolveError, Term};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PySyntaxError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+create_exception!(
+    amateurlog,
+    PrologError,
+    PyException,
+    "A query that threw an exception it didn't catch, ran out of time, or ran out of a resource."
+);
+
+/// A program to query.
+#[pyclass(module = "amateurlog")]
+#[derive(Default)]
+struct Database {
+    db: ::amateurlog::Database,
+}
+
+#[pymethods]
+impl Database {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+    /// Adds the clauses in `source`, raising `SyntaxError` if it doesn't
+    /// parse.
+    fn consult(&mut self, source: &str) -> PyResult<()> {
+        self.db.consult(source).map_err(syntax_error)
+    }
+    /// The solutions of `text`, at most `limit` of them if it's given, each a
+    /// dict from the query's variable names to their values.
+    #[pyo3(signature = (text, limit = None))]
+    fn query<'py>(
+        &self,
+        py: Python<'py>,
+        text: &str,
+        limit: Option<usize>,
+    ) -> PyResult<Bound<'py, PyList>> {
+        let query = parse_query(text).map_err(|err| syntax_error(ParseErrors(vec![err])))?;
+        let goal = query.goal.clone();
+        let answers = py
+            .allow_threads(|| match limit {
+                Some(n) => Ok(self.db.satisfy_n(goal, n)),
+                None => self.db.try_satisfy_all(goal),
+            })
+            .map_err(solve_error)?;
+        let solutions = PyList::empty(py);
+        for bindings in &answers {
+            let solution = PyDict::new(py);
+            for (name, value) in query.solution(bindings).values() {
+                solution.set_item(name, to_python(py, value)?)?;
+            }
+            solutions.append(solution)?;
+        }
+        Ok(solutions)
+    }
+}
+
+fn to_python<'py>(py: Python<'py>, term: &Term) -> PyResult<Bound<'py, PyAny>> {
+    if let Some(items) = Bindings::new().list_to_vec(term) {
+        let list = PyList::empty(py);
+        for item in &items {
+            list.append(to_python(py, item)?)?;
+        }
+        return Ok(list.into_any());
+    }
+    Ok(match term {
+        Term::Atom(a) => a.as_str().into_pyobject(py)?.into_any(),
+        Term::String(text) => (**text).into_pyobject(py)?.into_any(),
+        Term::Integer(n) => n.into_pyobject(py)?.into_any(),
+        Term::Variable(_) => py.None().into_bound(py),
+        Term::Functor(f) => {
+            let compound = PyDict::new(py);
+            compound.set_item("functor", f.name().as_str())?;
+            let args = PyList::empty(py);
+            for arg in f.args() {
+                args.append(to_python(py, arg)?)?;
+            }
+            compound.set_item("args", args)?;
+            compound.into_any()
+        }
+    })
+}
+
+fn syntax_error(errors: ParseErrors) -> PyErr {
+    PySyntaxError::new_err(errors.to_string())
+}
+
+fn solve_error(err: SolveError) -> PyErr {
+    PrologError::new_err(err.to_string())
+}
+
+#[pymodule]
+fn amateurlog(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Database>()?;
+    m.add("PrologError", m.py().get_type::<PrologError>())?;
+    Ok(())
+}
@@ -0,0 +1,18 @@
+// With the `ffi` feature, writes the C header for src/ffi.rs to
+// include/amateurlog.h.
+fn main() {
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        let dir = std::env::var("CARGO_MANIFEST_DIR").expect("set by cargo");
+        cbindgen::Builder::new()
+            .with_crate(&dir)
+            .with_language(cbindgen::Language::C)
+            .with_include_guard("AMATEURLOG_H")
+            .with_header("/* Generated from src/ffi.rs by build.rs; don't edit. */")
+            .with_documentation(true)
+            .generate()
+            .expect("src/ffi.rs declares a C interface")
+            .write_to_file(format!("{}/include/amateurlog.h", dir));
+    }
+}
@@ -0,0 +1,94 @@
+/* Generated from src/ffi.rs by build.rs; don't edit. */
+
+#ifndef AMATEURLOG_H
+#define AMATEURLOG_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A program.
+ */
+typedef struct AmateurlogDatabase AmateurlogDatabase;
+
+/**
+ * The solutions of a query, found one at a time as they're asked for, on a
+ * thread of the query's own.
+ */
+typedef struct AmateurlogQuery AmateurlogQuery;
+
+/**
+ * An empty program.
+ */
+struct AmateurlogDatabase *amateurlog_database_new(void);
+
+/**
+ * Frees a program. Its queries go on working.
+ *
+ * # Safety
+ *
+ * `db` is null or came from `amateurlog_database_new` and isn't used again.
+ */
+void amateurlog_database_free(struct AmateurlogDatabase *db);
+
+/**
+ * Adds the clauses in `source`, returning 0, or -1 if it doesn't parse.
+ *
+ * # Safety
+ *
+ * `db` came from `amateurlog_database_new`, and `source` is a
+ * NUL-terminated string.
+ */
+int amateurlog_consult(struct AmateurlogDatabase *db, const char *source);
+
+/**
+ * Starts `text` on the program as it is now, returning null if it doesn't
+ * parse. Nothing is searched for until `amateurlog_next_solution`.
+ *
+ * # Safety
+ *
+ * `db` came from `amateurlog_database_new`, and `text` is a NUL-terminated
+ * string.
+ */
+struct AmateurlogQuery *amateurlog_query(const struct AmateurlogDatabase *db, const char *text);
+
+/**
+ * Finds the query's next solution. Returns 1, setting `*solution` to a JSON
+ * object of its variables' values as `Solution::to_json` writes them; 0
+ * once there are no more; or -1 for an uncaught exception, a timeout or an
+ * exhausted resource.
+ *
+ * # Safety
+ *
+ * `query` came from `amateurlog_query`, and `solution` points to a `char *`
+ * to set.
+ */
+int amateurlog_next_solution(struct AmateurlogQuery *query, char **solution);
+
+/**
+ * Stops a query and frees it.
+ *
+ * # Safety
+ *
+ * `query` is null or came from `amateurlog_query` and isn't used again.
+ */
+void amateurlog_query_free(struct AmateurlogQuery *query);
+
+/**
+ * Frees a string handed out by this library.
+ *
+ * # Safety
+ *
+ * `text` is null or came from this library and isn't used again.
+ */
+void amateurlog_string_free(char *text);
+
+/**
+ * Why the last call on this thread to fail did, or null. The text lasts
+ * until the next call that fails.
+ */
+const char *amateurlog_last_error(void);
+
+#endif  /* AMATEURLOG_H */
@@ -0,0 +1,173 @@
+//! `aggregate_all/3`: a figure over all the solutions of a goal, found with
+//! `findall/3`.
+
+use crate::arith::{self, Number};
+use crate::builtins::{self, Alternatives};
+use crate::error;
+use crate::solver::{Bindings, Solver};
+use crate::Term;
+use alloc::boxed::Box;
+use alloc::vec;
+use core::iter;
+
+/// `aggregate_all(Spec, Goal, Result)`, where Spec is `count`, `sum(Expr)`,
+/// `max(Expr)`, `min(Expr)`, `max(Expr, Witness)`, `min(Expr, Witness)`,
+/// `bag(Template)` or `set(Template)`. `max` and `min` fail if Goal has no
+/// solutions; the witness forms give `max(Value, Witness)`.
+pub(crate) fn aggregate_all(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    let spec = match bindings.walk(&args[0]) {
+        Term::Variable(_) => return builtins::raise(solver, error::instantiation_error()),
+        spec => spec.clone(),
+    };
+    let (name, spec_args) = match &spec {
+        Term::Atom(name) => (name.as_str(), &[][..]),
+        Term::Functor(f) => (f.name.as_str(), &f.args[..]),
+        _ => ("", &[][..]),
+    };
+    let found = match (name, spec_args) {
+        ("count", []) => {
+            let solutions = builtins::collect(solver, &Term::nil(), &args[1], bindings);
+            Ok(Some(Term::Integer(solutions.len() as i64)))
+        }
+        ("bag", [template]) => Ok(Some(Term::list_from_vec(builtins::collect(
+            solver, template, &args[1], bindings,
+        )))),
+        ("set", [template]) => {
+            let mut items = builtins::collect(solver, template, &args[1], bindings);
+            items.sort();
+            items.dedup();
+            Ok(Some(Term::list_from_vec(items)))
+        }
+        ("sum", [expr]) => {
+            let values = builtins::collect(solver, expr, &args[1], bindings);
+            let rationals = arith::rationals(solver);
+            let sum = values.into_iter().try_fold(Term::Integer(0), |sum, value| {
+                let sum = Term::functor_from_str("+", vec![sum, value]);
+                arith::eval(&sum, &Bindings::new(), rationals).map(Number::to_term)
+            });
+            sum.map(Some)
+        }
+        ("max" | "min", [expr]) => extreme(solver, name, expr, None, &args[1], bindings),
+        ("max" | "min", [expr, witness]) => {
+            extreme(solver, name, expr, Some(witness), &args[1], bindings)
+        }
+        _ => Err(error::domain_error(
+            "aggregate_spec",
+            bindings.resolve(&spec),
+        )),
+    };
+    match found {
+        Ok(Some(result)) => Box::new(iter::once(builtins::unify(vec![(args[2].clone(), result)]))),
+        Ok(None) => builtins::failure(),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+// the greatest or least value of `expr`, with the `witness` of the first
+// solution giving it if there is one, or `None` if `goal` has no solutions
+fn extreme(
+    solver: &mut Solver,
+    name: &str,
+    expr: &Term,
+    witness: Option<&Term>,
+    goal: &Term,
+    bindings: &Bindings,
+) -> Result<Option<Term>, Term> {
+    let pair = Term::functor_from_str(
+        "-",
+        vec![expr.clone(), witness.cloned().unwrap_or(Term::nil())],
+    );
+    let rationals = arith::rationals(solver);
+    let mut best: Option<(Number, Term)> = None;
+    for found in builtins::collect(solver, &pair, goal, bindings) {
+        let Term::Functor(found) = found else {
+            continue;
+        };
+        let [value, witness] = [&found.args[0], &found.args[1]];
+        let value = arith::eval(value, &Bindings::new(), rationals)?;
+        let better = best.as_ref().is_none_or(|(best, _)| {
+            if name == "max" {
+                value > *best
+            } else {
+                value < *best
+            }
+        });
+        if better {
+            best = Some((value, witness.clone()));
+        }
+    }
+    Ok(best.map(|(value, found)| match witness {
+        Some(_) => Term::functor_from_str(name, vec![value.to_term(), found]),
+        None => value.to_term(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    // the value of the query's last variable in each solution
+    fn answer(db: &Database, query: &str) -> Result<Vec<String>, SolveError> {
+        let query = parse_query(query).unwrap();
+        let answers = db.try_satisfy_all(query.goal.clone())?;
+        Ok(answers
+            .iter()
+            .map(|bindings| {
+                let solution = query.solution(bindings);
+                let (_, value) = solution.values().last().unwrap();
+                value.pretty().to_string()
+            })
+            .collect())
+    }
+
+    #[test]
+    fn aggregates_all_solutions() {
+        let mut db = Database::new();
+        db.consult(
+            "likes(sam, chocolate). likes(olive, chocolate). likes(popeye, spinach).
+            age(sam, 30). age(olive, 25). age(popeye, 40).",
+        )
+        .unwrap();
+        let one = |query: &str| answer(&db, query).unwrap();
+        assert_eq!(one("aggregate_all(count, likes(_, chocolate), N)"), ["2"]);
+        assert_eq!(one("aggregate_all(count, likes(_, cake), N)"), ["0"]);
+        assert_eq!(one("aggregate_all(sum(A), age(_, A), S)"), ["95"]);
+        assert_eq!(one("aggregate_all(sum(A), age(nobody, A), S)"), ["0"]);
+        assert_eq!(one("aggregate_all(max(A), age(_, A), M)"), ["40"]);
+        assert_eq!(
+            one("aggregate_all(min(A - 5, W), age(W, A), M)"),
+            ["min(20, olive)"]
+        );
+        assert!(one("aggregate_all(max(A), age(nobody, A), M)").is_empty());
+        assert_eq!(
+            one("aggregate_all(bag(F), likes(_, F), B)"),
+            ["[chocolate, chocolate, spinach]"]
+        );
+        assert_eq!(
+            one("aggregate_all(set(F), likes(_, F), B)"),
+            ["[chocolate, spinach]"]
+        );
+        for (query, error) in [
+            ("aggregate_all(_, true, _)", "instantiation_error"),
+            (
+                "aggregate_all(average(X), age(_, X), _)",
+                "domain_error(aggregate_spec",
+            ),
+            (
+                "aggregate_all(sum(W), age(W, _), _)",
+                "type_error(evaluable",
+            ),
+        ] {
+            match answer(&db, query) {
+                Err(SolveError::Uncaught(ball)) => {
+                    assert!(ball.to_string().contains(error), "{}", ball)
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+}
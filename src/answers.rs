@@ -0,0 +1,141 @@
+//! `Database::answers`: a query's solutions one at a time, as an iterator.
+//! The search runs on a thread of its own and only looks for each solution
+//! once it's asked for, so a query can be left part-way through and resumed,
+//! as the REPL does between `;`s. Dropping the iterator stops the search;
+//! `Answers::keep_changes` stops it keeping what the query asserted.
+
+use crate::stream::Producer;
+use crate::{Bindings, Database, Functor, SolveError};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+
+/// The solutions of a query, each found when `next` is called. An uncaught
+/// exception or a timeout is the last item.
+pub struct Answers {
+    producer: Producer,
+}
+
+// wakes the thread waiting in `next`
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+impl Iterator for Answers {
+    type Item = Result<Bindings, SolveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let waker = Arc::new(Unpark(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match self.producer.poll_next(&mut cx) {
+                Poll::Ready(found) => return found,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+}
+
+impl Answers {
+    /// Stops the search, keeping in `db` what the query asserted and
+    /// retracted, and the flags it set, as `Database::for_each_solution_mut`
+    /// does. `db` is only copied if it's shared and the query changed it.
+    pub fn keep_changes(mut self, db: &mut Arc<Database>) {
+        self.producer.cancel();
+        while self.next().is_some() {}
+        let Some((changes, flags)) = self.producer.changed() else {
+            return;
+        };
+        if !changes.is_empty() || flags != db.config.flags {
+            Arc::make_mut(db).keep(changes, flags);
+        }
+    }
+}
+
+impl Database {
+    /// The solutions of `goal`, searched for on another thread as they're
+    /// asked for.
+    pub fn answers(self: Arc<Self>, goal: Functor) -> Answers {
+        Answers {
+            producer: Producer::start(self, goal),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+
+    fn program(text: &str) -> Arc<Database> {
+        let mut db = Database::new();
+        db.consult(text).unwrap();
+        Arc::new(db)
+    }
+
+    #[test]
+    fn answers_are_found_as_asked_for() {
+        let db = program("nat(0).\nnat(N) :- nat(M), N is M + 1.");
+        let query = parse_query("nat(N)").unwrap();
+        let mut answers = db.clone().answers(query.goal.clone());
+        let mut next = || query.solution(&answers.next().unwrap().unwrap()).to_json();
+        assert_eq!(next(), r#"{"N": 0}"#);
+        assert_eq!(next(), r#"{"N": 1}"#);
+        let query = parse_query("nat(2), X is foo").unwrap();
+        let answers: Vec<_> = db.answers(query.goal).collect();
+        assert_eq!(answers.len(), 1);
+        assert!(matches!(answers[0], Err(SolveError::Uncaught(_))));
+    }
+
+    #[test]
+    fn answers_end_with_the_search() {
+        let db = program("n(1). n(2).");
+        let query = parse_query("n(X)").unwrap();
+        let mut answers = db.answers(query.goal);
+        assert!(answers.by_ref().take(2).all(|answer| answer.is_ok()));
+        assert!(answers.next().is_none());
+        assert!(answers.next().is_none());
+    }
+
+    #[test]
+    fn the_search_waits_to_be_asked() {
+        let db = program("n(1). n(2).");
+        let query = parse_query("n(X), nb_setval(last, X)").unwrap();
+        let mut answers = db.clone().answers(query.goal);
+        assert!(answers.next().unwrap().is_ok());
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        let last = parse_query("nb_getval(last, X)").unwrap();
+        let found = db.satisfy_all(last.goal.clone());
+        assert_eq!(last.solution(&found[0]).to_json(), r#"{"X": 1}"#);
+    }
+
+    #[test]
+    fn cancelled_answers_end_with_an_error() {
+        let token = CancellationToken::new();
+        let mut db = Database::new();
+        db.consult("nat(0).\nnat(N) :- nat(M), N is M + 1.")
+            .unwrap();
+        db.set_config(SolverConfig::default().cancellation(token.clone()));
+        let query = parse_query("nat(N)").unwrap();
+        let mut answers = Arc::new(db).answers(query.goal);
+        assert!(answers.next().unwrap().is_ok());
+        token.cancel();
+        assert!(matches!(answers.next(), Some(Err(SolveError::Cancelled))));
+        assert!(answers.next().is_none());
+    }
+
+    #[test]
+    fn kept_answers_change_the_database() {
+        let mut db = program("n(1). n(2).");
+        let query = parse_query("n(X), assertz(seen(X))").unwrap();
+        let mut answers = db.clone().answers(query.goal);
+        assert!(answers.next().unwrap().is_ok());
+        answers.keep_changes(&mut db);
+        let seen = parse_query("seen(X)").unwrap();
+        assert_eq!(db.satisfy_all(seen.goal).len(), 1);
+    }
+}
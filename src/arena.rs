@@ -0,0 +1,234 @@
+//! Scratch space for resolution. Every clause tried is copied with fresh
+//! variables, every goal called is taken apart, and backtracking lets go of
+//! the terms variables were bound to, so a query makes and drops compound
+//! terms at each step. Rather than going to the allocator for each, the
+//! solver keeps the nodes it's done with in its arena and builds the next
+//! terms out of them; likewise the argument lists of the calls it's done
+//! with. A query ends up reusing about as many of each as it has alive at
+//! once, all freed together when the solver is dropped.
+//!
+//! The nodes are the boxes `Term::Functor` holds, so they're kept on a free
+//! list rather than carved out of one block: a `Box` can only be given back
+//! to the allocator it came from.
+use crate::compat::HashMap;
+use crate::{Alias, Atom, Functor, Span, Term, Variable};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use rand::Rng;
+
+// how deeply copying a term recurses before going on with a work list
+const COPY_DEPTH: usize = 64;
+
+#[derive(Default)]
+pub(crate) struct Arena {
+    // nodes given back, emptied, their argument lists' room kept
+    #[allow(clippy::vec_box)]
+    free: Vec<Box<Functor>>,
+    // argument lists given back, emptied
+    lists: Vec<Vec<Term>>,
+}
+
+impl Arena {
+    /// An empty compound term named `name`, reusing a node given back if
+    /// there is one.
+    pub(crate) fn node(&mut self, name: Atom) -> Box<Functor> {
+        match self.free.pop() {
+            Some(mut node) => {
+                node.name = name;
+                node
+            }
+            None => Box::new(Functor::new_fact(name, Vec::new())),
+        }
+    }
+    /// An empty argument list, reusing one given back if there is one.
+    pub(crate) fn take(&mut self) -> Vec<Term> {
+        self.lists.pop().unwrap_or_default()
+    }
+    /// Lets go of `terms`, keeping the list and their nodes.
+    pub(crate) fn give(&mut self, mut terms: Vec<Term>) {
+        for term in terms.drain(..) {
+            self.reclaim(term);
+        }
+        self.lists.push(terms);
+    }
+    /// Lets go of `term`, keeping its nodes, and those of its arguments, to
+    /// build later terms out of.
+    pub(crate) fn reclaim(&mut self, term: Term) {
+        let Term::Functor(node) = term else {
+            return;
+        };
+        // each node is emptied where it lands on the free list, its compound
+        // arguments joining it there, so deep terms need no deep Rust stack
+        let mut next = self.free.len();
+        self.free.push(node);
+        while let Some(node) = self.free.get_mut(next) {
+            let mut args = core::mem::take(&mut node.args);
+            node.body.clear();
+            node.span = None;
+            node.ix = 0;
+            for arg in args.drain(..) {
+                if let Term::Functor(f) = arg {
+                    self.free.push(f);
+                }
+            }
+            self.free[next].args = args;
+            next += 1;
+        }
+    }
+    /// The name, arguments and span of `functor`, keeping its node, which
+    /// gets a spare list for the arguments taken out.
+    pub(crate) fn take_apart(
+        &mut self,
+        mut functor: Box<Functor>,
+    ) -> (Atom, Vec<Term>, Option<Arc<Span>>) {
+        let spare = self.take();
+        let parts = (
+            functor.name,
+            core::mem::replace(&mut functor.args, spare),
+            functor.span.take(),
+        );
+        self.reclaim(Term::Functor(functor));
+        parts
+    }
+    /// A copy of `functor`'s head, built out of spare nodes, with each
+    /// variable replaced as `Term::rename` would; its body is left out.
+    pub(crate) fn copy<Generator: Rng>(
+        &mut self,
+        functor: &Functor,
+        rng: &mut Generator,
+        renamed: &mut HashMap<Alias, Term>,
+    ) -> Box<Functor> {
+        let mut node = self.copy_within(functor, COPY_DEPTH, rng, renamed);
+        node.span = functor.span.clone();
+        node
+    }
+    fn copy_within<Generator: Rng>(
+        &mut self,
+        functor: &Functor,
+        depth: usize,
+        rng: &mut Generator,
+        renamed: &mut HashMap<Alias, Term>,
+    ) -> Box<Functor> {
+        if depth == 0 {
+            return self.copy_deep(functor, rng, renamed);
+        }
+        let mut node = self.node(functor.name);
+        for arg in &functor.args {
+            let arg = match arg {
+                Term::Variable(v) => fresh(v, rng, renamed),
+                Term::Functor(f) => Term::Functor(self.copy_within(f, depth - 1, rng, renamed)),
+                arg => arg.clone(),
+            };
+            node.args.push(arg);
+        }
+        node
+    }
+    fn copy_deep<Generator: Rng>(
+        &mut self,
+        functor: &Functor,
+        rng: &mut Generator,
+        renamed: &mut HashMap<Alias, Term>,
+    ) -> Box<Functor> {
+        // what's left to do, last first, building terms on `done` as their
+        // arguments are finished
+        enum Task<'a> {
+            Copy(&'a Term),
+            Build(&'a Functor),
+        }
+        let mut tasks = vec![Task::Build(functor)];
+        tasks.extend(functor.args.iter().rev().map(Task::Copy));
+        let mut done: Vec<Term> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Copy(Term::Variable(v)) => done.push(fresh(v, rng, renamed)),
+                Task::Copy(Term::Functor(f)) => {
+                    tasks.push(Task::Build(f));
+                    tasks.extend(f.args.iter().rev().map(Task::Copy));
+                }
+                Task::Copy(term) => done.push(term.clone()),
+                Task::Build(f) => {
+                    let mut node = self.node(f.name);
+                    node.args.extend(done.drain(done.len() - f.arity()..));
+                    done.push(Term::Functor(node));
+                }
+            }
+        }
+        match done.pop() {
+            Some(Term::Functor(node)) => node,
+            _ => unreachable!("a compound term copied"),
+        }
+    }
+}
+
+// the variable replacing `v` in a copy
+fn fresh<Generator: Rng>(
+    v: &Variable,
+    rng: &mut Generator,
+    renamed: &mut HashMap<Alias, Term>,
+) -> Term {
+    renamed
+        .entry(v.alias)
+        .or_insert_with(|| Term::Variable(Variable::new_anonymous(rng)))
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compat::thread_rng;
+    use crate::parser::parse_query;
+
+    #[test]
+    fn reuses_nodes() {
+        let mut arena = Arena::default();
+        let mut rng = thread_rng();
+        let clause = parse_query("p(f(X, g(Y)), X)").unwrap().goal;
+        let copy = arena.copy(&clause, &mut rng, &mut HashMap::new());
+        let Term::Functor(f) = &copy.args[0] else {
+            panic!("a compound argument");
+        };
+        assert_eq!(f.name.as_str(), "f");
+        assert_eq!(f.args[0], copy.args[1]);
+        assert_ne!(f.args[0], clause.args[1]);
+        arena.reclaim(Term::Functor(copy));
+        assert_eq!(arena.free.len(), 3);
+        assert!(arena.free.iter().all(|node| node.args.is_empty()));
+        let again = arena.copy(&clause, &mut rng, &mut HashMap::new());
+        assert!(arena.free.is_empty());
+        assert_eq!(again.args.len(), 2);
+    }
+
+    #[test]
+    fn reuses_argument_lists() {
+        let mut arena = Arena::default();
+        let mut args = arena.take();
+        args.extend([Term::atom_from_str("a"), Term::functor_from_str("f", vec![])]);
+        let capacity = args.capacity();
+        arena.give(args);
+        assert_eq!(arena.free.len(), 1);
+        let args = arena.take();
+        assert!(args.is_empty());
+        assert_eq!(args.capacity(), capacity);
+        assert_eq!(arena.take().capacity(), 0);
+    }
+
+    #[test]
+    fn deep_terms_need_no_deep_stack() {
+        let mut arena = Arena::default();
+        let list = Term::list_from_vec(vec![Term::Integer(1); 100_000]);
+        let goal = Functor::new_fact(Atom::new("p"), vec![list]);
+        let copy = arena.copy(&goal, &mut thread_rng(), &mut HashMap::new());
+        let mut items = 0;
+        let mut rest = &copy.args[0];
+        while let Term::Functor(cons) = rest {
+            assert_eq!(cons.args[0], Term::Integer(1));
+            items += 1;
+            rest = &cons.args[1];
+        }
+        assert_eq!((items, rest), (100_000, &Term::nil()));
+        arena.reclaim(Term::Functor(copy));
+        assert_eq!(arena.free.len(), 100_001);
+    }
+}
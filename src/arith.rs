@@ -0,0 +1,577 @@
+use crate::builtins::{self, Alternatives};
+use crate::error;
+use crate::solver::{Bindings, Solver};
+use crate::{Functor, Term};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::iter;
+
+/// A value of an arithmetic expression: `numerator / denominator` in lowest
+/// terms, with a positive denominator. Integers have denominator 1; other
+/// values are rationals, written as the term `N rdiv D`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Number {
+    numerator: i64,
+    denominator: i64,
+}
+
+impl Number {
+    fn integer(n: i64) -> Self {
+        Number {
+            numerator: n,
+            denominator: 1,
+        }
+    }
+    // `n / d` in lowest terms, if it fits
+    pub(crate) fn ratio(n: i128, d: i128) -> Result<Self, Term> {
+        if d == 0 {
+            return Err(error::evaluation_error("zero_divisor"));
+        }
+        let divisor = gcd(n, d) * d.signum();
+        Ok(Number {
+            numerator: i64::try_from(n / divisor).map_err(|_| overflow())?,
+            denominator: i64::try_from(d / divisor).map_err(|_| overflow())?,
+        })
+    }
+    fn as_integer(self) -> Option<i64> {
+        (self.denominator == 1).then_some(self.numerator)
+    }
+    // the value of an argument that must be an integer
+    fn whole(self) -> Result<i64, Term> {
+        self.as_integer()
+            .ok_or_else(|| error::type_error("integer", self.to_term()))
+    }
+    fn parts(self) -> (i128, i128) {
+        (self.numerator.into(), self.denominator.into())
+    }
+    pub(crate) fn to_term(self) -> Term {
+        match self.as_integer() {
+            Some(n) => Term::Integer(n),
+            None => Term::functor_from_str(
+                "rdiv",
+                vec![
+                    Term::Integer(self.numerator),
+                    Term::Integer(self.denominator),
+                ],
+            ),
+        }
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ((a, b), (c, d)) = (self.parts(), other.parts());
+        (a * d).cmp(&(c * b))
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Evaluates an arithmetic expression, or returns the error term to throw:
+/// for an unbound variable, something that isn't arithmetic, division by
+/// zero or overflow. `/` of two integers that don't divide exactly is a
+/// rational with `rationals`, and otherwise undefined.
+pub(crate) fn eval(term: &Term, bindings: &Bindings, rationals: bool) -> Result<Number, Term> {
+    // what's left to do, last first, applying each operator to the values
+    // of its operands on `done` once they're worked out, so deep
+    // expressions need no deep Rust stack
+    enum Task<'a> {
+        Eval(&'a Term),
+        Apply(&'a Functor),
+    }
+    let mut tasks = vec![Task::Eval(term)];
+    let mut done: Vec<Number> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Eval(term) => match bindings.walk(term) {
+                Term::Integer(n) => done.push(Number::integer(*n)),
+                Term::Variable(_) => return Err(error::instantiation_error()),
+                Term::Atom(a) => return Err(not_evaluable(a.as_str(), 0)),
+                Term::String(_) => {
+                    return Err(error::type_error("evaluable", bindings.resolve(term)))
+                }
+                Term::Functor(f) if matches!(f.arity(), 1 | 2) => {
+                    tasks.push(Task::Apply(f));
+                    tasks.extend(f.args.iter().rev().map(Task::Eval));
+                }
+                Term::Functor(f) => return Err(not_evaluable(f.name.as_str(), f.arity())),
+            },
+            Task::Apply(f) => {
+                let operands = done.split_off(done.len() - f.arity());
+                let value = match operands[..] {
+                    [x] => unary(f.name.as_str(), x)?,
+                    [x, y] => binary(f.name.as_str(), x, y, rationals)?,
+                    _ => unreachable!("operators take one or two operands"),
+                };
+                done.push(value);
+            }
+        }
+    }
+    Ok(done.pop().expect("one value worked out"))
+}
+
+fn overflow() -> Term {
+    error::evaluation_error("int_overflow")
+}
+
+fn whole(n: Option<i64>) -> Result<Number, Term> {
+    n.map(Number::integer).ok_or_else(overflow)
+}
+
+fn unary(name: &str, x: Number) -> Result<Number, Term> {
+    let (n, d) = x.parts();
+    match name {
+        "-" => Number::ratio(-n, d),
+        "+" => Ok(x),
+        "abs" => Number::ratio(n.abs(), d),
+        "sign" => Ok(Number::integer(x.numerator.signum())),
+        "floor" => Number::ratio(n.div_euclid(d), 1),
+        "ceiling" => Number::ratio(-(-n).div_euclid(d), 1),
+        "truncate" => Number::ratio(n / d, 1),
+        "\\" => Ok(Number::integer(!x.whole()?)),
+        "msb" => match x.whole()? {
+            n if n > 0 => Ok(Number::integer(63 - i64::from(n.leading_zeros()))),
+            n => Err(error::type_error("not_less_than_one", Term::Integer(n))),
+        },
+        name => Err(not_evaluable(name, 1)),
+    }
+}
+
+fn binary(name: &str, x: Number, y: Number, rationals: bool) -> Result<Number, Term> {
+    let ((a, b), (c, d)) = (x.parts(), y.parts());
+    let divides = matches!(name, "//" | "rem" | "mod");
+    if divides && y.numerator == 0 {
+        return Err(error::evaluation_error("zero_divisor"));
+    }
+    match name {
+        "+" => Number::ratio(a * d + c * b, b * d),
+        "-" => Number::ratio(a * d - c * b, b * d),
+        "*" => Number::ratio(a * c, b * d),
+        "rdiv" => Number::ratio(a * d, b * c),
+        "/" => {
+            let exact = Number::ratio(a * d, b * c)?;
+            let integers = x.as_integer().is_some() && y.as_integer().is_some();
+            if integers && exact.as_integer().is_none() && !rationals {
+                return Err(error::evaluation_error("undefined"));
+            }
+            Ok(exact)
+        }
+        "min" => Ok(x.min(y)),
+        "max" => Ok(x.max(y)),
+        "//" => whole(x.whole()?.checked_div(y.whole()?)),
+        "rem" => whole(x.whole()?.checked_rem(y.whole()?)),
+        "mod" => {
+            let (x, y) = (x.whole()?, y.whole()?);
+            whole(x.checked_rem(y).map(|r| {
+                if r != 0 && (r < 0) != (y < 0) {
+                    r + y
+                } else {
+                    r
+                }
+            }))
+        }
+        "/\\" => Ok(Number::integer(x.whole()? & y.whole()?)),
+        "\\/" => Ok(Number::integer(x.whole()? | y.whole()?)),
+        "xor" => Ok(Number::integer(x.whole()? ^ y.whole()?)),
+        "<<" => shift(x.whole()?, y.whole()?),
+        ">>" => shift(x.whole()?, y.whole()?.checked_neg().ok_or_else(overflow)?),
+        "gcd" => Number::ratio(gcd(x.whole()?.into(), y.whole()?.into()), 1),
+        name => Err(not_evaluable(name, 2)),
+    }
+}
+
+// `x` shifted left by `by` bits, or right (arithmetically) if `by` is negative
+fn shift(x: i64, by: i64) -> Result<Number, Term> {
+    if by < 0 {
+        let by = u32::try_from(by.unsigned_abs()).unwrap_or(u32::MAX).min(63);
+        return Ok(Number::integer(x >> by));
+    }
+    match u32::try_from(by) {
+        _ if x == 0 => Ok(Number::integer(0)),
+        Ok(by) if by < 64 => Number::ratio(i128::from(x) << by, 1),
+        _ => Err(overflow()),
+    }
+}
+
+fn not_evaluable(name: &str, arity: usize) -> Term {
+    error::type_error("evaluable", error::indicator(name, arity))
+}
+
+// whether `/` may give rationals in `solver`'s queries
+pub(crate) fn rationals(solver: &Solver) -> bool {
+    solver.db().config.prefer_rationals
+}
+
+pub(crate) fn is(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    match eval(&args[1], bindings, rationals(solver)) {
+        Ok(n) => Box::new(iter::once(builtins::unify(vec![(
+            args[0].clone(),
+            n.to_term(),
+        )]))),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+fn comparison(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+    holds: fn(Ordering) -> bool,
+) -> Alternatives {
+    let rationals = rationals(solver);
+    let values = eval(&args[0], bindings, rationals)
+        .and_then(|x| Ok((x, eval(&args[1], bindings, rationals)?)));
+    match values {
+        Ok((x, y)) if holds(x.cmp(&y)) => builtins::success(),
+        Ok(_) => builtins::failure(),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+pub(crate) fn equal(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    comparison(solver, args, bindings, Ordering::is_eq)
+}
+
+pub(crate) fn not_equal(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    comparison(solver, args, bindings, Ordering::is_ne)
+}
+
+pub(crate) fn less(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    comparison(solver, args, bindings, Ordering::is_lt)
+}
+
+pub(crate) fn greater(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    comparison(solver, args, bindings, Ordering::is_gt)
+}
+
+pub(crate) fn less_or_equal(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    comparison(solver, args, bindings, Ordering::is_le)
+}
+
+pub(crate) fn greater_or_equal(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    comparison(solver, args, bindings, Ordering::is_ge)
+}
+
+// an integer argument, `None` if it is unbound
+fn integer(term: &Term, bindings: &Bindings) -> Result<Option<i64>, Term> {
+    match bindings.walk(term) {
+        Term::Integer(n) => Ok(Some(*n)),
+        Term::Variable(_) => Ok(None),
+        other => Err(error::type_error("integer", other.clone())),
+    }
+}
+
+fn equals(term: &Term, n: i64) -> Alternatives {
+    Box::new(iter::once(builtins::unify(vec![(
+        term.clone(),
+        Term::Integer(n),
+    )])))
+}
+
+/// `between(Low, High, X)`: enumerates `Low..=High` into `X`, or checks it if
+/// it is bound. `High` may be `inf` or `infinite`.
+pub(crate) fn between(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let high = match bindings.walk(&args[1]) {
+        Term::Atom(a) if matches!(a.as_str(), "inf" | "infinite") => Ok(Some(i64::MAX)),
+        high => integer(high, bindings),
+    };
+    let bounds = integer(&args[0], bindings).and_then(|low| Ok((low, high?)));
+    let (low, high) = match bounds {
+        Ok((Some(low), Some(high))) => (low, high),
+        Ok(_) => return builtins::raise(solver, error::instantiation_error()),
+        Err(ball) => return builtins::raise(solver, ball),
+    };
+    match integer(&args[2], bindings) {
+        Ok(Some(x)) if (low..=high).contains(&x) => builtins::success(),
+        Ok(Some(_)) => builtins::failure(),
+        Ok(None) => {
+            let x = args[2].clone();
+            Box::new(
+                (low..=high).map(move |n| builtins::unify(vec![(x.clone(), Term::Integer(n))])),
+            )
+        }
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+/// `succ(X, Y)`: `Y` is `X + 1`, both natural numbers.
+pub(crate) fn succ(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let values = integer(&args[0], bindings).and_then(|x| Ok((x, integer(&args[1], bindings)?)));
+    let not_natural = |n: i64| error::type_error("not_less_than_zero", Term::Integer(n));
+    let result = values.and_then(|values| match values {
+        (Some(x), _) if x < 0 => Err(not_natural(x)),
+        (_, Some(y)) if y < 0 => Err(not_natural(y)),
+        (Some(x), _) => x
+            .checked_add(1)
+            .map(|y| Some((&args[1], y)))
+            .ok_or_else(|| error::evaluation_error("int_overflow")),
+        (None, Some(0)) => Ok(None),
+        (None, Some(y)) => Ok(Some((&args[0], y - 1))),
+        (None, None) => Err(error::instantiation_error()),
+    });
+    match result {
+        Ok(Some((term, n))) => equals(term, n),
+        Ok(None) => builtins::failure(),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+/// `plus(X, Y, Z)`: `Z` is `X + Y`, given any two of them.
+pub(crate) fn plus(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let values: Result<Vec<Option<i64>>, Term> =
+        args.iter().map(|arg| integer(arg, bindings)).collect();
+    let result = match values.as_deref() {
+        Ok([Some(x), Some(y), _]) => x.checked_add(*y).map(|z| (&args[2], z)),
+        Ok([Some(x), None, Some(z)]) => z.checked_sub(*x).map(|y| (&args[1], y)),
+        Ok([None, Some(y), Some(z)]) => z.checked_sub(*y).map(|x| (&args[0], x)),
+        Ok(_) => return builtins::raise(solver, error::instantiation_error()),
+        Err(ball) => return builtins::raise(solver, ball.clone()),
+    };
+    match result {
+        Some((term, n)) => equals(term, n),
+        None => builtins::raise(solver, error::evaluation_error("int_overflow")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{answers, consulted};
+    use crate::*;
+    use std::ops::ControlFlow;
+
+    #[test]
+    fn evaluates_with_precedence() {
+        assert_eq!(
+            answers(&Database::new(), "X is 2 + 3 * 4 - 10 // 3").unwrap(),
+            vec!["X = 11"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "X is (0 - 7) mod 3, Y is (0 - 7) rem 3").unwrap(),
+            vec!["X = 2, Y = -1"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "X is 12 / 4").unwrap(),
+            vec!["X = 3"]
+        );
+    }
+
+    #[test]
+    fn divides_exactly_into_rationals() {
+        let solved = |db: &Database, query: &str| -> Vec<String> {
+            let query = parse_query(query).unwrap();
+            db.satisfy_all(query.goal.clone())
+                .iter()
+                .map(|b| b.resolve(&query.variables[0].1).pretty().to_string())
+                .collect()
+        };
+        let mut db = Database::new();
+        assert_eq!(solved(&db, "X is 1 rdiv 3 + 1 rdiv 6"), vec!["1 rdiv 2"]);
+        assert_eq!(solved(&db, "X is 4 rdiv -6 * 3"), vec!["-2"]);
+        assert_eq!(solved(&db, "X = y, 1 rdiv 3 < 1 rdiv 2"), vec!["y"]);
+        assert_eq!(solved(&db, "X = y, 2 rdiv 4 =:= 1 rdiv 2"), vec!["y"]);
+        assert!(solved(&db, "X is 1 / 3").is_empty());
+        db.set_config(SolverConfig::default().prefer_rationals(true));
+        assert_eq!(solved(&db, "X is 1 / 3 - 1"), vec!["-2 rdiv 3"]);
+        assert_eq!(solved(&db, "X is 6 / 3"), vec!["2"]);
+        let query = parse_query("X is (1 rdiv 2) // 1").unwrap();
+        let Err(SolveError::Uncaught(ball)) = db.try_satisfy_all(query.goal) else {
+            panic!("expected a type error")
+        };
+        assert!(ball
+            .to_string()
+            .starts_with("error(type_error(integer, rdiv(1, 2)), "));
+    }
+
+    #[test]
+    fn integer_functions() {
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "A is 1 << 4, B is -16 >> 2, C is 12 /\\ 10, D is 12 \\/ 3, E is 5 xor 3"
+            )
+            .unwrap(),
+            vec!["A = 16, B = -4, C = 8, D = 15, E = 6"]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "A is \\ 5, B is msb(1000), C is gcd(12, -18), D is min(3, 1 rdiv 2)"
+            )
+            .unwrap(),
+            vec!["A = -6, B = 9, C = 6, D = rdiv(1, 2)"]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "A is abs(-3), B is sign(-7 rdiv 2), C is max(2, 5)"
+            )
+            .unwrap(),
+            vec!["A = 3, B = -1, C = 5"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "A is floor(-7 rdiv 2), B is ceiling(-7 rdiv 2), C is truncate(-7 rdiv 2), D is floor(4)").unwrap(),
+            vec!["A = -4, B = -3, C = -3, D = 4"]
+        );
+        assert!(answers(&Database::new(), "X is 1 << 64").is_err());
+    }
+
+    #[test]
+    fn negates() {
+        assert_eq!(
+            answers(&Database::new(), "X is 3 - -2").unwrap(),
+            vec!["X = 5"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "X is -(2 * 3), Y is - 4 + 1").unwrap(),
+            vec!["X = -6, Y = -3"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "X is 3 -2").unwrap(),
+            vec!["X = 1"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "X = -1, integer(X)").unwrap(),
+            vec!["X = -1"]
+        );
+    }
+
+    #[test]
+    fn errors_are_thrown() {
+        let thrown = |query: &str| {
+            let query = parse_query(query).unwrap();
+            match Database::new().try_satisfy_all(query.goal) {
+                Err(SolveError::Uncaught(ball)) => ball.to_string(),
+                Ok(_) => String::from("no error"),
+                Err(err) => panic!("{}", err),
+            }
+        };
+        assert!(thrown("X is Y + 1").starts_with("error(instantiation_error, "));
+        assert!(thrown("X is foo + 1").starts_with("error(type_error(evaluable, /(foo, 0)), "));
+        assert!(thrown("X is 1 // 0").starts_with("error(evaluation_error(zero_divisor), "));
+        assert!(thrown("X is 7 / 2").starts_with("error(evaluation_error(undefined), "));
+        assert!(thrown("1 < a").starts_with("error(type_error(evaluable, "));
+        assert!(thrown("X is foo(1)").starts_with("error(type_error(evaluable, /(foo, 1)), "));
+    }
+
+    #[test]
+    fn compares_values() {
+        assert_eq!(answers(&Database::new(), "1 + 2 =:= 3").unwrap().len(), 1);
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "2 * 2 > 3, 3 =< 3, 3 >= 3, 1 < 2, 1 =\\= 2"
+            )
+            .unwrap()
+            .len(),
+            1
+        );
+        assert!(answers(&Database::new(), "2 < 1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn between_enumerates_and_checks() {
+        assert_eq!(
+            answers(&Database::new(), "between(1, 3, X)").unwrap(),
+            vec!["X = 1", "X = 2", "X = 3"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "between(1, 3, 3)").unwrap().len(),
+            1
+        );
+        assert!(answers(&Database::new(), "between(3, 1, X)")
+            .unwrap()
+            .is_empty());
+        let query = parse_query("between(5, inf, X), X > 6").unwrap();
+        let mut found = Vec::new();
+        let _ = Database::new().for_each_solution(query.goal.clone(), |b| {
+            found.push(query.solution(b).values()[0].1.to_string());
+            if found.len() < 2 {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        });
+        assert_eq!(found, vec!["7", "8"]);
+    }
+
+    #[test]
+    fn succ_and_plus_run_both_ways() {
+        assert_eq!(
+            answers(&Database::new(), "succ(3, X)").unwrap(),
+            vec!["X = 4"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "succ(X, 4)").unwrap(),
+            vec!["X = 3"]
+        );
+        assert!(answers(&Database::new(), "succ(X, 0)").unwrap().is_empty());
+        assert_eq!(
+            answers(&Database::new(), "plus(2, X, 5)").unwrap(),
+            vec!["X = 3"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "plus(X, 2, 5)").unwrap(),
+            vec!["X = 3"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "plus(2, 3, X)").unwrap(),
+            vec!["X = 5"]
+        );
+        let thrown = |query: &str| {
+            let query = parse_query(query).unwrap();
+            Database::new().try_satisfy_all(query.goal).is_err()
+        };
+        assert!(thrown("succ(X, Y)"));
+        assert!(thrown("succ(X, -1)"));
+        assert!(thrown("plus(X, Y, 1)"));
+        assert!(thrown("between(1, a, X)"));
+    }
+
+    #[test]
+    fn arithmetic_in_rules() {
+        assert_eq!(
+            answers(
+                &consulted("price(apple, 3). total(F, N, T) :- price(F, P), T is P * N."),
+                "total(apple, 4, T)"
+            )
+            .unwrap(),
+            vec!["T = 12"]
+        );
+    }
+
+    #[test]
+    fn deep_expressions_need_no_deep_stack() {
+        let sum = vec!["1"; 100_000].join(" + ");
+        assert_eq!(
+            answers(&Database::new(), &format!("X is {}", sum)).unwrap(),
+            vec!["X = 100000"]
+        );
+        let query = format!("catch(X is {} + a, error(E, _), true)", sum);
+        assert_eq!(
+            answers(&Database::new(), &query).unwrap(),
+            vec!["X = X, E = type_error(evaluable, /(a, 0))"]
+        );
+    }
+}
@@ -0,0 +1,606 @@
+//! Built-ins for taking atoms apart and putting them together:
+//! `atom_length/2`, `atom_concat/3`, `atom_chars/2`, `atom_codes/2` and
+//! `sub_atom/5`, for converting between numbers and text, and for single
+//! characters: `char_code/2`, `char_type/2`, `upcase_atom/2` and
+//! `downcase_atom/2`. Lengths and offsets count characters, not bytes.
+
+use crate::builtins::{self, Alternatives, Branch};
+use crate::error;
+use crate::solver::{Bindings, Solver};
+use crate::Term;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter;
+
+// the text of an atomic term, `None` if it's unbound
+pub(crate) fn text(term: &Term, bindings: &Bindings) -> Result<Option<String>, Term> {
+    match bindings.walk(term) {
+        Term::Variable(_) => Ok(None),
+        Term::Atom(a) => Ok(Some(a.as_str().to_string())),
+        Term::Integer(n) => Ok(Some(n.to_string())),
+        Term::String(text) => Ok(Some(text.to_string())),
+        Term::Functor(_) => Err(error::type_error("atom", bindings.resolve(term))),
+    }
+}
+
+// a non-negative integer argument, `None` if it's unbound
+fn count(term: &Term, bindings: &Bindings) -> Result<Option<usize>, Term> {
+    match bindings.walk(term) {
+        Term::Variable(_) => Ok(None),
+        Term::Integer(n) if *n < 0 => {
+            Err(error::domain_error("not_less_than_zero", Term::Integer(*n)))
+        }
+        Term::Integer(n) => Ok(Some(*n as usize)),
+        other => Err(error::type_error("integer", other.clone())),
+    }
+}
+
+pub(crate) fn atom(text: &str) -> Term {
+    Term::atom_from_str(text)
+}
+
+fn equals(term: &Term, value: Term) -> Alternatives {
+    Box::new(iter::once(builtins::unify(vec![(term.clone(), value)])))
+}
+
+pub(crate) fn atom_length(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let length = text(&args[0], bindings).and_then(|text| {
+        count(&args[1], bindings)?;
+        text.ok_or_else(error::instantiation_error)
+            .map(|text| text.chars().count())
+    });
+    match length {
+        Ok(n) => equals(&args[1], Term::Integer(n as i64)),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+/// `atom_concat(A, B, AB)`: joins `A` and `B`, or enumerates the ways of
+/// splitting `AB` if either is unbound.
+pub(crate) fn atom_concat(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    concat(solver, args, bindings, atom)
+}
+
+// `atom_concat/3`, with the text it gives made into a term by `whole`
+pub(crate) fn concat(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+    whole: fn(&str) -> Term,
+) -> Alternatives {
+    let parts = text(&args[0], bindings).and_then(|a| Ok((a, text(&args[1], bindings)?)));
+    let joined = match parts {
+        Ok((Some(a), Some(b))) => return equals(&args[2], whole(&(a + &b))),
+        Ok(_) => text(&args[2], bindings),
+        Err(ball) => Err(ball),
+    };
+    let text = match joined {
+        Ok(Some(text)) => text,
+        Ok(None) => return builtins::raise(solver, error::instantiation_error()),
+        Err(ball) => return builtins::raise(solver, ball),
+    };
+    let (a, b) = (args[0].clone(), args[1].clone());
+    let splits = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(iter::once(text.len()))
+        .collect::<Vec<_>>();
+    Box::new(splits.into_iter().map(move |i| -> Branch {
+        builtins::unify(vec![
+            (a.clone(), whole(&text[..i])),
+            (b.clone(), whole(&text[i..])),
+        ])
+    }))
+}
+
+pub(crate) fn atom_chars(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    convert(solver, args, bindings, atom, char_item, char_of)
+}
+
+pub(crate) fn atom_codes(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    convert(solver, args, bindings, atom, code_item, code_of)
+}
+
+pub(crate) fn number_chars(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    convert_number(solver, args, bindings, char_item, char_of)
+}
+
+pub(crate) fn number_codes(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    convert_number(solver, args, bindings, code_item, code_of)
+}
+
+/// `atom_number(Atom, Number)`: fails, rather than throwing, if `Atom`
+/// doesn't spell a number.
+pub(crate) fn atom_number(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let text = match text(&args[0], bindings) {
+        Ok(Some(text)) => text,
+        Ok(None) => {
+            return match bindings.walk(&args[1]) {
+                Term::Integer(n) => equals(&args[0], atom(&n.to_string())),
+                Term::Variable(_) => builtins::raise(solver, error::instantiation_error()),
+                other => builtins::raise(solver, error::type_error("number", other.clone())),
+            }
+        }
+        Err(ball) => return builtins::raise(solver, ball),
+    };
+    match parse_number(&text) {
+        Some(n) => equals(&args[1], Term::Integer(n)),
+        None => builtins::failure(),
+    }
+}
+
+pub(crate) fn char_item(c: char) -> Term {
+    atom(c.encode_utf8(&mut [0; 4]))
+}
+
+pub(crate) fn char_of(item: &Term) -> Result<char, Term> {
+    match item {
+        Term::Atom(a) if a.as_str().chars().count() == 1 => Ok(a.as_str().chars().next().unwrap()),
+        other => Err(error::type_error("character", other.clone())),
+    }
+}
+
+fn code_item(c: char) -> Term {
+    Term::Integer(c as i64)
+}
+
+pub(crate) fn code_of(item: &Term) -> Result<char, Term> {
+    match item {
+        Term::Integer(n) => u32::try_from(*n)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| error::representation_error("character_code")),
+        other => Err(error::type_error("integer", other.clone())),
+    }
+}
+
+// the text spelled by a list of characters, each read by `from_item`
+pub(crate) fn spelled(
+    items: &[Term],
+    bindings: &Bindings,
+    from_item: fn(&Term) -> Result<char, Term>,
+) -> Result<String, Term> {
+    items
+        .iter()
+        .map(|item| match bindings.walk(item) {
+            Term::Variable(_) => Err(error::instantiation_error()),
+            item => from_item(item),
+        })
+        .collect()
+}
+
+// an optionally negative decimal integer, after any leading layout
+fn parse_number(text: &str) -> Option<i64> {
+    let text = text.trim_start();
+    if text.starts_with('+') {
+        return None;
+    }
+    text.parse().ok()
+}
+
+// between a text, made into a term by `whole`, and the list of its
+// characters, each as `item` makes it
+pub(crate) fn convert(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+    whole: fn(&str) -> Term,
+    item: fn(char) -> Term,
+    from_item: fn(&Term) -> Result<char, Term>,
+) -> Alternatives {
+    match text(&args[0], bindings) {
+        Ok(Some(text)) => {
+            return equals(
+                &args[1],
+                Term::list_from_vec(text.chars().map(item).collect()),
+            )
+        }
+        Ok(None) => {}
+        Err(ball) => return builtins::raise(solver, ball),
+    }
+    let Some(items) = builtins::list(solver, &args[1], bindings) else {
+        return builtins::failure();
+    };
+    match spelled(&items, bindings, from_item) {
+        Ok(text) => equals(&args[0], whole(&text)),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+// like `convert`, but a fully instantiated list is parsed even when the
+// number is given, so `number_codes(N, " 12")` works
+fn convert_number(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+    item: fn(char) -> Term,
+    from_item: fn(&Term) -> Result<char, Term>,
+) -> Alternatives {
+    let items = bindings.list_to_vec(&args[1]);
+    let ground = |items: &Vec<Term>| {
+        items
+            .iter()
+            .all(|i| !matches!(bindings.walk(i), Term::Variable(_)))
+    };
+    if let Some(items) = items.filter(ground) {
+        let ball = match spelled(&items, bindings, from_item).map(|text| parse_number(&text)) {
+            Ok(Some(n)) => return equals(&args[0], Term::Integer(n)),
+            Ok(None) => error::syntax_error("illegal_number"),
+            Err(ball) => ball,
+        };
+        return builtins::raise(solver, ball);
+    }
+    match bindings.walk(&args[0]) {
+        Term::Integer(n) => {
+            let items = n.to_string().chars().map(item).collect();
+            equals(&args[1], Term::list_from_vec(items))
+        }
+        Term::Variable(_) => match builtins::list(solver, &args[1], bindings) {
+            Some(_) => builtins::raise(solver, error::instantiation_error()),
+            None => builtins::failure(),
+        },
+        other => builtins::raise(solver, error::type_error("number", other.clone())),
+    }
+}
+
+/// `char_code(Char, Code)`: converts whichever is given.
+pub(crate) fn char_code(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let converted = match (bindings.walk(&args[0]), bindings.walk(&args[1])) {
+        (Term::Variable(_), Term::Variable(_)) => Err(error::instantiation_error()),
+        (Term::Variable(_), code) => code_of(code).map(|c| (&args[0], char_item(c))),
+        (char, _) => char_of(char).map(|c| (&args[1], code_item(c))),
+    };
+    match converted {
+        Ok((term, value)) => equals(term, value),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+/// `upcase_atom(Text, Upper)`: `Upper` is `Text` in upper case, as an atom.
+pub(crate) fn upcase_atom(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    change_case(solver, args, bindings, str::to_uppercase)
+}
+
+/// `downcase_atom(Text, Lower)`: `Lower` is `Text` in lower case, as an atom.
+pub(crate) fn downcase_atom(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    change_case(solver, args, bindings, str::to_lowercase)
+}
+
+fn change_case(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+    change: fn(&str) -> String,
+) -> Alternatives {
+    match text(&args[0], bindings) {
+        Ok(Some(text)) => equals(&args[1], atom(&change(&text))),
+        Ok(None) => builtins::raise(solver, error::instantiation_error()),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+const CHAR_TYPES: &[&str] = &[
+    "alnum",
+    "alpha",
+    "csym",
+    "csymf",
+    "digit",
+    "space",
+    "white",
+    "upper",
+    "lower",
+    "punct",
+    "graph",
+    "end_of_line",
+    "to_lower",
+    "to_upper",
+];
+
+/// `char_type(Char, Type)`: `Char` is of `Type`, one of `alnum`, `alpha`
+/// (a letter), `csym` (a letter, digit or `_`), `csymf` (a letter or `_`),
+/// `digit(Weight)`, `space`, `white` (a space or tab), `upper(Lower)`,
+/// `lower(Upper)`, `punct`, `graph`, `end_of_line`, `to_lower(Lower)` and
+/// `to_upper(Upper)`, with `digit`, `upper` and `lower` also taken without
+/// an argument. Enumerates the types of `Char` if `Type` is unbound.
+pub(crate) fn char_type(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let c = match bindings.walk(&args[0]) {
+        Term::Variable(_) => return builtins::raise(solver, error::instantiation_error()),
+        char => match char_of(char) {
+            Ok(c) => c,
+            Err(ball) => return builtins::raise(solver, ball),
+        },
+    };
+    let known = match bindings.walk(&args[1]) {
+        Term::Variable(_) => true,
+        Term::Atom(a) => CHAR_TYPES.contains(&a.as_str()),
+        Term::Functor(f) => f.arity() == 1 && CHAR_TYPES.contains(&f.name.as_str()),
+        _ => false,
+    };
+    if !known {
+        let ball = error::domain_error("char_type", bindings.resolve(&args[1]));
+        return builtins::raise(solver, ball);
+    }
+    let kind = args[1].clone();
+    Box::new(
+        char_types(c)
+            .into_iter()
+            .map(move |t| builtins::unify(vec![(kind.clone(), t)])),
+    )
+}
+
+// every type `c` has, as `char_type/2` would give them
+fn char_types(c: char) -> Vec<Term> {
+    let with = |name: &str, arg: Term| Term::functor_from_str(name, vec![arg]);
+    let lower: String = c.to_lowercase().collect();
+    let upper: String = c.to_uppercase().collect();
+    let mut types = Vec::new();
+    let mut add = |name: &str, holds: bool| {
+        if holds {
+            types.push(atom(name));
+        }
+    };
+    add("alnum", c.is_alphanumeric());
+    add("alpha", c.is_alphabetic());
+    add("csym", c.is_alphanumeric() || c == '_');
+    add("csymf", c.is_alphabetic() || c == '_');
+    add("digit", c.is_ascii_digit());
+    add("space", c.is_whitespace());
+    add("white", c == ' ' || c == '\t');
+    add("upper", c.is_uppercase());
+    add("lower", c.is_lowercase());
+    add("punct", c.is_ascii_punctuation());
+    add("graph", !c.is_whitespace() && !c.is_control());
+    add("end_of_line", c == '\n' || c == '\r');
+    if let Some(weight) = c.to_digit(10) {
+        types.push(with("digit", Term::Integer(weight as i64)));
+    }
+    if c.is_uppercase() {
+        types.push(with("upper", atom(&lower)));
+    }
+    if c.is_lowercase() {
+        types.push(with("lower", atom(&upper)));
+    }
+    types.push(with("to_lower", atom(&lower)));
+    types.push(with("to_upper", atom(&upper)));
+    types
+}
+
+/// `sub_atom(Atom, Before, Length, After, Sub)`: `Sub` is the part of
+/// `Atom` that starts `Before` characters in, is `Length` long and leaves
+/// `After` characters over. Enumerates whatever isn't given.
+pub(crate) fn sub_atom(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let given = (|| {
+        let whole = text(&args[0], bindings)?.ok_or_else(error::instantiation_error)?;
+        let before = count(&args[1], bindings)?;
+        let length = count(&args[2], bindings)?;
+        let after = count(&args[3], bindings)?;
+        let sub = match bindings.walk(&args[4]) {
+            Term::Variable(_) => None,
+            Term::Atom(a) => Some(a.as_str().chars().collect::<Vec<_>>()),
+            other => return Err(error::type_error("atom", other.clone())),
+        };
+        Ok((whole, before, length, after, sub))
+    })();
+    let (whole, before, length, after, sub) = match given {
+        Ok(given) => given,
+        Err(ball) => return builtins::raise(solver, ball),
+    };
+    let chars: Rc<[char]> = whole.chars().collect();
+    let n = chars.len();
+    let length = sub.as_ref().map(Vec::len).or(length);
+    let outputs = args[1..].to_vec();
+    let starts = match before {
+        Some(b) => b..=b,
+        None => 0..=n,
+    };
+    Box::new(
+        starts
+            .flat_map(move |b| {
+                let lengths = match length {
+                    Some(l) => l..=l,
+                    None => 0..=n.saturating_sub(b),
+                };
+                lengths.map(move |l| (b, l))
+            })
+            .filter(move |&(b, l)| b + l <= n && after.is_none_or(|a| b + l + a == n))
+            .filter({
+                let chars = chars.clone();
+                move |&(b, l)| sub.as_ref().is_none_or(|sub| chars[b..b + l] == sub[..])
+            })
+            .map(move |(b, l)| -> Branch {
+                let sub: String = chars[b..b + l].iter().collect();
+                builtins::unify(vec![
+                    (outputs[0].clone(), Term::Integer(b as i64)),
+                    (outputs[1].clone(), Term::Integer(l as i64)),
+                    (outputs[2].clone(), Term::Integer((n - b - l) as i64)),
+                    (outputs[3].clone(), atom(&sub)),
+                ])
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+
+    fn thrown(query: &str) -> String {
+        match answers(&Database::new(), query) {
+            Err(SolveError::Uncaught(ball)) => ball.to_string(),
+            Ok(answers) => panic!("{} succeeded with {:?}", query, answers),
+            Err(err) => panic!("{}: {}", query, err),
+        }
+    }
+
+    #[test]
+    fn measures_and_joins_atoms() {
+        assert_eq!(
+            answers(&Database::new(), "atom_length(hello, N)").unwrap(),
+            vec!["N = 5"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "atom_length('héllo', N)").unwrap(),
+            vec!["N = 5"]
+        );
+        assert!(thrown("atom_length(_, _)").contains("instantiation_error"));
+        assert!(thrown("atom_length(f(x), _)").contains("type_error(atom"));
+        assert_eq!(
+            answers(&Database::new(), "atom_concat(hello, ' world', X)").unwrap(),
+            vec!["X = 'hello world'"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "atom_concat(X, Y, abc)").unwrap(),
+            vec![
+                "X = '', Y = abc",
+                "X = a, Y = bc",
+                "X = ab, Y = c",
+                "X = abc, Y = ''"
+            ]
+        );
+        assert_eq!(
+            answers(&Database::new(), "atom_concat(X, c, abc)").unwrap(),
+            vec!["X = ab"]
+        );
+        assert!(thrown("atom_concat(a, _, _)").contains("instantiation_error"));
+    }
+
+    #[test]
+    fn converts_to_and_from_lists() {
+        assert_eq!(
+            answers(&Database::new(), "atom_chars(abc, [X|_])").unwrap(),
+            vec!["X = a"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "atom_chars(X, [h, i])").unwrap(),
+            vec!["X = hi"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "atom_codes(X, [104, 105])").unwrap(),
+            vec!["X = hi"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "atom_codes(hi, [H|_])").unwrap(),
+            vec!["H = 104"]
+        );
+        assert!(thrown("atom_chars(_, [a|_])").contains("instantiation_error"));
+        assert!(thrown("atom_chars(_, [ab])").contains("type_error(character, ab)"));
+    }
+
+    #[test]
+    fn converts_numbers_to_and_from_text() {
+        assert_eq!(
+            answers(&Database::new(), "number_codes(N, [49, 50])").unwrap(),
+            vec!["N = 12"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "number_chars(N, [' ', '-', '7'])").unwrap(),
+            vec!["N = -7"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "number_chars(12, ['1', '2'])")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            answers(&Database::new(), "number_chars(42, [X|_])").unwrap(),
+            vec!["X = '4'"]
+        );
+        assert!(thrown("number_chars(_, [a])").contains("syntax_error(illegal_number)"));
+        assert!(thrown("number_chars(_, ['1'|_])").contains("instantiation_error"));
+        assert!(thrown("number_codes(a, _)").contains("type_error(number, a)"));
+        assert_eq!(
+            answers(&Database::new(), "atom_number('12', N)").unwrap(),
+            vec!["N = 12"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "atom_number(A, 5)").unwrap(),
+            vec!["A = '5'"]
+        );
+        assert!(answers(&Database::new(), "atom_number(twelve, _)")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn handles_characters() {
+        assert_eq!(
+            answers(&Database::new(), "char_code(a, X), char_code(Y, 98)").unwrap(),
+            vec!["X = 97, Y = b"]
+        );
+        assert!(thrown("char_code(_, _)").contains("instantiation_error"));
+        assert!(thrown("char_code(ab, _)").contains("type_error(character, ab)"));
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "upcase_atom('hello World', U), downcase_atom('ÀB', L)"
+            )
+            .unwrap(),
+            vec!["U = 'HELLO WORLD', L = àb"]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "char_type(a, alpha), char_type('A', upper(L)), char_type('7', digit(W))"
+            )
+            .unwrap(),
+            vec!["L = a, W = 7"]
+        );
+        assert!(answers(&Database::new(), "char_type('1', alpha)")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            answers(&Database::new(), "char_type(' ', T)").unwrap(),
+            vec![
+                "T = space",
+                "T = white",
+                "T = to_lower(' ')",
+                "T = to_upper(' ')"
+            ]
+        );
+        assert!(thrown("char_type(a, colour)").contains("domain_error(char_type, colour)"));
+    }
+
+    #[test]
+    fn finds_sub_atoms() {
+        assert_eq!(
+            answers(&Database::new(), "sub_atom(hello, 1, 3, A, S)").unwrap(),
+            vec!["A = 1, S = ell"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "sub_atom(abcab, B, _, A, ab)").unwrap(),
+            vec!["B = 0, A = 3", "B = 3, A = 0"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "sub_atom(abc, B, 2, _, S)").unwrap(),
+            vec!["B = 0, S = ab", "B = 1, S = bc"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "sub_atom(abc, _, _, 0, S)")
+                .unwrap()
+                .len(),
+            4
+        );
+        assert_eq!(
+            answers(&Database::new(), "sub_atom(abc, _, _, _, _)")
+                .unwrap()
+                .len(),
+            10
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Attributed variables: `put_attr/3`, `get_attr/3` and `del_attr/2`. When a
+//! variable with attributes is bound, `Module:attr_unify_hook(Value, Other)`
+//! is called for each of its attributes right after the unification, and the
+//! unification only stands if every hook succeeds.
+use crate::builtins::{self, Alternatives, Branch};
+use crate::module::qualify;
+use crate::solver::{Bindings, Solver, Undo};
+use crate::{Atom, Term, Variable};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter;
+
+impl Bindings {
+    /// The value of `var`'s attribute for `module`, if it has one.
+    pub fn attribute(&self, var: &Variable, module: &str) -> Option<&Term> {
+        self.attributes
+            .get(&var.alias)?
+            .iter()
+            .find(|(m, _)| m.as_str() == module)
+            .map(|(_, value)| value)
+    }
+    pub(crate) fn has_attributes(&self, var: &Variable) -> bool {
+        self.attributes.contains_key(&var.alias)
+    }
+    /// Queues the hooks of `var`, which has just been bound to `other`.
+    pub(crate) fn wake(&mut self, var: &Variable, other: &Term) {
+        let Some(attributes) = self.set_attributes(var, None) else {
+            return;
+        };
+        for (module, value) in attributes {
+            let hook = Term::functor_from_str("attr_unify_hook", vec![value, other.clone()]);
+            self.wakeups.push(qualify(&module, hook));
+        }
+    }
+    /// The hook goals queued since the last call, to be run next.
+    pub(crate) fn take_wakeups(&mut self) -> Vec<Term> {
+        core::mem::take(&mut self.wakeups)
+    }
+    fn put_attribute(&mut self, var: &Variable, module: Atom, value: Term) {
+        let mut attributes = self.attributes.get(&var.alias).cloned().unwrap_or_default();
+        match attributes.iter_mut().find(|(m, _)| *m == module) {
+            Some((_, old)) => *old = value,
+            None => attributes.push((module, value)),
+        }
+        self.set_attributes(var, Some(attributes));
+    }
+    fn delete_attribute(&mut self, var: &Variable, module: &Atom) {
+        if let Some(attributes) = self.attributes.get(&var.alias) {
+            let mut attributes = attributes.clone();
+            attributes.retain(|(m, _)| m != module);
+            self.set_attributes(var, Some(attributes).filter(|a| !a.is_empty()));
+        }
+    }
+    // replaces all of `var`'s attributes, returning the old ones
+    fn set_attributes(
+        &mut self,
+        var: &Variable,
+        attributes: Option<Vec<(Atom, Term)>>,
+    ) -> Option<Vec<(Atom, Term)>> {
+        let old = match attributes {
+            Some(attributes) => self.attributes.insert(var.alias, attributes),
+            None => self.attributes.remove(&var.alias),
+        };
+        self.record(Undo::Attributes(var.alias, old.clone()));
+        old
+    }
+}
+
+// the unbound variable and module an attribute built-in applies to
+fn target(args: &[Term], bindings: &Bindings) -> Option<(Variable, Atom)> {
+    match (bindings.walk(&args[0]), bindings.walk(&args[1])) {
+        (Term::Variable(v), Term::Atom(m)) => Some((v.clone(), *m)),
+        (_, _) => None,
+    }
+}
+
+pub(crate) fn put_attr(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let Some((var, module)) = target(args, bindings) else {
+        return builtins::failure();
+    };
+    let value = args[2].clone();
+    Box::new(iter::once(Box::new(move |bindings: &mut Bindings| {
+        bindings.put_attribute(&var, module, value);
+        true
+    }) as Branch))
+}
+
+pub(crate) fn get_attr(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let value = target(args, bindings)
+        .and_then(|(var, module)| bindings.attribute(&var, module.as_str()).cloned());
+    match value {
+        Some(value) => Box::new(iter::once(builtins::unify(vec![(args[2].clone(), value)]))),
+        None => builtins::failure(),
+    }
+}
+
+pub(crate) fn del_attr(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let Some((var, module)) = target(args, bindings) else {
+        return builtins::success();
+    };
+    Box::new(iter::once(Box::new(move |bindings: &mut Bindings| {
+        bindings.delete_attribute(&var, &module);
+        true
+    }) as Branch))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{answers, consulted};
+    use crate::*;
+
+    const EVEN: &str = "
+        :- module(even, []).
+        attr_unify_hook(yes, Value) :- small_even(Value).
+        small_even(2).
+        small_even(4).";
+
+    #[test]
+    fn get_put_and_delete() {
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "put_attr(X, m, a), put_attr(X, m, b), get_attr(X, m, V)"
+            )
+            .unwrap(),
+            vec!["X = X, V = b"]
+        );
+        assert!(answers(
+            &Database::new(),
+            "put_attr(X, m, a), del_attr(X, m), get_attr(X, m, _)"
+        )
+        .unwrap()
+        .is_empty());
+        assert!(answers(&Database::new(), "put_attr(x, m, a)")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn hook_decides_unification() {
+        assert_eq!(
+            answers(&consulted(EVEN), "put_attr(X, even, yes), X = 4").unwrap(),
+            vec!["X = 4"]
+        );
+        assert!(answers(&consulted(EVEN), "put_attr(X, even, yes), X = 3")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn plain_variables_bind_to_attributed_ones() {
+        assert!(
+            answers(&consulted(EVEN), "put_attr(X, even, yes), Y = X, Y = 3")
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            answers(&consulted(EVEN), "put_attr(X, even, yes), X = Y, Y = 2").unwrap(),
+            vec!["X = 2, Y = 2"]
+        );
+    }
+
+    #[test]
+    fn hooks_run_after_head_unification() {
+        let program = format!("small(2). small(3).\n{}", EVEN);
+        assert_eq!(
+            answers(&consulted(&program), "put_attr(X, even, yes), small(X)").unwrap(),
+            vec!["X = 2"]
+        );
+    }
+}
@@ -0,0 +1,12 @@
+use std::io;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    match amateurlog::lsp::serve(io::stdin().lock(), io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("amateurlog-lsp: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
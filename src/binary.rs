@@ -0,0 +1,366 @@
+//! A compact binary encoding of a database's clauses.
+//!
+//! After the magic bytes and a format version come a table of every name
+//! used (atoms, functors, variables, modules, strings), then each module with its
+//! exports and clauses. Names are referred to by index and integers are
+//! varints, so large fact tables take little more than their distinct names.
+//! Foreign predicates are not saved.
+use crate::module::Module;
+use crate::{Alias, Atom, Database, Functor, Term, Variable, VariableName};
+use rand::rngs::ThreadRng;
+use rand::thread_rng;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"AMLG";
+const VERSION: u16 = 1;
+
+const ATOM: u8 = 0;
+const INTEGER: u8 = 1;
+const VARIABLE: u8 = 2;
+const FUNCTOR: u8 = 3;
+const STRING: u8 = 4;
+
+impl Database {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut modules: Vec<&Module> = self.modules.values().collect();
+        modules.sort_by(|fst, snd| fst.name().as_str().cmp(snd.name().as_str()));
+        let mut names = Names::default();
+        let mut body = Vec::new();
+        write_varint(&mut body, modules.len() as u64)?;
+        for module in modules {
+            write_varint(&mut body, names.index(module.name().as_str()))?;
+            write_varint(&mut body, module.exports.len() as u64)?;
+            for (name, arity) in &module.exports {
+                write_varint(&mut body, names.index(name.as_str()))?;
+                write_varint(&mut body, *arity as u64)?;
+            }
+            write_varint(&mut body, module.len() as u64)?;
+            for clause in module.clauses() {
+                let mut vars = HashMap::new();
+                write_goal(&mut body, clause, &mut names, &mut vars)?;
+                write_varint(&mut body, clause.body.len() as u64)?;
+                for goal in &clause.body {
+                    write_goal(&mut body, goal, &mut names, &mut vars)?;
+                }
+            }
+        }
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        write_varint(writer, names.list.len() as u64)?;
+        for name in &names.list {
+            write_varint(writer, name.len() as u64)?;
+            writer.write_all(name.as_bytes())?;
+        }
+        writer.write_all(&body)
+    }
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not an amateurlog database"));
+        }
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported format version {}", version)));
+        }
+        let mut names = Vec::new();
+        for _ in 0..read_varint(reader)? {
+            let len = read_varint(reader)?;
+            let mut name = Vec::new();
+            if reader.take(len).read_to_end(&mut name)? as u64 != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            names.push(String::from_utf8(name).map_err(|_| invalid("name is not UTF-8"))?);
+        }
+        let mut decoder = Decoder {
+            reader,
+            names,
+            rng: thread_rng(),
+        };
+        let mut db = Database::new();
+        for _ in 0..decoder.len()? {
+            let name = decoder.atom()?;
+            let mut exports = Vec::new();
+            for _ in 0..decoder.len()? {
+                exports.push((decoder.atom()?, decoder.len()?));
+            }
+            let mut module = Module::new(name, exports);
+            for _ in 0..decoder.len()? {
+                let mut vars = Vec::new();
+                let head = decoder.goal(&mut vars)?;
+                let mut body = Vec::new();
+                for _ in 0..decoder.len()? {
+                    body.push(decoder.goal(&mut vars)?);
+                }
+                module.add(Functor::new_rule(head.name, head.into_args(), body));
+            }
+            db.modules.insert(name, module);
+        }
+        Ok(db)
+    }
+}
+
+#[derive(Default)]
+struct Names {
+    list: Vec<String>,
+    indices: HashMap<String, u64>,
+}
+
+impl Names {
+    fn index(&mut self, name: &str) -> u64 {
+        if let Some(ix) = self.indices.get(name) {
+            return *ix;
+        }
+        let ix = self.list.len() as u64;
+        self.list.push(String::from(name));
+        self.indices.insert(String::from(name), ix);
+        ix
+    }
+}
+
+fn write_goal<W: Write>(
+    writer: &mut W,
+    goal: &Functor,
+    names: &mut Names,
+    vars: &mut HashMap<Alias, u64>,
+) -> io::Result<()> {
+    write_varint(writer, names.index(goal.name.as_str()))?;
+    write_varint(writer, goal.args.len() as u64)?;
+    for arg in &goal.args {
+        write_term(writer, arg, names, vars)?;
+    }
+    Ok(())
+}
+
+// a variable is its number within the clause; its first occurrence is
+// followed by its name's index plus one, or 0 if it is anonymous
+fn write_term<W: Write>(
+    writer: &mut W,
+    term: &Term,
+    names: &mut Names,
+    vars: &mut HashMap<Alias, u64>,
+) -> io::Result<()> {
+    match term {
+        Term::Atom(a) => {
+            writer.write_all(&[ATOM])?;
+            write_varint(writer, names.index(a.as_str()))
+        }
+        Term::Integer(n) => {
+            writer.write_all(&[INTEGER])?;
+            write_varint(writer, ((n << 1) ^ (n >> 63)) as u64)
+        }
+        Term::String(text) => {
+            writer.write_all(&[STRING])?;
+            write_varint(writer, names.index(text))
+        }
+        Term::Variable(v) => {
+            writer.write_all(&[VARIABLE])?;
+            let next = vars.len() as u64;
+            let number = *vars.entry(v.alias).or_insert(next);
+            write_varint(writer, number)?;
+            if number == next {
+                let name = match v.name {
+                    VariableName::Name(name) => names.index(name.as_str()) + 1,
+                    VariableName::Anonymous => 0,
+                };
+                write_varint(writer, name)?;
+            }
+            Ok(())
+        }
+        Term::Functor(f) => {
+            writer.write_all(&[FUNCTOR])?;
+            write_goal(writer, f, names, vars)
+        }
+    }
+}
+
+struct Decoder<'r, R> {
+    reader: &'r mut R,
+    names: Vec<String>,
+    rng: ThreadRng,
+}
+
+impl<R: Read> Decoder<'_, R> {
+    fn len(&mut self) -> io::Result<usize> {
+        read_len(self.reader)
+    }
+    fn name(&mut self) -> io::Result<String> {
+        let ix = self.len()?;
+        self.names
+            .get(ix)
+            .cloned()
+            .ok_or_else(|| invalid("name index out of range"))
+    }
+    fn atom(&mut self) -> io::Result<Atom> {
+        Ok(Atom::new(&self.name()?))
+    }
+    fn goal(&mut self, vars: &mut Vec<Term>) -> io::Result<Functor> {
+        let name = self.atom()?;
+        let mut args = Vec::new();
+        for _ in 0..self.len()? {
+            args.push(self.term(vars)?);
+        }
+        Ok(Functor::new_fact(name, args))
+    }
+    fn term(&mut self, vars: &mut Vec<Term>) -> io::Result<Term> {
+        let mut tag = [0];
+        self.reader.read_exact(&mut tag)?;
+        match tag[0] {
+            ATOM => Ok(Term::Atom(self.atom()?)),
+            INTEGER => {
+                let n = read_varint(self.reader)?;
+                Ok(Term::Integer(((n >> 1) as i64) ^ -((n & 1) as i64)))
+            }
+            VARIABLE => {
+                let number = self.len()?;
+                if number < vars.len() {
+                    return Ok(vars[number].clone());
+                }
+                if number > vars.len() {
+                    return Err(invalid("variable number out of order"));
+                }
+                let var = match self.len()? {
+                    0 => Variable::new_anonymous(&mut self.rng),
+                    ix => {
+                        let name = self
+                            .names
+                            .get(ix - 1)
+                            .ok_or_else(|| invalid("name index out of range"))?;
+                        Variable::new_named(name, &mut self.rng)
+                    }
+                };
+                vars.push(Term::Variable(var));
+                Ok(vars[number].clone())
+            }
+            FUNCTOR => Ok(Term::Functor(Box::new(self.goal(vars)?))),
+            STRING => Ok(Term::String(self.name()?.into())),
+            tag => Err(invalid(&format!("unknown term tag {}", tag))),
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_varint<W: Write + ?Sized>(writer: &mut W, mut n: u64) -> io::Result<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint<R: Read + ?Sized>(reader: &mut R) -> io::Result<u64> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        n |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+fn read_len<R: Read + ?Sized>(reader: &mut R) -> io::Result<usize> {
+    usize::try_from(read_varint(reader)?).map_err(|_| invalid("length too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+    use std::str::FromStr;
+
+    fn round_trip(db: &Database) -> Database {
+        let mut bytes = Vec::new();
+        db.write_to(&mut bytes).unwrap();
+        Database::read_from(&mut bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn round_trips_clauses() {
+        let mut db = Database::new();
+        db.consult(
+            "age(sam, 30). likes(olive, f(Z, [x])).
+             older(X, Y) :- age(X, A), age(Y, B), A > B.",
+        )
+        .unwrap();
+        db.add(Functor::new_fact(
+            Atom::from_str("age").unwrap(),
+            vec![
+                Term::atom_from_str("popeye"),
+                Term::Integer(-45_000_000_000),
+            ],
+        ));
+        let loaded = round_trip(&db);
+        let clauses: Vec<String> = loaded
+            .module("user")
+            .unwrap()
+            .clauses()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            clauses,
+            vec![
+                "age(sam, 30)",
+                "age(popeye, -45000000000)",
+                "likes(olive, f(Z, .(x, [])))",
+                "older(X, Y)"
+            ]
+        );
+        assert_eq!(
+            answers(&loaded, "older(sam, X)").unwrap(),
+            vec!["X = popeye"]
+        );
+    }
+
+    #[test]
+    fn round_trips_modules() {
+        let mut db = Database::new();
+        db.consult(":- module(a, [pa/1]). pa(X) :- helper(X). helper(from_a).")
+            .unwrap();
+        let loaded = round_trip(&db);
+        assert_eq!(answers(&loaded, "pa(X)").unwrap(), vec!["X = from_a"]);
+        assert!(answers(&loaded, "helper(X)").is_err());
+    }
+
+    #[test]
+    fn saves_to_files() {
+        let mut db = Database::new();
+        db.consult("likes(sam, chocolate).").unwrap();
+        let path = std::env::temp_dir().join(format!("amateurlog-{}.db", std::process::id()));
+        db.save(&path).unwrap();
+        let loaded = Database::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            answers(&loaded.unwrap(), "likes(X, chocolate)").unwrap(),
+            vec!["X = sam"]
+        );
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        assert!(Database::read_from(&mut &b"nope"[..]).is_err());
+        assert!(Database::read_from(&mut &b"AMLG\x09\x00"[..]).is_err());
+        assert!(Database::read_from(&mut &b"AMLG\x01\x00\x01"[..]).is_err());
+    }
+}
@@ -0,0 +1,647 @@
+use crate::compat::{HashMap, HashSet};
+use crate::error;
+#[cfg(feature = "std")]
+use crate::input;
+use crate::solver::{Bindings, Halt, Solver};
+use crate::{
+    aggregate, arith, atoms, attributes, clpfd, debugger, dynamic, flags, globals, listing, order,
+    output, random, stats, strings, terms,
+};
+use crate::{Alias, Arity, Atom, Functor, OccursCheck, Term, Variable};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::iter;
+use core::mem;
+use core::ops::ControlFlow;
+
+/// Pairs of terms that must unify for one solution of a built-in.
+pub(crate) type Unifier = Vec<(Term, Term)>;
+/// One solution of a built-in, applied to a copy of the caller's bindings.
+/// Returns false if it turns out not to hold.
+pub(crate) type Branch = Box<dyn FnOnce(&mut Bindings) -> bool>;
+pub(crate) type Alternatives = Box<dyn Iterator<Item = Branch>>;
+pub(crate) type Builtin = fn(&mut Solver, &[Term], &Bindings) -> Alternatives;
+
+pub(crate) fn lookup(name: &Atom, arity: Arity) -> Option<Builtin> {
+    match (name.as_str(), arity) {
+        ("true", 0) => Some(succeed),
+        ("fail" | "false", 0) => Some(fail),
+        ("=", 2) => Some(equals),
+        ("unify_with_occurs_check", 2) => Some(unify_with_occurs_check),
+        ("throw", 1) => Some(throw),
+        ("copy_term", 2) => Some(copy_term),
+        ("var", 1) => Some(|_, args, bindings| type_test(args, bindings, is_var)),
+        ("nonvar", 1) => Some(|_, args, bindings| type_test(args, bindings, |t| !is_var(t))),
+        ("atom", 1) => Some(|_, args, bindings| type_test(args, bindings, is_atom)),
+        ("number" | "integer", 1) => {
+            Some(|_, args, bindings| type_test(args, bindings, is_integer))
+        }
+        ("atomic", 1) => Some(|_, args, bindings| {
+            type_test(args, bindings, |t| {
+                is_atom(t) || is_integer(t) || is_string(t)
+            })
+        }),
+        ("string", 1) => Some(|_, args, bindings| type_test(args, bindings, is_string)),
+        ("compound", 1) => Some(|_, args, bindings| type_test(args, bindings, is_compound)),
+        ("callable", 1) => {
+            Some(|_, args, bindings| type_test(args, bindings, |t| is_atom(t) || is_compound(t)))
+        }
+        ("ground", 1) => Some(ground),
+        ("functor", 3) => Some(terms::functor),
+        ("arg", 3) => Some(terms::arg),
+        ("=..", 2) => Some(terms::univ),
+        ("atom_length", 2) => Some(atoms::atom_length),
+        ("atom_concat", 3) => Some(atoms::atom_concat),
+        ("atom_chars", 2) => Some(atoms::atom_chars),
+        ("atom_codes", 2) => Some(atoms::atom_codes),
+        ("sub_atom", 5) => Some(atoms::sub_atom),
+        ("number_chars", 2) => Some(atoms::number_chars),
+        ("number_codes", 2) => Some(atoms::number_codes),
+        ("atom_number", 2) => Some(atoms::atom_number),
+        ("char_code", 2) => Some(atoms::char_code),
+        ("char_type", 2) => Some(atoms::char_type),
+        ("upcase_atom", 2) => Some(atoms::upcase_atom),
+        ("downcase_atom", 2) => Some(atoms::downcase_atom),
+        ("string_concat", 3) => Some(strings::string_concat),
+        ("string_chars", 2) => Some(strings::string_chars),
+        ("string_to_atom", 2) => Some(strings::string_to_atom),
+        ("split_string", 4) => Some(strings::split_string),
+        ("write", 1) => Some(output::write),
+        ("writeln", 1) => Some(output::writeln),
+        ("nl", 0) => Some(output::nl),
+        ("format", 1 | 2) => Some(output::format),
+        #[cfg(feature = "std")]
+        ("read", 1) => Some(input::read),
+        #[cfg(feature = "std")]
+        ("read_term", 2) => Some(input::read_term),
+        ("assert" | "assertz", 1) => Some(dynamic::assertz),
+        ("asserta", 1) => Some(dynamic::asserta),
+        ("retract", 1) => Some(dynamic::retract),
+        ("listing", 0) => Some(listing::listing_all),
+        ("listing", 1) => Some(listing::listing_some),
+        ("spy", 1) => Some(debugger::spy),
+        ("nospy", 1) => Some(debugger::nospy),
+        ("compare", 3) => Some(order::compare),
+        ("==", 2) => Some(order::identical),
+        ("\\==", 2) => Some(order::not_identical),
+        ("@<", 2) => Some(order::less),
+        ("@>", 2) => Some(order::greater),
+        ("@=<", 2) => Some(order::less_or_equal),
+        ("@>=", 2) => Some(order::greater_or_equal),
+        ("sort", 2) => Some(order::sort),
+        ("msort", 2) => Some(order::msort),
+        ("predsort", 3) => Some(order::predsort),
+        ("findall", 3) => Some(findall),
+        ("bagof", 3) => Some(bagof),
+        ("setof", 3) => Some(setof),
+        ("aggregate_all", 3) => Some(aggregate::aggregate_all),
+        ("is", 2) => Some(arith::is),
+        ("=:=", 2) => Some(arith::equal),
+        ("=\\=", 2) => Some(arith::not_equal),
+        ("<", 2) => Some(arith::less),
+        (">", 2) => Some(arith::greater),
+        ("=<", 2) => Some(arith::less_or_equal),
+        (">=", 2) => Some(arith::greater_or_equal),
+        ("between", 3) => Some(arith::between),
+        ("succ", 2) => Some(arith::succ),
+        ("plus", 3) => Some(arith::plus),
+        ("random", 1) => Some(random::random),
+        ("random_between", 3) => Some(random::random_between),
+        ("random_member", 2) => Some(random::random_member),
+        ("random_permutation", 2) => Some(random::random_permutation),
+        ("set_prolog_flag", 2) => Some(flags::set_prolog_flag),
+        ("current_prolog_flag", 2) => Some(flags::current_prolog_flag),
+        ("nb_setval", 2) => Some(globals::nb_setval),
+        ("nb_getval", 2) => Some(globals::nb_getval),
+        ("statistics", 2) => Some(stats::statistics),
+        ("in", 2) => Some(clpfd::in_domain),
+        ("ins", 2) => Some(clpfd::ins_domain),
+        ("#=", 2) => Some(clpfd::equal),
+        ("#\\=", 2) => Some(clpfd::not_equal),
+        ("#<", 2) => Some(clpfd::less),
+        ("#>", 2) => Some(clpfd::greater),
+        ("#=<", 2) => Some(clpfd::less_or_equal),
+        ("#>=", 2) => Some(clpfd::greater_or_equal),
+        ("all_different" | "all_distinct", 1) => Some(clpfd::all_different),
+        ("label", 1) => Some(clpfd::label),
+        ("put_attr", 3) => Some(attributes::put_attr),
+        ("get_attr", 3) => Some(attributes::get_attr),
+        ("del_attr", 2) => Some(attributes::del_attr),
+        ("labeling", 2) => Some(clpfd::labeling),
+        _ => None,
+    }
+}
+
+pub(crate) fn unify(unifier: Unifier) -> Branch {
+    Box::new(move |bindings| unifier.iter().all(|(fst, snd)| bindings.unify(fst, snd)))
+}
+
+pub(crate) fn success() -> Alternatives {
+    Box::new(iter::once(unify(Vec::new())))
+}
+
+pub(crate) fn failure() -> Alternatives {
+    Box::new(iter::empty())
+}
+
+/// Throws `ball` from the built-in being called.
+pub(crate) fn raise(solver: &mut Solver, ball: Term) -> Alternatives {
+    solver.raise(ball);
+    failure()
+}
+
+fn succeed(_: &mut Solver, _: &[Term], _: &Bindings) -> Alternatives {
+    success()
+}
+
+fn fail(_: &mut Solver, _: &[Term], _: &Bindings) -> Alternatives {
+    failure()
+}
+
+fn equals(_: &mut Solver, args: &[Term], _: &Bindings) -> Alternatives {
+    Box::new(iter::once(unify(vec![(args[0].clone(), args[1].clone())])))
+}
+
+/// The items of a proper list, or `None` after raising an instantiation
+/// error for a partial list or a type error for anything else.
+pub(crate) fn list(solver: &mut Solver, term: &Term, bindings: &Bindings) -> Option<Vec<Term>> {
+    if let Some(items) = bindings.list_to_vec(term) {
+        return Some(items);
+    }
+    let ball = match bindings.list_end(term, |_| {}) {
+        Term::Variable(_) => error::instantiation_error(),
+        _ => error::type_error("list", bindings.resolve(term)),
+    };
+    solver.raise(ball);
+    None
+}
+
+/// `goal` with `extra` arguments added after its own, as `call/N` builds it.
+pub(crate) fn add_args(goal: &Term, extra: Vec<Term>) -> Option<Term> {
+    match goal {
+        Term::Atom(name) if extra.is_empty() => Some(Term::Atom(*name)),
+        Term::Atom(name) => Some(Term::Functor(Box::new(Functor::new_fact(*name, extra)))),
+        Term::Functor(f) => {
+            let args = f.args.iter().cloned().chain(extra).collect();
+            Some(Term::Functor(Box::new(Functor::new_fact(f.name, args))))
+        }
+        Term::Variable(_) | Term::Integer(_) | Term::String(_) => None,
+    }
+}
+
+fn unify_with_occurs_check(_: &mut Solver, args: &[Term], _: &Bindings) -> Alternatives {
+    let (fst, snd) = (args[0].clone(), args[1].clone());
+    Box::new(iter::once(Box::new(move |bindings: &mut Bindings| {
+        let occurs_check = mem::replace(&mut bindings.occurs_check, OccursCheck::True);
+        let unified = bindings.unify(&fst, &snd);
+        bindings.occurs_check = occurs_check;
+        unified
+    }) as Branch))
+}
+
+fn copy_term(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let copy = bindings.copy_term(&args[0]);
+    Box::new(iter::once(unify(vec![(args[1].clone(), copy)])))
+}
+
+fn type_test(args: &[Term], bindings: &Bindings, test: fn(&Term) -> bool) -> Alternatives {
+    if test(bindings.walk(&args[0])) {
+        success()
+    } else {
+        failure()
+    }
+}
+
+fn is_var(term: &Term) -> bool {
+    matches!(term, Term::Variable(_))
+}
+
+fn is_atom(term: &Term) -> bool {
+    matches!(term, Term::Atom(_))
+}
+
+fn is_integer(term: &Term) -> bool {
+    matches!(term, Term::Integer(_))
+}
+
+fn is_string(term: &Term) -> bool {
+    matches!(term, Term::String(_))
+}
+
+fn is_compound(term: &Term) -> bool {
+    matches!(term, Term::Functor(_))
+}
+
+fn ground(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let mut vars = Vec::new();
+    term_variables(&bindings.resolve(&args[0]), &mut vars);
+    if vars.is_empty() {
+        success()
+    } else {
+        failure()
+    }
+}
+
+fn throw(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    match bindings.resolve(&args[0]) {
+        Term::Variable(_) => raise(solver, error::instantiation_error()),
+        ball => raise(solver, ball),
+    }
+}
+
+fn findall(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let results = collect(solver, &args[0], &args[1], bindings);
+    Box::new(iter::once(unify(vec![(
+        args[2].clone(),
+        Term::list_from_vec(results),
+    )])))
+}
+
+fn bagof(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    Box::new(
+        collect_groups(solver, args, bindings, false)
+            .into_iter()
+            .map(unify),
+    )
+}
+
+fn setof(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    Box::new(
+        collect_groups(solver, args, bindings, true)
+            .into_iter()
+            .map(unify),
+    )
+}
+
+/// Proves `goal` in an isolated copy of `bindings`, returning a fresh copy of
+/// `template` for each solution.
+pub(crate) fn collect(
+    solver: &mut Solver,
+    template: &Term,
+    goal: &Term,
+    bindings: &Bindings,
+) -> Vec<Term> {
+    let mut results = Vec::new();
+    let goal = solver.in_context(goal.clone());
+    let flow = solver.solve(&[goal], &mut bindings.clone(), &mut |b, _| {
+        results.push(b.resolve(template));
+        ControlFlow::Continue(())
+    });
+    if let ControlFlow::Break(Halt::Thrown(ball)) = flow {
+        solver.raise(ball);
+    }
+    results
+        .iter()
+        .map(|t| solver.rename(t, &mut HashMap::new()))
+        .collect()
+}
+
+// bagof/setof: one solution per distinct binding of the goal's free variables
+fn collect_groups(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+    sorted: bool,
+) -> Vec<Unifier> {
+    let (template, bag) = (bindings.resolve(&args[0]), &args[2]);
+    let mut goal = bindings.resolve(&args[1]);
+    let mut bound = Vec::new();
+    term_variables(&template, &mut bound);
+    while let Term::Functor(f) = &goal {
+        if f.name.as_str() != "^" || f.arity() != 2 {
+            break;
+        }
+        term_variables(&f.args[0], &mut bound);
+        goal = f.args[1].clone();
+    }
+    let mut free = Vec::new();
+    term_variables(&goal, &mut free);
+    let bound: HashSet<Alias> = bound.iter().map(|v| v.alias).collect();
+    free.retain(|v| !bound.contains(&v.alias));
+    let witness = Term::list_from_vec(free.into_iter().map(Term::Variable).collect());
+
+    let pair = Term::functor_from_str("-", vec![witness.clone(), template]);
+    let mut pairs: Vec<(Term, Term)> = collect(solver, &pair, &goal, bindings)
+        .into_iter()
+        .filter_map(|p| match p {
+            Term::Functor(f) => {
+                let mut args = f.into_args().into_iter();
+                Some((args.next()?, args.next()?))
+            }
+            _ => None,
+        })
+        .collect();
+    pairs.sort_by(|fst, snd| fst.0.cmp(&snd.0));
+
+    let mut groups: Vec<(Vec<Term>, Vec<Term>)> = Vec::new();
+    for (w, t) in pairs {
+        match groups.last_mut() {
+            Some((witnesses, items)) if variant(&witnesses[0], &w) => {
+                witnesses.push(w);
+                items.push(t);
+            }
+            _ => groups.push((vec![w], vec![t])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(witnesses, mut items)| {
+            if sorted {
+                items.sort();
+                items.dedup_by(|fst, snd| fst.cmp(&snd) == Ordering::Equal);
+            }
+            let mut unifier: Unifier = witnesses
+                .into_iter()
+                .map(|w| (witness.clone(), w))
+                .collect();
+            unifier.push((bag.clone(), Term::list_from_vec(items)));
+            unifier
+        })
+        .collect()
+}
+
+/// Collects the distinct variables of an already-resolved term, in order of appearance.
+pub(crate) fn term_variables(term: &Term, vars: &mut Vec<Variable>) {
+    let mut seen: HashSet<Alias> = vars.iter().map(|v| v.alias).collect();
+    // what's left to look through, last first, so deep terms need no deep
+    // Rust stack
+    let mut pending = vec![term];
+    while let Some(term) = pending.pop() {
+        match term {
+            Term::Variable(v) => {
+                if seen.insert(v.alias) {
+                    vars.push(v.clone());
+                }
+            }
+            Term::Functor(f) => pending.extend(f.args.iter().rev()),
+            Term::Atom(_) | Term::Integer(_) | Term::String(_) => {}
+        }
+    }
+}
+
+/// Whether two resolved terms are equal up to a consistent renaming of variables.
+pub(crate) fn variant(fst: &Term, snd: &Term) -> bool {
+    let mut forward: HashMap<Alias, Alias> = HashMap::new();
+    let mut backward: HashMap<Alias, Alias> = HashMap::new();
+    // pairs left to compare, last first, so deep terms need no deep Rust
+    // stack
+    let mut pending = vec![(fst, snd)];
+    while let Some(pair) = pending.pop() {
+        let alike = match pair {
+            (Term::Variable(fst_v), Term::Variable(snd_v)) => {
+                let f = forward.entry(fst_v.alias).or_insert_with(|| snd_v.alias);
+                let b = backward.entry(snd_v.alias).or_insert_with(|| fst_v.alias);
+                *f == snd_v.alias && *b == fst_v.alias
+            }
+            (Term::Atom(fst_a), Term::Atom(snd_a)) => fst_a == snd_a,
+            (Term::Integer(fst_n), Term::Integer(snd_n)) => fst_n == snd_n,
+            (Term::String(fst_s), Term::String(snd_s)) => fst_s == snd_s,
+            (Term::Functor(fst_f), Term::Functor(snd_f)) => {
+                let alike = fst_f.name == snd_f.name && fst_f.arity() == snd_f.arity();
+                pending.extend(fst_f.args.iter().zip(&snd_f.args).rev());
+                alike
+            }
+            (_, _) => false,
+        };
+        if !alike {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compat::thread_rng;
+    use crate::testing::answers;
+    use crate::*;
+    use std::str::FromStr;
+
+    fn likes() -> Database {
+        Database::from_rules(
+            [
+                ("sam", "chocolate"),
+                ("popeye", "treats"),
+                ("olive", "chocolate"),
+            ]
+            .iter()
+            .map(|(who, what)| {
+                Functor::new_fact(
+                    Atom::from_str("likes").unwrap(),
+                    vec![Term::atom_from_str(who), Term::atom_from_str(what)],
+                )
+            })
+            .collect(),
+        )
+    }
+
+    fn goal(name: &str, args: Vec<Term>) -> Functor {
+        Functor::new_fact(Atom::from_str(name).unwrap(), args)
+    }
+
+    fn atoms(names: &[&str]) -> Vec<Term> {
+        names.iter().map(|n| Term::atom_from_str(n)).collect()
+    }
+
+    #[test]
+    fn type_tests() {
+        let holds = |query: &str| !answers(&Database::new(), query).unwrap().is_empty();
+        assert!(holds(
+            "var(X), X = a, nonvar(X), atom(X), atomic(X), callable(X)"
+        ));
+        assert!(holds("number(3), integer(3), atomic(3)"));
+        assert!(holds("compound(f(x)), callable(f(x)), callable(foo)"));
+        assert!(!holds("atom(f(x))"));
+        assert!(!holds("compound(a)"));
+        assert!(!holds("atom(1)"));
+        assert!(!holds("nonvar(_)"));
+        assert!(holds("X = f(Y), Y = a, ground(X)"));
+        assert!(!holds("ground(f(a, _))"));
+    }
+
+    #[test]
+    fn copy_term_renames_variables_consistently() {
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "X = a, copy_term(f(X, Y, Y), C), C = f(A, B, c)"
+            )
+            .unwrap(),
+            vec!["X = a, Y = Y, C = f(a, c, c), A = a, B = c"]
+        );
+        let query = parse_query("copy_term(g(X, X, Y), g(P, Q, R))").unwrap();
+        let solutions = Database::new().satisfy_all(query.goal.clone());
+        let solution = query.solution(&solutions[0]);
+        let value = |name: &str| {
+            let (_, value) = solution.values().iter().find(|(n, _)| n == name).unwrap();
+            value.to_string()
+        };
+        assert_eq!(value("P"), value("Q"));
+        assert_ne!(value("P"), value("X"));
+        assert_ne!(value("P"), value("R"));
+    }
+
+    #[test]
+    fn findall_collects_every_solution() {
+        let mut gen = thread_rng();
+        let x = Term::variable_from_str("X", &mut gen);
+        let l = Term::variable_from_str("L", &mut gen);
+        let answers = likes().satisfy_all(goal(
+            "findall",
+            vec![
+                x.clone(),
+                Term::functor_from_str("likes", vec![x, Term::atom_from_str("chocolate")]),
+                l.clone(),
+            ],
+        ));
+        assert_eq!(answers.len(), 1);
+        assert_eq!(
+            answers[0].list_to_vec(&l).expect("findall list"),
+            atoms(&["sam", "olive"])
+        );
+    }
+
+    #[test]
+    fn findall_without_solutions_is_empty() {
+        let mut gen = thread_rng();
+        let x = Term::variable_from_str("X", &mut gen);
+        let l = Term::variable_from_str("L", &mut gen);
+        let answers = likes().satisfy_all(goal(
+            "findall",
+            vec![
+                x.clone(),
+                Term::functor_from_str("likes", vec![x, Term::atom_from_str("oranges")]),
+                l.clone(),
+            ],
+        ));
+        assert_eq!(answers[0].list_to_vec(&l), Some(Vec::new()));
+    }
+
+    #[test]
+    fn findall_does_not_bind_template() {
+        let mut gen = thread_rng();
+        let x = Term::variable_from_str("X", &mut gen);
+        let y = Term::variable_from_str("Y", &mut gen);
+        let answers = likes().satisfy_all(goal(
+            "findall",
+            vec![
+                x.clone(),
+                Term::functor_from_str("likes", vec![x.clone(), y]),
+                Term::variable_from_str("L", &mut gen),
+            ],
+        ));
+        assert!(matches!(answers[0].resolve(&x), Term::Variable(_)));
+    }
+
+    #[test]
+    fn findall_through_rule() {
+        let mut gen = thread_rng();
+        let fan = Term::variable_from_str("Fan", &mut gen);
+        let rule = Functor::new_rule(
+            Atom::from_str("chocoholic").unwrap(),
+            vec![fan.clone()],
+            vec![goal("likes", vec![fan, Term::atom_from_str("chocolate")])],
+        );
+        let mut db = likes();
+        db.add(rule);
+        let x = Term::variable_from_str("X", &mut gen);
+        let l = Term::variable_from_str("L", &mut gen);
+        let answers = db.satisfy_all(goal(
+            "findall",
+            vec![
+                x.clone(),
+                Term::functor_from_str("chocoholic", vec![x]),
+                l.clone(),
+            ],
+        ));
+        assert_eq!(
+            answers[0].list_to_vec(&l).expect("findall list"),
+            atoms(&["sam", "olive"])
+        );
+    }
+
+    #[test]
+    fn bagof_fails_without_solutions() {
+        let mut gen = thread_rng();
+        let x = Term::variable_from_str("X", &mut gen);
+        let answers = likes().satisfy_all(goal(
+            "bagof",
+            vec![
+                x.clone(),
+                Term::functor_from_str("likes", vec![x, Term::atom_from_str("oranges")]),
+                Term::variable_from_str("L", &mut gen),
+            ],
+        ));
+        assert!(answers.is_empty());
+    }
+
+    #[test]
+    fn bagof_groups_by_free_variables() {
+        let mut gen = thread_rng();
+        let x = Term::variable_from_str("X", &mut gen);
+        let y = Term::variable_from_str("Y", &mut gen);
+        let l = Term::variable_from_str("L", &mut gen);
+        let answers = likes().satisfy_all(goal(
+            "bagof",
+            vec![
+                x.clone(),
+                Term::functor_from_str("likes", vec![x, y.clone()]),
+                l.clone(),
+            ],
+        ));
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[0].resolve(&y), Term::atom_from_str("chocolate"));
+        assert_eq!(
+            answers[0].list_to_vec(&l).expect("bagof list"),
+            atoms(&["sam", "olive"])
+        );
+        assert_eq!(answers[1].resolve(&y), Term::atom_from_str("treats"));
+        assert_eq!(
+            answers[1].list_to_vec(&l).expect("bagof list"),
+            atoms(&["popeye"])
+        );
+    }
+
+    #[test]
+    fn setof_sorts_and_honours_caret() {
+        let mut gen = thread_rng();
+        let x = Term::variable_from_str("X", &mut gen);
+        let y = Term::variable_from_str("Y", &mut gen);
+        let l = Term::variable_from_str("L", &mut gen);
+        let answers = likes().satisfy_all(goal(
+            "setof",
+            vec![
+                y.clone(),
+                Term::functor_from_str(
+                    "^",
+                    vec![x.clone(), Term::functor_from_str("likes", vec![x, y])],
+                ),
+                l.clone(),
+            ],
+        ));
+        assert_eq!(answers.len(), 1);
+        assert_eq!(
+            answers[0].list_to_vec(&l).expect("setof list"),
+            atoms(&["chocolate", "treats"])
+        );
+    }
+
+    #[test]
+    fn long_lists_need_no_deep_stack() {
+        // a list is nested as deeply as it's long
+        let mut db = Database::new();
+        db.consult("member(X, [X|_]). member(X, [_|T]) :- member(X, T).")
+            .unwrap();
+        for goal in [
+            "ground(L)",
+            "setof(X, member(X, [L]), [_])",
+            "bagof(X, member(X-M, [a-L, b-L]), [a, b])",
+        ] {
+            let query = format!("findall(X, between(1, 100000, X), L), {}", goal);
+            let query = parse_query(&query).unwrap();
+            assert_eq!(db.satisfy_all(query.goal).len(), 1, "{}", goal);
+        }
+    }
+}
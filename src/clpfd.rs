@@ -0,0 +1,1092 @@
+//! Finite domain constraints over integers: `in/2`, `ins/2`, the `#=` family,
+//! `all_different/1` and labeling. Domains and constraints live in the
+//! `Bindings` of each branch, and are narrowed by bounds propagation whenever a
+//! constraint is posted or a constrained variable is bound. Expressions are
+//! linear sums; products of variables, `//`, `rem`, `mod`, `min`, `max` and
+//! `abs` in them each stand for a fresh variable constrained to their value.
+use crate::builtins::{self, Alternatives, Branch};
+use crate::compat::thread_rng;
+use crate::compat::HashMap;
+use crate::error;
+use crate::solver::{Bindings, Solver, Undo};
+use crate::{Alias, Term, Variable};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::iter;
+
+/// The integers a variable can still take, as sorted, disjoint, inclusive
+/// ranges. `i64::MIN` and `i64::MAX` stand for `inf` and `sup`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Domain(Vec<(i64, i64)>);
+
+impl Domain {
+    fn all() -> Self {
+        Self(vec![(i64::MIN, i64::MAX)])
+    }
+    fn range(lo: i64, hi: i64) -> Self {
+        Self(if lo <= hi { vec![(lo, hi)] } else { Vec::new() })
+    }
+    fn min(&self) -> i64 {
+        self.0.first().map_or(i64::MAX, |&(lo, _)| lo)
+    }
+    fn max(&self) -> i64 {
+        self.0.last().map_or(i64::MIN, |&(_, hi)| hi)
+    }
+    fn is_finite(&self) -> bool {
+        !self.0.is_empty() && self.min() != i64::MIN && self.max() != i64::MAX
+    }
+    fn size(&self) -> u128 {
+        self.0
+            .iter()
+            .map(|&(lo, hi)| (hi as i128 - lo as i128 + 1) as u128)
+            .sum()
+    }
+    fn singleton(&self) -> Option<i64> {
+        match self.0.as_slice() {
+            [(lo, hi)] if lo == hi => Some(*lo),
+            _ => None,
+        }
+    }
+    fn contains(&self, n: i64) -> bool {
+        self.0.iter().any(|&(lo, hi)| lo <= n && n <= hi)
+    }
+    fn intersect(&self, other: &Domain) -> Domain {
+        let mut ranges = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while let (Some(&(fst_lo, fst_hi)), Some(&(snd_lo, snd_hi))) =
+            (self.0.get(i), other.0.get(j))
+        {
+            let (lo, hi) = (fst_lo.max(snd_lo), fst_hi.min(snd_hi));
+            if lo <= hi {
+                ranges.push((lo, hi));
+            }
+            if fst_hi < snd_hi {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        Domain(ranges)
+    }
+    fn union(&self, other: &Domain) -> Domain {
+        let mut ranges: Vec<(i64, i64)> = self.0.iter().chain(&other.0).copied().collect();
+        ranges.sort();
+        let mut merged: Vec<(i64, i64)> = Vec::new();
+        for (lo, hi) in ranges {
+            match merged.last_mut() {
+                Some(last) if lo <= last.1.saturating_add(1) => last.1 = last.1.max(hi),
+                _ => merged.push((lo, hi)),
+            }
+        }
+        Domain(merged)
+    }
+    fn remove(&self, n: i64) -> Domain {
+        let mut ranges = Vec::new();
+        for &(lo, hi) in &self.0 {
+            if n < lo || hi < n {
+                ranges.push((lo, hi));
+                continue;
+            }
+            if lo < n {
+                ranges.push((lo, n - 1));
+            }
+            if n < hi {
+                ranges.push((n + 1, hi));
+            }
+        }
+        Domain(ranges)
+    }
+}
+
+/// The domains and constraints of one branch of the search.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Store {
+    // domains of unbound constrained variables, by alias
+    domains: HashMap<Alias, Domain>,
+    constraints: Vec<Constraint>,
+}
+
+impl Store {
+    pub(crate) fn restore_domain(&mut self, alias: Alias, domain: Option<Domain>) {
+        match domain {
+            Some(domain) => self.domains.insert(alias, domain),
+            None => self.domains.remove(&alias),
+        };
+    }
+    pub(crate) fn pop_constraint(&mut self) {
+        self.constraints.pop();
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Relation {
+    Equal,
+    NotEqual,
+    AtMost,
+}
+
+#[derive(Clone, Debug)]
+enum Constraint {
+    // sum(coefficient * variable) + constant, related to 0
+    Linear {
+        terms: Vec<(i64, Term)>,
+        constant: i64,
+        relation: Relation,
+    },
+    AllDifferent(Vec<Term>),
+    // the last term is `op` of the others
+    Function(Op, Vec<Term>, Term),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Times,
+    Div,
+    Rem,
+    Mod,
+    Min,
+    Max,
+    Abs,
+}
+
+impl Op {
+    // `None` where the operation is undefined or overflows
+    fn apply(self, x: i64, y: i64) -> Option<i64> {
+        match self {
+            Op::Times => x.checked_mul(y),
+            Op::Div => x.checked_div(y),
+            Op::Rem => x.checked_rem(y),
+            Op::Mod => {
+                let r = x.checked_rem(y)?;
+                Some(if r != 0 && (r < 0) != (y < 0) {
+                    r + y
+                } else {
+                    r
+                })
+            }
+            Op::Min => Some(x.min(y)),
+            Op::Max => Some(x.max(y)),
+            Op::Abs => x.checked_abs(),
+        }
+    }
+}
+
+impl Bindings {
+    /// Checks the binding just made for `var` against its domain, if it has
+    /// one, and propagates the consequences.
+    pub(crate) fn check_domain(&mut self, var: &Variable) -> bool {
+        let Some(domain) = self.set_domain(var, None) else {
+            return true;
+        };
+        self.restrict(&Term::Variable(var.clone()), &domain)
+            .is_some()
+            && self.propagate()
+    }
+    // `None` for terms that can never be integers
+    fn domain_of(&self, term: &Term) -> Option<Domain> {
+        match self.walk(term) {
+            Term::Integer(n) => Some(Domain::range(*n, *n)),
+            Term::Variable(v) => Some(
+                self.constraints
+                    .domains
+                    .get(&v.alias)
+                    .cloned()
+                    .unwrap_or_else(Domain::all),
+            ),
+            _ => None,
+        }
+    }
+    // Narrows `term` to `domain`, binding it once only one value is left.
+    // Returns whether anything changed, or `None` if no value is left.
+    fn restrict(&mut self, term: &Term, domain: &Domain) -> Option<bool> {
+        match self.walk(term).clone() {
+            Term::Integer(n) => domain.contains(n).then_some(false),
+            Term::Variable(v) => {
+                let current = self.domain_of(term)?;
+                let narrowed = current.intersect(domain);
+                let changed = narrowed != current;
+                match narrowed.singleton() {
+                    _ if narrowed.0.is_empty() => return None,
+                    Some(n) => {
+                        self.set_domain(&v, None);
+                        self.bind(&v, Term::Integer(n));
+                    }
+                    None => {
+                        self.set_domain(&v, Some(narrowed));
+                    }
+                }
+                Some(changed)
+            }
+            _ => None,
+        }
+    }
+    fn post(&mut self, constraint: Constraint) -> bool {
+        let terms: Vec<&Term> = match &constraint {
+            Constraint::Linear { terms, .. } => terms.iter().map(|(_, t)| t).collect(),
+            Constraint::AllDifferent(terms) => terms.iter().collect(),
+            Constraint::Function(_, args, result) => args.iter().chain([result]).collect(),
+        };
+        if terms
+            .into_iter()
+            .any(|t| self.restrict(t, &Domain::all()).is_none())
+        {
+            return false;
+        }
+        self.constraints.constraints.push(constraint);
+        self.record(Undo::Constraint);
+        self.propagate()
+    }
+    // replaces `var`'s domain, returning the old one
+    fn set_domain(&mut self, var: &Variable, domain: Option<Domain>) -> Option<Domain> {
+        let old = match domain {
+            Some(domain) => self.constraints.domains.insert(var.alias, domain),
+            None => self.constraints.domains.remove(&var.alias),
+        };
+        if old.is_some() || self.constraints.domains.contains_key(&var.alias) {
+            self.record(Undo::Domain(var.alias, old.clone()));
+        }
+        old
+    }
+    // revises every constraint until none of them narrows anything
+    fn propagate(&mut self) -> bool {
+        loop {
+            let mut changed = false;
+            for constraint in self.constraints.constraints.clone() {
+                match self.revise(&constraint) {
+                    Some(revised) => changed |= revised,
+                    None => return false,
+                }
+            }
+            if !changed {
+                return true;
+            }
+        }
+    }
+    fn revise(&mut self, constraint: &Constraint) -> Option<bool> {
+        match constraint {
+            Constraint::Linear {
+                terms,
+                constant,
+                relation: Relation::Equal,
+            } => Some(self.at_most(terms, *constant, 1)? | self.at_most(terms, *constant, -1)?),
+            Constraint::Linear {
+                terms,
+                constant,
+                relation: Relation::AtMost,
+            } => self.at_most(terms, *constant, 1),
+            Constraint::Linear {
+                terms,
+                constant,
+                relation: Relation::NotEqual,
+            } => self.not_equal(terms, *constant),
+            Constraint::AllDifferent(terms) => self.all_different(terms),
+            Constraint::Function(op, args, result) => self.function(*op, args, result),
+        }
+    }
+    // sign * (sum + constant) =< 0: each term is at most what the least
+    // possible values of the others leave room for
+    fn at_most(&mut self, terms: &[(i64, Term)], constant: i64, sign: i128) -> Option<bool> {
+        let constant = sign * constant as i128;
+        let mut lows = Vec::with_capacity(terms.len());
+        for (c, t) in terms {
+            let (c, domain) = (sign * *c as i128, self.domain_of(t)?);
+            lows.push(match c > 0 {
+                true if domain.min() != i64::MIN => Some(c * domain.min() as i128),
+                false if domain.max() != i64::MAX => Some(c * domain.max() as i128),
+                _ => None,
+            });
+        }
+        if terms.is_empty() {
+            return (constant <= 0).then_some(false);
+        }
+        let mut changed = false;
+        for (i, (c, t)) in terms.iter().enumerate() {
+            let others = lows
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .try_fold(constant, |sum, (_, low)| Some(sum + (*low)?));
+            let Some(others) = others else {
+                continue;
+            };
+            let c = sign * *c as i128;
+            let domain = if c > 0 {
+                Domain::range(i64::MIN, saturate(div_floor(-others, c)))
+            } else {
+                Domain::range(saturate(div_ceil(-others, c)), i64::MAX)
+            };
+            changed |= self.restrict(t, &domain)?;
+        }
+        Some(changed)
+    }
+    // once all but one term are known, the last can't take the value that
+    // would make the sum zero
+    fn not_equal(&mut self, terms: &[(i64, Term)], constant: i64) -> Option<bool> {
+        let mut sum = constant as i128;
+        let mut unknown = None;
+        for (c, t) in terms {
+            match self.walk(t) {
+                Term::Integer(n) => sum += *c as i128 * *n as i128,
+                _ if unknown.is_none() => unknown = Some((*c as i128, t)),
+                _ => return Some(false),
+            }
+        }
+        let Some((c, t)) = unknown else {
+            return (sum != 0).then_some(false);
+        };
+        let domain = self.domain_of(t)?;
+        match i64::try_from(-sum / c) {
+            Ok(value) if sum % c == 0 && domain.contains(value) => {
+                self.restrict(t, &domain.remove(value))
+            }
+            _ => Some(false),
+        }
+    }
+    fn all_different(&mut self, terms: &[Term]) -> Option<bool> {
+        let mut changed = false;
+        for (i, term) in terms.iter().enumerate() {
+            let &Term::Integer(n) = self.walk(term) else {
+                continue;
+            };
+            for (j, other) in terms.iter().enumerate() {
+                if i != j {
+                    let domain = self.domain_of(other)?;
+                    changed |= self.restrict(other, &domain.remove(n))?;
+                }
+            }
+        }
+        Some(changed)
+    }
+}
+
+impl Bindings {
+    // `result` = `op`(args): the result is the operation's value once the
+    // arguments are known, and is otherwise kept within the bounds the
+    // arguments' bounds give it, which narrow the arguments in turn where
+    // the operation allows
+    fn function(&mut self, op: Op, args: &[Term], result: &Term) -> Option<bool> {
+        let (x, y) = (&args[0], args.get(1).unwrap_or(&args[0]));
+        let mut changed = false;
+        if matches!(op, Op::Div | Op::Rem | Op::Mod) {
+            changed |= self.restrict(y, &self.domain_of(y)?.remove(0))?;
+        }
+        if let (Term::Integer(x), Term::Integer(y)) = (self.walk(x), self.walk(y)) {
+            let value = op.apply(*x, *y)?;
+            return Some(changed | self.restrict(result, &Domain::range(value, value))?);
+        }
+        let bounds = |domain: Domain| (domain.min() as i128, domain.max() as i128);
+        let ((xl, xh), (yl, yh)) = (bounds(self.domain_of(x)?), bounds(self.domain_of(y)?));
+        let (zl, zh) = match op {
+            Op::Times => extremes([xl * yl, xl * yh, xh * yl, xh * yh]),
+            Op::Div if yl > 0 || yh < 0 => extremes([xl / yl, xl / yh, xh / yl, xh / yh]),
+            Op::Div => {
+                let most = xl.abs().max(xh.abs());
+                (-most, most)
+            }
+            Op::Rem => {
+                let most = (yl.abs().max(yh.abs()) - 1).min(xl.abs().max(xh.abs()));
+                (
+                    if xl >= 0 { 0 } else { -most },
+                    if xh <= 0 { 0 } else { most },
+                )
+            }
+            Op::Mod if yl > 0 => (0, if xl >= 0 { xh.min(yh - 1) } else { yh - 1 }),
+            Op::Mod if yh < 0 => (yl + 1, 0),
+            Op::Mod => (yl + 1, yh - 1),
+            Op::Min => (xl.min(yl), xh.min(yh)),
+            Op::Max => (xl.max(yl), xh.max(yh)),
+            Op::Abs if xl >= 0 => (xl, xh),
+            Op::Abs if xh <= 0 => (-xh, -xl),
+            Op::Abs => (0, xh.max(-xl)),
+        };
+        changed |= self.restrict(result, &Domain::range(saturate(zl), saturate(zh)))?;
+        let (zl, zh) = bounds(self.domain_of(result)?);
+        match op {
+            // a factor is the product over the other, where that can't be 0
+            Op::Times => {
+                for (factor, (ol, oh)) in [(x, (yl, yh)), (y, (xl, xh))] {
+                    if ol > 0 || oh < 0 {
+                        let (lo, hi) = quotients((zl, zh), (ol, oh));
+                        let domain = Domain::range(saturate(lo), saturate(hi));
+                        changed |= self.restrict(factor, &domain)?;
+                    }
+                }
+            }
+            Op::Min => {
+                for arg in [x, y] {
+                    changed |= self.restrict(arg, &Domain::range(saturate(zl), i64::MAX))?;
+                }
+            }
+            Op::Max => {
+                for arg in [x, y] {
+                    changed |= self.restrict(arg, &Domain::range(i64::MIN, saturate(zh)))?;
+                }
+            }
+            Op::Abs => {
+                let (lo, hi) = (saturate(zl), saturate(zh));
+                let domain = Domain::range(lo, hi).union(&Domain::range(-hi, -lo));
+                changed |= self.restrict(x, &domain)?;
+            }
+            Op::Div | Op::Rem | Op::Mod => {}
+        }
+        Some(changed)
+    }
+}
+
+// bounds on the integers that, times a divisor within `divisor` (which
+// doesn't take in 0), can make a product from `lo` to `hi`
+fn quotients((lo, hi): (i128, i128), divisor: (i128, i128)) -> (i128, i128) {
+    let corners = [
+        (lo, divisor.0),
+        (lo, divisor.1),
+        (hi, divisor.0),
+        (hi, divisor.1),
+    ];
+    let least = corners.map(|(a, b)| div_ceil(a, b)).into_iter().min();
+    let greatest = corners.map(|(a, b)| div_floor(a, b)).into_iter().max();
+    (least.unwrap_or_default(), greatest.unwrap_or_default())
+}
+
+// the least and greatest of `values`
+fn extremes(values: [i128; 4]) -> (i128, i128) {
+    let least = values.into_iter().min().unwrap_or_default();
+    let greatest = values.into_iter().max().unwrap_or_default();
+    (least, greatest)
+}
+
+fn div_floor(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        q - 1
+    } else {
+        q
+    }
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    if a % b != 0 && (a < 0) == (b < 0) {
+        q + 1
+    } else {
+        q
+    }
+}
+
+fn saturate(n: i128) -> i64 {
+    n.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+// sum(coefficient * variable) + constant
+struct Linear {
+    terms: Vec<(i64, Term)>,
+    constant: i64,
+}
+
+impl Linear {
+    fn scale(self, k: i64) -> Result<Self, Term> {
+        Ok(Self {
+            terms: self
+                .terms
+                .into_iter()
+                .map(|(c, t)| Ok((c.checked_mul(k).ok_or_else(overflow)?, t)))
+                .collect::<Result<_, Term>>()?,
+            constant: self.constant.checked_mul(k).ok_or_else(overflow)?,
+        })
+    }
+    fn add(mut self, other: Self, sign: i64) -> Result<Self, Term> {
+        let other = other.scale(sign)?;
+        self.terms.extend(other.terms);
+        self.constant = self
+            .constant
+            .checked_add(other.constant)
+            .ok_or_else(overflow)?;
+        Ok(self)
+    }
+    // a term for the sum: itself if it's a single variable or a number, and
+    // otherwise a fresh variable constrained to equal it
+    fn into_term(self, functions: &mut Vec<Constraint>) -> Term {
+        match self.terms.as_slice() {
+            [] => return Term::Integer(self.constant),
+            [(1, term)] if self.constant == 0 => return term.clone(),
+            _ => {}
+        }
+        let fresh = Term::Variable(Variable::new_anonymous(&mut thread_rng()));
+        let mut terms = self.terms;
+        terms.push((-1, fresh.clone()));
+        functions.push(Constraint::Linear {
+            terms,
+            constant: self.constant,
+            relation: Relation::Equal,
+        });
+        fresh
+    }
+}
+
+fn overflow() -> Term {
+    error::evaluation_error("int_overflow")
+}
+
+// `term` as a linear sum, each part of it that isn't linear standing for a
+// fresh variable, whose constraint goes on `functions`; an error term for
+// anything that isn't an expression
+fn linear(
+    term: &Term,
+    bindings: &Bindings,
+    functions: &mut Vec<Constraint>,
+) -> Result<Linear, Term> {
+    let f = match bindings.walk(term) {
+        Term::Integer(n) => {
+            return Ok(Linear {
+                terms: Vec::new(),
+                constant: *n,
+            })
+        }
+        var @ Term::Variable(_) => {
+            return Ok(Linear {
+                terms: vec![(1, var.clone())],
+                constant: 0,
+            })
+        }
+        Term::Atom(a) => return Err(not_evaluable(a.as_str(), 0)),
+        Term::String(_) => return Err(error::type_error("evaluable", bindings.resolve(term))),
+        Term::Functor(f) => f,
+    };
+    let op = match (f.name.as_str(), f.args.as_slice()) {
+        ("-", [x]) => return linear(x, bindings, functions)?.scale(-1),
+        ("+", [x]) => return linear(x, bindings, functions),
+        ("abs", [_]) => Op::Abs,
+        ("+" | "-", [x, y]) => {
+            let sign = if f.name.as_str() == "+" { 1 } else { -1 };
+            return linear(x, bindings, functions)?.add(linear(y, bindings, functions)?, sign);
+        }
+        ("*", [x, y]) => {
+            let (x, y) = (
+                linear(x, bindings, functions)?,
+                linear(y, bindings, functions)?,
+            );
+            if x.terms.is_empty() {
+                return y.scale(x.constant);
+            }
+            if y.terms.is_empty() {
+                return x.scale(y.constant);
+            }
+            return Ok(function(Op::Times, vec![x, y], functions));
+        }
+        ("//", [_, _]) => Op::Div,
+        ("rem", [_, _]) => Op::Rem,
+        ("mod", [_, _]) => Op::Mod,
+        ("min", [_, _]) => Op::Min,
+        ("max", [_, _]) => Op::Max,
+        (name, args) => return Err(not_evaluable(name, args.len())),
+    };
+    let args = f
+        .args
+        .iter()
+        .map(|arg| linear(arg, bindings, functions))
+        .collect::<Result<_, Term>>()?;
+    Ok(function(op, args, functions))
+}
+
+// a fresh variable for `op` of `args`, as a sum
+fn function(op: Op, args: Vec<Linear>, functions: &mut Vec<Constraint>) -> Linear {
+    let args = args
+        .into_iter()
+        .map(|arg| arg.into_term(functions))
+        .collect();
+    let result = Term::Variable(Variable::new_anonymous(&mut thread_rng()));
+    functions.push(Constraint::Function(op, args, result.clone()));
+    Linear {
+        terms: vec![(1, result)],
+        constant: 0,
+    }
+}
+
+fn not_evaluable(name: &str, arity: usize) -> Term {
+    error::type_error("evaluable", error::indicator(name, arity))
+}
+
+// lhs - rhs + offset `relation` 0, with each variable mentioned once, after
+// the constraints on the fresh variables standing for its parts that aren't
+// linear
+fn relate(
+    lhs: &Term,
+    rhs: &Term,
+    offset: i64,
+    relation: Relation,
+    bindings: &Bindings,
+) -> Result<Vec<Constraint>, Term> {
+    let mut constraints = Vec::new();
+    let lhs = linear(lhs, bindings, &mut constraints)?;
+    let sum = lhs.add(linear(rhs, bindings, &mut constraints)?, -1)?;
+    let mut terms: Vec<(i64, Term)> = Vec::new();
+    for (c, t) in sum.terms {
+        let seen = terms.iter_mut().find(|(_, seen)| match (seen, &t) {
+            (Term::Variable(seen), Term::Variable(v)) => seen.alias == v.alias,
+            (_, _) => false,
+        });
+        match seen {
+            Some((total, _)) => *total = total.checked_add(c).ok_or_else(overflow)?,
+            None => terms.push((c, t)),
+        }
+    }
+    terms.retain(|(c, _)| *c != 0);
+    constraints.push(Constraint::Linear {
+        terms,
+        constant: sum.constant.checked_add(offset).ok_or_else(overflow)?,
+        relation,
+    });
+    Ok(constraints)
+}
+
+fn post(solver: &mut Solver, constraints: Result<Vec<Constraint>, Term>) -> Alternatives {
+    match constraints {
+        Ok(constraints) => Box::new(iter::once(Box::new(move |bindings: &mut Bindings| {
+            constraints
+                .into_iter()
+                .all(|constraint| bindings.post(constraint))
+        }) as Branch)),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+pub(crate) fn equal(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    post(
+        solver,
+        relate(&args[0], &args[1], 0, Relation::Equal, bindings),
+    )
+}
+
+pub(crate) fn not_equal(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    post(
+        solver,
+        relate(&args[0], &args[1], 0, Relation::NotEqual, bindings),
+    )
+}
+
+pub(crate) fn less(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    post(
+        solver,
+        relate(&args[0], &args[1], 1, Relation::AtMost, bindings),
+    )
+}
+
+pub(crate) fn greater(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    post(
+        solver,
+        relate(&args[1], &args[0], 1, Relation::AtMost, bindings),
+    )
+}
+
+pub(crate) fn less_or_equal(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    post(
+        solver,
+        relate(&args[0], &args[1], 0, Relation::AtMost, bindings),
+    )
+}
+
+pub(crate) fn greater_or_equal(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    post(
+        solver,
+        relate(&args[1], &args[0], 0, Relation::AtMost, bindings),
+    )
+}
+
+pub(crate) fn all_different(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    let terms = items(&args[0], bindings).and_then(|terms| integers(terms, bindings));
+    post(
+        solver,
+        terms.map(|terms| vec![Constraint::AllDifferent(terms)]),
+    )
+}
+
+pub(crate) fn in_domain(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let vars = integers(vec![args[0].clone()], bindings);
+    restrict_all(solver, vars, &args[1], bindings)
+}
+
+pub(crate) fn ins_domain(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let vars = items(&args[0], bindings).and_then(|vars| integers(vars, bindings));
+    restrict_all(solver, vars, &args[1], bindings)
+}
+
+fn items(list: &Term, bindings: &Bindings) -> Result<Vec<Term>, Term> {
+    match bindings.list_to_vec(list) {
+        Some(items) => Ok(items),
+        None if matches!(bindings.walk(list), Term::Variable(_)) => {
+            Err(error::instantiation_error())
+        }
+        None => Err(error::type_error("list", bindings.resolve(list))),
+    }
+}
+
+// `terms`, if each is a variable or an integer
+fn integers(terms: Vec<Term>, bindings: &Bindings) -> Result<Vec<Term>, Term> {
+    match terms
+        .iter()
+        .find(|term| !matches!(bindings.walk(term), Term::Variable(_) | Term::Integer(_)))
+    {
+        Some(term) => Err(error::type_error("integer", bindings.resolve(term))),
+        None => Ok(terms),
+    }
+}
+
+fn restrict_all(
+    solver: &mut Solver,
+    vars: Result<Vec<Term>, Term>,
+    domain: &Term,
+    bindings: &Bindings,
+) -> Alternatives {
+    let (vars, domain) = match (vars, domain_from_term(domain, bindings)) {
+        (Ok(vars), Ok(domain)) => (vars, domain),
+        (Err(ball), _) | (_, Err(ball)) => return builtins::raise(solver, ball),
+    };
+    Box::new(iter::once(Box::new(move |bindings: &mut Bindings| {
+        vars.iter()
+            .all(|var| bindings.restrict(var, &domain).is_some())
+            && bindings.propagate()
+    }) as Branch))
+}
+
+// N, Lo..Hi (with `inf` and `sup`) or Domain \/ Domain
+fn domain_from_term(term: &Term, bindings: &Bindings) -> Result<Domain, Term> {
+    let bound = |term: &Term| match bindings.walk(term) {
+        Term::Integer(n) => Ok(*n),
+        Term::Atom(a) if a.as_str() == "inf" => Ok(i64::MIN),
+        Term::Atom(a) if a.as_str() == "sup" => Ok(i64::MAX),
+        Term::Variable(_) => Err(error::instantiation_error()),
+        other => Err(error::type_error("integer", bindings.resolve(other))),
+    };
+    match bindings.walk(term) {
+        Term::Integer(n) => Ok(Domain::range(*n, *n)),
+        Term::Variable(_) => Err(error::instantiation_error()),
+        Term::Functor(f) if f.name.as_str() == ".." && f.arity() == 2 => {
+            Ok(Domain::range(bound(&f.args[0])?, bound(&f.args[1])?))
+        }
+        Term::Functor(f) if f.name.as_str() == "\\/" && f.arity() == 2 => {
+            Ok(domain_from_term(&f.args[0], bindings)?
+                .union(&domain_from_term(&f.args[1], bindings)?))
+        }
+        other => Err(error::domain_error("clpfd_domain", bindings.resolve(other))),
+    }
+}
+
+pub(crate) fn label(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    search(solver, &Term::nil(), &args[0], bindings)
+}
+
+pub(crate) fn labeling(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    search(solver, &args[0], &args[1], bindings)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Selection {
+    Leftmost,
+    FirstFail,
+    Min,
+    Max,
+}
+
+/// Depth-first search over the values of some variables, trying either the
+/// smallest or largest remaining value of the selected variable, then the rest.
+struct Labeling {
+    vars: Vec<Term>,
+    selection: Selection,
+    descending: bool,
+    // branches still to explore, the next one last
+    pending: Vec<Bindings>,
+}
+
+fn search(solver: &mut Solver, options: &Term, vars: &Term, bindings: &Bindings) -> Alternatives {
+    let (Some(options), Some(vars)) = (bindings.list_to_vec(options), bindings.list_to_vec(vars))
+    else {
+        return builtins::failure();
+    };
+    for var in &vars {
+        match bindings.domain_of(var) {
+            None => {
+                return builtins::raise(solver, error::type_error("integer", bindings.resolve(var)))
+            }
+            // unbounded domains can't be enumerated
+            Some(domain) if !domain.is_finite() => {
+                return builtins::raise(solver, error::instantiation_error())
+            }
+            Some(_) => {}
+        }
+    }
+    let mut labeling = Labeling {
+        vars,
+        selection: Selection::Leftmost,
+        descending: false,
+        pending: vec![bindings.clone()],
+    };
+    for option in options {
+        let Term::Atom(option) = bindings.walk(&option) else {
+            return builtins::failure();
+        };
+        match option.as_str() {
+            "leftmost" => labeling.selection = Selection::Leftmost,
+            "ff" => labeling.selection = Selection::FirstFail,
+            "min" => labeling.selection = Selection::Min,
+            "max" => labeling.selection = Selection::Max,
+            "up" => labeling.descending = false,
+            "down" => labeling.descending = true,
+            _ => return builtins::failure(),
+        }
+    }
+    Box::new(labeling)
+}
+
+impl Labeling {
+    // the next variable to label and its domain, `Some(None)` once all are
+    // integers, or `None` if one can never be
+    fn select(&self, bindings: &Bindings) -> Option<Option<(Term, Domain)>> {
+        let mut unlabeled = Vec::new();
+        for var in &self.vars {
+            if !matches!(bindings.walk(var), Term::Integer(_)) {
+                unlabeled.push((var.clone(), bindings.domain_of(var)?));
+            }
+        }
+        let selected = match self.selection {
+            Selection::Leftmost => unlabeled.into_iter().next(),
+            Selection::FirstFail => unlabeled.into_iter().min_by_key(|(_, d)| d.size()),
+            Selection::Min => unlabeled.into_iter().min_by_key(|(_, d)| d.min()),
+            Selection::Max => unlabeled.into_iter().min_by_key(|(_, d)| Reverse(d.max())),
+        };
+        Some(selected)
+    }
+}
+
+impl Iterator for Labeling {
+    type Item = Branch;
+
+    fn next(&mut self) -> Option<Branch> {
+        while let Some(bindings) = self.pending.pop() {
+            let Some(selected) = self.select(&bindings) else {
+                continue;
+            };
+            let Some((var, domain)) = selected else {
+                let values = self
+                    .vars
+                    .iter()
+                    .map(|var| (var.clone(), bindings.resolve(var)))
+                    .collect();
+                return Some(builtins::unify(values));
+            };
+            // propagation only ever narrows the checked domains
+            if !domain.is_finite() {
+                continue;
+            }
+            let value = if self.descending {
+                domain.max()
+            } else {
+                domain.min()
+            };
+            let mut rest = bindings.clone();
+            if rest.restrict(&var, &domain.remove(value)).is_some() && rest.propagate() {
+                self.pending.push(rest);
+            }
+            let mut next = bindings;
+            if next.unify(&var, &Term::Integer(value)) {
+                self.pending.push(next);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{answers, consulted};
+    use crate::*;
+
+    #[test]
+    fn narrows_and_labels() {
+        assert_eq!(
+            answers(&Database::new(), "X in 1..10, X #> 3, X #< 6, label([X])").unwrap(),
+            vec!["X = 4", "X = 5"]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "X in 1..2 \\/ 5..6, labeling([down], [X])"
+            )
+            .unwrap(),
+            vec!["X = 6", "X = 5", "X = 2", "X = 1"]
+        );
+    }
+
+    #[test]
+    fn propagates_without_labeling() {
+        assert_eq!(
+            answers(&Database::new(), "[A, B, C] ins 1..3, A #< B, B #< C").unwrap(),
+            vec!["A = 1, B = 2, C = 3"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "X #= Y + 2, Y in 0..3, X #>= 5").unwrap(),
+            vec!["X = 5, Y = 3"]
+        );
+    }
+
+    #[test]
+    fn bindings_respect_domains() {
+        assert!(answers(&Database::new(), "X in 1..3, X = 5")
+            .unwrap()
+            .is_empty());
+        assert!(answers(&Database::new(), "X #> 3, X = foo")
+            .unwrap()
+            .is_empty());
+        assert!(answers(&Database::new(), "X in 1..3, Y in 4..6, X = Y")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            answers(&Database::new(), "X #\\= 2, X in 1..3, X = 3").unwrap(),
+            vec!["X = 3"]
+        );
+        assert!(answers(&Database::new(), "X #\\= 2, X in 1..3, X = 2")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn all_different_prunes() {
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "[X, Y, Z] ins 1..3, all_different([X, Y, Z]), X = 1, Y #< Z"
+            )
+            .unwrap(),
+            vec!["X = 1, Y = 2, Z = 3"]
+        );
+    }
+
+    #[test]
+    fn four_queens() {
+        let program = "
+            queens([A, B, C, D]) :-
+                [A, B, C, D] ins 1..4,
+                all_different([A, B, C, D]),
+                A #\\= B + 1, A #\\= B - 1, A #\\= C + 2, A #\\= C - 2,
+                A #\\= D + 3, A #\\= D - 3, B #\\= C + 1, B #\\= C - 1,
+                B #\\= D + 2, B #\\= D - 2, C #\\= D + 1, C #\\= D - 1,
+                labeling([ff], [A, B, C, D]).";
+        assert_eq!(
+            answers(&consulted(program), "queens(Qs)").unwrap(),
+            vec![
+                "Qs = .(2, .(4, .(1, .(3, []))))",
+                "Qs = .(3, .(1, .(4, .(2, []))))"
+            ]
+        );
+    }
+
+    #[test]
+    fn non_linear_parts_are_constrained_too() {
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "X #= Y*Z, Y in 1..2, Z in 1..2, label([X, Y, Z])"
+            )
+            .unwrap(),
+            vec![
+                "X = 1, Y = 1, Z = 1",
+                "X = 2, Y = 1, Z = 2",
+                "X = 2, Y = 2, Z = 1",
+                "X = 4, Y = 2, Z = 2"
+            ]
+        );
+        assert_eq!(
+            answers(&Database::new(), "Y * Y #= 49, Y in -10..10, label([Y])").unwrap(),
+            vec!["Y = -7", "Y = 7"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "X #= abs(Y), Y in -3..2, X #>= 3").unwrap(),
+            vec!["X = 3, Y = -3"]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "X #= Y mod 3, Y in 0..10, X #>= 2, label([Y])"
+            )
+            .unwrap(),
+            vec!["X = 2, Y = 2", "X = 2, Y = 5", "X = 2, Y = 8"]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "min(A, B) #>= 4, max(A, B) #< 6, [A, B] ins 0..9, label([A, B])"
+            )
+            .unwrap(),
+            vec![
+                "A = 4, B = 4",
+                "A = 4, B = 5",
+                "A = 5, B = 4",
+                "A = 5, B = 5"
+            ]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "X #= Y // 2 + Y rem 2, Y in 4..5, label([Y])"
+            )
+            .unwrap(),
+            vec!["X = 2, Y = 4", "X = 3, Y = 5"]
+        );
+        assert!(answers(&Database::new(), "X #= 5 mod Y, Y in 0..0")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn six_queens() {
+        let program = "
+            queens(Qs) :- Qs = [_, _, _, _, _, _], Qs ins 1..6, safe(Qs), label(Qs).
+            safe([]).
+            safe([Q|Qs]) :- apart(Q, Qs, 1), safe(Qs).
+            apart(_, [], _).
+            apart(Q, [Q1|Qs], D) :-
+                Q #\\= Q1, abs(Q - Q1) #\\= D, D1 is D + 1, apart(Q, Qs, D1).";
+        assert_eq!(
+            answers(&consulted(program), "queens(Qs)").unwrap(),
+            vec![
+                "Qs = .(2, .(4, .(6, .(1, .(3, .(5, []))))))",
+                "Qs = .(3, .(6, .(2, .(5, .(1, .(4, []))))))",
+                "Qs = .(4, .(1, .(5, .(2, .(6, .(3, []))))))",
+                "Qs = .(5, .(3, .(1, .(6, .(4, .(2, []))))))"
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_expressions_and_domains_throw() {
+        for (query, error) in [
+            ("X #= foo(Y)", "type_error(evaluable, /(foo, 1))"),
+            ("X #> a", "type_error(evaluable, /(a, 0))"),
+            ("X in foo", "domain_error(clpfd_domain, foo)"),
+            ("X in 1..a", "type_error(integer, a)"),
+            ("X in D", "instantiation_error"),
+            ("[X, a] ins 1..3", "type_error(integer, a)"),
+            ("all_different(foo)", "type_error(list, foo)"),
+        ] {
+            let query = parse_query(query).unwrap();
+            match Database::new().try_satisfy_all(query.goal) {
+                Err(SolveError::Uncaught(ball)) => {
+                    assert!(ball.to_string().contains(error), "{}", ball)
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! What the solver takes from std, with stand-ins for when the `std`
+//! feature is off: hash maps from `hashbrown`, locks from `spin`, and in
+//! place of `thread_rng` a counter that hands out distinct numbers, which is
+//! all variable names need.
+
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
+
+#[cfg(not(feature = "std"))]
+pub(crate) use hashbrown::{HashMap, HashSet};
+
+#[cfg(feature = "std")]
+pub(crate) use std::collections::hash_map::DefaultHasher;
+
+#[cfg(feature = "std")]
+pub(crate) use rand::{rngs::ThreadRng, thread_rng};
+
+/// A lock that shrugs off poisoning: what a panicking holder left is still
+/// the best there is.
+#[derive(Debug, Default)]
+pub(crate) struct Mutex<T>(
+    #[cfg(feature = "std")] std::sync::Mutex<T>,
+    #[cfg(not(feature = "std"))] spin::Mutex<T>,
+);
+
+#[cfg(feature = "std")]
+pub(crate) type MutexGuard<'a, T> = std::sync::MutexGuard<'a, T>;
+
+#[cfg(not(feature = "std"))]
+pub(crate) type MutexGuard<'a, T> = spin::MutexGuard<'a, T>;
+
+impl<T> Mutex<T> {
+    pub(crate) fn new(value: T) -> Self {
+        #[cfg(feature = "std")]
+        return Self(std::sync::Mutex::new(value));
+        #[cfg(not(feature = "std"))]
+        return Self(spin::Mutex::new(value));
+    }
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        #[cfg(feature = "std")]
+        return self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        #[cfg(not(feature = "std"))]
+        return self.0.lock();
+    }
+}
+
+/// Like `Mutex`, for many readers or one writer.
+#[derive(Debug, Default)]
+pub(crate) struct RwLock<T>(
+    #[cfg(feature = "std")] std::sync::RwLock<T>,
+    #[cfg(not(feature = "std"))] spin::RwLock<T>,
+);
+
+impl<T> RwLock<T> {
+    #[cfg(feature = "std")]
+    pub(crate) fn read(&self) -> std::sync::RwLockReadGuard<'_, T> {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn read(&self) -> spin::RwLockReadGuard<'_, T> {
+        self.0.read()
+    }
+    #[cfg(feature = "std")]
+    pub(crate) fn write(&self) -> std::sync::RwLockWriteGuard<'_, T> {
+        self.0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn write(&self) -> spin::RwLockWriteGuard<'_, T> {
+        self.0.write()
+    }
+}
+
+/// A value made the first time it's wanted.
+pub(crate) struct OnceLock<T>(
+    #[cfg(feature = "std")] std::sync::OnceLock<T>,
+    #[cfg(not(feature = "std"))] spin::Once<T>,
+);
+
+impl<T> OnceLock<T> {
+    pub(crate) const fn new() -> Self {
+        #[cfg(feature = "std")]
+        return Self(std::sync::OnceLock::new());
+        #[cfg(not(feature = "std"))]
+        return Self(spin::Once::new());
+    }
+    pub(crate) fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        #[cfg(feature = "std")]
+        return self.0.get_or_init(init);
+        #[cfg(not(feature = "std"))]
+        return self.0.call_once(init);
+    }
+}
+
+/// FNV-1a: the same hash for the same input every time, which is what
+/// `DefaultHasher::new` promises.
+#[cfg(not(feature = "std"))]
+pub(crate) struct DefaultHasher(u64);
+
+#[cfg(not(feature = "std"))]
+impl DefaultHasher {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::hash::Hasher for DefaultHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+/// Numbers that differ from every other this program has drawn: a shared
+/// count, scrambled so they don't look alike.
+#[cfg(not(feature = "std"))]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ThreadRng;
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn thread_rng() -> ThreadRng {
+    ThreadRng
+}
+
+#[cfg(not(feature = "std"))]
+impl rand::RngCore for ThreadRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+    fn next_u64(&mut self) -> u64 {
+        static COUNT: spin::Mutex<u64> = spin::Mutex::new(0);
+        let mut count = COUNT.lock();
+        *count += 1;
+        // splitmix64's finalizer, a bijection, so distinct counts stay distinct
+        let mut z = count.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn draws_differ_and_locks_outlast_panics() {
+        let mut rng = thread_rng();
+        assert_ne!(rng.gen::<u64>(), rng.gen::<u64>());
+        let count = Arc::new(Mutex::new(1));
+        let held = Arc::clone(&count);
+        let panicked = thread::spawn(move || {
+            let _count = held.lock();
+            panic!("while holding the lock");
+        })
+        .join();
+        assert!(panicked.is_err());
+        *count.lock() += 1;
+        assert_eq!(*count.lock(), 2);
+    }
+}
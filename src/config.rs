@@ -0,0 +1,370 @@
+use crate::distinct::Filter;
+use crate::flags::{Flags, Unknown};
+use crate::solver::Bindings;
+use crate::{Database, Term};
+use alloc::sync::Arc;
+use core::ops::ControlFlow;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use core::time::Duration;
+
+/// Limits on how a query is solved, how it does arithmetic and how source
+/// is read, set with `Database::set_config`.
+#[derive(Clone, Debug, Default)]
+pub struct SolverConfig {
+    #[cfg(feature = "std")]
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) max_solutions: Option<usize>,
+    pub(crate) prefer_rationals: bool,
+    pub(crate) flags: Flags,
+    pub(crate) max_bindings: Option<usize>,
+    pub(crate) max_terms: Option<usize>,
+    pub(crate) distinct: bool,
+    pub(crate) strategy: Strategy,
+    pub(crate) cancellation: Option<CancellationToken>,
+}
+
+/// A flag another thread can raise to stop the queries watching it, set on
+/// them with `SolverConfig::cancellation`. Clones share the one flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Stops the queries watching the token, at their next call, with
+    /// `SolveError::Cancelled`; and those started later, as soon as they
+    /// start.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether unification checks that a variable doesn't occur in the term
+/// it's bound to, like Prolog's `occurs_check` flag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OccursCheck {
+    /// Binds regardless, which is quick but can build cyclic terms.
+    #[default]
+    Off,
+    /// Fails to unify `X` with `f(X)`.
+    True,
+    /// Throws `occurs_check(X, f(X))` instead of unifying `X` with `f(X)`.
+    Error,
+}
+
+/// The order a query tries the alternatives of its goals in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Prolog's own: every alternative of a clause is tried, all the way
+    /// down, before the next clause. Quick and lean, but a clause whose
+    /// derivations go on forever hides the solutions of the clauses after it.
+    #[default]
+    DepthFirst,
+    /// Solutions come in order of how many goals prove them, fewest first,
+    /// so each one is found in the end, whatever the order of the clauses.
+    /// Searches depth-first again and again, each time cutting derivations
+    /// off at twice the length of the last (iterative deepening), until a
+    /// search cuts none off. The conditions of if-then-elses and the goals
+    /// built-ins such as `findall/3` prove aren't cut off, and goals with
+    /// side effects, such as `write/1`, repeat them in each search.
+    BreadthFirst,
+}
+
+/// What `"text"` in source reads as, like Prolog's `double_quotes` flag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DoubleQuotes {
+    /// A list of character codes.
+    Codes,
+    /// A list of one-character atoms.
+    Chars,
+    Atom,
+    #[default]
+    String,
+}
+
+impl SolverConfig {
+    /// Gives up on a query with `SolveError::TimedOut` once it has run for
+    /// `timeout`. Needs a clock, so it needs the `std` feature and isn't
+    /// supported on `wasm32-unknown-unknown`.
+    #[cfg(feature = "std")]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Stops a query after its first `max` solutions.
+    pub fn max_solutions(mut self, max: usize) -> Self {
+        self.max_solutions = Some(max);
+        self
+    }
+    /// Stops a query with `SolveError::ResourceExceeded` once a goal it calls
+    /// has an argument nested more than `depth` deep, following bindings; a
+    /// list counts one level per item.
+    pub fn max_term_depth(mut self, depth: usize) -> Self {
+        self.flags.max_term_depth = Some(depth);
+        self
+    }
+    /// Stops a query with `SolveError::ResourceExceeded` once it has more than
+    /// `max` variables bound at a time.
+    pub fn max_bindings(mut self, max: usize) -> Self {
+        self.max_bindings = Some(max);
+        self
+    }
+    /// Stops a query with `SolveError::ResourceExceeded` once it has copied
+    /// more than `max` terms out of the database's clauses, counting every
+    /// atom, number, variable and compound term of each clause it tries.
+    pub fn max_terms(mut self, max: usize) -> Self {
+        self.max_terms = Some(max);
+        self
+    }
+    /// Leaves out each solution whose bindings repeat an earlier solution's,
+    /// up to renaming variables, as if the query were wrapped in
+    /// `distinct/1`.
+    pub fn distinct(mut self, distinct: bool) -> Self {
+        self.distinct = distinct;
+        self
+    }
+    /// Gives up on a query with `SolveError::Cancelled` once `token` is
+    /// cancelled. The query checks at each call, so even one that never
+    /// finds a solution stops soon after, with its bindings undone.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+    /// Searches as `strategy` says.
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+    /// Makes `/` of two integers that don't divide exactly give a rational,
+    /// `N rdiv D`, rather than raise an evaluation error.
+    pub fn prefer_rationals(mut self, prefer: bool) -> Self {
+        self.prefer_rationals = prefer;
+        self
+    }
+    /// Reads double-quoted text in consulted source and `read_term/2` as
+    /// `double_quotes` says, rather than as a string. A file can also set it
+    /// for itself with `:- set_prolog_flag(double_quotes, codes).`
+    pub fn double_quotes(mut self, double_quotes: DoubleQuotes) -> Self {
+        self.flags.double_quotes = double_quotes;
+        self
+    }
+    /// Checks each binding for cycles as `occurs_check` says.
+    /// `unify_with_occurs_check/2` always checks.
+    pub fn occurs_check(mut self, occurs_check: OccursCheck) -> Self {
+        self.flags.occurs_check = occurs_check;
+        self
+    }
+    /// Whether calling a predicate with no clauses throws or fails.
+    pub fn unknown(mut self, unknown: Unknown) -> Self {
+        self.flags.unknown = unknown;
+        self
+    }
+    // leaves out the solutions of `goal` not wanted
+    pub(crate) fn filter(&self, goal: &Term) -> Filter {
+        Filter::new(goal, self.distinct)
+    }
+    // counts off the solutions a query may still find
+    pub(crate) fn allowance(&self) -> Allowance {
+        Allowance(self.max_solutions)
+    }
+}
+
+pub(crate) struct Allowance(Option<usize>);
+
+impl Allowance {
+    pub(crate) fn exhausted(&self) -> bool {
+        self.0 == Some(0)
+    }
+    /// Uses up one solution, breaking once none are left.
+    pub(crate) fn spend(&mut self) -> ControlFlow<()> {
+        if let Some(left) = &mut self.0 {
+            *left = left.saturating_sub(1);
+        }
+        if self.exhausted() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+impl Database {
+    /// Applies `config` to the queries run from now on, flags included.
+    pub fn set_config(&mut self, config: SolverConfig) {
+        self.config = config;
+    }
+    // the bindings a query starts from
+    pub(crate) fn bindings(&self) -> Bindings {
+        let mut bindings = Bindings::new();
+        bindings.occurs_check = self.config.flags.occurs_check;
+        bindings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    #[cfg(feature = "std")]
+    use std::time::{Duration, Instant};
+
+    #[cfg(feature = "std")]
+    fn timed(timeout: Duration) -> Database {
+        let mut db = Database::new();
+        db.consult("count(N) :- between(1, N, _).").unwrap();
+        db.set_config(SolverConfig::default().timeout(timeout));
+        db
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn endless_queries_time_out() {
+        let db = timed(Duration::from_millis(50));
+        let query = parse_query("between(1, inf, _), fail").unwrap();
+        let started = Instant::now();
+        let result = db.try_satisfy_all(query.goal);
+        assert!(matches!(result, Err(SolveError::TimedOut)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn quick_queries_finish() {
+        let db = timed(Duration::from_secs(60));
+        let query = parse_query("count(3)").unwrap();
+        assert_eq!(db.try_satisfy_all(query.goal).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn solutions_are_capped() {
+        let mut db = Database::new();
+        db.set_config(SolverConfig::default().max_solutions(2));
+        let query = parse_query("between(1, inf, X)").unwrap();
+        assert_eq!(db.satisfy_all(query.goal.clone()).len(), 2);
+        db.set_config(SolverConfig::default().max_solutions(0));
+        assert!(db.satisfy_all(query.goal.clone()).is_empty());
+        db.set_config(SolverConfig::default());
+        assert_eq!(db.satisfy_n(query.goal, 3).len(), 3);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn timeouts_are_not_caught() {
+        let db = timed(Duration::from_millis(50));
+        let query = parse_query("catch((between(1, inf, _), fail), _, true)").unwrap();
+        assert!(matches!(
+            db.try_satisfy_all(query.goal),
+            Err(SolveError::TimedOut)
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn cancelled_queries_stop() {
+        let token = CancellationToken::new();
+        let mut db = Database::new();
+        db.set_config(SolverConfig::default().cancellation(token.clone()));
+        let query = parse_query("catch((between(1, inf, _), fail), _, true)").unwrap();
+        let cancel = std::thread::spawn({
+            let token = token.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(50));
+                token.cancel();
+            }
+        });
+        let started = Instant::now();
+        let result = db.try_satisfy_all(query.goal);
+        cancel.join().unwrap();
+        assert!(matches!(result, Err(SolveError::Cancelled)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(token.is_cancelled());
+        let query = parse_query("true").unwrap();
+        assert!(matches!(
+            db.try_satisfy_all(query.goal.clone()),
+            Err(SolveError::Cancelled)
+        ));
+        db.set_config(SolverConfig::default().cancellation(CancellationToken::new()));
+        assert_eq!(db.try_satisfy_all(query.goal).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn breadth_first_gets_past_endless_clauses() {
+        let mut db = Database::new();
+        db.consult(
+            "nat(s(X)) :- nat(X).
+            nat(0).
+            loop :- loop.
+            loop.
+            parent(tom, bob). parent(tom, liz). parent(bob, ann).
+            ancestor(X, Y) :- ancestor(X, Z), parent(Z, Y).
+            ancestor(X, Y) :- parent(X, Y).
+            big(X) :- findall(C, parent(X, C), [_, _|_]).",
+        )
+        .unwrap();
+        db.set_config(SolverConfig::default().strategy(Strategy::BreadthFirst));
+        let answers = |query: &str, n| {
+            let query = parse_query(query).unwrap();
+            let found = db.satisfy_n(query.goal.clone(), n);
+            found
+                .iter()
+                .map(|b| {
+                    let solution = query.solution(b);
+                    let values = solution.values().iter();
+                    let values: Vec<_> = values.map(|(n, v)| format!("{} = {}", n, v)).collect();
+                    values.join(", ")
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(answers("nat(X)", 3), ["X = 0", "X = s(0)", "X = s(s(0))"]);
+        assert_eq!(answers("loop", 1), [""]);
+        assert_eq!(
+            answers("ancestor(tom, X)", 3),
+            ["X = bob", "X = liz", "X = ann"]
+        );
+        // conditions and findall/3 aren't cut off, so they aren't misled
+        assert_eq!(
+            answers("ancestor(A, ann), (big(A) -> B = big ; B = small)", 2),
+            ["A = bob, B = small", "A = tom, B = big"]
+        );
+    }
+
+    #[test]
+    fn occurs_check_fails_or_throws() {
+        let mut db = Database::new();
+        db.consult("same(X, X).").unwrap();
+        let count = |db: &Database, query: &str| {
+            let query = parse_query(query).unwrap();
+            db.try_satisfy_all(query.goal).map(|answers| answers.len())
+        };
+        assert_eq!(count(&db, "X = f(X)").unwrap(), 1);
+        assert_eq!(count(&db, "unify_with_occurs_check(X, f(X))").unwrap(), 0);
+        assert_eq!(
+            count(&db, "unify_with_occurs_check(f(X, Y), f(Y, g))").unwrap(),
+            1
+        );
+        assert_eq!(count(&db, "X = f(X), same(Y, Y)").unwrap(), 1);
+        db.set_config(SolverConfig::default().occurs_check(OccursCheck::True));
+        assert_eq!(count(&db, "X = f(X)").unwrap(), 0);
+        assert_eq!(count(&db, "same(Y, g(Y))").unwrap(), 0);
+        assert_eq!(count(&db, "X = Y, Y = X, X = f(Z)").unwrap(), 1);
+        db.set_config(SolverConfig::default().occurs_check(OccursCheck::Error));
+        match count(&db, "same(A, [a|A])") {
+            Err(SolveError::Uncaught(ball)) => {
+                assert!(
+                    ball.to_string().contains("occurs_check(A, .(a, A))"),
+                    "{}",
+                    ball
+                )
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            count(&db, "catch(X = f(X), error(occurs_check(_, _), _), true)").unwrap(),
+            1
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Converting between Rust values and terms: `ToTerm` and `FromTerm`, and
+//! `ToFact` for values that stand for a fact, such as a row of a table. With
+//! the `derive` feature, `#[derive(ToFact)]` writes the conversion for a
+//! struct, and `#[predicate]` makes a Rust function a predicate.
+
+use crate::{Arity, Atom, Database, Functor, Rows, Term};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A Rust value as a term. Integers are integers, text and `bool`s atoms,
+/// `Vec`s and slices lists, and tuples comma terms, as `(a, 1)` is
+/// `','(a, 1)`.
+pub trait ToTerm {
+    fn to_term(&self) -> Term;
+}
+
+impl ToTerm for Term {
+    fn to_term(&self) -> Term {
+        self.clone()
+    }
+}
+
+macro_rules! integer_to_term {
+    ($($t:ty),*) => {
+        $(impl ToTerm for $t {
+            fn to_term(&self) -> Term {
+                Term::Integer(i64::from(*self))
+            }
+        })*
+    };
+}
+
+integer_to_term!(i8, i16, i32, i64, u8, u16, u32);
+
+impl ToTerm for bool {
+    fn to_term(&self) -> Term {
+        Term::atom_from_str(if *self { "true" } else { "false" })
+    }
+}
+
+impl ToTerm for str {
+    fn to_term(&self) -> Term {
+        Term::Atom(Atom::new(self))
+    }
+}
+
+impl ToTerm for String {
+    fn to_term(&self) -> Term {
+        self.as_str().to_term()
+    }
+}
+
+impl ToTerm for char {
+    fn to_term(&self) -> Term {
+        self.to_string().to_term()
+    }
+}
+
+impl<T: ToTerm> ToTerm for [T] {
+    fn to_term(&self) -> Term {
+        Term::list_from_vec(self.iter().map(ToTerm::to_term).collect())
+    }
+}
+
+impl<T: ToTerm> ToTerm for Vec<T> {
+    fn to_term(&self) -> Term {
+        self.as_slice().to_term()
+    }
+}
+
+impl<T: ToTerm + ?Sized> ToTerm for &T {
+    fn to_term(&self) -> Term {
+        (**self).to_term()
+    }
+}
+
+/// A Rust value read from a term, if the term is one: the reverse of
+/// `ToTerm`. Text can be read from atoms and strings alike, and pairs from
+/// `A-B` as well as `(A, B)`. An `Option` is `None` for an unbound variable.
+pub trait FromTerm: Sized {
+    fn from_term(term: &Term) -> Option<Self>;
+}
+
+impl FromTerm for Term {
+    fn from_term(term: &Term) -> Option<Self> {
+        Some(term.clone())
+    }
+}
+
+macro_rules! integer_from_term {
+    ($($t:ty),*) => {
+        $(impl FromTerm for $t {
+            fn from_term(term: &Term) -> Option<Self> {
+                match term {
+                    Term::Integer(n) => <$t>::try_from(*n).ok(),
+                    _ => None,
+                }
+            }
+        })*
+    };
+}
+
+integer_from_term!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+impl FromTerm for bool {
+    fn from_term(term: &Term) -> Option<Self> {
+        match term {
+            Term::Atom(a) if a.as_str() == "true" => Some(true),
+            Term::Atom(a) if a.as_str() == "false" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl FromTerm for String {
+    fn from_term(term: &Term) -> Option<Self> {
+        match term {
+            Term::Atom(a) => Some(a.as_str().to_string()),
+            Term::String(text) => Some(text.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl FromTerm for char {
+    fn from_term(term: &Term) -> Option<Self> {
+        let text = String::from_term(term)?;
+        let mut chars = text.chars();
+        chars.next().filter(|_| chars.next().is_none())
+    }
+}
+
+impl<T: FromTerm> FromTerm for Option<T> {
+    fn from_term(term: &Term) -> Option<Self> {
+        match term {
+            Term::Variable(_) => Some(None),
+            term => T::from_term(term).map(Some),
+        }
+    }
+}
+
+impl<T: FromTerm> FromTerm for Vec<T> {
+    fn from_term(mut term: &Term) -> Option<Self> {
+        let mut items = Vec::new();
+        loop {
+            match term {
+                Term::Atom(a) if a.as_str() == "[]" => return Some(items),
+                Term::Functor(f) if f.name.as_str() == "." && f.arity() == 2 => {
+                    items.push(T::from_term(&f.args[0])?);
+                    term = &f.args[1];
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+// `(First, Rest)`, or `First-Rest`
+fn split(term: &Term) -> Option<(&Term, &Term)> {
+    match term {
+        Term::Functor(f) if matches!(f.name.as_str(), "," | "-") && f.arity() == 2 => {
+            Some((&f.args[0], &f.args[1]))
+        }
+        _ => None,
+    }
+}
+
+macro_rules! tuple_terms {
+    ($($t:ident $v:ident),+; $last:ident $w:ident) => {
+        impl<$($t: ToTerm,)+ $last: ToTerm> ToTerm for ($($t,)+ $last) {
+            fn to_term(&self) -> Term {
+                let ($($v,)+ $w) = self;
+                [$($v.to_term()),+]
+                    .into_iter()
+                    .rev()
+                    .fold($w.to_term(), |rest, item| {
+                        Term::functor_from_str(",", vec![item, rest])
+                    })
+            }
+        }
+
+        impl<$($t: FromTerm,)+ $last: FromTerm> FromTerm for ($($t,)+ $last) {
+            fn from_term(term: &Term) -> Option<Self> {
+                let rest = term;
+                $(let ($v, rest) = split(rest)?;)+
+                Some(($($t::from_term($v)?,)+ $last::from_term(rest)?))
+            }
+        }
+    };
+}
+
+tuple_terms!(A a; B b);
+tuple_terms!(A a, B b; C c);
+tuple_terms!(A a, B b, C c; D d);
+
+/// A Rust value as a fact, `name(Args...)`.
+pub trait ToFact {
+    fn name(&self) -> &str;
+    fn args(&self) -> Vec<Term>;
+    fn to_fact(&self) -> Functor {
+        Functor::new_fact(Atom::new(self.name()), self.args())
+    }
+}
+
+impl<T: ToFact + ?Sized> ToFact for &T {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+    fn args(&self) -> Vec<Term> {
+        (**self).args()
+    }
+}
+
+/// A Rust function made a predicate by `#[predicate]`, to be added to a
+/// database with `Database::add_predicate`.
+pub struct Predicate {
+    pub name: &'static str,
+    pub arity: Arity,
+    /// The rows of arguments answering a call, given its arguments with
+    /// their bindings resolved.
+    pub rows: fn(&[Term]) -> Rows,
+}
+
+impl Database {
+    /// Makes `predicate` callable from queries and rules.
+    pub fn add_predicate(&mut self, predicate: Predicate) {
+        let rows = predicate.rows;
+        self.register_rows(predicate.name, predicate.arity, move |args, bindings| {
+            let args: Vec<Term> = args.iter().map(|arg| bindings.resolve(arg)).collect();
+            rows(&args)
+        });
+    }
+    /// Adds each of `facts`, giving how many there were.
+    pub fn add_facts<T: ToFact>(&mut self, facts: impl IntoIterator<Item = T>) -> usize {
+        let mut added = 0;
+        for fact in facts {
+            self.add(fact.to_fact());
+            added += 1;
+        }
+        added
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+
+    struct Manual(&'static str, Vec<u8>);
+
+    impl ToFact for Manual {
+        fn name(&self) -> &str {
+            "manual"
+        }
+        fn args(&self) -> Vec<Term> {
+            vec![self.0.to_term(), self.1.to_term()]
+        }
+    }
+
+    #[test]
+    fn values_become_facts() {
+        let mut db = Database::new();
+        assert_eq!(
+            db.add_facts([Manual("a", vec![1, 2]), Manual("b", vec![])]),
+            2
+        );
+        assert_eq!(answers(&db, "manual(X, [_|_])").unwrap(), ["X = a"]);
+        #[cfg(feature = "derive")]
+        {
+            #[derive(ToFact)]
+            struct Employee {
+                name: String,
+                dept: &'static str,
+                salary: i64,
+            }
+            #[derive(ToFact)]
+            #[fact(name = "reports")]
+            struct ReportsTo(&'static str, &'static str);
+            #[derive(ToFact)]
+            struct HTTPServer;
+            let employees = vec![
+                Employee {
+                    name: "sam".to_string(),
+                    dept: "sales",
+                    salary: 30,
+                },
+                Employee {
+                    name: "olive".to_string(),
+                    dept: "it",
+                    salary: 40,
+                },
+            ];
+            assert_eq!(db.add_facts(&employees), 2);
+            db.add_facts([ReportsTo("olive", "sam")]);
+            db.add_facts([HTTPServer]);
+            assert_eq!(
+                answers(&db, "reports(X, B), employee(B, D, S)").unwrap(),
+                ["X = olive, B = sam, D = sales, S = 30"]
+            );
+            assert_eq!(answers(&db, "http_server").unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn terms_become_values() {
+        let mut db = Database::new();
+        db.consult("owns(sam, [spinach, pipe], 30 - true).")
+            .unwrap();
+        let query = parse_query("owns(Who, Things, Pair), Missing = _").unwrap();
+        let solutions = db.satisfy_all(query.goal.clone());
+        let solution = query.solution(&solutions[0]);
+        assert_eq!(solution.get_as::<String>("Who").as_deref(), Some("sam"));
+        assert_eq!(
+            solution.get_as::<Vec<String>>("Things"),
+            Some(vec!["spinach".to_string(), "pipe".to_string()])
+        );
+        assert_eq!(solution.get_as::<(u8, bool)>("Pair"), Some((30, true)));
+        assert_eq!(solution.get_as::<Option<i64>>("Missing"), Some(None));
+        assert_eq!(solution.get_as::<i64>("Who"), None);
+        assert_eq!(solution.get_as::<i64>("Nobody"), None);
+        let triple = (1, "two".to_string(), vec!['c']);
+        assert_eq!(triple.to_term().pretty().to_string(), "1, two, [c]");
+        assert_eq!(
+            <(i32, String, Vec<char>)>::from_term(&triple.to_term()),
+            Some(triple)
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    mod predicates {
+        use crate::predicate;
+
+        #[predicate]
+        pub fn double(n: i64) -> i64 {
+            n * 2
+        }
+
+        #[predicate(name = "even")]
+        pub fn is_even(n: i64) -> bool {
+            n % 2 == 0
+        }
+
+        #[predicate]
+        pub fn divisors(n: u32) -> impl Iterator<Item = u32> {
+            (1..=n).filter(move |d| n.is_multiple_of(*d))
+        }
+
+        #[predicate]
+        pub fn shout(text: String) -> Option<String> {
+            (!text.is_empty()).then(|| text.to_uppercase())
+        }
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn functions_become_predicates() {
+        use predicates::*;
+        let mut db = Database::new();
+        db.add_predicate(double_predicate());
+        db.add_predicate(is_even_predicate());
+        db.add_predicate(divisors_predicate());
+        db.add_predicate(shout_predicate());
+        db.consult("quadruple(X, Y) :- double(X, Z), double(Z, Y).")
+            .unwrap();
+        assert_eq!(answers(&db, "quadruple(3, Y)").unwrap(), ["Y = 12"]);
+        assert_eq!(answers(&db, "double(4, 8), even(4)").unwrap().len(), 1);
+        assert!(answers(&db, "double(X, 8)").unwrap().is_empty());
+        assert!(answers(&db, "even(3)").unwrap().is_empty());
+        assert_eq!(answers(&db, "divisors(12, D), even(D)").unwrap().len(), 4);
+        assert_eq!(answers(&db, "shout(hi, X)").unwrap(), ["X = 'HI'"]);
+        assert!(answers(&db, "shout('', X)").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+//! Loading facts from CSV: `Database::load_csv` adds a fact for each row of
+//! a file, typing each column as its schema says. Rows are read one at a
+//! time, so a file needn't fit in memory, only its facts. Fields may be
+//! quoted, with `""` for a quote inside one, and quoted fields may span
+//! lines.
+
+use crate::{Atom, Database, Functor, Term};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::mem;
+use std::path::Path;
+
+/// What a CSV column becomes in each fact.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    Atom,
+    Integer,
+    String,
+    /// Left out of the facts.
+    Skip,
+}
+
+/// The columns of a CSV file, and how it's laid out.
+#[derive(Clone, Debug)]
+pub struct CsvSchema {
+    columns: Vec<Column>,
+    header: bool,
+    separator: char,
+}
+
+impl CsvSchema {
+    /// Comma-separated columns, without a header.
+    pub fn new(columns: &[Column]) -> Self {
+        Self {
+            columns: columns.to_vec(),
+            header: false,
+            separator: ',',
+        }
+    }
+    /// Skips the first row, a header naming the columns.
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+    pub fn separator(mut self, separator: char) -> Self {
+        self.separator = separator;
+        self
+    }
+}
+
+impl Database {
+    /// Adds `name(Column...)` for each row of the CSV file at `path`, giving
+    /// how many facts were added. A row with the wrong number of fields or a
+    /// field that isn't an integer where one is wanted is an `InvalidData`
+    /// error, once the rows before it are added.
+    pub fn load_csv<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        name: &str,
+        schema: &CsvSchema,
+    ) -> io::Result<usize> {
+        self.read_csv(BufReader::new(File::open(path)?), name, schema)
+    }
+    /// Like `load_csv`, reading the rows from `reader`.
+    pub fn read_csv<R: BufRead>(
+        &mut self,
+        mut reader: R,
+        name: &str,
+        schema: &CsvSchema,
+    ) -> io::Result<usize> {
+        let name = Atom::new(name);
+        let mut record = String::new();
+        let mut fields = Vec::new();
+        let (mut line, mut added) = (0, 0);
+        loop {
+            record.clear();
+            let first = line + 1;
+            // a record is complete once its quotes are
+            loop {
+                if reader.read_line(&mut record)? == 0 {
+                    break;
+                }
+                line += 1;
+                if record.matches('"').count().is_multiple_of(2) {
+                    break;
+                }
+            }
+            if record.is_empty() {
+                return Ok(added);
+            }
+            let record = record.trim_end_matches(['\r', '\n']);
+            if (schema.header && first == 1) || record.is_empty() {
+                continue;
+            }
+            split(record, schema.separator, &mut fields);
+            let invalid = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", first, message),
+                )
+            };
+            if fields.len() != schema.columns.len() {
+                let message = format!(
+                    "{} fields where the schema has {} columns",
+                    fields.len(),
+                    schema.columns.len()
+                );
+                return Err(invalid(message));
+            }
+            let mut args = Vec::with_capacity(fields.len());
+            for (n, (field, column)) in fields.iter_mut().zip(&schema.columns).enumerate() {
+                args.push(match column {
+                    Column::Atom => Term::Atom(Atom::new(field)),
+                    Column::Integer => match field.trim().parse() {
+                        Ok(n) => Term::Integer(n),
+                        Err(_) => {
+                            let message = format!("column {}: {} isn't an integer", n + 1, field);
+                            return Err(invalid(message));
+                        }
+                    },
+                    Column::String => Term::String(mem::take(field).into()),
+                    Column::Skip => continue,
+                });
+            }
+            self.add(Functor::new_fact(name, args));
+            added += 1;
+        }
+    }
+}
+
+// the fields of a record, unquoted
+fn split(record: &str, separator: char, fields: &mut Vec<String>) {
+    fields.clear();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => fields.push(mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+
+    #[test]
+    fn rows_become_facts() {
+        let csv = "name,age,note,id\r\n\
+            sam,30,\"likes \"\"quotes\"\", commas\",1\r\n\
+            olive, 25 ,\"two\nlines\",2\n\
+            \n\
+            popeye,40,,3\n";
+        let schema = CsvSchema::new(&[Column::Atom, Column::Integer, Column::String, Column::Skip])
+            .header(true);
+        let mut db = Database::new();
+        assert_eq!(db.read_csv(csv.as_bytes(), "person", &schema).unwrap(), 3);
+        assert_eq!(
+            answers(&db, "person(X, A, _), A > 28").unwrap(),
+            ["X = sam, A = 30", "X = popeye, A = 40"]
+        );
+        assert_eq!(
+            answers(&db, "person(sam, _, N)").unwrap(),
+            [r#"N = "likes \"quotes\", commas""#]
+        );
+        assert_eq!(
+            answers(&db, "person(olive, _, N)").unwrap(),
+            [r#"N = "two\nlines""#]
+        );
+        let error = db
+            .read_csv(
+                "a,1\nb,x\n".as_bytes(),
+                "p",
+                &CsvSchema::new(&[Column::Atom, Column::Integer]),
+            )
+            .unwrap_err();
+        assert_eq!(error.to_string(), "line 2: column 2: x isn't an integer");
+        assert_eq!(answers(&db, "p(X, _)").unwrap().len(), 1);
+        let tabs = CsvSchema::new(&[Column::Atom, Column::Atom]).separator('\t');
+        assert_eq!(db.read_csv("a\tb,c\n".as_bytes(), "q", &tabs).unwrap(), 1);
+        assert_eq!(answers(&db, "q(a, X)").unwrap(), ["X = 'b,c'"]);
+    }
+}
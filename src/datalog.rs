@@ -0,0 +1,1006 @@
+//! Bottom-up evaluation for Datalog programs: `Database::materialize` finds
+//! every fact the rules derive from the facts, a set at a time, rather than
+//! proving goals one at a time by resolution. Rounds are semi-naive: each
+//! joins the rules against at least one fact new in the round before, so a
+//! derivation is never repeated, and the last round finds nothing new.
+//!
+//! A Datalog program's arguments are atoms, numbers, strings and variables,
+//! never compound terms; its facts are ground; and each variable of a rule
+//! appears in a goal of its body that calls a predicate. Besides predicates,
+//! bodies can compare terms with `=`, `\=` and the arithmetic comparisons,
+//! and negate goals with `\+`.
+//!
+//! Negation must be stratified: no predicate may depend on the negation of
+//! one that depends on it. The predicates are then evaluated a stratum at a
+//! time, each after every predicate it negates is complete.
+
+use crate::compat::{HashMap, HashSet};
+use crate::{builtins, Alias, Arity, Atom, Database, Functor, Term, VariableName};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+
+/// Why a program can't be evaluated bottom-up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DatalogError {
+    /// A clause of the predicate `name/arity` isn't Datalog.
+    NotDatalog { predicate: String, reason: String },
+    /// A variable of a clause of `name/arity` isn't bound by any goal of its
+    /// body calling a predicate.
+    Unsafe { predicate: String, variable: String },
+    /// `name/arity` depends on the negation of `negated`, which depends on
+    /// `name/arity` in turn, so the program has no strata.
+    NotStratifiable { predicate: String, negated: String },
+}
+
+impl fmt::Display for DatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatalogError::NotDatalog { predicate, reason } => {
+                write!(f, "{} isn't Datalog: {}", predicate, reason)
+            }
+            DatalogError::Unsafe {
+                predicate,
+                variable,
+            } => write!(
+                f,
+                "{} is unsafe: {} isn't bound by a goal calling a predicate",
+                predicate, variable
+            ),
+            DatalogError::NotStratifiable { predicate, negated } => write!(
+                f,
+                "{} isn't stratifiable: it depends on \\+ {}, which depends on it",
+                predicate, negated
+            ),
+        }
+    }
+}
+
+impl core::error::Error for DatalogError {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Constant {
+    Atom(Atom),
+    Integer(i64),
+    String(Arc<str>),
+}
+
+impl Constant {
+    fn to_term(&self) -> Term {
+        match self {
+            Constant::Atom(a) => Term::Atom(*a),
+            Constant::Integer(n) => Term::Integer(*n),
+            Constant::String(text) => Term::String(text.clone()),
+        }
+    }
+}
+
+type Tuple = Vec<Constant>;
+type Key = (Atom, Arity);
+
+#[derive(Clone, Debug)]
+enum Arg {
+    Variable(usize),
+    Constant(Constant),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn named(name: &str) -> Option<Self> {
+        Some(match name {
+            "=" | "==" | "=:=" => Comparison::Equal,
+            "\\=" | "\\==" | "=\\=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "=<" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return None,
+        })
+    }
+    // integers compare by value; anything else only as equal or not
+    fn holds(self, x: &Constant, y: &Constant) -> bool {
+        match (self, x, y) {
+            (Comparison::Equal, _, _) => x == y,
+            (Comparison::NotEqual, _, _) => x != y,
+            (_, Constant::Integer(x), Constant::Integer(y)) => match self {
+                Comparison::Less => x < y,
+                Comparison::Greater => x > y,
+                Comparison::LessOrEqual => x <= y,
+                _ => x >= y,
+            },
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Literal {
+    Relation(Key, Vec<Arg>),
+    // `\+ name(Args...)`, holding if no tuple matches
+    Negated(Key, Vec<Arg>),
+    Compare(Comparison, Arg, Arg),
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    head: (Key, Vec<Arg>),
+    // goals calling predicates first, then negations and comparisons
+    body: Vec<Literal>,
+    variables: usize,
+}
+
+#[derive(Default)]
+struct Relation {
+    // in the order derived
+    tuples: Vec<Tuple>,
+    set: HashSet<Tuple>,
+}
+
+impl Relation {
+    fn insert(&mut self, tuple: Tuple) -> bool {
+        if self.set.contains(&tuple) {
+            return false;
+        }
+        self.set.insert(tuple.clone());
+        self.tuples.push(tuple);
+        true
+    }
+}
+
+/// Every fact a Datalog program holds or derives.
+pub struct Model {
+    relations: HashMap<Key, Relation>,
+    // the predicates, in the order the program defines them
+    order: Vec<Key>,
+}
+
+impl Model {
+    /// The facts of `name/arity`, in the order they were derived.
+    pub fn relation(&self, name: &str, arity: Arity) -> Vec<Functor> {
+        let key = (Atom::new(name), arity);
+        self.relations
+            .get(&key)
+            .map_or(Vec::new(), |relation| facts(&key, relation))
+    }
+    /// Every fact, predicate by predicate, ready for `Database::from_rules`.
+    pub fn facts(&self) -> Vec<Functor> {
+        self.order
+            .iter()
+            .flat_map(|key| facts(key, &self.relations[key]))
+            .collect()
+    }
+    /// How many facts there are.
+    pub fn len(&self) -> usize {
+        self.relations.values().map(|r| r.tuples.len()).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn facts((name, _): &Key, relation: &Relation) -> Vec<Functor> {
+    relation
+        .tuples
+        .iter()
+        .map(|tuple| Functor::new_fact(*name, tuple.iter().map(Constant::to_term).collect()))
+        .collect()
+}
+
+impl Database {
+    /// The facts of the program with those its rules derive, found bottom-up.
+    /// The program is made of the predicates a query could call, and must be
+    /// Datalog.
+    pub fn materialize(&self) -> Result<Model, DatalogError> {
+        let program = program(self)?;
+        let mut relations = program.relations();
+        for stratum in strata(&program.rules)? {
+            evaluate(&stratum, &mut relations);
+        }
+        Ok(Model {
+            relations,
+            order: program.predicates,
+        })
+    }
+    /// The instances of `goal` that hold, found bottom-up but only deriving
+    /// facts that answering `goal` needs: the rules are rewritten with magic
+    /// sets, which pass the bindings of `goal` down to the goals it calls.
+    pub fn materialize_query(&self, goal: Functor) -> Result<Vec<Functor>, DatalogError> {
+        let program = program(self)?;
+        strata(&program.rules)?;
+        // the query's constants, and the argument each variable first appears at
+        let mut variables: Vec<Alias> = Vec::new();
+        let mut pattern = Vec::new();
+        for term in &goal.args {
+            pattern.push(match constant(term) {
+                Ok(constant) => Ok(constant),
+                Err(Some(reason)) => return Err(not_datalog(&goal, reason)),
+                Err(None) => {
+                    let Term::Variable(v) = term else {
+                        unreachable!("only variables aren't constants or compound terms")
+                    };
+                    Err(match variables.iter().position(|alias| *alias == v.alias) {
+                        Some(n) => n,
+                        None => {
+                            variables.push(v.alias);
+                            variables.len() - 1
+                        }
+                    })
+                }
+            });
+        }
+        let query = (
+            (goal.name, goal.arity()),
+            pattern.iter().map(Result::is_ok).collect::<Vec<_>>(),
+        );
+        let rules = magic(&program.rules, &query);
+        let mut relations = program.relations();
+        let seed = pattern.iter().filter_map(|c| c.clone().ok()).collect();
+        relations.entry(magic_key(&query)).or_default().insert(seed);
+        for stratum in strata(&rules)? {
+            evaluate(&stratum, &mut relations);
+        }
+        let Some(answers) = relations.get(&adorned_key(&query)) else {
+            return Ok(Vec::new());
+        };
+        let matches = |tuple: &Tuple| {
+            let mut first: Vec<&Constant> = Vec::new();
+            tuple.iter().zip(&pattern).all(|(c, wanted)| match wanted {
+                Ok(wanted) => c == wanted,
+                Err(n) if *n < first.len() => first[*n] == c,
+                Err(_) => {
+                    first.push(c);
+                    true
+                }
+            })
+        };
+        Ok(answers
+            .tuples
+            .iter()
+            .filter(|tuple| matches(tuple))
+            .map(|tuple| {
+                Functor::new_fact(goal.name, tuple.iter().map(Constant::to_term).collect())
+            })
+            .collect())
+    }
+}
+
+// the rules and facts of the predicates callable from `user`
+struct Program {
+    rules: Vec<Rule>,
+    facts: Vec<(Key, Tuple)>,
+    // in the order they're defined
+    predicates: Vec<Key>,
+}
+
+impl Program {
+    // the facts, with an empty relation for every predicate
+    fn relations(&self) -> HashMap<Key, Relation> {
+        let mut relations: HashMap<Key, Relation> = HashMap::new();
+        for (key, tuple) in &self.facts {
+            relations.entry(*key).or_default().insert(tuple.clone());
+        }
+        for key in &self.predicates {
+            relations.entry(*key).or_default();
+        }
+        relations
+    }
+}
+
+fn program(db: &Database) -> Result<Program, DatalogError> {
+    let mut rules = Vec::new();
+    let mut facts = Vec::new();
+    let predicates = db.predicates();
+    for (name, arity) in &predicates {
+        for clause in db.clauses(name.as_str(), *arity) {
+            if clause.body.is_empty() {
+                let tuple = clause
+                    .args
+                    .iter()
+                    .map(|arg| match constant(arg) {
+                        Ok(constant) => Ok(constant),
+                        Err(Some(reason)) => Err(not_datalog(clause, reason)),
+                        Err(None) => Err(not_datalog(clause, "a fact has a variable".into())),
+                    })
+                    .collect::<Result<_, _>>()?;
+                facts.push(((*name, *arity), tuple));
+            } else {
+                rules.push(rule(clause)?);
+            }
+        }
+    }
+    Ok(Program {
+        rules,
+        facts,
+        predicates,
+    })
+}
+
+// an argument that isn't a variable as a constant, `Err(None)` for a
+// variable and `Err(Some(reason))` for anything else
+fn constant(term: &Term) -> Result<Constant, Option<String>> {
+    match term {
+        Term::Atom(a) => Ok(Constant::Atom(*a)),
+        Term::Integer(n) => Ok(Constant::Integer(*n)),
+        Term::String(text) => Ok(Constant::String(text.clone())),
+        Term::Variable(_) => Err(None),
+        Term::Functor(_) => Err(Some(format!("{} is a compound term", term.pretty()))),
+    }
+}
+
+fn indicator(clause: &Functor) -> String {
+    format!("{}/{}", clause.name, clause.arity())
+}
+
+fn not_datalog(clause: &Functor, reason: String) -> DatalogError {
+    DatalogError::NotDatalog {
+        predicate: indicator(clause),
+        reason,
+    }
+}
+
+// the names of the control constructs the solver runs itself
+const CONTROL: [&str; 11] = [
+    ",", ";", "->", "call", "\\+", "catch", ">>", "maplist", "foldl", "distinct", ":",
+];
+
+// whether `name/arity` can be a predicate of a Datalog program
+fn callable(name: &str, arity: Arity) -> bool {
+    !CONTROL.contains(&name)
+        && Comparison::named(name).is_none()
+        && builtins::lookup(&Atom::new(name), arity).is_none()
+}
+
+fn rule(clause: &Functor) -> Result<Rule, DatalogError> {
+    let mut variables: Vec<(Alias, String)> = Vec::new();
+    let mut arg = |term: &Term| -> Result<Arg, DatalogError> {
+        match constant(term) {
+            Ok(constant) => Ok(Arg::Constant(constant)),
+            Err(Some(reason)) => Err(not_datalog(clause, reason)),
+            Err(None) => {
+                let Term::Variable(v) = term else {
+                    unreachable!("only variables aren't constants or compound terms")
+                };
+                let n = match variables.iter().position(|(alias, _)| *alias == v.alias) {
+                    Some(n) => n,
+                    None => {
+                        let shown = match &v.name {
+                            VariableName::Anonymous => "_".to_string(),
+                            VariableName::Name(_) => v.to_string(),
+                        };
+                        variables.push((v.alias, shown));
+                        variables.len() - 1
+                    }
+                };
+                Ok(Arg::Variable(n))
+            }
+        }
+    };
+    let head: Vec<Arg> = clause.args.iter().map(&mut arg).collect::<Result<_, _>>()?;
+    let mut relations = Vec::new();
+    let mut filters = Vec::new();
+    for goal in &clause.body {
+        let (name, arity) = (goal.name.as_str(), goal.arity());
+        if let (Some(comparison), 2) = (Comparison::named(name), arity) {
+            let (x, y) = (arg(&goal.args[0])?, arg(&goal.args[1])?);
+            filters.push(Literal::Compare(comparison, x, y));
+        } else if name == "true" && arity == 0 {
+        } else if name == "\\+" && arity == 1 {
+            let (negated, args) = match &goal.args[0] {
+                Term::Atom(a) => (*a, Vec::new()),
+                Term::Functor(f) => (f.name, f.args.clone()),
+                other => {
+                    let reason = format!("its body negates {}", other.pretty());
+                    return Err(not_datalog(clause, reason));
+                }
+            };
+            if !callable(negated.as_str(), args.len()) {
+                let reason = format!("its body negates {}/{}", negated, args.len());
+                return Err(not_datalog(clause, reason));
+            }
+            let args: Vec<Arg> = args.iter().map(&mut arg).collect::<Result<_, _>>()?;
+            filters.push(Literal::Negated((negated, args.len()), args));
+        } else if !callable(name, arity) {
+            let reason = format!("its body calls {}/{}", goal.name, arity);
+            return Err(not_datalog(clause, reason));
+        } else {
+            let args = goal.args.iter().map(&mut arg).collect::<Result<_, _>>()?;
+            relations.push(Literal::Relation((goal.name, arity), args));
+        }
+    }
+    // range restriction: every variable is bound by a goal calling a predicate
+    let mut bound = vec![false; variables.len()];
+    for literal in &relations {
+        if let Literal::Relation(_, args) = literal {
+            variables_of(args).for_each(|n| bound[n] = true);
+        }
+    }
+    if let Some(n) = bound.iter().position(|bound| !bound) {
+        return Err(DatalogError::Unsafe {
+            predicate: indicator(clause),
+            variable: variables[n].1.clone(),
+        });
+    }
+    relations.extend(filters);
+    Ok(Rule {
+        head: ((clause.name, clause.arity()), head),
+        body: relations,
+        variables: variables.len(),
+    })
+}
+
+fn key_indicator((name, arity): &Key) -> String {
+    format!("{}/{}", name, arity)
+}
+
+// the rules grouped into strata, lowest first: a predicate's stratum is at
+// least that of each predicate it calls, and above that of each it negates
+fn strata(rules: &[Rule]) -> Result<Vec<Vec<&Rule>>, DatalogError> {
+    let mut calls: HashMap<Key, Vec<Key>> = HashMap::new();
+    for rule in rules {
+        let called = calls.entry(rule.head.0).or_default();
+        for literal in &rule.body {
+            if let Literal::Relation(key, _) | Literal::Negated(key, _) = literal {
+                called.push(*key);
+            }
+        }
+    }
+    let reaches = |from: Key, to: Key| {
+        let mut seen: HashSet<Key> = HashSet::from([from]);
+        let mut pending = vec![from];
+        while let Some(key) = pending.pop() {
+            if key == to {
+                return true;
+            }
+            for next in calls.get(&key).into_iter().flatten() {
+                if seen.insert(*next) {
+                    pending.push(*next);
+                }
+            }
+        }
+        false
+    };
+    for rule in rules {
+        for literal in &rule.body {
+            if let Literal::Negated(key, _) = literal {
+                if reaches(*key, rule.head.0) {
+                    return Err(DatalogError::NotStratifiable {
+                        predicate: key_indicator(&rule.head.0),
+                        negated: key_indicator(key),
+                    });
+                }
+            }
+        }
+    }
+    // without cycles through negation, raising strata until they settle ends
+    let mut stratum: HashMap<Key, usize> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for rule in rules {
+            let lowest = rule
+                .body
+                .iter()
+                .map(|literal| match literal {
+                    Literal::Relation(key, _) => stratum.get(key).copied().unwrap_or(0),
+                    Literal::Negated(key, _) => stratum.get(key).copied().unwrap_or(0) + 1,
+                    Literal::Compare(..) => 0,
+                })
+                .max()
+                .unwrap_or(0);
+            let head = stratum.entry(rule.head.0).or_insert(0);
+            if *head < lowest {
+                *head = lowest;
+                changed = true;
+            }
+        }
+    }
+    let mut strata: Vec<Vec<&Rule>> = Vec::new();
+    for rule in rules {
+        let n = stratum[&rule.head.0];
+        if strata.len() <= n {
+            strata.resize_with(n + 1, Vec::new);
+        }
+        strata[n].push(rule);
+    }
+    Ok(strata)
+}
+
+// `name/arity` called with the arguments marked `true` bound
+type Adorned = (Key, Vec<bool>);
+
+// `bf`-style: which arguments are bound and which free
+fn adornment(bound: &[bool]) -> String {
+    bound.iter().map(|&b| if b { 'b' } else { 'f' }).collect()
+}
+
+// the predicate holding the answers to an adorned predicate's calls
+fn adorned_key(((name, arity), bound): &Adorned) -> Key {
+    (Atom::new(&format!("{}${}", name, adornment(bound))), *arity)
+}
+
+// the predicate holding the bound arguments an adorned predicate is called
+// with
+fn magic_key(((name, _), bound): &Adorned) -> Key {
+    let arity = bound.iter().filter(|&&b| b).count();
+    (
+        Atom::new(&format!("$magic${}${}", name, adornment(bound))),
+        arity,
+    )
+}
+
+fn variables_of(args: &[Arg]) -> impl Iterator<Item = usize> + '_ {
+    args.iter().filter_map(|arg| match arg {
+        Arg::Variable(n) => Some(*n),
+        Arg::Constant(_) => None,
+    })
+}
+
+fn bound_args(args: &[Arg], bound: &[bool]) -> Vec<Arg> {
+    args.iter()
+        .zip(bound)
+        .filter(|(_, &b)| b)
+        .map(|(arg, _)| arg.clone())
+        .collect()
+}
+
+// `rules` rewritten to answer `query`: an adorned predicate's rules only
+// fire for the bindings in its magic predicate, and each goal a rule calls
+// adds its bindings, given those of the goals before it, to its own. What's
+// negated is left as it was, and evaluated in full.
+fn magic(rules: &[Rule], query: &Adorned) -> Vec<Rule> {
+    let defined: HashSet<Key> = rules.iter().map(|rule| rule.head.0).collect();
+    let mut rewritten = Vec::new();
+    let mut negated = Vec::new();
+    let mut pending = vec![query.clone()];
+    let mut done: HashSet<Adorned> = HashSet::from([query.clone()]);
+    while let Some(adorned) = pending.pop() {
+        let (key, bound) = &adorned;
+        // the predicate's facts answer its calls too
+        let all: Vec<Arg> = (0..key.1).map(Arg::Variable).collect();
+        rewritten.push(Rule {
+            head: (adorned_key(&adorned), all.clone()),
+            body: vec![
+                Literal::Relation(magic_key(&adorned), bound_args(&all, bound)),
+                Literal::Relation(*key, all),
+            ],
+            variables: key.1,
+        });
+        for rule in rules.iter().filter(|rule| rule.head.0 == *key) {
+            let head = bound_args(&rule.head.1, bound);
+            let mut known: HashSet<usize> = variables_of(&head).collect();
+            let mut body = vec![Literal::Relation(magic_key(&adorned), head.clone())];
+            for literal in &rule.body {
+                let Literal::Relation(called, args) = literal else {
+                    if let Literal::Negated(key, _) = literal {
+                        negated.push(*key);
+                    }
+                    body.push(literal.clone());
+                    continue;
+                };
+                if defined.contains(called) {
+                    let pattern: Vec<bool> = args
+                        .iter()
+                        .map(|arg| match arg {
+                            Arg::Constant(_) => true,
+                            Arg::Variable(n) => known.contains(n),
+                        })
+                        .collect();
+                    let call = (*called, pattern);
+                    rewritten.push(Rule {
+                        head: (magic_key(&call), bound_args(args, &call.1)),
+                        body: body.clone(),
+                        variables: rule.variables,
+                    });
+                    body.push(Literal::Relation(adorned_key(&call), args.clone()));
+                    if done.insert(call.clone()) {
+                        pending.push(call);
+                    }
+                } else {
+                    body.push(literal.clone());
+                }
+                known.extend(variables_of(args));
+            }
+            rewritten.push(Rule {
+                head: (adorned_key(&adorned), rule.head.1.clone()),
+                body,
+                variables: rule.variables,
+            });
+        }
+    }
+    // the predicates negated, and those they depend on
+    let mut needed = HashSet::new();
+    while let Some(key) = negated.pop() {
+        if needed.insert(key) {
+            for rule in rules.iter().filter(|rule| rule.head.0 == key) {
+                for literal in &rule.body {
+                    if let Literal::Relation(key, _) | Literal::Negated(key, _) = literal {
+                        negated.push(*key);
+                    }
+                }
+            }
+        }
+    }
+    rewritten.extend(
+        rules
+            .iter()
+            .filter(|rule| needed.contains(&rule.head.0))
+            .cloned(),
+    );
+    rewritten
+}
+
+// the relation a goal joins against: the whole of it, or only the tuples
+// new in the last round
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Version {
+    Full,
+    Delta,
+}
+
+// indexes over the relations as they stand in one round, by predicate,
+// version and the columns looked up
+type Indexes = HashMap<(Key, Version, Vec<usize>), HashMap<Tuple, Vec<usize>>>;
+
+// semi-naive iteration until no rule derives anything new
+fn evaluate(rules: &[&Rule], relations: &mut HashMap<Key, Relation>) {
+    // the first round takes every fact as new
+    let mut delta: HashMap<Key, Relation> = HashMap::new();
+    for (key, relation) in relations.iter() {
+        let fresh = delta.entry(*key).or_default();
+        for tuple in &relation.tuples {
+            fresh.insert(tuple.clone());
+        }
+    }
+    let mut first = true;
+    loop {
+        let mut derived: Vec<(Key, Tuple)> = Vec::new();
+        let mut indexes = Indexes::new();
+        for rule in rules {
+            let joins: Vec<usize> = rule
+                .body
+                .iter()
+                .enumerate()
+                .filter(|(_, literal)| matches!(literal, Literal::Relation(..)))
+                .map(|(i, _)| i)
+                .collect();
+            if joins.is_empty() && first {
+                join(
+                    rule,
+                    0,
+                    None,
+                    relations,
+                    &delta,
+                    &mut indexes,
+                    &mut vec![None; rule.variables],
+                    &mut derived,
+                );
+            }
+            for i in joins {
+                let mut bindings = vec![None; rule.variables];
+                join(
+                    rule,
+                    0,
+                    Some(i),
+                    relations,
+                    &delta,
+                    &mut indexes,
+                    &mut bindings,
+                    &mut derived,
+                );
+            }
+        }
+        first = false;
+        let mut fresh: HashMap<Key, Relation> = HashMap::new();
+        for (key, tuple) in derived {
+            if relations.entry(key).or_default().insert(tuple.clone()) {
+                fresh.entry(key).or_default().insert(tuple);
+            }
+        }
+        if fresh.is_empty() {
+            return;
+        }
+        delta = fresh;
+    }
+}
+
+// the value of `arg` under `bindings`, if it has one
+fn value<'a>(arg: &'a Arg, bindings: &'a [Option<Constant>]) -> Option<&'a Constant> {
+    match arg {
+        Arg::Constant(constant) => Some(constant),
+        Arg::Variable(n) => bindings[*n].as_ref(),
+    }
+}
+
+// joins the goals of `rule`'s body from the `at`th on, using the last
+// round's new tuples for the `delta`th and every tuple for the others, and
+// adds the head of each way through to `derived`
+#[allow(clippy::too_many_arguments)]
+fn join(
+    rule: &Rule,
+    at: usize,
+    delta: Option<usize>,
+    relations: &HashMap<Key, Relation>,
+    new: &HashMap<Key, Relation>,
+    indexes: &mut Indexes,
+    bindings: &mut Vec<Option<Constant>>,
+    derived: &mut Vec<(Key, Tuple)>,
+) {
+    let Some(literal) = rule.body.get(at) else {
+        let (key, args) = &rule.head;
+        let tuple = args
+            .iter()
+            .map(|arg| {
+                value(arg, bindings)
+                    .cloned()
+                    .expect("rules are range restricted")
+            })
+            .collect();
+        derived.push((*key, tuple));
+        return;
+    };
+    match literal {
+        Literal::Negated(key, args) => {
+            let tuple: Tuple = args
+                .iter()
+                .map(|arg| {
+                    value(arg, bindings)
+                        .cloned()
+                        .expect("rules are range restricted")
+                })
+                .collect();
+            // a lower stratum's, so complete
+            if !relations.get(key).is_some_and(|r| r.set.contains(&tuple)) {
+                join(
+                    rule,
+                    at + 1,
+                    delta,
+                    relations,
+                    new,
+                    indexes,
+                    bindings,
+                    derived,
+                );
+            }
+        }
+        Literal::Compare(comparison, x, y) => {
+            let (x, y) = (value(x, bindings), value(y, bindings));
+            if x.zip(y).is_some_and(|(x, y)| comparison.holds(x, y)) {
+                join(
+                    rule,
+                    at + 1,
+                    delta,
+                    relations,
+                    new,
+                    indexes,
+                    bindings,
+                    derived,
+                );
+            }
+        }
+        Literal::Relation(key, args) => {
+            let version = if delta == Some(at) {
+                Version::Delta
+            } else {
+                Version::Full
+            };
+            let Some(relation) = (match version {
+                Version::Full => relations.get(key),
+                Version::Delta => new.get(key),
+            }) else {
+                return;
+            };
+            let columns: Vec<usize> = (0..args.len())
+                .filter(|&i| value(&args[i], bindings).is_some())
+                .collect();
+            let looked_up: Tuple = columns
+                .iter()
+                .map(|&i| value(&args[i], bindings).cloned().expect("bound column"))
+                .collect();
+            let index = indexes
+                .entry((*key, version, columns.clone()))
+                .or_insert_with(|| {
+                    let mut index: HashMap<Tuple, Vec<usize>> = HashMap::new();
+                    for (n, tuple) in relation.tuples.iter().enumerate() {
+                        let at: Tuple = columns.iter().map(|&i| tuple[i].clone()).collect();
+                        index.entry(at).or_default().push(n);
+                    }
+                    index
+                });
+            let Some(matches) = index.get(&looked_up).cloned() else {
+                return;
+            };
+            for n in matches {
+                let tuple = &relation.tuples[n];
+                let mut bound = Vec::new();
+                let mut consistent = true;
+                for (arg, constant) in args.iter().zip(tuple) {
+                    if let Arg::Variable(v) = arg {
+                        match &bindings[*v] {
+                            Some(value) if value != constant => consistent = false,
+                            Some(_) => {}
+                            None => {
+                                bindings[*v] = Some(constant.clone());
+                                bound.push(*v);
+                            }
+                        }
+                    }
+                }
+                if consistent {
+                    join(
+                        rule,
+                        at + 1,
+                        delta,
+                        relations,
+                        new,
+                        indexes,
+                        bindings,
+                        derived,
+                    );
+                }
+                for v in bound {
+                    bindings[v] = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn program(text: &str) -> Database {
+        let mut db = Database::new();
+        db.consult(text).unwrap();
+        db
+    }
+
+    fn shown(facts: Vec<Functor>) -> Vec<String> {
+        let mut shown: Vec<String> = facts.iter().map(|f| f.to_string()).collect();
+        shown.sort();
+        shown
+    }
+
+    #[test]
+    fn derives_the_transitive_closure() {
+        let db = program(
+            "edge(a, b). edge(b, c). edge(c, d). edge(d, b).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), edge(Y, Z).
+            cycle(X) :- path(X, X).
+            far(X, Y) :- path(X, Y), X \\= Y, Y \\= b.",
+        );
+        let model = db.materialize().unwrap();
+        assert_eq!(model.relation("path", 2).len(), 12);
+        assert_eq!(
+            shown(model.relation("cycle", 1)),
+            ["cycle(b)", "cycle(c)", "cycle(d)"]
+        );
+        assert_eq!(model.relation("far", 2).len(), 6);
+        assert_eq!(model.len(), 4 + 12 + 3 + 6);
+        // the left-recursive rules loop when solved top-down; their model
+        // answers at once
+        let facts = Database::from_rules(model.facts());
+        let query = parse_query("path(a, X)").unwrap();
+        assert_eq!(facts.satisfy_all(query.goal).len(), 3);
+    }
+
+    #[test]
+    fn compares_numbers_and_joins_many_goals() {
+        let db = program(
+            "age(sam, 30). age(olive, 25). age(popeye, 40).
+            friends(sam, olive). friends(olive, popeye).
+            older_friend(X, Y) :- friends(X, Y), age(X, A), age(Y, B), A < B.
+            adult :- age(_, A), A >= 18.",
+        );
+        let model = db.materialize().unwrap();
+        assert_eq!(
+            shown(model.relation("older_friend", 2)),
+            ["older_friend(olive, popeye)"]
+        );
+        assert_eq!(shown(model.relation("adult", 0)), ["adult"]);
+    }
+
+    #[test]
+    fn negation_is_evaluated_a_stratum_at_a_time() {
+        let db = program(
+            "node(a). node(b). node(c). node(d).
+            edge(a, b). edge(b, c).
+            reach(X, Y) :- edge(X, Y).
+            reach(X, Z) :- reach(X, Y), edge(Y, Z).
+            unreachable(X, Y) :- node(X), node(Y), \\+ reach(X, Y), X \\= Y.
+            isolated(X) :- node(X), \\+ linked(X).
+            linked(X) :- unreachable(X, _), \\+ lonely.
+            linked(X) :- edge(X, _).
+            linked(X) :- edge(_, X).",
+        );
+        let model = db.materialize().unwrap();
+        assert_eq!(model.relation("unreachable", 2).len(), 12 - 3);
+        assert_eq!(shown(model.relation("isolated", 1)), Vec::<String>::new());
+        let db = program(
+            "p(a). q(a) :- p(a), \\+ r(a). r(X) :- q(X).
+            s :- \\+ s.",
+        );
+        assert_eq!(
+            db.materialize().err().unwrap().to_string(),
+            "q/1 isn't stratifiable: it depends on \\+ r/1, which depends on it"
+        );
+    }
+
+    #[test]
+    fn magic_sets_answer_a_query_as_the_whole_model_would() {
+        let mut text: String = (0..20)
+            .map(|n| format!("edge({}, {}). ", n, n + 1))
+            .collect();
+        text.push_str(
+            "edge(20, 0). edge(100, 101).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), edge(Y, Z).
+            between(X, Y, Z) :- path(X, Y), path(Y, Z), Y \\= 100.
+            leads_on(X) :- edge(X, _).
+            dead_end(X) :- path(_, X), \\+ leads_on(X).",
+        );
+        let db = program(&text);
+        let model = db.materialize().unwrap();
+        let answers = |query: &str| {
+            let goal = parse_query(query).unwrap().goal;
+            shown(db.materialize_query(goal).unwrap())
+        };
+        let whole = |name: &str, arity, wanted: &dyn Fn(&Functor) -> bool| {
+            let facts = model.relation(name, arity);
+            shown(facts.into_iter().filter(|fact| wanted(fact)).collect())
+        };
+        let path_from_3 = answers("path(3, Y)");
+        assert_eq!(path_from_3.len(), 21);
+        assert_eq!(
+            path_from_3,
+            whole("path", 2, &|fact| fact.to_string().starts_with("path(3,"))
+        );
+        assert_eq!(answers("path(X, X)").len(), 21);
+        assert_eq!(answers("path(100, 101)"), ["path(100, 101)"]);
+        assert_eq!(answers("path(101, X)"), Vec::<String>::new());
+        assert_eq!(answers("between(5, Y, 7)").len(), 21);
+        assert_eq!(answers("dead_end(X)"), ["dead_end(101)"]);
+    }
+
+    #[test]
+    fn rejects_programs_that_are_not_datalog() {
+        let error = |text: &str| program(text).materialize().err().unwrap().to_string();
+        assert_eq!(
+            error("p(f(X)) :- q(X). q(a)."),
+            "p/1 isn't Datalog: f(X) is a compound term"
+        );
+        assert_eq!(error("p(X)."), "p/1 isn't Datalog: a fact has a variable");
+        assert_eq!(
+            error("p(X) :- q(X) ; r(X). q(a). r(b)."),
+            "p/1 isn't Datalog: its body calls ;/2"
+        );
+        assert_eq!(
+            error("p(X) :- q(X), \\+ atom(X). q(a)."),
+            "p/1 isn't Datalog: its body negates atom/1"
+        );
+        assert_eq!(
+            error("p(X, Y) :- q(X). q(a)."),
+            "p/2 is unsafe: Y isn't bound by a goal calling a predicate"
+        );
+        assert_eq!(
+            error("p(X) :- q(X), X < Y. q(1)."),
+            "p/1 is unsafe: Y isn't bound by a goal calling a predicate"
+        );
+        assert_eq!(
+            error("p(X) :- q(X), \\+ r(X, _). q(1)."),
+            "p/1 is unsafe: _ isn't bound by a goal calling a predicate"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! A debugger on the classic four-port model: a goal is entered at its
+//! `call` port, left at `exit` with each solution, re-entered at `redo` on
+//! backtracking and left at `fail` once it has no more. A `Debugger` set with
+//! `Database::set_debugger` is shown the ports and says how to go on; spy
+//! points, set with `spy/1` or `Database::spy`, mark the predicates to stop
+//! at when leaping.
+
+use crate::builtins::{self, Alternatives};
+use crate::compat::{HashSet, Mutex, MutexGuard};
+use crate::error;
+use crate::module::predicate_indicator;
+use crate::observer::Observer;
+use crate::solver::{Bindings, Solver};
+use crate::{Arity, Atom, Database, Term};
+use alloc::sync::Arc;
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Port {
+    Call,
+    Exit,
+    Redo,
+    Fail,
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Port::Call => "Call",
+            Port::Exit => "Exit",
+            Port::Redo => "Redo",
+            Port::Fail => "Fail",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What to do after a port is shown.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Show the next port.
+    Creep,
+    /// Show nothing until a port of a spied predicate.
+    Leap,
+    /// Show nothing until this goal exits or fails.
+    Skip,
+    /// Give up on the query.
+    Abort,
+}
+
+/// Shown each port the search passes, with how deeply the goal is nested
+/// (1 for the query's own goals) and the goal as it stands.
+pub trait Debugger: Send + Sync {
+    fn port(&self, port: Port, depth: usize, goal: &Term) -> Command;
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Debugging {
+    pub(crate) debugger: Option<Arc<dyn Debugger>>,
+    pub(crate) observer: Option<Arc<dyn Observer>>,
+    spied: Arc<Mutex<HashSet<(Atom, Arity)>>>,
+}
+
+impl fmt::Debug for Debugging {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Debugging")
+    }
+}
+
+impl Debugging {
+    pub(crate) fn spied(&self, name: &Atom, arity: Arity) -> bool {
+        self.spy_points().contains(&(*name, arity))
+    }
+    // whether a query starts out leaping, rather than creeping through
+    // every port
+    pub(crate) fn leaping(&self) -> bool {
+        !self.spy_points().is_empty()
+    }
+    fn spy_points(&self) -> MutexGuard<'_, HashSet<(Atom, Arity)>> {
+        self.spied.lock()
+    }
+}
+
+impl Database {
+    /// Shows `debugger` the ports of every query from now on: all of them,
+    /// or if there are spy points, those of spied predicates.
+    pub fn set_debugger<D: Debugger + 'static>(&mut self, debugger: D) {
+        self.debugging.debugger = Some(Arc::new(debugger));
+    }
+    /// Sets a spy point on `name/arity`.
+    pub fn spy(&self, name: &str, arity: Arity) {
+        self.debugging.spy_points().insert((Atom::new(name), arity));
+    }
+    pub fn nospy(&self, name: &str, arity: Arity) {
+        self.debugging
+            .spy_points()
+            .remove(&(Atom::new(name), arity));
+    }
+}
+
+/// `spy(Name/Arity)`
+pub(crate) fn spy(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    match indicated(&args[0], bindings) {
+        Ok((name, arity)) => {
+            solver.db().spy(name.as_str(), arity);
+            builtins::success()
+        }
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+/// `nospy(Name/Arity)`
+pub(crate) fn nospy(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    match indicated(&args[0], bindings) {
+        Ok((name, arity)) => {
+            solver.db().nospy(name.as_str(), arity);
+            builtins::success()
+        }
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+fn indicated(term: &Term, bindings: &Bindings) -> Result<(Atom, Arity), Term> {
+    let term = bindings.resolve(term);
+    predicate_indicator(&term).ok_or_else(|| match term {
+        Term::Variable(_) => error::instantiation_error(),
+        term => error::type_error("predicate_indicator", term),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+
+    // answers every port with the next of `commands`, creeping once they
+    // run out, and keeps what it was shown
+    #[derive(Clone, Default)]
+    struct Scripted {
+        commands: Arc<Mutex<Vec<Command>>>,
+        shown: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Debugger for Scripted {
+        fn port(&self, port: Port, depth: usize, goal: &Term) -> Command {
+            self.shown
+                .lock()
+                .unwrap()
+                .push(format!("{} ({}) {}", port, depth, goal));
+            let mut commands = self.commands.lock().unwrap();
+            if commands.is_empty() {
+                Command::Creep
+            } else {
+                commands.remove(0)
+            }
+        }
+    }
+
+    fn debugged(program: &str, query: &str, commands: Vec<Command>) -> Vec<String> {
+        let scripted = Scripted {
+            commands: Arc::new(Mutex::new(commands)),
+            ..Scripted::default()
+        };
+        let mut db = Database::new();
+        db.consult(program).unwrap();
+        db.set_debugger(scripted.clone());
+        db.satisfy_all(parse_query(query).unwrap().goal);
+        let shown = scripted.shown.lock().unwrap().clone();
+        shown
+    }
+
+    const FAMILY: &str = "parent(tom, bob). parent(bob, ann).
+        grandparent(X, Z) :- parent(X, Y), parent(Y, Z).";
+
+    #[test]
+    fn creeping_shows_every_port() {
+        let shown = debugged(FAMILY, "parent(tom, X)", Vec::new());
+        assert_eq!(
+            shown,
+            vec![
+                "Call (1) parent(tom, X)",
+                "Exit (1) parent(tom, bob)",
+                "Redo (1) parent(tom, X)",
+                "Fail (1) parent(tom, X)",
+            ]
+        );
+    }
+
+    #[test]
+    fn skipping_hides_subgoals() {
+        let shown = debugged(FAMILY, "grandparent(tom, W)", vec![Command::Skip]);
+        assert_eq!(shown[0], "Call (1) grandparent(tom, W)");
+        assert_eq!(shown[1], "Exit (1) grandparent(tom, ann)");
+        let nested = debugged(FAMILY, "grandparent(tom, W)", Vec::new());
+        assert!(nested.contains(&"Call (2) parent(bob, W)".to_string()));
+    }
+
+    #[test]
+    fn leaping_stops_at_spy_points() {
+        let mut db = Database::new();
+        db.consult(FAMILY).unwrap();
+        let scripted = Scripted {
+            commands: Arc::new(Mutex::new(vec![Command::Leap; 100])),
+            ..Scripted::default()
+        };
+        db.set_debugger(scripted.clone());
+        db.satisfy_all(parse_query("spy(parent/2)").unwrap().goal);
+        scripted.shown.lock().unwrap().clear();
+        db.satisfy_all(parse_query("grandparent(tom, W)").unwrap().goal);
+        let shown = scripted.shown.lock().unwrap().clone();
+        assert!(!shown.is_empty());
+        assert!(shown
+            .iter()
+            .all(|port| port.contains("parent(") && !port.contains("grand")));
+        let aborted = debugged(FAMILY, "parent(X, Y)", vec![Command::Abort]);
+        assert_eq!(aborted, vec!["Call (1) parent(X, Y)"]);
+    }
+}
@@ -0,0 +1,118 @@
+//! Leaving out repeated answers: `distinct/1,2` and
+//! `SolverConfig::distinct`. An answer repeats an earlier one if it's a
+//! variant of it, the same up to renaming variables; answers are kept in a
+//! table keyed by a hash that numbers variables by where they first appear,
+//! so variants hash alike.
+
+use crate::builtins::variant;
+use crate::compat::{DefaultHasher, HashMap};
+use crate::solver::Bindings;
+use crate::{Alias, Term};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+
+/// The answers seen so far.
+#[derive(Default)]
+pub(crate) struct Seen(HashMap<u64, Vec<Term>>);
+
+impl Seen {
+    /// Whether the resolved `answer` is new, remembering it if it is.
+    pub(crate) fn insert(&mut self, answer: Term) -> bool {
+        let bucket = self.0.entry(variant_hash(&answer)).or_default();
+        if bucket.iter().any(|seen| variant(seen, &answer)) {
+            return false;
+        }
+        bucket.push(answer);
+        true
+    }
+}
+
+// a hash that variants of a term share
+fn variant_hash(term: &Term) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    // variables, numbered in order of appearance
+    let mut vars: HashMap<Alias, usize> = HashMap::new();
+    // what's left to hash, last first, so deep terms need no deep Rust stack
+    let mut pending = vec![term];
+    while let Some(term) = pending.pop() {
+        match term {
+            Term::Variable(v) => {
+                let next = vars.len();
+                (0u8, *vars.entry(v.alias).or_insert(next)).hash(&mut hasher);
+            }
+            Term::Atom(a) => (1u8, a.as_str()).hash(&mut hasher),
+            Term::Integer(n) => (2u8, n).hash(&mut hasher),
+            Term::String(text) => (3u8, &**text).hash(&mut hasher),
+            Term::Functor(f) => {
+                (4u8, f.name.as_str(), f.arity()).hash(&mut hasher);
+                pending.extend(f.args.iter().rev());
+            }
+        }
+    }
+    hasher.finish()
+}
+
+/// Passes on a query's answers, leaving out repeats if asked to.
+pub(crate) struct Filter {
+    goal: Term,
+    seen: Option<Seen>,
+}
+
+impl Filter {
+    pub(crate) fn new(goal: &Term, distinct: bool) -> Self {
+        Self {
+            goal: goal.clone(),
+            seen: distinct.then(Seen::default),
+        }
+    }
+    /// Whether the solution in `bindings` is to be given.
+    pub(crate) fn wanted(&mut self, bindings: &Bindings) -> bool {
+        match &mut self.seen {
+            Some(seen) => seen.insert(bindings.resolve(&self.goal)),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+
+    #[test]
+    fn repeated_answers_are_left_out() {
+        let mut db = Database::new();
+        db.consult(
+            "edge(a, b). edge(b, a). edge(a, c).
+            linked(X, Y) :- edge(X, Y).
+            linked(X, Y) :- edge(Y, X).
+            pair(f(_, Z, Z)). pair(f(_, W, W)). pair(f(V, V, _)).",
+        )
+        .unwrap();
+        assert_eq!(answers(&db, "linked(a, Y)").unwrap().len(), 3);
+        assert_eq!(
+            answers(&db, "distinct(linked(a, Y))").unwrap(),
+            ["Y = b", "Y = c"]
+        );
+        assert_eq!(answers(&db, "distinct(X, linked(X, _))").unwrap().len(), 3);
+        assert_eq!(answers(&db, "distinct(pair(P))").unwrap().len(), 2);
+        db.set_config(SolverConfig::default().distinct(true));
+        assert_eq!(answers(&db, "linked(a, Y)").unwrap().len(), 2);
+        assert_eq!(answers(&db, "pair(P)").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn long_lists_need_no_deep_stack() {
+        let mut db = Database::new();
+        db.consult("member(X, [X|_]). member(X, [_|T]) :- member(X, T).")
+            .unwrap();
+        let long = "findall(X, between(1, 100000, X), L)";
+        for goal in ["distinct(L, true)", "distinct(M, member(M, [L, L]))"] {
+            assert_eq!(
+                answers(&db, &format!("{}, {}", long, goal)).unwrap().len(),
+                1
+            );
+        }
+    }
+}
@@ -0,0 +1,485 @@
+//! Changing the database at run time: `assert/1`, `asserta/1`, `assertz/1`
+//! and `retract/1`.
+//!
+//! A query only borrows the database, so its solver keeps the changes it
+//! makes to one side, in `Changes`; `Database::directive` writes them back.
+//! Every change starts a new generation, and a call sees the clauses alive
+//! in the generation it started in, whatever is asserted or retracted
+//! while it runs: the ISO "logical update view".
+
+use crate::builtins::{self, Alternatives};
+use crate::compat::{thread_rng, HashMap};
+use crate::error;
+use crate::parser;
+use crate::solver::{self, Bindings, Solver};
+use crate::{Arity, Atom, Database, Flags, Functor, Term};
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter;
+use core::ops::{ControlFlow, Range};
+
+/// A predicate in the module it's defined in: module, name and arity.
+pub(crate) type Key = (Atom, Atom, Arity);
+
+/// The clauses asserted and retracted by one query.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Changes {
+    generation: u64,
+    asserted: HashMap<Key, Asserted>,
+    // clauses of the database itself that were retracted, by predicate and
+    // `ix`, with the generation they went in
+    retracted: HashMap<(Key, u32), u64>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Asserted {
+    // by `asserta`, in the order asserted, so tried last to first
+    first: Vec<Stamped>,
+    // by `assertz`
+    last: Vec<Stamped>,
+}
+
+#[derive(Clone, Debug)]
+struct Stamped {
+    clause: Functor,
+    born: u64,
+    died: Option<u64>,
+}
+
+impl Stamped {
+    fn alive(&self, generation: u64) -> bool {
+        self.born <= generation && self.died.is_none_or(|died| died > generation)
+    }
+}
+
+/// Where a clause of a predicate is, in the order calls try them.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Slot {
+    First(usize),
+    Stored(usize),
+    Last(usize),
+}
+
+/// The slots of a predicate as a call found them, in the order it tries them.
+#[derive(Clone, Debug)]
+pub(crate) struct Slots {
+    // the `asserta` slots left, tried last to first
+    first: usize,
+    stored: Range<usize>,
+    last: Range<usize>,
+}
+
+impl Iterator for Slots {
+    type Item = Slot;
+    fn next(&mut self) -> Option<Slot> {
+        if self.first > 0 {
+            self.first -= 1;
+            return Some(Slot::First(self.first));
+        }
+        let stored = self.stored.next().map(Slot::Stored);
+        stored.or_else(|| self.last.next().map(Slot::Last))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.first + self.stored.len() + self.last.len();
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for Slots {}
+
+impl Changes {
+    #[cfg(any(feature = "std", feature = "parallel"))]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.asserted.is_empty() && self.retracted.is_empty()
+    }
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+    /// The module whose clauses answer `name/arity` when called from
+    /// `context`, counting those asserted so far.
+    pub(crate) fn home(
+        &self,
+        db: &Database,
+        context: &Atom,
+        name: &Atom,
+        arity: Arity,
+    ) -> Option<Atom> {
+        if let Some(module) = db.visible_module(context, name, arity) {
+            return Some(*module.name());
+        }
+        [*context, crate::module::user()]
+            .into_iter()
+            .find(|module| self.asserted.contains_key(&(*module, *name, arity)))
+    }
+    /// Every slot of `key`, given the number of clauses the database has for it.
+    pub(crate) fn slots(&self, key: &Key, stored: usize) -> Slots {
+        let (first, last) = self
+            .asserted
+            .get(key)
+            .map_or((0, 0), |a| (a.first.len(), a.last.len()));
+        Slots {
+            first,
+            stored: 0..stored,
+            last: 0..last,
+        }
+    }
+    /// The clause in `slot`, if it was alive in `generation`.
+    pub(crate) fn visible<'s>(
+        &self,
+        key: &Key,
+        slot: Slot,
+        stored: &'s [Functor],
+        generation: u64,
+    ) -> Option<Cow<'s, Functor>> {
+        let asserted = |slot| {
+            let asserted = self.asserted.get(key)?;
+            let stamped = match slot {
+                Slot::First(i) => &asserted.first[i],
+                Slot::Last(i) => &asserted.last[i],
+                Slot::Stored(_) => unreachable!("not an asserted slot"),
+            };
+            stamped
+                .alive(generation)
+                .then(|| Cow::Owned(stamped.clause.clone()))
+        };
+        match slot {
+            Slot::Stored(i) => {
+                let clause = &stored[i];
+                let retracted = self.retracted.get(&(*key, clause.ix));
+                retracted
+                    .is_none_or(|died| *died > generation)
+                    .then_some(Cow::Borrowed(clause))
+            }
+            slot => asserted(slot),
+        }
+    }
+    /// The clauses of `key` alive now, in the order calls try them.
+    pub(crate) fn current<'s>(&'s self, db: &'s Database, key: &Key) -> Vec<Cow<'s, Functor>> {
+        let stored = db
+            .modules
+            .get(&key.0)
+            .map_or(&[][..], |m| m.predicate(&key.1, key.2));
+        self.slots(key, stored.len())
+            .filter_map(|slot| self.visible(key, slot, stored, self.generation))
+            .collect()
+    }
+    /// Every predicate that may have clauses alive now, by module, `user`
+    /// first: the database's in order of definition, then those only
+    /// asserted so far, by name.
+    pub(crate) fn predicates(&self, db: &Database) -> Vec<Key> {
+        let mut keys: Vec<Key> = db
+            .modules
+            .values()
+            .flat_map(|m| {
+                m.predicates()
+                    .map(|((name, arity), _)| (*m.name(), *name, *arity))
+            })
+            .collect();
+        let mut asserted: Vec<Key> = self
+            .asserted
+            .keys()
+            .filter(|key| !keys.contains(key))
+            .copied()
+            .collect();
+        asserted.sort_by_key(|(_, name, arity)| (name.as_str(), *arity));
+        keys.extend(asserted);
+        // a stable sort, so each module's predicates stay in order
+        keys.sort_by_key(|(module, _, _)| (*module != crate::module::user(), module.as_str()));
+        keys
+    }
+    fn assert(&mut self, key: Key, clause: Functor, first: bool) {
+        self.generation += 1;
+        let asserted = self.asserted.entry(key).or_default();
+        let stamped = Stamped {
+            clause,
+            born: self.generation,
+            died: None,
+        };
+        if first {
+            asserted.first.push(stamped);
+        } else {
+            asserted.last.push(stamped);
+        }
+    }
+    fn retract(&mut self, key: Key, slot: Slot, stored: &[Functor]) {
+        self.generation += 1;
+        let generation = self.generation;
+        match slot {
+            Slot::Stored(i) => {
+                self.retracted.insert((key, stored[i].ix), generation);
+            }
+            Slot::First(i) | Slot::Last(i) => {
+                let asserted = self.asserted.get_mut(&key).expect("slot was visible");
+                let stamped = match slot {
+                    Slot::First(_) => &mut asserted.first[i],
+                    _ => &mut asserted.last[i],
+                };
+                stamped.died = Some(generation);
+            }
+        }
+    }
+}
+
+impl Database {
+    // proves `goal` up to its first solution and keeps its changes
+    pub(crate) fn run_directive(&mut self, goal: Functor) -> bool {
+        let mut solver = Solver::new(self);
+        let mut proved = false;
+        let flow = solver.solve(
+            &[Term::Functor(Box::new(goal))],
+            &mut Bindings::new(),
+            &mut |_, _| {
+                proved = true;
+                ControlFlow::Break(())
+            },
+        );
+        let flags = *solver.flags();
+        let changes = solver.into_changes();
+        if solver::outcome(flow).is_err() {
+            return false;
+        }
+        self.keep(changes, flags);
+        proved
+    }
+    // keeps what a query asserted and retracted, and the flags it set; the
+    // predicates changed stay defined, as dynamic ones, even if no clauses
+    // are left
+    pub(crate) fn keep(&mut self, changes: Changes, flags: Flags) {
+        self.config.flags = flags;
+        for (((module, name, arity), ix), _) in changes.retracted {
+            if let Some(module) = self.modules.get_mut(&module) {
+                module.remove(name, arity, ix);
+                module.declare_dynamic(name, arity);
+            }
+        }
+        for ((module, name, arity), asserted) in changes.asserted {
+            let module = self
+                .modules
+                .entry(module)
+                .or_insert_with(|| crate::Module::new(module, Vec::new()));
+            module.declare_dynamic(name, arity);
+            for stamped in asserted.first.into_iter().filter(|s| s.died.is_none()) {
+                module.add_first(stamped.clause);
+            }
+            for stamped in asserted.last.into_iter().filter(|s| s.died.is_none()) {
+                module.add(stamped.clause);
+            }
+        }
+    }
+}
+
+pub(crate) fn assertz(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    add(solver, &args[0], bindings, false)
+}
+
+pub(crate) fn asserta(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    add(solver, &args[0], bindings, true)
+}
+
+fn add(solver: &mut Solver, clause: &Term, bindings: &Bindings, first: bool) -> Alternatives {
+    let clause = match to_clause(bindings.copy_term(clause)) {
+        Ok(clause) => clause,
+        Err(ball) => return builtins::raise(solver, ball),
+    };
+    let (db, context) = (solver.db(), solver.context());
+    let (name, arity) = (clause.name, clause.arity());
+    let home = solver
+        .changes()
+        .home(db, &context, &name, arity)
+        .unwrap_or(context);
+    solver.changes().assert((home, name, arity), clause, first);
+    builtins::success()
+}
+
+/// `retract(Clause)`: removes the first clause that unifies with `Clause`,
+/// either `Head :- Body` or a fact. Unlike ISO's, it doesn't go on to the
+/// next matching clause on backtracking.
+pub(crate) fn retract(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let (head, body) = match bindings.walk(&args[0]) {
+        Term::Functor(f) if f.name.as_str() == ":-" && f.arity() == 2 => {
+            (f.args[0].clone(), f.args[1].clone())
+        }
+        _ => (args[0].clone(), Term::atom_from_str("true")),
+    };
+    let (name, arity) = match bindings.walk(&head) {
+        Term::Atom(name) => (*name, 0),
+        Term::Functor(f) => (f.name, f.arity()),
+        Term::Variable(_) => return builtins::raise(solver, error::instantiation_error()),
+        other => return builtins::raise(solver, error::type_error("callable", other.clone())),
+    };
+    let db = solver.db();
+    let context = solver.context();
+    let Some(home) = solver.changes().home(db, &context, &name, arity) else {
+        return builtins::failure();
+    };
+    let key = (home, name, arity);
+    let stored = db
+        .modules
+        .get(&home)
+        .map_or(&[][..], |m| m.predicate(&name, arity));
+    let generation = solver.changes().generation();
+    let mut trial = bindings.clone();
+    let slots: Vec<_> = solver.changes().slots(&key, stored.len()).collect();
+    for slot in slots {
+        let Some(clause) = solver.changes().visible(&key, slot, stored, generation) else {
+            continue;
+        };
+        let clause = clause.into_owned();
+        let (fresh_head, fresh_body) = clause_terms(&clause);
+        let renamed = Term::functor_from_str(":-", vec![fresh_head, fresh_body])
+            .copy_fresh(&mut thread_rng());
+        let given = Term::functor_from_str(":-", vec![head.clone(), body.clone()]);
+        let mark = trial.mark();
+        let matched = trial.unify(&given, &renamed);
+        trial.undo_to(mark);
+        if matched {
+            solver.changes().retract(key, slot, stored);
+            return Box::new(iter::once(builtins::unify(vec![(given, renamed)])));
+        }
+    }
+    builtins::failure()
+}
+
+// a clause term, `Head :- Body` or just `Head`, as a stored clause
+fn to_clause(term: Term) -> Result<Functor, Term> {
+    let head = match &term {
+        Term::Functor(f) if f.name.as_str() == ":-" && f.arity() == 2 => &f.args[0],
+        head => head,
+    };
+    match head {
+        Term::Variable(_) => return Err(error::instantiation_error()),
+        Term::Integer(_) | Term::String(_) => {
+            return Err(error::type_error("callable", head.clone()))
+        }
+        Term::Atom(_) | Term::Functor(_) => {}
+    }
+    parser::clause(term.clone()).ok_or_else(|| error::type_error("callable", term))
+}
+
+// a stored clause's head and its body as one goal
+fn clause_terms(clause: &Functor) -> (Term, Term) {
+    let head = if clause.args.is_empty() {
+        Term::Atom(clause.name)
+    } else {
+        Term::Functor(Box::new(Functor::new_fact(
+            clause.name,
+            clause.args.clone(),
+        )))
+    };
+    let goals = clause.body.iter().map(|goal| {
+        if goal.args.is_empty() {
+            Term::Atom(goal.name)
+        } else {
+            Term::Functor(Box::new(Functor::new_fact(goal.name, goal.args.clone())))
+        }
+    });
+    let body = goals
+        .rev()
+        .reduce(|rest, goal| Term::functor_from_str(",", vec![goal, rest]))
+        .unwrap_or_else(|| Term::atom_from_str("true"));
+    (head, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{answers, consulted};
+    use crate::*;
+
+    #[test]
+    fn dynamic_predicates_fail_where_unknown_ones_throw() {
+        let mut db = consulted(
+            ":- dynamic counter/1.
+            :- dynamic seen/1, visited/2.
+            :- dynamic([cache/2]).
+            :- assertz(gone(1)), retract(gone(1)).",
+        );
+        let solved = |db: &Database, query: &str| {
+            let query = parse_query(query).unwrap();
+            db.try_satisfy_all(query.goal)
+                .map(|answers| answers.len())
+                .map_err(|err| err.to_string())
+        };
+        for query in [
+            "counter(_)",
+            "visited(_, _)",
+            "cache(_, _)",
+            "gone(_)",
+            "assertz(fresh(1)), retract(fresh(1)), fresh(_)",
+        ] {
+            assert_eq!(solved(&db, query), Ok(0), "{}", query);
+        }
+        let thrown = solved(&db, "missing(1)").unwrap_err();
+        assert!(
+            thrown.contains("existence_error(procedure, missing/1)"),
+            "{}",
+            thrown
+        );
+        assert!(solved(&db, "counter(_, _)").is_err());
+        assert!(db.module("user").unwrap().is_dynamic(&Atom::new("seen"), 1));
+        db.set_config(SolverConfig::default().unknown(Unknown::Fail));
+        assert_eq!(solved(&db, "missing(1)"), Ok(0));
+    }
+
+    #[test]
+    fn calls_see_the_clauses_they_started_with() {
+        let db = consulted("p(1). p(2).");
+        assert_eq!(
+            answers(&db, "p(X), assertz(p(3))").unwrap(),
+            vec!["X = 1", "X = 2"]
+        );
+        assert_eq!(
+            answers(&db, "assertz(p(3)), asserta(p(0)), p(X)").unwrap(),
+            vec!["X = 0", "X = 1", "X = 2", "X = 3"]
+        );
+        assert_eq!(answers(&db, "assertz(q(a)), q(X)").unwrap(), vec!["X = a"]);
+    }
+
+    #[test]
+    fn retracted_clauses_are_tried_by_running_calls() {
+        let db = consulted("p(1). p(2). p(3).");
+        assert_eq!(answers(&db, "p(X), retract(p(3))").unwrap(), vec!["X = 1"]);
+        assert_eq!(
+            answers(&db, "p(X), retract(p(_))").unwrap(),
+            vec!["X = 1", "X = 2", "X = 3"]
+        );
+        assert_eq!(
+            answers(&db, "retract(p(2)), p(X)").unwrap(),
+            vec!["X = 1", "X = 3"]
+        );
+        assert_eq!(
+            answers(&db, "assertz((r(X) :- p(X))), retract((r(Y) :- B)), Y = 1").unwrap(),
+            vec!["X = X, Y = 1, B = p(1)"]
+        );
+    }
+
+    #[test]
+    fn directives_keep_their_changes() {
+        let db = consulted(
+            "counter(0).\n:- assertz(seen(a)).\n:- retract(counter(0)), assertz(counter(1)).",
+        );
+        assert_eq!(answers(&db, "seen(X)").unwrap(), vec!["X = a"]);
+        assert_eq!(answers(&db, "counter(X)").unwrap(), vec!["X = 1"]);
+    }
+
+    #[test]
+    fn only_mut_queries_keep_their_changes() {
+        let mut db = consulted("counter(0).");
+        let run = |db: &mut Database, query: &str| {
+            let goal = parse_query(query).unwrap().goal;
+            db.for_each_solution_mut(goal, |_| ControlFlow::Break(()))
+        };
+        db.for_each_solution(parse_query("assertz(lost(a))").unwrap().goal, |_| {
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert!(answers(&db, "lost(X)").is_err());
+        run(&mut db, "retract(counter(0)), assertz(counter(1))").unwrap();
+        assert_eq!(answers(&db, "counter(X)").unwrap(), vec!["X = 1"]);
+        assert!(run(&mut db, "assertz(seen(a)), throw(oops)").is_err());
+        assert_eq!(answers(&db, "seen(X)").unwrap(), vec!["X = a"]);
+        run(&mut db, "set_prolog_flag(unknown, fail)").unwrap();
+        assert!(answers(&db, "missing").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+//! Line editing for the REPL, by rustyline: moving about the line, recalling
+//! earlier queries with the arrow keys or Ctrl-R, and keeping them in a
+//! history file between sessions. Piped input is read a line at a time.
+
+use rustyline::error::ReadlineError;
+use rustyline::{
+    Cmd, ConditionalEventHandler, Config, DefaultEditor, Event, EventContext, EventHandler,
+    KeyCode, KeyEvent, Modifiers, RepeatCount,
+};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+
+// how many lines the history file keeps
+const HISTORY_LIMIT: usize = 1000;
+
+/// Reads lines from the terminal, with history.
+pub struct Editor {
+    lines: DefaultEditor,
+    // reads answers, each the first key pressed
+    answers: DefaultEditor,
+    pressed: Arc<Mutex<Option<char>>>,
+    file: Option<PathBuf>,
+}
+
+// ends the line at the first key pressed, noting which
+struct FirstKey(Arc<Mutex<Option<char>>>);
+
+impl ConditionalEventHandler for FirstKey {
+    fn handle(&self, event: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        let key = match event.get(0)? {
+            KeyEvent(KeyCode::Char(c), modifiers) if (*modifiers - Modifiers::SHIFT).is_empty() => {
+                *c
+            }
+            KeyEvent(KeyCode::Enter, _) => '\n',
+            _ => return None,
+        };
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(key);
+        Some(Cmd::AcceptLine)
+    }
+}
+
+impl Editor {
+    /// An editor recalling the lines kept in `file`, if any.
+    pub fn new(file: Option<PathBuf>) -> io::Result<Self> {
+        let config = Config::builder()
+            .max_history_size(HISTORY_LIMIT)
+            .and_then(|config| config.history_ignore_dups(true))
+            .map_err(io_error)?
+            .build();
+        let mut lines = DefaultEditor::with_config(config).map_err(io_error)?;
+        if let Some(file) = &file {
+            let _ = lines.load_history(file);
+        }
+        let pressed = Arc::new(Mutex::new(None));
+        let mut answers = DefaultEditor::new().map_err(io_error)?;
+        answers.bind_sequence(
+            Event::Any,
+            EventHandler::Conditional(Box::new(FirstKey(pressed.clone()))),
+        );
+        Ok(Self {
+            lines,
+            answers,
+            pressed,
+            file,
+        })
+    }
+    /// The next line, without its newline, or `None` at the end of input. A
+    /// line abandoned with Ctrl-C comes back empty.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        if !io::stdin().is_terminal() {
+            return piped_line(prompt);
+        }
+        match self.lines.readline(prompt) {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => Ok(None),
+            Err(err) => Err(io_error(err)),
+        }
+    }
+    /// Shows `prompt` and takes the key pressed in answer, `\n` for Enter and
+    /// at the end of input or on Ctrl-C, showing what `reply` gives for it.
+    /// Piped input answers with its next line: `;` if it starts with one, and
+    /// Enter otherwise.
+    pub fn read_answer(
+        &mut self,
+        prompt: &str,
+        reply: fn(char) -> &'static str,
+    ) -> io::Result<char> {
+        if !io::stdin().is_terminal() {
+            let key = match piped_line(prompt)? {
+                Some(line) if line.trim_start().starts_with(';') => ';',
+                _ => '\n',
+            };
+            println!("{}", reply(key));
+            return Ok(key);
+        }
+        *self.pressed.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        match self.answers.readline(prompt) {
+            Ok(_) | Err(ReadlineError::Interrupted | ReadlineError::Eof) => {}
+            Err(err) => return Err(io_error(err)),
+        }
+        let key = self
+            .pressed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .unwrap_or('\n');
+        // rustyline has ended the line: the reply goes back up, after the prompt
+        let column = prompt
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count();
+        println!("\x1b[1A\x1b[{}G{}", column + 1, reply(key));
+        Ok(key)
+    }
+    /// Remembers `line`, in the history file too.
+    pub fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || !self.lines.add_history_entry(line).unwrap_or(false) {
+            return;
+        }
+        if let Some(file) = &self.file {
+            let _ = self.lines.append_history(file);
+        }
+    }
+}
+
+// a line of input that isn't a terminal, shown after `prompt`
+fn piped_line(prompt: &str) -> io::Result<Option<String>> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+}
+
+fn io_error(err: ReadlineError) -> io::Error {
+    match err {
+        ReadlineError::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn history_is_kept_in_a_file() {
+        let file = std::env::temp_dir().join(format!("amateurlog-history-{}", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let mut editor = Editor::new(Some(file.clone())).unwrap();
+        editor.add_history("likes(X, Y).");
+        editor.add_history("likes(X, Y).");
+        editor.add_history(" ");
+        editor.add_history("food(F).");
+        let recalled = Editor::new(Some(file.clone())).unwrap();
+        let lines = |editor: &Editor| editor.lines.history().iter().cloned().collect::<Vec<_>>();
+        assert_eq!(lines(&recalled), ["likes(X, Y).", "food(F)."]);
+        assert_eq!(lines(&editor), lines(&recalled));
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use crate::compat::thread_rng;
+use crate::{Arity, Atom, Span, Term, Variable};
+use alloc::vec;
+use core::fmt;
+
+/// Why a query stopped before all its solutions were found.
+#[derive(Clone, Debug)]
+pub enum SolveError {
+    /// A ball thrown by `throw/1` or an erroring built-in that no `catch/3` caught.
+    Uncaught(Term),
+    /// The query ran longer than `SolverConfig::timeout` allows.
+    TimedOut,
+    /// The query's `CancellationToken` was cancelled.
+    Cancelled,
+    /// The query went past one of the limits set on `SolverConfig`.
+    ResourceExceeded(Resource),
+}
+
+/// What a query can run out of, short of time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resource {
+    /// `SolverConfig::max_term_depth`
+    TermDepth,
+    /// `SolverConfig::max_bindings`
+    Bindings,
+    /// `SolverConfig::max_terms`
+    Terms,
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolveError::Uncaught(ball) => write!(f, "uncaught exception: {}", ball.pretty()),
+            SolveError::TimedOut => write!(f, "timed out"),
+            SolveError::Cancelled => write!(f, "cancelled"),
+            SolveError::ResourceExceeded(resource) => write!(f, "too many {}", resource),
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::TermDepth => write!(f, "levels of term nesting"),
+            Resource::Bindings => write!(f, "variable bindings"),
+            Resource::Terms => write!(f, "terms"),
+        }
+    }
+}
+
+impl core::error::Error for SolveError {}
+
+// error(Formal, _), as ISO built-ins throw
+fn error(formal: Term) -> Term {
+    let context = Term::Variable(Variable::new_anonymous(&mut thread_rng()));
+    Term::functor_from_str("error", vec![formal, context])
+}
+
+/// `ball` as thrown by the built-in `name/arity`, called from `span`: if it
+/// is an error whose context was left open, the context becomes
+/// `context(Name/Arity, File:Line:Column)`. Balls given to `throw/1` are
+/// passed on as they are.
+pub(crate) fn in_context(ball: Term, name: &Atom, arity: Arity, span: Option<&Span>) -> Term {
+    let Some(span) = span else {
+        return ball;
+    };
+    match ball {
+        Term::Functor(mut f)
+            if f.name.as_str() == "error"
+                && f.args.len() == 2
+                && matches!(f.args[1], Term::Variable(_))
+                && name.as_str() != "throw" =>
+        {
+            let at = Term::functor_from_str(
+                ":",
+                vec![
+                    Term::atom_from_str(&span.file),
+                    Term::functor_from_str(
+                        ":",
+                        vec![
+                            Term::Integer(span.line as i64),
+                            Term::Integer(span.column as i64),
+                        ],
+                    ),
+                ],
+            );
+            let culprit = indicator(name.as_str(), arity);
+            f.args[1] = Term::functor_from_str("context", vec![culprit, at]);
+            Term::Functor(f)
+        }
+        ball => ball,
+    }
+}
+
+pub(crate) fn instantiation_error() -> Term {
+    error(Term::atom_from_str("instantiation_error"))
+}
+
+pub(crate) fn type_error(kind: &str, culprit: Term) -> Term {
+    error(Term::functor_from_str(
+        "type_error",
+        vec![Term::atom_from_str(kind), culprit],
+    ))
+}
+
+pub(crate) fn domain_error(domain: &str, culprit: Term) -> Term {
+    error(Term::functor_from_str(
+        "domain_error",
+        vec![Term::atom_from_str(domain), culprit],
+    ))
+}
+
+pub(crate) fn existence_error(kind: &str, culprit: Term) -> Term {
+    error(Term::functor_from_str(
+        "existence_error",
+        vec![Term::atom_from_str(kind), culprit],
+    ))
+}
+
+pub(crate) fn permission_error(action: &str, kind: &str, culprit: Term) -> Term {
+    error(Term::functor_from_str(
+        "permission_error",
+        vec![
+            Term::atom_from_str(action),
+            Term::atom_from_str(kind),
+            culprit,
+        ],
+    ))
+}
+
+pub(crate) fn format_error(message: &str) -> Term {
+    error(Term::functor_from_str(
+        "format",
+        vec![Term::atom_from_str(message)],
+    ))
+}
+
+pub(crate) fn io_error(message: &str) -> Term {
+    error(Term::functor_from_str(
+        "io_error",
+        vec![Term::atom_from_str("write"), Term::atom_from_str(message)],
+    ))
+}
+
+pub(crate) fn syntax_error(kind: &str) -> Term {
+    error(Term::functor_from_str(
+        "syntax_error",
+        vec![Term::atom_from_str(kind)],
+    ))
+}
+
+pub(crate) fn representation_error(limit: &str) -> Term {
+    error(Term::functor_from_str(
+        "representation_error",
+        vec![Term::atom_from_str(limit)],
+    ))
+}
+
+pub(crate) fn occurs_check(var: Term, term: Term) -> Term {
+    error(Term::functor_from_str("occurs_check", vec![var, term]))
+}
+
+pub(crate) fn evaluation_error(kind: &str) -> Term {
+    error(Term::functor_from_str(
+        "evaluation_error",
+        vec![Term::atom_from_str(kind)],
+    ))
+}
+
+/// `Name/Arity`, as used in error terms.
+pub(crate) fn indicator(name: &str, arity: usize) -> Term {
+    Term::functor_from_str(
+        "/",
+        vec![Term::atom_from_str(name), Term::Integer(arity as i64)],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{answers, consulted};
+    use crate::*;
+
+    #[test]
+    fn catch_binds_the_ball() {
+        assert_eq!(
+            answers(&Database::new(), "catch(throw(oops(1)), oops(E), true)").unwrap(),
+            vec!["E = 1"]
+        );
+        assert_eq!(
+            answers(
+                &consulted("p(1). p(2)."),
+                "catch((p(X), throw(found(X))), found(Y), Z = caught)"
+            )
+            .unwrap(),
+            vec!["X = X, Y = 1, Z = caught"]
+        );
+    }
+
+    #[test]
+    fn unmatched_balls_propagate() {
+        let Err(SolveError::Uncaught(ball)) = answers(&Database::new(), "catch(throw(a), b, true)")
+        else {
+            panic!("expected an uncaught exception")
+        };
+        assert_eq!(ball.to_string(), "a");
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "catch(catch(throw(a), b, true), a, X = outer)"
+            )
+            .unwrap(),
+            vec!["X = outer"]
+        );
+    }
+
+    #[test]
+    fn builtin_errors_can_be_caught() {
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "catch(X is foo + 1, error(type_error(T, _), _), true)"
+            )
+            .unwrap(),
+            vec!["X = X, T = evaluable"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "catch(throw(_), error(E, _), true)").unwrap(),
+            vec!["E = instantiation_error"]
+        );
+    }
+
+    #[test]
+    fn errors_say_where_the_goal_was_written() {
+        let mut db = Database::new();
+        db.consult_named("kb.pl", "double(X, Y) :-\n    Y is X * 2.")
+            .unwrap();
+        let query = parse_query("double(a, Y)").unwrap();
+        let Err(err) = db.try_satisfy_all(query.goal) else {
+            panic!("expected an uncaught exception")
+        };
+        assert_eq!(
+            err.to_string(),
+            "uncaught exception: error(type_error(evaluable, a/0), context((is)/2, 'kb.pl':2:5))"
+        );
+        let thrown = answers(
+            &consulted("p :- throw(error(mine, _))."),
+            "catch(p, error(_, C), true)",
+        );
+        assert!(!thrown.unwrap()[0].contains("context"));
+    }
+
+    #[test]
+    fn only_the_goal_is_protected() {
+        assert_eq!(
+            answers(&consulted("p(1). p(2)."), "catch(p(X), _, true)").unwrap(),
+            vec!["X = 1", "X = 2"]
+        );
+        assert!(answers(&Database::new(), "catch(true, _, true), throw(after)").is_err());
+        assert_eq!(
+            answers(&Database::new(), "findall(X, catch(throw(x), x, X = 1), L)").unwrap(),
+            vec!["X = X, L = .(1, [])"]
+        );
+    }
+}
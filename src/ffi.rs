@@ -0,0 +1,260 @@
+//! A C interface, declared in `include/amateurlog.h`. Programs and queries
+//! are opaque handles, made by `amateurlog_database_new` and
+//! `amateurlog_query` and given back to `amateurlog_database_free` and
+//! `amateurlog_query_free`. Strings are UTF-8 and NUL-terminated both ways;
+//! those handed out are freed with `amateurlog_string_free`. A call that
+//! fails says why in `amateurlog_last_error`.
+//!
+//! ```c
+//! AmateurlogDatabase *db = amateurlog_database_new();
+//! amateurlog_consult(db, "parent(tom, bob). parent(bob, ann).");
+//! AmateurlogQuery *query = amateurlog_query(db, "parent(X, Y)");
+//! char *solution;
+//! while (amateurlog_next_solution(query, &solution) == 1) {
+//!     puts(solution); /* {"X": "tom", "Y": "bob"} */
+//!     amateurlog_string_free(solution);
+//! }
+//! amateurlog_query_free(query);
+//! amateurlog_database_free(db);
+//! ```
+
+use crate::{parse_query, Answers, Database, Query};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+use std::sync::Arc;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A program.
+pub struct AmateurlogDatabase {
+    db: Database,
+}
+
+/// The solutions of a query, found one at a time as they're asked for, on a
+/// thread of the query's own.
+pub struct AmateurlogQuery {
+    query: Query,
+    answers: Answers,
+}
+
+/// An empty program.
+#[no_mangle]
+pub extern "C" fn amateurlog_database_new() -> *mut AmateurlogDatabase {
+    Box::into_raw(Box::new(AmateurlogDatabase {
+        db: Database::new(),
+    }))
+}
+
+/// Frees a program. Its queries go on working.
+///
+/// # Safety
+///
+/// `db` is null or came from `amateurlog_database_new` and isn't used again.
+#[no_mangle]
+pub unsafe extern "C" fn amateurlog_database_free(db: *mut AmateurlogDatabase) {
+    if !db.is_null() {
+        drop(Box::from_raw(db));
+    }
+}
+
+/// Adds the clauses in `source`, returning 0, or -1 if it doesn't parse.
+///
+/// # Safety
+///
+/// `db` came from `amateurlog_database_new`, and `source` is a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn amateurlog_consult(
+    db: *mut AmateurlogDatabase,
+    source: *const c_char,
+) -> c_int {
+    let Some(db) = db.as_mut() else {
+        return fail("no database");
+    };
+    let Some(source) = text(source) else {
+        return -1;
+    };
+    match db.db.consult(source) {
+        Ok(()) => 0,
+        Err(errors) => fail(&errors.to_string()),
+    }
+}
+
+/// Starts `text` on the program as it is now, returning null if it doesn't
+/// parse. Nothing is searched for until `amateurlog_next_solution`.
+///
+/// # Safety
+///
+/// `db` came from `amateurlog_database_new`, and `text` is a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn amateurlog_query(
+    db: *const AmateurlogDatabase,
+    text: *const c_char,
+) -> *mut AmateurlogQuery {
+    let Some(db) = db.as_ref() else {
+        fail("no database");
+        return ptr::null_mut();
+    };
+    let Some(text) = self::text(text) else {
+        return ptr::null_mut();
+    };
+    match parse_query(text) {
+        Ok(query) => {
+            let answers = Arc::new(db.db.clone()).answers(query.goal.clone());
+            Box::into_raw(Box::new(AmateurlogQuery { query, answers }))
+        }
+        Err(err) => {
+            fail(&err.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Finds the query's next solution. Returns 1, setting `*solution` to a JSON
+/// object of its variables' values as `Solution::to_json` writes them; 0
+/// once there are no more; or -1 for an uncaught exception, a timeout or an
+/// exhausted resource.
+///
+/// # Safety
+///
+/// `query` came from `amateurlog_query`, and `solution` points to a `char *`
+/// to set.
+#[no_mangle]
+pub unsafe extern "C" fn amateurlog_next_solution(
+    query: *mut AmateurlogQuery,
+    solution: *mut *mut c_char,
+) -> c_int {
+    let (Some(query), false) = (query.as_mut(), solution.is_null()) else {
+        return fail("no query or nowhere to put its solution");
+    };
+    match query.answers.next() {
+        None => 0,
+        Some(Ok(bindings)) => {
+            let json = query.query.solution(&bindings).to_json();
+            // JSON escapes control characters, NUL among them
+            *solution = CString::new(json).map_or(ptr::null_mut(), CString::into_raw);
+            1
+        }
+        Some(Err(err)) => fail(&err.to_string()),
+    }
+}
+
+/// Stops a query and frees it.
+///
+/// # Safety
+///
+/// `query` is null or came from `amateurlog_query` and isn't used again.
+#[no_mangle]
+pub unsafe extern "C" fn amateurlog_query_free(query: *mut AmateurlogQuery) {
+    if !query.is_null() {
+        drop(Box::from_raw(query));
+    }
+}
+
+/// Frees a string handed out by this library.
+///
+/// # Safety
+///
+/// `text` is null or came from this library and isn't used again.
+#[no_mangle]
+pub unsafe extern "C" fn amateurlog_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+/// Why the last call on this thread to fail did, or null. The text lasts
+/// until the next call that fails.
+#[no_mangle]
+pub extern "C" fn amateurlog_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |err| err.as_ptr())
+    })
+}
+
+// `text` as a `&str`, or `None` having said why not
+unsafe fn text<'a>(text: *const c_char) -> Option<&'a str> {
+    if text.is_null() {
+        fail("no text");
+        return None;
+    }
+    match CStr::from_ptr(text).to_str() {
+        Ok(text) => Some(text),
+        Err(err) => {
+            fail(&format!("not UTF-8: {}", err));
+            None
+        }
+    }
+}
+
+// records `message` for `amateurlog_last_error`, returning -1
+fn fail(message: &str) -> c_int {
+    let message = CString::new(message.replace('\0', "\\0")).expect("NULs replaced");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    -1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next(query: *mut AmateurlogQuery) -> Result<Option<String>, String> {
+        let mut solution = ptr::null_mut();
+        unsafe {
+            match amateurlog_next_solution(query, &mut solution) {
+                1 => {
+                    let json = CStr::from_ptr(solution).to_str().unwrap().to_string();
+                    amateurlog_string_free(solution);
+                    Ok(Some(json))
+                }
+                0 => Ok(None),
+                _ => Err(last_error()),
+            }
+        }
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(amateurlog_last_error()) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn queries_through_handles() {
+        let source = CString::new("parent(tom, bob). parent(bob, ann).").unwrap();
+        let text = |text: &str| CString::new(text).unwrap();
+        unsafe {
+            let db = amateurlog_database_new();
+            assert_eq!(amateurlog_consult(db, source.as_ptr()), 0);
+            assert_eq!(amateurlog_consult(db, text("oops(").as_ptr()), -1);
+            assert!(last_error().contains("line 1"));
+            let query = amateurlog_query(db, text("parent(X, Y)").as_ptr());
+            // the program as it was when the query started
+            amateurlog_database_free(db);
+            assert_eq!(next(query), Ok(Some(r#"{"X": "tom", "Y": "bob"}"#.into())));
+            assert_eq!(next(query), Ok(Some(r#"{"X": "bob", "Y": "ann"}"#.into())));
+            assert_eq!(next(query), Ok(None));
+            assert_eq!(next(query), Ok(None));
+            amateurlog_query_free(query);
+
+            let db = amateurlog_database_new();
+            assert!(amateurlog_query(db, text("X =").as_ptr()).is_null());
+            let endless = amateurlog_query(db, text("between(1, inf, X)").as_ptr());
+            assert_eq!(next(endless), Ok(Some(r#"{"X": 1}"#.into())));
+            amateurlog_query_free(endless);
+            let thrown = amateurlog_query(db, text("X = 1 ; throw(oops)").as_ptr());
+            assert_eq!(next(thrown), Ok(Some(r#"{"X": 1}"#.into())));
+            assert_eq!(next(thrown), Err("uncaught exception: oops".into()));
+            assert_eq!(next(thrown), Ok(None));
+            amateurlog_query_free(thrown);
+            amateurlog_database_free(db);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Prolog flags: `set_prolog_flag/2` and `current_prolog_flag/2`.
+//!
+//! A query starts from the database's flags and sets its own copy, so a
+//! change lasts until the query ends; one made by a directive is kept by
+//! the database, except `double_quotes`, which only lasts to the end of the
+//! file.
+
+use crate::builtins::{self, Alternatives, Branch};
+use crate::config::{DoubleQuotes, OccursCheck};
+use crate::error;
+use crate::solver::{Bindings, Solver};
+use crate::{Database, Term};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter;
+
+/// The flags queries run with, set with `SolverConfig` or by
+/// `set_prolog_flag/2` in a directive, or a query whose changes are kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub occurs_check: OccursCheck,
+    pub double_quotes: DoubleQuotes,
+    /// See `SolverConfig::max_term_depth`.
+    pub max_term_depth: Option<usize>,
+    pub unknown: Unknown,
+}
+
+/// What calling a predicate with no clauses does, like Prolog's `unknown`
+/// flag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Unknown {
+    /// Throws `existence_error(procedure, Name/Arity)`, unless the
+    /// predicate is dynamic.
+    #[default]
+    Error,
+    Fail,
+}
+
+// flags that can be read but not set
+const READ_ONLY: [&str; 3] = ["bounded", "max_integer", "min_integer"];
+
+impl Flags {
+    // each flag with its value, in the order current_prolog_flag/2 gives them
+    fn values(&self) -> Vec<(&'static str, Term)> {
+        let occurs_check = match self.occurs_check {
+            OccursCheck::Off => "false",
+            OccursCheck::True => "true",
+            OccursCheck::Error => "error",
+        };
+        let double_quotes = match self.double_quotes {
+            DoubleQuotes::Codes => "codes",
+            DoubleQuotes::Chars => "chars",
+            DoubleQuotes::Atom => "atom",
+            DoubleQuotes::String => "string",
+        };
+        let max_term_depth = match self.max_term_depth {
+            Some(depth) => Term::Integer(i64::try_from(depth).unwrap_or(i64::MAX)),
+            None => Term::atom_from_str("inf"),
+        };
+        let unknown = match self.unknown {
+            Unknown::Error => "error",
+            Unknown::Fail => "fail",
+        };
+        vec![
+            ("bounded", Term::atom_from_str("true")),
+            ("double_quotes", Term::atom_from_str(double_quotes)),
+            ("max_integer", Term::Integer(i64::MAX)),
+            ("max_term_depth", max_term_depth),
+            ("min_integer", Term::Integer(i64::MIN)),
+            ("occurs_check", Term::atom_from_str(occurs_check)),
+            ("unknown", Term::atom_from_str(unknown)),
+        ]
+    }
+    // sets `flag` to `value`, or gives the error term to throw
+    fn set(&mut self, flag: &str, value: &Term) -> Result<(), Term> {
+        let name = |value: &Term| match value {
+            Term::Atom(a) => Some(a.as_str()),
+            _ => None,
+        };
+        let set = match flag {
+            "double_quotes" => double_quotes(value).map(|dq| self.double_quotes = dq),
+            "occurs_check" => match name(value) {
+                Some("false") => Some(OccursCheck::Off),
+                Some("true") => Some(OccursCheck::True),
+                Some("error") => Some(OccursCheck::Error),
+                _ => None,
+            }
+            .map(|oc| self.occurs_check = oc),
+            "max_term_depth" => match value {
+                Term::Integer(n) => usize::try_from(*n).ok().map(Some),
+                _ if name(value) == Some("inf") => Some(None),
+                _ => None,
+            }
+            .map(|depth| self.max_term_depth = depth),
+            "unknown" => match name(value) {
+                Some("error") => Some(Unknown::Error),
+                Some("fail") => Some(Unknown::Fail),
+                _ => None,
+            }
+            .map(|unknown| self.unknown = unknown),
+            _ if READ_ONLY.contains(&flag) => {
+                return Err(error::permission_error(
+                    "modify",
+                    "flag",
+                    Term::atom_from_str(flag),
+                ))
+            }
+            _ => {
+                return Err(error::domain_error(
+                    "prolog_flag",
+                    Term::atom_from_str(flag),
+                ))
+            }
+        };
+        set.ok_or_else(|| {
+            let culprit =
+                Term::functor_from_str("+", vec![Term::atom_from_str(flag), value.clone()]);
+            error::domain_error("flag_value", culprit)
+        })
+    }
+}
+
+/// The `double_quotes` a flag value names, if any.
+pub(crate) fn double_quotes(value: &Term) -> Option<DoubleQuotes> {
+    match value {
+        Term::Atom(a) => match a.as_str() {
+            "codes" => Some(DoubleQuotes::Codes),
+            "chars" => Some(DoubleQuotes::Chars),
+            "atom" => Some(DoubleQuotes::Atom),
+            "string" => Some(DoubleQuotes::String),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl Database {
+    /// The flags queries start with.
+    pub fn flags(&self) -> &Flags {
+        &self.config.flags
+    }
+}
+
+/// `set_prolog_flag(Flag, Value)`: sets `Flag` for the rest of the query.
+pub(crate) fn set_prolog_flag(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    let set = match (bindings.walk(&args[0]), bindings.resolve(&args[1])) {
+        (Term::Variable(_), _) | (_, Term::Variable(_)) => Err(error::instantiation_error()),
+        (Term::Atom(flag), value) => solver.flags_mut().set(flag.as_str(), &value),
+        (other, _) => Err(error::type_error("atom", other.clone())),
+    };
+    if let Err(ball) = set {
+        return builtins::raise(solver, ball);
+    }
+    // bindings check for cycles as they were made with
+    let occurs_check = solver.flags().occurs_check;
+    Box::new(iter::once(Box::new(move |bindings: &mut Bindings| {
+        bindings.occurs_check = occurs_check;
+        true
+    }) as Branch))
+}
+
+/// `current_prolog_flag(Flag, Value)`: `Flag` is set to `Value`, enumerating
+/// the flags when `Flag` is unbound.
+pub(crate) fn current_prolog_flag(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    let wanted = match bindings.walk(&args[0]) {
+        Term::Atom(flag) => Some(flag.as_str()),
+        Term::Variable(_) => None,
+        other => return builtins::raise(solver, error::type_error("atom", other.clone())),
+    };
+    let (flag, value) = (args[0].clone(), args[1].clone());
+    let values = solver.flags().values();
+    Box::new(
+        values
+            .into_iter()
+            .filter(move |(name, _)| wanted.is_none_or(|wanted| wanted == *name))
+            .map(move |(name, current)| {
+                builtins::unify(vec![
+                    (flag.clone(), Term::atom_from_str(name)),
+                    (value.clone(), current),
+                ])
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn count(db: &Database, query: &str) -> Result<usize, SolveError> {
+        let query = parse_query(query).unwrap();
+        db.try_satisfy_all(query.goal).map(|answers| answers.len())
+    }
+
+    #[test]
+    fn flags_are_read_and_set() {
+        let mut db = Database::new();
+        assert_eq!(
+            count(&db, "current_prolog_flag(double_quotes, string)").unwrap(),
+            1
+        );
+        assert_eq!(count(&db, "current_prolog_flag(F, _)").unwrap(), 7);
+        assert_eq!(
+            count(&db, "set_prolog_flag(occurs_check, true), X = f(X)").unwrap(),
+            0
+        );
+        assert_eq!(count(&db, "X = f(X)").unwrap(), 1);
+        assert_eq!(
+            count(
+                &db,
+                "set_prolog_flag(max_term_depth, 3), current_prolog_flag(max_term_depth, 3)"
+            )
+            .unwrap(),
+            1
+        );
+        for (query, error) in [
+            (
+                "set_prolog_flag(nope, 1)",
+                "domain_error(prolog_flag, nope)",
+            ),
+            (
+                "set_prolog_flag(unknown, maybe)",
+                "domain_error(flag_value, +(unknown, maybe))",
+            ),
+            (
+                "set_prolog_flag(bounded, false)",
+                "permission_error(modify, flag, bounded)",
+            ),
+        ] {
+            match count(&db, query) {
+                Err(SolveError::Uncaught(ball)) => {
+                    assert!(ball.to_string().contains(error), "{}", ball)
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+        db.consult(":- set_prolog_flag(unknown, fail).").unwrap();
+        assert_eq!(db.flags().unknown, Unknown::Fail);
+        assert_eq!(count(&db, "nowhere(1)").unwrap(), 0);
+        db.set_config(SolverConfig::default().occurs_check(OccursCheck::Error));
+        assert_eq!(db.flags().unknown, Unknown::Error);
+        assert_eq!(
+            count(&db, "current_prolog_flag(occurs_check, error)").unwrap(),
+            1
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{Arity, Atom, Bindings, Database, Term};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A predicate implemented in Rust. It receives the goal's arguments and the
+/// current bindings, and returns one row of argument values per solution; each
+/// row is unified with the arguments in turn.
+pub type ForeignFn = dyn Fn(&[Term], &Bindings) -> Vec<Vec<Term>> + Send + Sync;
+
+/// The rows answering a call of a foreign predicate, read as the goal
+/// backtracks into them.
+pub type Rows = Box<dyn Iterator<Item = Vec<Term>>>;
+
+pub(crate) type RowsFn = dyn Fn(&[Term], &Bindings) -> Rows + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct ForeignPredicate(pub(crate) Arc<RowsFn>);
+
+impl fmt::Debug for ForeignPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ForeignPredicate")
+    }
+}
+
+impl Database {
+    /// Makes `name/arity` callable from queries and rules, answered by `predicate`.
+    pub fn register<F>(&mut self, name: &str, arity: Arity, predicate: F)
+    where
+        F: Fn(&[Term], &Bindings) -> Vec<Vec<Term>> + Send + Sync + 'static,
+    {
+        self.register_rows(name, arity, move |args, bindings| {
+            Box::new(predicate(args, bindings).into_iter())
+        });
+    }
+    /// Like `register`, with the rows given lazily.
+    pub(crate) fn register_rows<F>(&mut self, name: &str, arity: Arity, predicate: F)
+    where
+        F: Fn(&[Term], &Bindings) -> Rows + Send + Sync + 'static,
+    {
+        self.foreign.insert(
+            (Atom::new(name), arity),
+            ForeignPredicate(Arc::new(predicate)),
+        );
+    }
+    pub(crate) fn foreign(&self, name: &Atom, arity: Arity) -> Option<Arc<RowsFn>> {
+        self.foreign
+            .get(&(*name, arity))
+            .map(|predicate| Arc::clone(&predicate.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+
+    fn ages() -> Database {
+        let mut db = Database::new();
+        db.register("age", 2, |args, bindings| {
+            [("sam", 30), ("popeye", 45)]
+                .iter()
+                .map(|(name, age)| vec![Term::atom_from_str(name), Term::Integer(*age)])
+                .filter(|row| match bindings.walk(&args[0]) {
+                    Term::Variable(_) => true,
+                    name => *name == row[0],
+                })
+                .collect()
+        });
+        db
+    }
+
+    #[test]
+    fn foreign_predicate_enumerates_rows() {
+        let db = ages();
+        assert_eq!(
+            answers(&db, "age(X, A)").unwrap(),
+            vec!["X = sam, A = 30", "X = popeye, A = 45"]
+        );
+        assert_eq!(answers(&db, "age(popeye, A)").unwrap(), vec!["A = 45"]);
+        assert!(answers(&db, "age(sam, 45)").unwrap().is_empty());
+    }
+
+    #[test]
+    fn callable_from_rules() {
+        let mut db = ages();
+        db.consult("known(X) :- age(X, _).").unwrap();
+        assert_eq!(
+            answers(&db, "known(X)").unwrap(),
+            vec!["X = sam", "X = popeye"]
+        );
+    }
+
+    #[test]
+    fn side_effects() {
+        let mut db = Database::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        db.register("log", 1, move |args, bindings| {
+            log.lock()
+                .unwrap()
+                .push(bindings.resolve(&args[0]).to_string());
+            vec![args.to_vec()]
+        });
+        db.consult("greet(X) :- log(hello), log(X).").unwrap();
+        assert_eq!(answers(&db, "greet(world)").unwrap().len(), 1);
+        assert_eq!(*seen.lock().unwrap(), vec!["hello", "world"]);
+    }
+}
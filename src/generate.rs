@@ -0,0 +1,231 @@
+//! Random well-formed terms, clauses and databases, e.g. for property tests.
+//! With the `quickcheck` feature, `Term` and `Functor` also implement
+//! `quickcheck::Arbitrary` using the default `Config`.
+use crate::compat::HashMap;
+use crate::{Arity, Atom, Database, Functor, Term, Variable};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+use rand::Rng;
+
+/// The shape of generated terms. Each term is an atom, integer, variable or
+/// compound term, picked with the given relative weights; below `max_depth`
+/// levels only the first three are picked.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub max_depth: usize,
+    pub max_arity: Arity,
+    /// The most goals in a generated clause body.
+    pub max_body: usize,
+    /// Names of atoms and functors. Must not be empty.
+    pub atoms: Vec<String>,
+    /// Names of variables. Within one clause, a name is always the same variable.
+    pub variables: Vec<String>,
+    pub integers: RangeInclusive<i64>,
+    pub atom_weight: u32,
+    pub integer_weight: u32,
+    pub variable_weight: u32,
+    pub compound_weight: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_depth: 3,
+            max_arity: 3,
+            max_body: 3,
+            atoms: ["a", "b", "c", "f", "g"].map(String::from).to_vec(),
+            variables: ["X", "Y", "Z"].map(String::from).to_vec(),
+            integers: -100..=100,
+            atom_weight: 3,
+            integer_weight: 2,
+            variable_weight: 2,
+            compound_weight: 3,
+        }
+    }
+}
+
+impl Config {
+    pub fn term<R: Rng>(&self, rng: &mut R) -> Term {
+        self.term_at(rng, self.max_depth, &mut HashMap::new())
+    }
+    /// A fact: a functor of up to `max_arity` random arguments.
+    pub fn fact<R: Rng>(&self, rng: &mut R) -> Functor {
+        self.goal(rng, &mut HashMap::new())
+    }
+    /// A rule with up to `max_body` goals, sharing variables with its head.
+    pub fn clause<R: Rng>(&self, rng: &mut R) -> Functor {
+        let mut vars = HashMap::new();
+        let head = self.goal(rng, &mut vars);
+        let body = (0..rng.gen_range(0..=self.max_body))
+            .map(|_| self.goal(rng, &mut vars))
+            .collect();
+        Functor::new_rule(head.name, head.into_args(), body)
+    }
+    /// A database of `clauses` random clauses.
+    pub fn database<R: Rng>(&self, rng: &mut R, clauses: usize) -> Database {
+        Database::from_rules((0..clauses).map(|_| self.clause(rng)).collect())
+    }
+    fn goal<R: Rng>(&self, rng: &mut R, vars: &mut HashMap<String, Term>) -> Functor {
+        let args = (0..rng.gen_range(0..=self.max_arity))
+            .map(|_| self.term_at(rng, self.max_depth.saturating_sub(1), vars))
+            .collect();
+        Functor::new_fact(self.atom(rng), args)
+    }
+    fn atom<R: Rng>(&self, rng: &mut R) -> Atom {
+        Atom::new(&self.atoms[rng.gen_range(0..self.atoms.len())])
+    }
+    fn term_at<R: Rng>(&self, rng: &mut R, depth: usize, vars: &mut HashMap<String, Term>) -> Term {
+        let weights = [
+            self.atom_weight,
+            self.integer_weight,
+            if self.variables.is_empty() {
+                0
+            } else {
+                self.variable_weight
+            },
+            if depth == 0 || self.max_arity == 0 {
+                0
+            } else {
+                self.compound_weight
+            },
+        ];
+        let total: u32 = weights.iter().sum();
+        let mut pick = if total == 0 {
+            0
+        } else {
+            rng.gen_range(0..total)
+        };
+        let kind = weights
+            .iter()
+            .position(|&weight| {
+                let picked = pick < weight;
+                pick = pick.saturating_sub(weight);
+                picked
+            })
+            .unwrap_or(0);
+        match kind {
+            1 => Term::Integer(rng.gen_range(self.integers.clone())),
+            2 => {
+                let name = &self.variables[rng.gen_range(0..self.variables.len())];
+                vars.entry(name.clone())
+                    .or_insert_with(|| Term::Variable(Variable::new_named(name, rng)))
+                    .clone()
+            }
+            3 => {
+                let args = (0..rng.gen_range(1..=self.max_arity))
+                    .map(|_| self.term_at(rng, depth - 1, vars))
+                    .collect();
+                Term::Functor(Box::new(Functor::new_fact(self.atom(rng), args)))
+            }
+            _ => Term::Atom(self.atom(rng)),
+        }
+    }
+}
+
+#[cfg(feature = "quickcheck")]
+mod arbitrary {
+    use super::Config;
+    use crate::{Functor, Term};
+    use core::iter;
+    use quickcheck::{Arbitrary, Gen};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    impl Arbitrary for Term {
+        fn arbitrary(g: &mut Gen) -> Self {
+            Config::default().term(&mut StdRng::seed_from_u64(u64::arbitrary(g)))
+        }
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            match self {
+                Term::Integer(n) => Box::new(n.shrink().map(Term::Integer)),
+                Term::Functor(f) => Box::new(f.args.clone().into_iter()),
+                _ => Box::new(iter::empty()),
+            }
+        }
+    }
+
+    impl Arbitrary for Functor {
+        fn arbitrary(g: &mut Gen) -> Self {
+            Config::default().clause(&mut StdRng::seed_from_u64(u64::arbitrary(g)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compat::thread_rng;
+
+    fn depth(term: &Term) -> usize {
+        match term {
+            Term::Functor(f) => 1 + f.args.iter().map(depth).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn respects_depth_and_arity() {
+        let mut rng = thread_rng();
+        let config = Config {
+            max_depth: 2,
+            max_arity: 2,
+            ..Config::default()
+        };
+        for _ in 0..200 {
+            let term = config.term(&mut rng);
+            assert!(depth(&term) <= 2);
+            if let Term::Functor(f) = term {
+                assert!((1..=2).contains(&f.arity()));
+            }
+        }
+    }
+
+    #[test]
+    fn clause_variables_are_shared() {
+        let mut rng = thread_rng();
+        let config = Config {
+            variables: vec![String::from("X")],
+            atom_weight: 0,
+            integer_weight: 0,
+            compound_weight: 0,
+            max_arity: 1,
+            ..Config::default()
+        };
+        let mut aliases = Vec::new();
+        for _ in 0..20 {
+            let clause = config.clause(&mut rng);
+            for goal in std::iter::once(&clause).chain(&clause.body) {
+                for arg in &goal.args {
+                    let Term::Variable(v) = arg else {
+                        panic!("expected only variables")
+                    };
+                    aliases.push(v.alias);
+                }
+            }
+            aliases.dedup();
+            assert!(aliases.len() <= 1);
+            aliases.clear();
+        }
+    }
+
+    #[test]
+    fn generates_databases() {
+        let db = Config::default().database(&mut thread_rng(), 25);
+        assert_eq!(db.module("user").expect("user module").len(), 25);
+    }
+
+    #[cfg(feature = "quickcheck")]
+    quickcheck::quickcheck! {
+        fn display_round_trips(term: Term) -> quickcheck::TestResult {
+            let text = term.to_string();
+            if text.contains('-') {
+                // negative integers print as `-N` but parse as `-(N)`
+                return quickcheck::TestResult::discard();
+            }
+            let parsed = crate::parse_term(&text).expect("generated terms parse");
+            quickcheck::TestResult::from_bool(parsed.to_string() == text)
+        }
+    }
+}
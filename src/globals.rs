@@ -0,0 +1,111 @@
+//! Global variables: `nb_setval/2` and `nb_getval/2`. They live on the
+//! database rather than in a query's bindings, so backtracking doesn't undo
+//! them and they keep their values from one query to the next.
+
+use crate::builtins::{self, Alternatives};
+use crate::compat::{HashMap, Mutex, MutexGuard};
+use crate::error;
+use crate::solver::{Bindings, Solver};
+use crate::{Atom, Term};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::fmt;
+use core::iter;
+
+#[derive(Default)]
+pub(crate) struct Globals(Arc<Mutex<HashMap<Atom, Term>>>);
+
+// a cloned database starts with the same values but sets its own
+impl Clone for Globals {
+    fn clone(&self) -> Self {
+        Self(Arc::new(Mutex::new(self.values().clone())))
+    }
+}
+
+impl fmt::Debug for Globals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Globals")
+    }
+}
+
+impl Globals {
+    fn values(&self) -> MutexGuard<'_, HashMap<Atom, Term>> {
+        self.0.lock()
+    }
+}
+
+fn key(term: &Term, bindings: &Bindings) -> Result<Atom, Term> {
+    match bindings.walk(term) {
+        Term::Atom(name) => Ok(*name),
+        Term::Variable(_) => Err(error::instantiation_error()),
+        other => Err(error::type_error("atom", other.clone())),
+    }
+}
+
+/// `nb_setval(Key, Value)`: sets the global variable `Key` to a copy of
+/// `Value`.
+pub(crate) fn nb_setval(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    match key(&args[0], bindings) {
+        Ok(name) => {
+            let value = bindings.copy_term(&args[1]);
+            solver.globals().values().insert(name, value);
+            builtins::success()
+        }
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+/// `nb_getval(Key, Value)`: unifies `Value` with the value of the global
+/// variable `Key`, which must have been set.
+pub(crate) fn nb_getval(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let value = key(&args[0], bindings).and_then(|name| {
+        let value = solver.globals().values().get(&name).cloned();
+        value.ok_or_else(|| error::existence_error("variable", Term::Atom(name)))
+    });
+    match value {
+        Ok(value) => Box::new(iter::once(builtins::unify(vec![(args[1].clone(), value)]))),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn count(db: &Database, query: &str) -> Result<usize, SolveError> {
+        let query = parse_query(query).unwrap();
+        db.try_satisfy_all(query.goal).map(|answers| answers.len())
+    }
+
+    #[test]
+    fn values_outlive_backtracking_and_queries() {
+        let mut db = Database::new();
+        db.consult(
+            "bump :- nb_getval(counter, N), M is N + 1, nb_setval(counter, M).
+            count_items(L) :- nb_setval(counter, 0), (member(_, L), bump, fail ; true).
+            member(X, [X|_]).
+            member(X, [_|T]) :- member(X, T).",
+        )
+        .unwrap();
+        assert_eq!(
+            count(&db, "count_items([a, b, c]), nb_getval(counter, 3)").unwrap(),
+            1
+        );
+        assert_eq!(count(&db, "nb_getval(counter, 3)").unwrap(), 1);
+        let query = "nb_setval(pair, f(X, a)), X = b, nb_getval(pair, f(Y, a)), var(Y)";
+        assert_eq!(count(&db, query).unwrap(), 1);
+        let copy = db.clone();
+        assert_eq!(count(&copy, "nb_setval(counter, 10)").unwrap(), 1);
+        assert_eq!(count(&db, "nb_getval(counter, 3)").unwrap(), 1);
+        match count(&db, "nb_getval(missing, _)") {
+            Err(SolveError::Uncaught(ball)) => assert!(
+                ball.to_string()
+                    .contains("existence_error(variable, missing)"),
+                "{}",
+                ball
+            ),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Reading built-ins: `read_term/2` and `read/1`. They read from the
+//! database's input source, stdin unless replaced with `Database::set_input`.
+
+use crate::builtins::{self, Alternatives};
+use crate::error;
+use crate::parser;
+use crate::solver::{Bindings, Solver};
+use crate::{Database, DoubleQuotes, Term};
+use std::fmt;
+use std::io::{self, BufRead};
+use std::iter;
+use std::sync::{Arc, Mutex};
+
+pub(crate) type Source = dyn BufRead + Send;
+
+#[derive(Clone)]
+pub(crate) struct Input(Arc<Mutex<Reader>>);
+
+struct Reader {
+    source: Box<Source>,
+    // text read from `source` but not yet parsed
+    pending: String,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new(io::BufReader::new(io::stdin()))
+    }
+}
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Input")
+    }
+}
+
+impl Input {
+    fn new<R: BufRead + Send + 'static>(source: R) -> Self {
+        Self(Arc::new(Mutex::new(Reader {
+            source: Box::new(source),
+            pending: String::new(),
+        })))
+    }
+    // the next clause, reading lines until one is complete; `Ok(None)` at
+    // the end of input
+    fn read(&self, double_quotes: DoubleQuotes) -> Result<Option<parser::Read>, Term> {
+        let mut reader = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Reader { source, pending } = &mut *reader;
+        loop {
+            if let Some((used, read)) = parser::read_clause(pending, double_quotes) {
+                pending.drain(..used);
+                return read
+                    .map(Some)
+                    .map_err(|err| error::syntax_error(&err.message));
+            }
+            match source.read_line(pending) {
+                Ok(0) if pending.trim().is_empty() => return Ok(None),
+                Ok(0) => {
+                    pending.clear();
+                    return Err(error::syntax_error("end of file in clause"));
+                }
+                Ok(_) => {}
+                Err(err) => return Err(error::io_error(&err.to_string())),
+            }
+        }
+    }
+}
+
+impl Database {
+    /// Makes `read_term/2` and `read/1` read from `source` instead of stdin,
+    /// e.g. an `io::Cursor` over a string.
+    pub fn set_input<R: BufRead + Send + 'static>(&mut self, source: R) {
+        self.input = Input::new(source);
+    }
+}
+
+pub(crate) fn read(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    read_term(solver, &[args[0].clone(), Term::nil()], bindings)
+}
+
+/// `read_term(Term, Options)`: reads the next clause, or `end_of_file`.
+/// The options `variable_names(Names)` and `variables(Vars)` give the
+/// clause's `Name = Var` pairs and all its variables.
+pub(crate) fn read_term(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let Some(options) = builtins::list(solver, &args[1], bindings) else {
+        return builtins::failure();
+    };
+    let (term, names) = match solver.input().read(solver.flags().double_quotes) {
+        Ok(Some(read)) => read,
+        Ok(None) => (Term::atom_from_str("end_of_file"), Vec::new()),
+        Err(ball) => return builtins::raise(solver, ball),
+    };
+    let mut unifier = vec![(args[0].clone(), term.clone())];
+    for option in options {
+        let f = match bindings.walk(&option) {
+            Term::Functor(f) => f,
+            Term::Variable(_) => return builtins::raise(solver, error::instantiation_error()),
+            other => return builtins::raise(solver, read_option(other.clone())),
+        };
+        let value = match (f.name.as_str(), f.arity()) {
+            ("variable_names", 1) => Term::list_from_vec(
+                names
+                    .iter()
+                    .map(|(name, var)| {
+                        let name = Term::atom_from_str(name);
+                        Term::functor_from_str("=", vec![name, var.clone()])
+                    })
+                    .collect(),
+            ),
+            ("variables", 1) => {
+                let mut vars = Vec::new();
+                builtins::term_variables(&term, &mut vars);
+                Term::list_from_vec(vars.into_iter().map(Term::Variable).collect())
+            }
+            _ => return builtins::raise(solver, read_option(bindings.resolve(&option))),
+        };
+        unifier.push((f.args[0].clone(), value));
+    }
+    Box::new(iter::once(builtins::unify(unifier)))
+}
+
+fn read_option(option: Term) -> Term {
+    error::domain_error("read_option", option)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+    use std::io::Cursor;
+
+    fn reading(text: &str) -> Database {
+        let mut db = Database::new();
+        db.set_input(Cursor::new(text.to_string()));
+        db
+    }
+
+    #[test]
+    fn reads_terms_in_turn() {
+        let db = reading("likes(sam, pizza).\nfoo. 'multi\nline'.\n");
+        assert_eq!(
+            answers(&db, "read(X), read(Y)").unwrap(),
+            vec!["X = likes(sam, pizza), Y = foo"]
+        );
+        assert_eq!(
+            answers(&db, "read(X), read(Y)").unwrap(),
+            vec!["X = 'multi\\nline', Y = end_of_file"]
+        );
+    }
+
+    #[test]
+    fn reads_variable_names() {
+        let db = reading("f(X, Y, X).");
+        assert_eq!(
+            answers(&db, "read_term(T, [variable_names(['X' = a, 'Y' = b])])").unwrap(),
+            vec!["T = f(a, b, a)"]
+        );
+        let db = reading("f(X, _, X, Y).");
+        assert_eq!(
+            answers(&db, "read_term(T, [variables([x, y, z])])").unwrap(),
+            vec!["T = f(x, y, x, z)"]
+        );
+        let db = reading("f.");
+        assert!(answers(&db, "read_term(_, [bogus(_)])").is_err());
+    }
+
+    #[test]
+    fn syntax_errors_are_thrown() {
+        let db = reading("foo(. bar.\n");
+        match answers(&db, "read(_)") {
+            Err(SolveError::Uncaught(ball)) => assert!(ball.to_string().contains("syntax_error")),
+            other => panic!("read a malformed clause: {:?}", other),
+        }
+        assert_eq!(answers(&db, "read(X)").unwrap(), vec!["X = bar"]);
+        let db = reading("unfinished(");
+        assert!(answers(&db, "read(_)").is_err());
+    }
+}
@@ -0,0 +1,344 @@
+//! Just enough JSON for the language server's messages, and for loading
+//! JSON documents as facts: `Database::load_json` reads each document in a
+//! file as a term, arrays as lists and objects as their fields, and adds it
+//! as the argument of a fact.
+#![cfg_attr(not(feature = "lsp"), allow(dead_code))]
+
+use crate::solution::write_string;
+use crate::{Atom, Database, Functor, Term};
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+/// How `Database::load_json` reads an object.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JsonObjects {
+    /// `json([Key-Value, ...])`, a list of its fields as pairs.
+    #[default]
+    Pairs,
+    /// `json(Key(Value), ...)`, a field to an argument.
+    Functors,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub(crate) fn object<const N: usize>(fields: [(&str, Json); N]) -> Self {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+    pub(crate) fn string(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+    /// The value of `key`, if this is an object that has it.
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    /// Follows a path of keys through nested objects.
+    pub(crate) fn at(&self, path: &[&str]) -> Option<&Json> {
+        path.iter().try_fold(self, |json, key| json.get(key))
+    }
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub(crate) fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+            _ => None,
+        }
+    }
+    pub(crate) fn parse(text: &str) -> Option<Json> {
+        let mut chars = text.chars().peekable();
+        let json = value(&mut chars)?;
+        skip_space(&mut chars);
+        chars.peek().is_none().then_some(json)
+    }
+    /// As a term: strings are atoms, as are `true`, `false` and `null`.
+    /// Numbers that aren't integers are strings, there being no floats.
+    pub(crate) fn to_term(&self, objects: JsonObjects) -> Term {
+        match self {
+            Json::Null => Term::atom_from_str("null"),
+            Json::Bool(b) => Term::atom_from_str(if *b { "true" } else { "false" }),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+                Term::Integer(*n as i64)
+            }
+            Json::Number(n) => Term::String(n.to_string().into()),
+            Json::String(s) => Term::Atom(Atom::new(s)),
+            Json::Array(items) => {
+                Term::list_from_vec(items.iter().map(|item| item.to_term(objects)).collect())
+            }
+            Json::Object(fields) => {
+                let field = |(key, value): &(String, Json)| {
+                    let (key, value) = (Atom::new(key), value.to_term(objects));
+                    match objects {
+                        JsonObjects::Pairs => {
+                            Term::functor_from_str("-", vec![Term::Atom(key), value])
+                        }
+                        JsonObjects::Functors => {
+                            Term::Functor(Box::new(Functor::new_fact(key, vec![value])))
+                        }
+                    }
+                };
+                let fields: Vec<Term> = fields.iter().map(field).collect();
+                match objects {
+                    JsonObjects::Pairs => {
+                        Term::functor_from_str("json", vec![Term::list_from_vec(fields)])
+                    }
+                    JsonObjects::Functors if fields.is_empty() => Term::atom_from_str("json"),
+                    JsonObjects::Functors => Term::functor_from_str("json", fields),
+                }
+            }
+        }
+    }
+}
+
+impl Database {
+    /// Adds `name(Document)` for each JSON document in the file at `path`,
+    /// giving how many were added; documents follow one another, as in JSON
+    /// Lines. Text that isn't JSON is an `InvalidData` error, once the
+    /// documents before it are added.
+    pub fn load_json<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        name: &str,
+        objects: JsonObjects,
+    ) -> io::Result<usize> {
+        self.read_json(&fs::read_to_string(path)?, name, objects)
+    }
+    /// Like `load_json`, reading the documents in `text`.
+    pub fn read_json(&mut self, text: &str, name: &str, objects: JsonObjects) -> io::Result<usize> {
+        let name = Atom::new(name);
+        let mut chars = text.chars().peekable();
+        let mut added = 0;
+        loop {
+            skip_space(&mut chars);
+            if chars.peek().is_none() {
+                return Ok(added);
+            }
+            let Some(json) = value(&mut chars) else {
+                let message = format!("document {} isn't JSON", added + 1);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            };
+            self.add(Functor::new_fact(name, vec![json.to_term(objects)]));
+            added += 1;
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => {
+                let mut quoted = String::new();
+                write_string(&mut quoted, s);
+                f.write_str(&quoted)
+            }
+            Json::Array(items) => {
+                f.write_char('[')?;
+                for (ix, item) in items.iter().enumerate() {
+                    if ix > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(fields) => {
+                f.write_char('{')?;
+                for (ix, (key, value)) in fields.iter().enumerate() {
+                    if ix > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}:{}", Json::string(key), value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn skip_space(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn value(chars: &mut Peekable<Chars>) -> Option<Json> {
+    skip_space(chars);
+    match *chars.peek()? {
+        '{' => {
+            chars.next();
+            let mut fields = Vec::new();
+            skip_space(chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Some(Json::Object(fields));
+            }
+            loop {
+                skip_space(chars);
+                chars.next_if_eq(&'"')?;
+                let key = string(chars)?;
+                skip_space(chars);
+                chars.next_if_eq(&':')?;
+                fields.push((key, value(chars)?));
+                skip_space(chars);
+                match chars.next()? {
+                    ',' => continue,
+                    '}' => return Some(Json::Object(fields)),
+                    _ => return None,
+                }
+            }
+        }
+        '[' => {
+            chars.next();
+            let mut items = Vec::new();
+            skip_space(chars);
+            if chars.next_if_eq(&']').is_some() {
+                return Some(Json::Array(items));
+            }
+            loop {
+                items.push(value(chars)?);
+                skip_space(chars);
+                match chars.next()? {
+                    ',' => continue,
+                    ']' => return Some(Json::Array(items)),
+                    _ => return None,
+                }
+            }
+        }
+        '"' => {
+            chars.next();
+            string(chars).map(Json::String)
+        }
+        't' => keyword(chars, "true", Json::Bool(true)),
+        'f' => keyword(chars, "false", Json::Bool(false)),
+        'n' => keyword(chars, "null", Json::Null),
+        _ => {
+            let mut number = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+                number.push(c);
+            }
+            number.parse().ok().map(Json::Number)
+        }
+    }
+}
+
+fn keyword(chars: &mut Peekable<Chars>, word: &str, json: Json) -> Option<Json> {
+    for expected in word.chars() {
+        chars.next_if_eq(&expected)?;
+    }
+    Some(json)
+}
+
+// the rest of a string whose opening `"` was already consumed
+fn string(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut s = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(s),
+            '\\' => match chars.next()? {
+                'n' => s.push('\n'),
+                'r' => s.push('\r'),
+                't' => s.push('\t'),
+                'b' => s.push('\u{8}'),
+                'f' => s.push('\u{c}'),
+                'u' => {
+                    let unit = hex(chars)?;
+                    let c = if (0xd800..0xdc00).contains(&unit) {
+                        chars.next_if_eq(&'\\')?;
+                        chars.next_if_eq(&'u')?;
+                        let low = hex(chars)?;
+                        char::from_u32(
+                            0x10000 + ((unit - 0xd800) << 10) + (low.checked_sub(0xdc00)?),
+                        )
+                    } else {
+                        char::from_u32(unit)
+                    };
+                    s.push(c?);
+                }
+                c => s.push(c),
+            },
+            c => s.push(c),
+        }
+    }
+}
+
+fn hex(chars: &mut Peekable<Chars>) -> Option<u32> {
+    (0..4).try_fold(0, |n, _| Some(n * 16 + chars.next()?.to_digit(16)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let text =
+            r#"{"id":1,"params":{"uri":"file:///kb.pl","ok":[true,false,null]},"s":"a\"b\nc"}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(
+            json.at(&["params", "uri"]).unwrap().as_str(),
+            Some("file:///kb.pl")
+        );
+        assert_eq!(json.get("id").unwrap().as_usize(), Some(1));
+        assert_eq!(json.to_string(), text);
+        assert_eq!(Json::parse(r#" "é😀" "#), Some(Json::string("é😀")));
+        assert_eq!(Json::parse("[1, 2"), None);
+    }
+
+    #[test]
+    fn documents_become_facts() {
+        use crate::{parse_query, Database};
+        let answers = |db: &Database, query: &str| -> Vec<String> {
+            let query = parse_query(query).unwrap();
+            db.satisfy_all(query.goal.clone())
+                .iter()
+                .map(|bindings| query.solution(bindings).to_json())
+                .collect()
+        };
+        let text = r#"{"name": "sam", "age": 30, "pets": ["olive", "popeye"], "boss": null}
+            {"name": "olive", "age": 25.5, "pets": [], "admin": true}"#;
+        let mut db = Database::new();
+        assert_eq!(db.read_json(text, "user", JsonObjects::Pairs).unwrap(), 2);
+        assert_eq!(
+            answers(&db, "user(json([name-N, age-A|_]))"),
+            [r#"{"N": "sam", "A": 30}"#, r#"{"N": "olive", "A": "25.5"}"#]
+        );
+        assert_eq!(
+            answers(&db, "user(json([_, _, pets-P, admin-true]))"),
+            [r#"{"P": []}"#]
+        );
+        let mut db = Database::new();
+        db.read_json(text, "user", JsonObjects::Functors).unwrap();
+        assert_eq!(
+            answers(&db, "user(json(name(N), _, pets([P|_]), _))"),
+            [r#"{"N": "sam", "P": "olive"}"#]
+        );
+        let error = db
+            .read_json("{} [1, }", "user", JsonObjects::Functors)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "document 2 isn't JSON");
+        assert_eq!(answers(&db, "user(json)").len(), 1);
+    }
+}
@@ -1,75 +1,255 @@
+// the tests run on std either way, with the `std` feature's stand-ins
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+
+extern crate alloc;
+
+// so that derived impls, which name `::amateurlog`, work here too
+#[cfg(feature = "derive")]
+extern crate self as amateurlog;
+
+use crate::compat::HashMap;
+#[cfg(feature = "std")]
+use crate::compat::HashSet;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::fmt;
+use core::ops::ControlFlow;
+use core::str::FromStr;
 use rand::Rng;
-use std::convert::Infallible;
-use std::str::FromStr;
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+
+mod aggregate;
+#[cfg(feature = "std")]
+mod answers;
+mod arena;
+mod arith;
+mod atoms;
+mod attributes;
+#[cfg(feature = "std")]
+mod binary;
+mod builtins;
+mod clpfd;
+mod compat;
+mod config;
+mod convert;
+#[cfg(feature = "std")]
+mod csv;
+mod datalog;
+mod debugger;
+mod distinct;
+mod dynamic;
+mod error;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod flags;
+mod foreign;
+pub mod generate;
+mod globals;
+#[cfg(feature = "std")]
+mod input;
+#[cfg(feature = "std")]
+mod json;
+mod listing;
+mod load;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+mod merge;
+mod module;
+mod observer;
+mod order;
+mod output;
+mod parser;
+mod pretty;
+#[cfg(feature = "std")]
+mod profile;
+mod proof;
+mod random;
+#[cfg(feature = "std")]
+mod rdf;
+mod solution;
+mod solver;
+#[cfg(feature = "sqlite")]
+mod sql;
+mod stats;
+#[cfg(feature = "std")]
+mod stream;
+mod strings;
+mod symbol;
+#[cfg(feature = "tracing")]
+mod telemetry;
+mod terms;
+#[cfg(test)]
+mod testing;
+mod typed;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "derive")]
+pub use amateurlog_derive::{predicate, ToFact};
+#[cfg(feature = "std")]
+pub use answers::Answers;
+pub use config::{CancellationToken, DoubleQuotes, OccursCheck, SolverConfig, Strategy};
+pub use convert::{FromTerm, Predicate, ToFact, ToTerm};
+#[cfg(feature = "std")]
+pub use csv::{Column, CsvSchema};
+pub use datalog::{DatalogError, Model};
+pub use debugger::{Command, Debugger, Port};
+pub use error::{Resource, SolveError};
+pub use flags::{Flags, Unknown};
+pub use foreign::{ForeignFn, Rows};
+#[cfg(feature = "std")]
+pub use json::JsonObjects;
+pub use module::Module;
+pub use observer::{ClauseId, Event, Observer};
+pub use parser::{parse_query, parse_term, tokenize, ParseError, ParseErrors, Query, Span, Token};
+pub use pretty::Pretty;
+#[cfg(feature = "std")]
+pub use profile::{PredicateProfile, Profile};
+pub use proof::Proof;
+pub use solution::{solutions_to_json, Solution};
+pub use solver::Bindings;
+pub use stats::{PredicateStats, Stats};
+#[cfg(feature = "async")]
+pub use stream::Solutions;
+pub use typed::{Arguments, FromArgs, IntoArg, Signature, TypedQuery, Var};
+
+// what derived impls name, so they work in `no_std` crates too
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::boxed::Box;
+    pub use alloc::vec;
+    pub use alloc::vec::Vec;
+}
+
+/// An interned name, so copying, comparing and hashing atoms never touches
+/// their text.
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub struct Atom(u32);
+
+impl Atom {
+    pub(crate) fn new(name: &str) -> Self {
+        Self(symbol::intern(name))
+    }
+    pub fn as_str(&self) -> &'static str {
+        symbol::name(self.0)
+    }
+}
+
+impl fmt::Debug for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Atom({:?})", self.as_str())
+    }
+}
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
-pub struct Atom(String);
+impl From<Atom> for String {
+    fn from(atom: Atom) -> Self {
+        String::from(atom.as_str())
+    }
+}
 
-impl Into<String> for Atom {
-    fn into(self) -> String {
-        self.0
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        parser::write_atom(f, self.as_str())
     }
 }
 
 impl FromStr for Atom {
     type Err = Infallible; // TODO: numbers
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self(String::from(s)))
+        Ok(Self::new(s))
     }
 }
 
+/// A variable: what it was called, if anything, and the alias that tells it
+/// apart from every other. Both are plain numbers, so variables are copied,
+/// compared and hashed without touching the heap.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Variable {
     name: VariableName,
-    alias: String,
-    bound_to: Option<VariableBinding>,
+    alias: Alias,
+    // boxed, as only `Database::satisfy` binds variables this way
+    bound_to: Option<Box<VariableBinding>>,
+}
+
+/// The number telling a variable apart from every other.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Alias(u64);
+
+impl fmt::Display for Alias {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "var_{}", self.0)
+    }
 }
 
 impl Variable {
     pub fn new_named<Generator: Rng, N: AsRef<str>>(name: N, rng: &mut Generator) -> Self {
         Self {
-            name: VariableName::Name(String::from(name.as_ref())),
-            alias: Self::gen_alias(rng),
+            name: VariableName::Name(Atom::new(name.as_ref())),
+            alias: Alias(rng.gen()),
             bound_to: None,
         }
     }
     pub fn new_anonymous<Generator: Rng>(rng: &mut Generator) -> Self {
         Self {
             name: VariableName::Anonymous,
-            alias: Self::gen_alias(rng),
+            alias: Alias(rng.gen()),
             bound_to: None,
         }
     }
-    fn gen_alias<Generator: Rng>(rng: &mut Generator) -> String {
-        format!("var_{}", rng.gen::<u8>())
-    }
     pub fn bind(&mut self, binding: VariableBinding) {
-        self.bound_to = Some(binding);
+        self.bound_to = Some(Box::new(binding));
     }
-    fn resolve(&self) -> Option<Atom> {
-        match self.bound_to {
-            Some(VariableBinding::Atom(ref a)) => Some(a.clone()),
-            Some(VariableBinding::Variable(ref v)) => v.resolve(),
-            None => None,
+    /// The atom the variable was bound to with `bind`, following chains of
+    /// variables.
+    pub fn resolve(&self) -> Option<Atom> {
+        match self.value()? {
+            Term::Atom(a) => Some(a),
+            _ => None,
         }
     }
-    fn resolves_to(&self, other: &Atom) -> bool {
-        if let Some(ref a) = self.resolve() {
-            a == other
-        } else {
-            false
+    /// The term the variable was bound to with `bind`, following chains of
+    /// variables: the last variable of a chain if it's unbound, or what the
+    /// last one is bound to.
+    pub fn value(&self) -> Option<Term> {
+        let mut var = self;
+        loop {
+            match var.bound_to.as_deref()? {
+                VariableBinding::Atom(a) => return Some(Term::Atom(*a)),
+                VariableBinding::Term(term) => return Some(term.clone()),
+                VariableBinding::Variable(v) if v.bound_to.is_none() => {
+                    return Some(Term::Variable(Variable::clone(v)))
+                }
+                VariableBinding::Variable(v) => var = v,
+            }
         }
     }
-    fn is_bound(&self) -> bool {
-        return self.bound_to.is_some();
+}
+
+// takes a chain of `bind`ings apart a link at a time, rather than a Rust
+// stack frame each
+impl Drop for Variable {
+    fn drop(&mut self) {
+        let mut next = self.bound_to.take();
+        while let Some(binding) = next {
+            next = match *binding {
+                VariableBinding::Variable(mut v) => v.bound_to.take(),
+                VariableBinding::Atom(_) | VariableBinding::Term(_) => None,
+            };
+        }
     }
-    fn corefers_to(&self, other: &Variable) -> bool {
-        match (&self.bound_to, &other.bound_to) {
-            (
-                Some(VariableBinding::Variable(ref my_v)),
-                Some(VariableBinding::Variable(ref other_v)),
-            ) => my_v == other_v,
-            (_, _) => false,
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            VariableName::Name(name) => f.write_str(name.as_str()),
+            VariableName::Anonymous => write!(f, "_{}", self.alias),
         }
     }
 }
@@ -77,6 +257,9 @@ impl Variable {
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Term {
     Atom(Atom),
+    Integer(i64),
+    /// Text, written in double quotes.
+    String(Arc<str>),
     Variable(Variable),
     Functor(Box<Functor>),
 }
@@ -88,38 +271,186 @@ impl Term {
     pub fn variable_from_str<Generator: Rng, N: AsRef<str>>(name: N, rng: &mut Generator) -> Self {
         Self::Variable(Variable::new_named(name, rng))
     }
+    /// Copies the term with each distinct variable replaced by a fresh one.
+    pub fn copy_fresh<Generator: Rng>(&self, rng: &mut Generator) -> Self {
+        self.rename(rng, &mut HashMap::new())
+    }
+    // like `copy_fresh`, reusing the replacements already in `renamed`
+    pub(crate) fn rename<Generator: Rng>(
+        &self,
+        rng: &mut Generator,
+        renamed: &mut HashMap<Alias, Term>,
+    ) -> Self {
+        // what's left to do, last first, building terms on `done` as their
+        // arguments are finished, so deep terms need no deep Rust stack
+        enum Task<'a> {
+            Rename(&'a Term),
+            Build(&'a Functor),
+        }
+        let mut tasks = vec![Task::Rename(self)];
+        let mut done: Vec<Term> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Rename(Term::Variable(v)) => done.push(
+                    renamed
+                        .entry(v.alias)
+                        .or_insert_with(|| Term::Variable(Variable::new_anonymous(rng)))
+                        .clone(),
+                ),
+                Task::Rename(Term::Functor(f)) => {
+                    tasks.push(Task::Build(f));
+                    tasks.extend(f.args.iter().rev().map(Task::Rename));
+                }
+                Task::Rename(term) => done.push(term.clone()),
+                Task::Build(f) => {
+                    let args = done.split_off(done.len() - f.arity());
+                    done.push(Term::Functor(Box::new(Functor::new_fact(f.name, args))));
+                }
+            }
+        }
+        done.pop().expect("one term renamed")
+    }
+    pub fn functor_from_str(name: &str, args: Vec<Term>) -> Self {
+        Self::Functor(Box::new(Functor::new_fact(
+            Atom::from_str(name).unwrap(),
+            args,
+        )))
+    }
+    pub fn nil() -> Self {
+        Self::atom_from_str("[]")
+    }
+    pub fn cons(head: Term, tail: Term) -> Self {
+        Self::functor_from_str(".", vec![head, tail])
+    }
+    pub fn list_from_vec(items: Vec<Term>) -> Self {
+        items
+            .into_iter()
+            .rev()
+            .fold(Self::nil(), |tail, head| Self::cons(head, tail))
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Atom(a) => write!(f, "{}", a),
+            Term::Integer(n) => write!(f, "{}", n),
+            Term::String(text) => parser::write_string(f, text),
+            Term::Variable(v) => write!(f, "{}", v),
+            Term::Functor(func) => write!(f, "{}", func),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum VariableBinding {
     Variable(Box<Variable>),
     Atom(Atom),
+    /// Any other term: a number, a string or a compound term.
+    Term(Term),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum VariableName {
     Anonymous,
-    Name(String),
+    Name(Atom),
 }
 
 pub type Arity = usize;
 
-#[derive(Clone, Debug, PartialEq)]
-pub enum FunctorState {
-    NotYetMatched,
-    Matched,
-    NoMatch,
-    Fulfilled,
-}
-
 // Conjunction (,) is a 0-arity functor
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Functor {
     name: Atom,
+    // next to `name`, so the two share a word
+    ix: u32,
     args: Vec<Term>,
-    body: Vec<Box<Functor>>,
-    state: FunctorState,
-    ix: usize,
+    body: Vec<Functor>,
+    // where it was read from, if consulted
+    span: Option<Arc<Span>>,
+}
+
+// how deeply copying a term recurses before going on with a work list
+const CLONE_DEPTH: usize = 64;
+
+// copying a deeply nested term goes on, past `CLONE_DEPTH`, on a work list of
+// its own rather than a Rust stack frame per level
+impl Clone for Functor {
+    fn clone(&self) -> Self {
+        self.clone_within(CLONE_DEPTH)
+    }
+}
+
+impl Functor {
+    fn with_args(&self, args: Vec<Term>) -> Self {
+        Functor {
+            name: self.name,
+            ix: self.ix,
+            args,
+            body: self.body.clone(),
+            span: self.span.clone(),
+        }
+    }
+    fn clone_within(&self, depth: usize) -> Self {
+        if depth == 0 {
+            return self.clone_deep();
+        }
+        let args = self.args.iter().map(|arg| match arg {
+            Term::Functor(f) => Term::Functor(Box::new(f.clone_within(depth - 1))),
+            arg => arg.clone(),
+        });
+        self.with_args(args.collect())
+    }
+    fn clone_deep(&self) -> Self {
+        enum Task<'a> {
+            Copy(&'a Term),
+            Build(&'a Functor),
+        }
+        let mut tasks: Vec<Task> = self.args.iter().rev().map(Task::Copy).collect();
+        let mut done: Vec<Term> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Copy(Term::Functor(f)) => {
+                    tasks.push(Task::Build(f));
+                    tasks.extend(f.args.iter().rev().map(Task::Copy));
+                }
+                Task::Copy(term) => done.push(term.clone()),
+                Task::Build(f) => {
+                    let args = done.split_off(done.len() - f.arity());
+                    done.push(Term::Functor(Box::new(f.with_args(args))));
+                }
+            }
+        }
+        self.with_args(done)
+    }
+}
+
+// dropping a deeply nested term takes its compound arguments apart in a
+// loop rather than a Rust stack frame each: the last one is dropped next,
+// and any others (rarer, as in a list's items) wait on a work list
+impl Drop for Functor {
+    fn drop(&mut self) {
+        let mut pending: Vec<Box<Functor>> = Vec::new();
+        let mut next = take_compounds(&mut self.args, &mut pending);
+        while let Some(mut f) = next.or_else(|| pending.pop()) {
+            next = take_compounds(&mut f.args, &mut pending);
+        }
+    }
+}
+
+// the last compound term of `args`, taken out along with the others, which
+// go on `pending` (still boxed, as they're to be freed anyway)
+#[allow(clippy::vec_box)]
+fn take_compounds(args: &mut [Term], pending: &mut Vec<Box<Functor>>) -> Option<Box<Functor>> {
+    let mut last = None;
+    for arg in args {
+        if let Term::Functor(_) = arg {
+            if let Term::Functor(f) = core::mem::replace(arg, Term::Integer(0)) {
+                pending.extend(last.replace(f));
+            }
+        }
+    }
+    last
 }
 
 impl PartialEq for Functor {
@@ -130,29 +461,121 @@ impl PartialEq for Functor {
 
 impl Eq for Functor {}
 
+impl fmt::Display for Functor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // what's left to write, last first, so deep terms need no deep Rust
+        // stack
+        enum Task<'a> {
+            Write(&'a Term),
+            Text(&'static str),
+        }
+        fn arguments<'a>(functor: &'a Functor, tasks: &mut Vec<Task<'a>>) {
+            if functor.args.is_empty() {
+                return;
+            }
+            tasks.push(Task::Text(")"));
+            for (ix, arg) in functor.args.iter().enumerate().rev() {
+                tasks.push(Task::Write(arg));
+                tasks.push(Task::Text(if ix == 0 { "(" } else { ", " }));
+            }
+        }
+        write!(f, "{}", self.name)?;
+        let mut tasks = Vec::new();
+        arguments(self, &mut tasks);
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Write(Term::Functor(functor)) => {
+                    write!(f, "{}", functor.name)?;
+                    arguments(functor, &mut tasks);
+                }
+                Task::Write(term) => write!(f, "{}", term)?,
+                Task::Text(text) => f.write_str(text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Functor {
     pub fn new_fact(name: Atom, args: Vec<Term>) -> Self {
         Self::new_rule(name, args, Vec::new())
     }
-    pub fn new_rule(name: Atom, args: Vec<Term>, body: Vec<Box<Functor>>) -> Self {
+    pub fn new_rule(name: Atom, args: Vec<Term>, body: Vec<Functor>) -> Self {
         Self {
             name,
             args,
             body,
-            state: FunctorState::NotYetMatched,
             ix: 0,
+            span: None,
         }
     }
-    fn is_not_yet_matched(&self) -> bool {
-        self.state == FunctorState::NotYetMatched
+    /// The arguments, taken out of the functor.
+    pub(crate) fn into_args(mut self) -> Vec<Term> {
+        core::mem::take(&mut self.args)
+    }
+    pub(crate) fn into_parts(mut self) -> (Atom, Vec<Term>, Option<Arc<Span>>) {
+        (self.name, core::mem::take(&mut self.args), self.span.take())
+    }
+    pub(crate) fn located(mut self, span: Option<Arc<Span>>) -> Self {
+        self.span = span;
+        self
+    }
+    pub fn name(&self) -> &Atom {
+        &self.name
+    }
+    pub fn args(&self) -> &[Term] {
+        &self.args
+    }
+    /// The goals of a rule's body; empty for a fact.
+    pub fn body(&self) -> &[Functor] {
+        &self.body
+    }
+    pub fn arity(&self) -> Arity {
+        self.args.len()
+    }
+    /// Where the clause or goal was written, if it was consulted.
+    pub fn span(&self) -> Option<&Span> {
+        self.span.as_deref()
     }
 }
 
+#[cfg(feature = "parallel")]
+const PARALLEL_SPLIT_DEPTH: usize = 2;
+
+/// A knowledge base. Queries take `&self` and never change the stored
+/// clauses (`assert/1` and `retract/1` only change the query's own view), so
+/// a `Database` can be shared across threads, e.g. in an `Arc`, and queried
+/// from all of them at once. Loading clauses takes `&mut self`, as do
+/// `for_each_solution_mut` and `for_each_proof_mut`, which keep what their
+/// query asserts and retracts.
 #[derive(Clone, Debug)]
 pub struct Database {
-    facts: Vec<Functor>,
+    modules: HashMap<Atom, Module>,
+    loading: Atom,
+    foreign: HashMap<(Atom, Arity), foreign::ForeignPredicate>,
+    #[cfg(feature = "std")]
+    input: input::Input,
+    output: output::Output,
+    config: SolverConfig,
+    tracing: bool,
+    debugging: debugger::Debugging,
+    random: random::Random,
+    globals: globals::Globals,
+    warnings: Vec<ParseError>,
+    // canonical paths of the files loaded, and of those being loaded, the
+    // innermost last
+    #[cfg(feature = "std")]
+    loaded_files: HashSet<PathBuf>,
+    #[cfg(feature = "std")]
+    loading_files: Vec<PathBuf>,
 }
 
+// `Database` is documented as safe to share between threads; keep it so
+const _: () = {
+    const fn shareable<T: Send + Sync>() {}
+    shareable::<Database>()
+};
+
 impl Default for Database {
     fn default() -> Self {
         Self::new()
@@ -162,98 +585,269 @@ impl Default for Database {
 impl Database {
     pub fn new() -> Self {
         Self {
-            facts: Vec::default(),
+            modules: module::user_modules(),
+            loading: module::user(),
+            foreign: HashMap::new(),
+            #[cfg(feature = "std")]
+            input: input::Input::default(),
+            output: output::Output::default(),
+            config: SolverConfig::default(),
+            tracing: false,
+            debugging: debugger::Debugging::default(),
+            random: random::Random::default(),
+            globals: globals::Globals::default(),
+            warnings: Vec::new(),
+            #[cfg(feature = "std")]
+            loaded_files: HashSet::new(),
+            #[cfg(feature = "std")]
+            loading_files: Vec::new(),
         }
     }
-    pub fn add(&mut self, mut functor: Functor) {
-        functor.ix = self.facts.len();
-        self.facts.push(functor);
+    pub fn add(&mut self, functor: Functor) {
+        self.modules
+            .get_mut(&self.loading)
+            .expect("loading module exists")
+            .add(functor);
     }
     pub fn from_rules(rules: Vec<Functor>) -> Self {
         let mut db = Self::new();
-        for (ix, mut functor) in rules.into_iter().enumerate() {
-            functor.ix = ix;
-            db.facts.push(functor);
+        for functor in rules {
+            db.add(functor);
         }
         db
     }
-    pub fn satisfy<'a>(&mut self, goal: Functor) -> Option<Functor> {
-        let mut db = self.clone();
-        let g = goal.clone();
-        let mut matches = db.facts.iter_mut();
-        while let Some(mut matched) = matches.next() {
-            if matched == &g {
-                let g = goal.clone();
-                let unified = self.unify(&mut matched, g);
-                println!("unified {:?}", unified);
-                if unified.is_some() {
-                    return unified;
+    /// Forgets every clause, module and declaration, as a new `Database`
+    /// would, but keeps the configuration, tracing, the debugger and spy
+    /// points, foreign predicates, flags and global variables.
+    pub fn clear_clauses(&mut self) {
+        self.modules = module::user_modules();
+        self.loading = module::user();
+        self.warnings.clear();
+        #[cfg(feature = "std")]
+        self.loaded_files.clear();
+    }
+    /// Prints each goal to stderr as the solver calls it, or without the
+    /// `std` feature to the output sink.
+    pub fn trace(&mut self, enabled: bool) {
+        self.tracing = enabled;
+    }
+    /// Calls `on_solution` with the bindings of each solution of `goal`, until
+    /// it breaks or an exception goes uncaught.
+    pub fn for_each_solution<F>(&self, goal: Functor, mut on_solution: F) -> Result<(), SolveError>
+    where
+        F: FnMut(&Bindings) -> ControlFlow<()>,
+    {
+        self.search(goal, false, &mut |bindings, _| on_solution(bindings))
+            .0
+    }
+    /// Like `for_each_solution`, but also passes the derivation of each solution.
+    pub fn for_each_proof<F>(&self, goal: Functor, mut on_solution: F) -> Result<(), SolveError>
+    where
+        F: FnMut(&Bindings, &Proof) -> ControlFlow<()>,
+    {
+        self.search(goal, true, &mut |bindings, steps| {
+            on_solution(bindings, &Proof::from_steps(steps, bindings))
+        })
+        .0
+    }
+    /// Like `for_each_solution`, but keeps what the query asserted and
+    /// retracted, and the flags it set, once it ends, even by an uncaught
+    /// exception.
+    pub fn for_each_solution_mut<F>(
+        &mut self,
+        goal: Functor,
+        mut on_solution: F,
+    ) -> Result<(), SolveError>
+    where
+        F: FnMut(&Bindings) -> ControlFlow<()>,
+    {
+        let (outcome, changed) = self.search(goal, false, &mut |bindings, _| on_solution(bindings));
+        if let Some((changes, flags)) = changed {
+            self.keep(changes, flags);
+        }
+        outcome
+    }
+    /// Like `for_each_proof`, keeping the query's changes as
+    /// `for_each_solution_mut` does.
+    pub fn for_each_proof_mut<F>(
+        &mut self,
+        goal: Functor,
+        mut on_solution: F,
+    ) -> Result<(), SolveError>
+    where
+        F: FnMut(&Bindings, &Proof) -> ControlFlow<()>,
+    {
+        let (outcome, changed) = self.search(goal, true, &mut |bindings, steps| {
+            on_solution(bindings, &Proof::from_steps(steps, bindings))
+        });
+        if let Some((changes, flags)) = changed {
+            self.keep(changes, flags);
+        }
+        outcome
+    }
+    // passes on the solutions of `goal`, with their derivations if
+    // `recording`, returning how the search ended and, if it ran, what the
+    // query changed
+    fn search(
+        &self,
+        goal: Functor,
+        recording: bool,
+        on_solution: &mut solver::OnSolution,
+    ) -> (Result<(), SolveError>, Option<(dynamic::Changes, Flags)>) {
+        let mut allowance = self.config.allowance();
+        if allowance.exhausted() {
+            return (Ok(()), None);
+        }
+        let goal = Term::Functor(Box::new(goal));
+        let mut filter = self.config.filter(&goal);
+        let mut solver = solver::Solver::new(self);
+        if recording {
+            solver = solver.recording();
+        }
+        let flow = solver.solve(
+            core::slice::from_ref(&goal),
+            &mut self.bindings(),
+            &mut |bindings, steps| {
+                if !filter.wanted(bindings) {
+                    return ControlFlow::Continue(());
                 }
+                on_solution(bindings, steps)?;
+                allowance.spend()
+            },
+        );
+        let flags = *solver.flags();
+        (solver::outcome(flow), Some((solver.into_changes(), flags)))
+    }
+    /// The solutions of `goal` found before any uncaught exception.
+    pub fn satisfy_all(&self, goal: Functor) -> Vec<Bindings> {
+        let mut answers = Vec::new();
+        let _ = self.for_each_solution(goal, |bindings| {
+            answers.push(bindings.clone());
+            ControlFlow::Continue(())
+        });
+        answers
+    }
+    /// Like `satisfy_all`, but stops after the first `n` solutions.
+    pub fn satisfy_n(&self, goal: Functor, n: usize) -> Vec<Bindings> {
+        let mut answers = Vec::new();
+        if n == 0 {
+            return answers;
+        }
+        let _ = self.for_each_solution(goal, |bindings| {
+            answers.push(bindings.clone());
+            if answers.len() < n {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
             }
+        });
+        answers
+    }
+    /// Like `satisfy_all`, but fails with the ball of an uncaught exception.
+    pub fn try_satisfy_all(&self, goal: Functor) -> Result<Vec<Bindings>, SolveError> {
+        let mut answers = Vec::new();
+        self.for_each_solution(goal, |bindings| {
+            answers.push(bindings.clone());
+            ControlFlow::Continue(())
+        })?;
+        Ok(answers)
+    }
+    /// Like `satisfy_all`, but explores alternative clauses of the first few
+    /// choice points on threads of their own. Solutions come back in the same
+    /// order, each as soon as those before it have.
+    #[cfg(feature = "parallel")]
+    pub fn satisfy_all_parallel(&self, goal: Functor) -> Vec<Bindings> {
+        let mut answers = Vec::new();
+        let mut allowance = self.config.allowance();
+        if allowance.exhausted() {
+            return answers;
         }
-        None
-    }
-    fn unify(&self, fst: &mut Functor, mut snd: Functor) -> Option<Functor> {
-        use std::borrow::BorrowMut;
-        for (fst_term, snd_term) in fst.args.iter_mut().zip(snd.args.iter_mut()) {
-            println!("fst_term {:?}", fst_term);
-            println!("snd_term {:?}", snd_term);
-            match (fst_term, snd_term) {
-                (Term::Atom(fst_atom), Term::Atom(snd_atom)) if fst_atom == snd_atom => continue,
-                (Term::Atom(_), Term::Atom(_)) => return None,
-                (Term::Atom(fst_atom), Term::Variable(v)) if v.resolves_to(fst_atom) => continue,
-                (Term::Atom(_), Term::Variable(v)) if v.is_bound() => return None,
-                (Term::Atom(a), Term::Variable(v)) => v.bind(VariableBinding::Atom(a.clone())),
-                (Term::Atom(_), Term::Functor(_)) => return None,
-                (Term::Variable(v), Term::Atom(snd_atom)) if !v.is_bound() => {
-                    v.bind(VariableBinding::Atom(snd_atom.clone()))
-                }
-                (Term::Variable(v), Term::Atom(snd_atom)) if v.resolves_to(snd_atom) => continue,
-                (Term::Variable(_), Term::Atom(_)) => return None,
-                (Term::Variable(fst_v), Term::Variable(snd_v)) => {
-                    match (fst_v.resolve(), snd_v.resolve()) {
-                        (None, None) => {
-                            fst_v.bind(VariableBinding::Variable(Box::new(snd_v.clone())));
-                            snd_v.bind(VariableBinding::Variable(Box::new(fst_v.clone())));
-                        }
-                        (Some(Atom(a)), None) => snd_v.bind(VariableBinding::Atom(Atom(a))),
-                        (None, Some(Atom(a))) => fst_v.bind(VariableBinding::Atom(Atom(a))),
-                        (Some(Atom(_)), Some(Atom(_))) => return None,
-                    }
-                }
-                (Term::Variable(_), Term::Functor(_)) => return None,
-                (Term::Functor(_), Term::Atom(_)) => return None,
-                (Term::Functor(_), Term::Variable(_)) => return None,
-                (Term::Functor(fst_f), Term::Functor(snd_f)) => {
-                    if self.unify(fst_f.borrow_mut(), *snd_f.clone()).is_some() {
-                        continue;
-                    } else {
-                        return None;
+        let goal = Term::Functor(Box::new(goal));
+        let mut filter = self.config.filter(&goal);
+        let _ = solver::Solver::new(self)
+            .parallel(PARALLEL_SPLIT_DEPTH)
+            .solve(
+                core::slice::from_ref(&goal),
+                &mut self.bindings(),
+                &mut |bindings, _| {
+                    if !filter.wanted(bindings) {
+                        return ControlFlow::Continue(());
                     }
-                }
-            };
+                    answers.push(bindings.clone());
+                    allowance.spend()
+                },
+            );
+        answers
+    }
+    /// The first solution of `goal`: a copy of it in which each of the
+    /// goal's own variables is bound, as `Variable::value` reports, to its
+    /// value in the solution with every binding substituted, as
+    /// `Bindings::resolve` gives it. Variables left unbound stay unbound.
+    pub fn satisfy(&self, goal: Functor) -> Option<Functor> {
+        let mut answer = None;
+        let _ = self.for_each_solution(goal.clone(), |bindings| {
+            let mut goal = goal.clone();
+            goal.args
+                .iter_mut()
+                .for_each(|arg| bind_values(arg, bindings));
+            answer = Some(goal);
+            ControlFlow::Break(())
+        });
+        answer
+    }
+    /// Like `satisfy`, but also returns the proof of the solution.
+    pub fn satisfy_with_proof(&self, goal: Functor) -> Option<(Functor, Proof)> {
+        let mut answer = None;
+        let _ = self.for_each_proof(goal.clone(), |bindings, proof| {
+            let mut goal = goal.clone();
+            goal.args
+                .iter_mut()
+                .for_each(|arg| bind_values(arg, bindings));
+            answer = Some((goal, proof.clone()));
+            ControlFlow::Break(())
+        });
+        answer
+    }
+}
+
+// records in each variable of `term` the value `bindings` gives it, fully
+// resolved, if it has one
+pub(crate) fn bind_values(term: &mut Term, bindings: &Bindings) {
+    let mut pending = vec![term];
+    while let Some(term) = pending.pop() {
+        match term {
+            Term::Variable(v) => {
+                let binding = match bindings.resolve(&Term::Variable(v.clone())) {
+                    Term::Variable(bound) if bound.alias == v.alias => continue,
+                    Term::Variable(bound) => VariableBinding::Variable(Box::new(bound)),
+                    Term::Atom(a) => VariableBinding::Atom(a),
+                    value => VariableBinding::Term(value),
+                };
+                v.bind(binding);
+            }
+            Term::Functor(f) => pending.extend(f.args.iter_mut()),
+            Term::Atom(_) | Term::Integer(_) | Term::String(_) => {}
         }
-        Some(snd)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rand::thread_rng;
+    use crate::compat::thread_rng;
     #[test]
     fn satisfy_unary() {
         let mut gen = thread_rng();
-        let mut db = Database::from_rules(vec![Functor::new_fact(
-            Atom(String::from("cool")),
+        let db = Database::from_rules(vec![Functor::new_fact(
+            Atom::new("cool"),
             vec![Term::atom_from_str("rust")],
         )]);
         let goal = Functor::new_fact(
-            Atom(String::from("cool")),
+            Atom::new("cool"),
             vec![Term::variable_from_str("X", &mut gen)],
         );
         let answer = db.satisfy(goal).expect("answer");
-        if let Some(Term::Variable(v)) = answer.args.get(0) {
+        if let Some(Term::Variable(v)) = answer.args.first() {
             assert_eq!(
                 v.resolve().expect("satisfy_unary v resolved"),
                 Atom::from_str("rust").unwrap()
@@ -273,7 +867,7 @@ mod tests {
             Atom::from_str("likes").unwrap(),
             vec![Term::atom_from_str("popeye"), Term::atom_from_str("treats")],
         );
-        let mut db = Database::from_rules(vec![r1, r2]);
+        let db = Database::from_rules(vec![r1, r2]);
         let goal = Functor::new_fact(
             Atom::from_str("likes").unwrap(),
             vec![
@@ -282,7 +876,7 @@ mod tests {
             ],
         );
         let answer = db.satisfy(goal).expect("answer");
-        if let Some(Term::Variable(v)) = answer.args.get(0) {
+        if let Some(Term::Variable(v)) = answer.args.first() {
             assert_eq!(
                 v.resolve().expect("satisfy_two v resolved"),
                 Atom::from_str("sam").unwrap()
@@ -302,7 +896,7 @@ mod tests {
             Atom::from_str("likes").unwrap(),
             vec![Term::atom_from_str("popeye"), Term::atom_from_str("treats")],
         );
-        let mut db = Database::from_rules(vec![r1, r2]);
+        let db = Database::from_rules(vec![r1, r2]);
         let goal = Functor::new_fact(
             Atom::from_str("likes").unwrap(),
             vec![
@@ -311,7 +905,7 @@ mod tests {
             ],
         );
         let answer = db.satisfy(goal).expect("answer");
-        if let Some(Term::Variable(v)) = answer.args.get(0) {
+        if let Some(Term::Variable(v)) = answer.args.first() {
             assert_eq!(
                 v.resolve().expect("satisfy_backtrack v resolved"),
                 Atom::from_str("popeye").unwrap()
@@ -331,7 +925,7 @@ mod tests {
             Atom::from_str("likes").unwrap(),
             vec![Term::atom_from_str("popeye"), Term::atom_from_str("treats")],
         );
-        let mut db = Database::from_rules(vec![r1, r2]);
+        let db = Database::from_rules(vec![r1, r2]);
         let goal = Functor::new_fact(
             Atom::from_str("likes").unwrap(),
             vec![
@@ -354,7 +948,7 @@ mod tests {
                 ))),
             ],
         );
-        let mut db = Database::from_rules(vec![r1]);
+        let db = Database::from_rules(vec![r1]);
         let goal = Functor::new_fact(
             Atom::from_str("person").unwrap(),
             vec![
@@ -366,7 +960,7 @@ mod tests {
             ],
         );
         let answer = db.satisfy(goal).expect("satisfy_structure answer");
-        if let Some(Term::Variable(v)) = answer.args.get(0) {
+        if let Some(Term::Variable(v)) = answer.args.first() {
             assert_eq!(
                 v.resolve().expect("satisfy_structure v resolved"),
                 Atom::from_str("sam").unwrap()
@@ -375,4 +969,180 @@ mod tests {
             panic!("satisfy_structure variable unbound")
         }
     }
+    #[test]
+    fn satisfy_through_rules() {
+        let mut db = Database::new();
+        db.consult("likes(sam, X) :- food(X). food(pizza).")
+            .unwrap();
+        let query = parse_query("likes(sam, What)").unwrap();
+        let answer = db
+            .satisfy(query.goal)
+            .expect("satisfy_through_rules answer");
+        match answer.args.get(1) {
+            Some(Term::Variable(v)) => assert_eq!(v.resolve(), Some(Atom::new("pizza"))),
+            other => panic!("satisfy_through_rules unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn satisfy_binds_the_goals_own_variables() {
+        let mut db = Database::new();
+        db.consult("p(N, f(N, Y), Y, Z, Z) :- N is 1 + 2.").unwrap();
+        let query = parse_query("p(A, B, C, D, E)").unwrap();
+        let answer = db.satisfy(query.goal).expect("answer");
+        let values: Vec<Option<String>> = answer
+            .args()
+            .iter()
+            .map(|arg| match arg {
+                Term::Variable(v) => v.value().map(|value| value.to_string()),
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                Some("3".to_string()),
+                Some("f(3, C)".to_string()),
+                None,
+                Some("E".to_string()),
+                None
+            ]
+        );
+        let Term::Variable(a) = &answer.args()[0] else {
+            unreachable!()
+        };
+        assert_eq!(a.resolve(), None);
+        let query = parse_query("X = g(h(Y)), Y = [1]").unwrap();
+        let answer = db.satisfy(query.goal).expect("answer");
+        assert_eq!(
+            answer.args()[0].to_string(),
+            "=(X, g(h(Y)))",
+            "the goal keeps its variables"
+        );
+        let Term::Functor(eq) = &answer.args()[0] else {
+            unreachable!()
+        };
+        let Term::Variable(x) = &eq.args()[0] else {
+            unreachable!()
+        };
+        assert_eq!(
+            x.value().map(|x| x.pretty().to_string()),
+            Some("g(h([1]))".to_string())
+        );
+    }
+
+    #[test]
+    fn satisfy_with_proof_explains_the_answer() {
+        let mut db = Database::new();
+        db.consult("likes(sam, pizza). fan(X) :- likes(X, pizza).")
+            .unwrap();
+        let (answer, proof) = db
+            .satisfy_with_proof(parse_query("fan(Who)").unwrap().goal)
+            .expect("answer");
+        assert_eq!(answer.to_string(), "fan(Who)");
+        assert_eq!(proof.goal.to_string(), "fan(sam)");
+        assert_eq!(proof.bindings[0].1.to_string(), "sam");
+        assert_eq!(proof.subproofs[0].goal.to_string(), "likes(sam, pizza)");
+    }
+
+    #[test]
+    fn terms_stay_compact() {
+        use core::mem::size_of;
+        assert!(size_of::<Term>() <= 32, "{}", size_of::<Term>());
+        assert!(size_of::<Functor>() <= 64, "{}", size_of::<Functor>());
+        let mut rng = thread_rng();
+        let x = Variable::new_named("X", &mut rng);
+        assert_ne!(x, Variable::new_named("X", &mut rng));
+        assert_eq!(x.to_string(), "X");
+        let anonymous = Variable::new_anonymous(&mut rng).to_string();
+        assert!(anonymous.starts_with("_var_"), "{}", anonymous);
+    }
+
+    #[test]
+    fn concurrent_queries_share_one_database() {
+        let mut db = Database::new();
+        db.consult("n(1). n(2). n(3).").unwrap();
+        let db = std::sync::Arc::new(db);
+        let counts: Vec<usize> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    let query = parse_query("assertz(n(4)), n(X)").unwrap();
+                    db.satisfy_all(query.goal).len()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(counts, vec![4; 8]);
+        assert_eq!(db.clauses("n", 1).count(), 3);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn satisfy_all_parallel_matches_sequential() {
+        let mut program = String::new();
+        for i in 0..50 {
+            program.push_str(&format!(
+                "parent(p{i}, c{i}). parent(c{i}, g{i}a). parent(c{i}, g{i}b).\n"
+            ));
+        }
+        program.push_str("grandparent(X, Z) :- parent(X, Y), parent(Y, Z).");
+        let mut db = Database::new();
+        db.consult(&program).unwrap();
+        let query = parse_query("grandparent(A, B)").unwrap();
+        let render = |answers: Vec<Bindings>| -> Vec<String> {
+            answers
+                .iter()
+                .map(|b| query.solution(b).to_json())
+                .collect()
+        };
+        let sequential = render(db.satisfy_all(query.goal.clone()));
+        assert_eq!(sequential.len(), 100);
+        assert_eq!(
+            render(db.satisfy_all_parallel(query.goal.clone())),
+            sequential
+        );
+    }
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn satisfy_all_parallel_stops_endless_branches() {
+        let mut db = Database::new();
+        db.consult("n(X) :- between(1, inf, X). n(X) :- between(1, inf, X).")
+            .unwrap();
+        db.set_config(SolverConfig::default().max_solutions(3));
+        let query = parse_query("n(X)").unwrap();
+        let answers: Vec<String> = db
+            .satisfy_all_parallel(query.goal.clone())
+            .iter()
+            .map(|b| query.solution(b).to_string())
+            .collect();
+        assert_eq!(answers, ["X = 1", "X = 2", "X = 3"]);
+        // the branches watch the query's token too
+        db.consult("spin :- between(1, inf, _), fail. spin :- between(1, inf, _), fail.")
+            .unwrap();
+        let token = CancellationToken::new();
+        db.set_config(SolverConfig::default().cancellation(token.clone()));
+        let cancel = std::thread::spawn(move || {
+            std::thread::sleep(core::time::Duration::from_millis(50));
+            token.cancel();
+        });
+        let spin = parse_query("spin").unwrap();
+        assert!(db.satisfy_all_parallel(spin.goal).is_empty());
+        cancel.join().unwrap();
+    }
+    #[test]
+    fn clearing_clauses_keeps_the_configuration() {
+        let mut db = Database::new();
+        db.set_config(SolverConfig::default().distinct(true));
+        db.trace(true);
+        db.consult("p(1). p(1).").unwrap();
+        db.clear_clauses();
+        let goal = parse_query("p(X)").unwrap().goal;
+        assert!(db.satisfy_all(goal.clone()).is_empty());
+        assert!(db.tracing);
+        db.consult("p(2). p(2).").unwrap();
+        assert_eq!(db.satisfy_all(goal).len(), 1);
+    }
 }
@@ -0,0 +1,355 @@
+//! `listing/0,1`, `Database::listing` and `Database::to_source`: the clauses
+//! loaded, written out as source that consults back to the same program.
+
+use crate::builtins::{self, Alternatives};
+use crate::compat::{thread_rng, HashMap};
+use crate::dynamic::{Changes, Key};
+use crate::error;
+use crate::module::{predicate_indicator, user};
+use crate::output;
+use crate::pretty;
+use crate::solver::{Bindings, Solver};
+use crate::{Alias, Arity, Atom, Database, Functor, Term, Variable};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+impl Database {
+    /// Every clause, as source: `user`'s predicates first, then those of
+    /// each other module after its `:- module` directive.
+    pub fn listing(&self) -> String {
+        listing(self, &Changes::default(), |_| true, false)
+    }
+    /// The clauses of `name/arity`, in whichever modules define it.
+    pub fn listing_predicate(&self, name: &str, arity: Arity) -> String {
+        let name = Atom::new(name);
+        listing(
+            self,
+            &Changes::default(),
+            |(_, n, a)| *n == name && *a == arity,
+            false,
+        )
+    }
+    /// The whole database as source, for saving: `listing`, with each
+    /// dynamic predicate declared, clauses or not, and any strings marked to
+    /// read back as strings. Consulting it into an empty database gives one
+    /// that answers queries the same way.
+    pub fn to_source(&self) -> String {
+        let mut text = String::new();
+        let clauses = self.modules.values().flat_map(|m| m.clauses());
+        if clauses
+            .flat_map(|c| c.args.iter().chain(c.body.iter().flat_map(|g| &g.args)))
+            .any(holds_string)
+        {
+            text += ":- set_prolog_flag(double_quotes, string).\n\n";
+        }
+        text + &listing(self, &Changes::default(), |_| true, true)
+    }
+}
+
+fn holds_string(term: &Term) -> bool {
+    // what's left to look through, so deep terms need no deep Rust stack
+    let mut pending = vec![term];
+    while let Some(term) = pending.pop() {
+        match term {
+            Term::String(_) => return true,
+            Term::Functor(f) => pending.extend(&f.args),
+            Term::Atom(_) | Term::Integer(_) | Term::Variable(_) => {}
+        }
+    }
+    false
+}
+
+// the wanted predicates' clauses, by module; with `declare`, each dynamic
+// predicate starts with its declaration, and is written even without clauses
+fn listing(
+    db: &Database,
+    changes: &Changes,
+    wanted: impl Fn(&Key) -> bool,
+    declare: bool,
+) -> String {
+    let mut keys = changes.predicates(db);
+    if declare {
+        for m in db.modules.values() {
+            let dynamic = m
+                .dynamic()
+                .into_iter()
+                .map(|(name, arity)| (*m.name(), name, arity));
+            let undefined: Vec<Key> = dynamic.filter(|key| !keys.contains(key)).collect();
+            keys.extend(undefined);
+        }
+        keys.sort_by_key(|(module, _, _)| (*module != user(), module.as_str()));
+    }
+    let mut text = String::new();
+    let mut module = user();
+    for key in keys.into_iter().filter(|key| wanted(key)) {
+        let clauses = changes.current(db, &key);
+        let (_, name, arity) = key;
+        let dynamic = declare
+            && db
+                .modules
+                .get(&key.0)
+                .is_some_and(|m| m.is_dynamic(&name, arity));
+        if clauses.is_empty() && !dynamic {
+            continue;
+        }
+        if key.0 != module {
+            module = key.0;
+            let exports = db.module(module.as_str()).map_or(&[][..], |m| &m.exports);
+            let exports: Vec<String> = exports
+                .iter()
+                .map(|(name, arity)| format!("{}/{}", name, arity))
+                .collect();
+            text += &format!(":- module({}, [{}]).\n\n", module, exports.join(", "));
+        }
+        if dynamic {
+            text += &format!(":- dynamic {}/{}.\n", Term::Atom(name).pretty(), arity);
+            if !clauses.is_empty() {
+                text.push('\n');
+            }
+        }
+        for clause in &clauses {
+            text += &written(clause);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// a clause over as many lines as it has goals, its variables named `A`,
+// `B`, ... in order of appearance, or `_` if they appear once
+fn written(clause: &Functor) -> String {
+    let terms = clause
+        .args
+        .iter()
+        .chain(clause.body.iter().flat_map(|g| &g.args));
+    let seen = count(terms);
+    let mut named = HashMap::new();
+    let mut next = 0;
+    for (alias, occurrences) in seen {
+        let name = if occurrences == 1 {
+            String::from("_")
+        } else {
+            next += 1;
+            variable_name(next - 1)
+        };
+        let variable = Term::Variable(Variable::new_named(name, &mut thread_rng()));
+        named.insert(alias, variable);
+    }
+    // with every variable in `named`, renaming only puts the names in
+    let mut rng = thread_rng();
+    let mut goal = |name: Atom, args: &[Term]| match args {
+        [] => Term::Atom(name),
+        args => Term::Functor(Box::new(Functor::new_fact(
+            name,
+            args.iter()
+                .map(|arg| arg.rename(&mut rng, &mut named))
+                .collect(),
+        ))),
+    };
+    let head = goal(clause.name, &clause.args).pretty().to_string();
+    if clause.body.is_empty() {
+        return format!("{}.\n", head);
+    }
+    let body: Vec<String> = clause
+        .body
+        .iter()
+        .map(|g| format!("    {}", pretty::render(&goal(g.name, &g.args), 999)))
+        .collect();
+    format!("{} :-\n{}.\n", head, body.join(",\n"))
+}
+
+// the variables of `terms`, in order of appearance, each with how often
+// it appears
+fn count<'a>(terms: impl Iterator<Item = &'a Term>) -> Vec<(Alias, usize)> {
+    let mut seen: Vec<(Alias, usize)> = Vec::new();
+    let mut index: HashMap<Alias, usize> = HashMap::new();
+    // what's left to look through, last first, so deep terms need no deep
+    // Rust stack
+    let mut pending: Vec<&Term> = terms.collect();
+    pending.reverse();
+    while let Some(term) = pending.pop() {
+        match term {
+            Term::Variable(v) => {
+                let ix = *index.entry(v.alias).or_insert(seen.len());
+                if ix == seen.len() {
+                    seen.push((v.alias, 0));
+                }
+                seen[ix].1 += 1;
+            }
+            Term::Functor(f) => pending.extend(f.args.iter().rev()),
+            Term::Atom(_) | Term::Integer(_) | Term::String(_) => {}
+        }
+    }
+    seen
+}
+
+// A, B, ... Z, A1, B1, ...
+fn variable_name(ix: usize) -> String {
+    let letter = char::from(b'A' + (ix % 26) as u8);
+    match ix / 26 {
+        0 => letter.to_string(),
+        round => format!("{}{}", letter, round),
+    }
+}
+
+/// `listing`
+pub(crate) fn listing_all(solver: &mut Solver, _: &[Term], _: &Bindings) -> Alternatives {
+    let text = listing(solver.db(), solver.changes(), |_| true, false);
+    output::print(solver, &text)
+}
+
+/// `listing(Name/Arity)` or `listing(Name)`, for every arity
+pub(crate) fn listing_some(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    let spec = bindings.resolve(&args[0]);
+    let wanted: Box<dyn Fn(&Key) -> bool> = match (&spec, predicate_indicator(&spec)) {
+        (_, Some((name, arity))) => Box::new(move |(_, n, a)| *n == name && *a == arity),
+        (Term::Atom(name), None) => {
+            let name = *name;
+            Box::new(move |(_, n, _)| *n == name)
+        }
+        (Term::Variable(_), None) => return builtins::raise(solver, error::instantiation_error()),
+        (_, None) => {
+            let ball = error::type_error("predicate_indicator", spec);
+            return builtins::raise(solver, ball);
+        }
+    };
+    let text = listing(solver.db(), solver.changes(), wanted, false);
+    output::print(solver, &text)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    const FAMILY: &str = "parent(tom, bob). parent(bob, ann).
+        grandparent(X, Z) :- parent(X, Y), parent(Y, Z).
+        older(X, _) :- X = tom ; fail.";
+
+    #[test]
+    fn lists_clauses_as_source() {
+        let mut db = Database::new();
+        db.consult(FAMILY).unwrap();
+        assert_eq!(
+            db.listing(),
+            "parent(tom, bob).
+parent(bob, ann).
+
+grandparent(A, B) :-
+    parent(A, C),
+    parent(C, B).
+
+older(A, _) :-
+    (A = tom ; fail).
+
+"
+        );
+        assert_eq!(db.listing_predicate("parent", 1), "");
+        let mut again = Database::new();
+        again.consult(&db.listing()).unwrap();
+        assert_eq!(again.listing(), db.listing());
+    }
+
+    #[test]
+    fn source_consults_back_to_the_same_database() {
+        let mut db = Database::new();
+        db.consult(
+            ":- dynamic seen/1, 'to do'/2.
+            greeting(\"hello, world\").
+            likes(sam, 'ice cream').
+            :- assertz(seen(sam)).
+            :- module(m, [p/1]).
+            :- dynamic cache/2.
+            p(X) :- X = [a|_].",
+        )
+        .unwrap();
+        let source = db.to_source();
+        assert_eq!(
+            source,
+            ":- set_prolog_flag(double_quotes, string).
+
+greeting(\"hello, world\").
+
+likes(sam, 'ice cream').
+
+:- dynamic seen/1.
+
+seen(sam).
+
+:- dynamic 'to do'/2.
+
+:- module(m, [p/1]).
+
+p(A) :-
+    A = [a|_].
+
+:- dynamic cache/2.
+
+"
+        );
+        let mut again = Database::new();
+        again.set_config(SolverConfig::default().double_quotes(DoubleQuotes::Codes));
+        again.consult(&source).unwrap();
+        assert_eq!(again.to_source(), source);
+        assert!(again.take_warnings().is_empty());
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // without `std`, what's printed comes as text
+    #[cfg(not(feature = "std"))]
+    impl std::fmt::Write for Captured {
+        fn write_str(&mut self, text: &str) -> std::fmt::Result {
+            self.0.lock().unwrap().extend_from_slice(text.as_bytes());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn listing_sees_the_query_s_changes() {
+        let mut db = Database::new();
+        db.consult(":- module(m, [p/1]). p(1).").unwrap();
+        let captured = Captured::default();
+        db.set_output(captured.clone());
+        let query = parse_query("assertz(p(2)), retract(p(1)), assertz(q), listing(p/1)");
+        db.satisfy_all(query.unwrap().goal);
+        let printed = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(printed, ":- module(m, [p/1]).\n\np(2).\n\n");
+        let query = parse_query("listing(1)").unwrap();
+        assert!(db.try_satisfy_all(query.goal).is_err());
+    }
+
+    #[test]
+    fn long_lists_need_no_deep_stack() {
+        let items: Vec<String> = (1..=100_000).map(|n| n.to_string()).collect();
+        let clause = format!("big([{}]).\n", items.join(", "));
+        let mut db = Database::new();
+        db.consult(&clause).unwrap();
+        assert_eq!(db.listing(), format!("{}\n", clause));
+        assert_eq!(db.to_source(), db.listing());
+        let captured = Captured::default();
+        db.set_output(captured.clone());
+        db.satisfy_all(parse_query("listing(big/1)").unwrap().goal);
+        let printed = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(printed, db.listing());
+    }
+}
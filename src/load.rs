@@ -0,0 +1,180 @@
+//! Loading one source file from another: the `include/1` and
+//! `ensure_loaded/1` directives. A file is named by an atom or string, taken
+//! relative to the directory of the file loading it, with `.pl` added if
+//! there's no file by the name itself. Without the `std` feature there
+//! are no files, and both directives are errors.
+
+#[cfg(feature = "std")]
+use crate::Term;
+use crate::{Database, Functor, ParseError};
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String, vec::Vec};
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+/// `include(File)` or `ensure_loaded(File)`.
+pub(crate) fn is_load_directive(directive: &Functor) -> bool {
+    matches!(directive.name.as_str(), "include" | "ensure_loaded") && directive.arity() == 1
+}
+
+#[cfg(feature = "std")]
+impl Database {
+    // runs `include(File)` or `ensure_loaded(File)`, met while loading the
+    // file named `from`: include reads File's clauses in place of the
+    // directive, while ensure_loaded loads it apart, unless it was loaded
+    // already. Gives the errors in File, or why it couldn't be read
+    pub(crate) fn load_file(
+        &mut self,
+        directive: &Functor,
+        from: &str,
+    ) -> Result<Vec<ParseError>, String> {
+        let path = find(&directive.args[0], from)?;
+        let including = directive.name.as_str() == "include";
+        if including && self.loading_files.contains(&path) {
+            return Err(format!("{} includes itself", path.display()));
+        }
+        if !including && !self.loaded_files.insert(path.clone()) {
+            return Ok(Vec::new());
+        }
+        let source = fs::read_to_string(&path)
+            .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+        let name = path.to_string_lossy().into_owned();
+        self.loading_files.push(path);
+        let errors = if including {
+            self.consult_clauses(&name, &source)
+        } else {
+            let module = self.loading;
+            let errors = self.consult_clauses(&name, &source);
+            self.loading = module;
+            errors
+        };
+        self.loading_files.pop();
+        Ok(errors
+            .into_iter()
+            .map(|err| ParseError {
+                message: format!("{}: {}", name, err.message),
+                ..err
+            })
+            .collect())
+    }
+    // notes that the file named `file` is being loaded, if it is one, so
+    // that it isn't loaded again or included in itself, until `leave_file`
+    pub(crate) fn enter_file(&mut self, file: &str) -> bool {
+        let Ok(path) = fs::canonicalize(file) else {
+            return false;
+        };
+        self.loaded_files.insert(path.clone());
+        self.loading_files.push(path);
+        true
+    }
+    pub(crate) fn leave_file(&mut self) {
+        self.loading_files.pop();
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Database {
+    pub(crate) fn load_file(
+        &mut self,
+        directive: &Functor,
+        _: &str,
+    ) -> Result<Vec<ParseError>, String> {
+        Err(format!("{}/1 needs the std feature", directive.name))
+    }
+    pub(crate) fn enter_file(&mut self, _: &str) -> bool {
+        false
+    }
+    pub(crate) fn leave_file(&mut self) {}
+}
+
+// the canonical path of the file `spec` names
+#[cfg(feature = "std")]
+fn find(spec: &Term, from: &str) -> Result<PathBuf, String> {
+    let name = match spec {
+        Term::Atom(name) => name.as_str().to_string(),
+        Term::String(name) => name.to_string(),
+        other => return Err(format!("{} doesn't name a file", other)),
+    };
+    let dir = Path::new(from).parent().unwrap_or(Path::new(""));
+    let path = dir.join(&name);
+    let with_extension = path.with_extension("pl");
+    [path, with_extension]
+        .iter()
+        .find_map(|path| fs::canonicalize(path).ok().filter(|path| path.is_file()))
+        .ok_or_else(|| format!("cannot find {}", name))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // a fresh directory holding `files`
+    fn files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("amateurlog-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            fs::write(dir.join(name), text).unwrap();
+        }
+        dir
+    }
+
+    fn count(db: &Database, query: &str) -> usize {
+        db.satisfy_all(parse_query(query).unwrap().goal).len()
+    }
+
+    #[test]
+    fn files_include_and_load_others() {
+        let dir = files(
+            "load",
+            &[
+                ("main.pl", ":- include(facts).\n:- ensure_loaded('rules.pl').\n:- ensure_loaded(rules).\nmain."),
+                ("facts.pl", "likes(sam, pizza).\nlikes(sam, chocolate)."),
+                ("rules.pl", ":- ensure_loaded(main).\nfan(X) :- likes(X, pizza)."),
+            ],
+        );
+        let main = dir.join("main.pl");
+        let mut db = Database::new();
+        let source = fs::read_to_string(&main).unwrap();
+        db.consult_named(main.to_str().unwrap(), &source).unwrap();
+        assert_eq!(count(&db, "likes(sam, _)"), 2);
+        assert_eq!(count(&db, "fan(sam)"), 1);
+        assert_eq!(count(&db, "main"), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_errors_are_reported() {
+        let dir = files(
+            "load-errors",
+            &[
+                (
+                    "main.pl",
+                    ":- include(loop).\n:- include(missing).\n:- include(broken).",
+                ),
+                ("loop.pl", ":- include(main)."),
+                ("broken.pl", "ok.\nnot ok."),
+            ],
+        );
+        let main = dir.join("main.pl");
+        let source = fs::read_to_string(&main).unwrap();
+        let errors = Database::new()
+            .consult_named(main.to_str().unwrap(), &source)
+            .unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|err| err.message.as_str()).collect();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(
+            messages[0].ends_with("main.pl includes itself"),
+            "{}",
+            messages[0]
+        );
+        assert_eq!(messages[1], "cannot find missing");
+        assert!(messages[2].contains("broken.pl: "), "{}", messages[2]);
+        assert_eq!(errors[2].line, 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
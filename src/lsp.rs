@@ -0,0 +1,379 @@
+//! A language server for amateurlog source files, run by the
+//! `amateurlog-lsp` binary over stdin and stdout. It reports syntax errors,
+//! goes to the clauses defining a predicate, and completes predicate names,
+//! from the documents the editor has open.
+
+use crate::json::Json;
+use crate::parser::{self, ParseError};
+use crate::Arity;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+/// Answers the messages on `input` until the client says to exit.
+pub fn serve<R: BufRead, W: Write>(mut input: R, output: W) -> io::Result<()> {
+    let mut server = Server {
+        output,
+        documents: BTreeMap::new(),
+    };
+    while let Some(message) = receive(&mut input)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// reads one `Content-Length`-framed message
+fn receive<R: BufRead>(input: &mut R) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut body = vec![0; length.ok_or_else(|| invalid("missing content length"))?];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|_| invalid("message is not UTF-8"))?;
+    Json::parse(&body)
+        .map(Some)
+        .ok_or_else(|| invalid("message is not JSON"))
+}
+
+struct Server<W> {
+    output: W,
+    // text of each open document, by URI
+    documents: BTreeMap<String, String>,
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, message: Json) -> io::Result<()> {
+        let body = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        self.output.flush()
+    }
+    fn reply(&mut self, id: &Json, result: Json) -> io::Result<()> {
+        self.send(Json::object([
+            ("jsonrpc", Json::string("2.0")),
+            ("id", id.clone()),
+            ("result", result),
+        ]))
+    }
+    // handles one message, returning false once it's time to exit
+    fn handle(&mut self, message: &Json) -> io::Result<bool> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let uri = params
+            .at(&["textDocument", "uri"])
+            .and_then(Json::as_str)
+            .map(String::from);
+        let Some(id) = message.get("id") else {
+            match (method, uri) {
+                ("exit", _) => return Ok(false),
+                ("textDocument/didOpen", Some(uri)) => {
+                    let text = params.at(&["textDocument", "text"]).and_then(Json::as_str);
+                    self.update(uri, text.unwrap_or(""))?;
+                }
+                ("textDocument/didChange", Some(uri)) => {
+                    // the server asks for whole documents, so the last change
+                    // is the new text
+                    if let Some(Json::Array(changes)) = params.get("contentChanges") {
+                        let text = changes.last().and_then(|c| c.get("text"));
+                        if let Some(text) = text.and_then(Json::as_str) {
+                            self.update(uri, text)?;
+                        }
+                    }
+                }
+                ("textDocument/didClose", Some(uri)) => {
+                    self.documents.remove(&uri);
+                    self.publish(&uri, Vec::new())?;
+                }
+                _ => {}
+            }
+            return Ok(true);
+        };
+        let result = match method {
+            "initialize" => Json::object([(
+                "capabilities",
+                Json::object([
+                    ("textDocumentSync", Json::Number(1.0)),
+                    ("definitionProvider", Json::Bool(true)),
+                    ("completionProvider", Json::object([])),
+                ]),
+            )]),
+            "shutdown" => Json::Null,
+            "textDocument/definition" => {
+                let position = params.get("position");
+                let line = position.and_then(|p| p.get("line")?.as_usize());
+                let character = position.and_then(|p| p.get("character")?.as_usize());
+                let text = uri.as_ref().and_then(|uri| self.documents.get(uri));
+                match (text, line, character) {
+                    (Some(text), Some(line), Some(character)) => {
+                        let name = name_at(text, offset(text, line, character));
+                        Json::Array(self.definitions(name))
+                    }
+                    _ => Json::Null,
+                }
+            }
+            "textDocument/completion" => Json::Array(self.completions()),
+            _ => {
+                return self
+                    .send(Json::object([
+                        ("jsonrpc", Json::string("2.0")),
+                        ("id", id.clone()),
+                        (
+                            "error",
+                            Json::object([
+                                ("code", Json::Number(-32601.0)),
+                                (
+                                    "message",
+                                    Json::string(&format!("unknown method {}", method)),
+                                ),
+                            ]),
+                        ),
+                    ]))
+                    .map(|()| true)
+            }
+        };
+        self.reply(id, result).map(|()| true)
+    }
+    fn update(&mut self, uri: String, text: &str) -> io::Result<()> {
+        let (_, errors) = parser::outline(text);
+        let diagnostics = errors.iter().map(|err| diagnostic(text, err)).collect();
+        self.documents.insert(uri.clone(), text.to_string());
+        self.publish(&uri, diagnostics)
+    }
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Json>) -> io::Result<()> {
+        self.send(Json::object([
+            ("jsonrpc", Json::string("2.0")),
+            ("method", Json::string("textDocument/publishDiagnostics")),
+            (
+                "params",
+                Json::object([
+                    ("uri", Json::string(uri)),
+                    ("diagnostics", Json::Array(diagnostics)),
+                ]),
+            ),
+        ]))
+    }
+    // where each clause for a predicate called `name` starts, in every
+    // open document
+    fn definitions(&self, name: Option<&str>) -> Vec<Json> {
+        let Some(name) = name else {
+            return Vec::new();
+        };
+        let mut locations = Vec::new();
+        for (uri, text) in &self.documents {
+            let (clauses, _) = parser::outline(text);
+            for (predicate, start) in clauses {
+                if predicate.is_some_and(|(n, _)| n.as_str() == name) {
+                    let at = position(text, start);
+                    locations.push(Json::object([
+                        ("uri", Json::string(uri)),
+                        ("range", range(at.clone(), at)),
+                    ]));
+                }
+            }
+        }
+        locations
+    }
+    fn completions(&self) -> Vec<Json> {
+        let mut predicates: Vec<(String, Arity)> = Vec::new();
+        for text in self.documents.values() {
+            for (predicate, _) in parser::outline(text).0 {
+                if let Some((name, arity)) = predicate {
+                    let predicate = (name.as_str().to_string(), arity);
+                    if !predicates.contains(&predicate) {
+                        predicates.push(predicate);
+                    }
+                }
+            }
+        }
+        predicates
+            .into_iter()
+            .map(|(name, arity)| {
+                Json::object([
+                    ("label", Json::string(&name)),
+                    ("detail", Json::string(&format!("{}/{}", name, arity))),
+                    // function
+                    ("kind", Json::Number(3.0)),
+                ])
+            })
+            .collect()
+    }
+}
+
+fn diagnostic(text: &str, err: &ParseError) -> Json {
+    // an empty span, e.g. at the end of the text, still covers a character
+    let end = match text[err.span.start..].chars().next() {
+        Some(c) if err.span.is_empty() => err.span.start + c.len_utf8(),
+        _ => err.span.end,
+    };
+    let message = match (&err.expected, &err.found) {
+        (Some(_), Some(found)) => format!("{}, found {}", err.message, found),
+        _ => err.message.clone(),
+    };
+    Json::object([
+        (
+            "range",
+            range(position(text, err.span.start), position(text, end)),
+        ),
+        // error
+        ("severity", Json::Number(1.0)),
+        ("source", Json::string("amateurlog")),
+        ("message", Json::string(&message)),
+    ])
+}
+
+fn range(start: Json, end: Json) -> Json {
+    Json::object([("start", start), ("end", end)])
+}
+
+// the LSP position of a byte offset: its line, and its column in UTF-16
+// code units
+fn position(text: &str, offset: usize) -> Json {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |ix| ix + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    Json::object([
+        ("line", Json::Number(line as f64)),
+        ("character", Json::Number(character as f64)),
+    ])
+}
+
+// the byte offset of an LSP position
+fn offset(text: &str, line: usize, character: usize) -> usize {
+    let line_start: usize = text.split_inclusive('\n').take(line).map(str::len).sum();
+    let mut units = 0;
+    for (ix, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + ix;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+// the unquoted atom around `offset`, if there is one
+fn name_at(text: &str, offset: usize) -> Option<&str> {
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| parser::is_name_char(*c))
+        .last()
+        .map_or(offset, |(ix, _)| ix);
+    let end = text[offset..]
+        .char_indices()
+        .find(|(_, c)| !parser::is_name_char(*c))
+        .map_or(text.len(), |(ix, _)| offset + ix);
+    let name = &text[start..end];
+    name.starts_with(parser::is_name_start).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(messages: &[&str]) -> Vec<Json> {
+        let mut input = Vec::new();
+        for message in messages {
+            write!(
+                input,
+                "Content-Length: {}\r\n\r\n{}",
+                message.len(),
+                message
+            )
+            .unwrap();
+        }
+        let mut output = Vec::new();
+        serve(&input[..], &mut output).unwrap();
+        let mut output = &output[..];
+        let mut replies = Vec::new();
+        while let Some(reply) = receive(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        replies
+    }
+
+    const OPEN: &str = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///kb.pl","text":"likes(sam, pizza).\nfan(X) :- likes(X, pizza).\nbroken(.\nlikes(ann, tea).\n"}}}"#;
+
+    #[test]
+    fn reports_syntax_errors() {
+        let replies = session(&[OPEN]);
+        let diagnostics = replies[0].at(&["params", "diagnostics"]).unwrap();
+        let Json::Array(diagnostics) = diagnostics else {
+            panic!("diagnostics are a list")
+        };
+        assert_eq!(diagnostics.len(), 1);
+        let start = diagnostics[0].at(&["range", "start"]).unwrap();
+        assert_eq!(start.get("line").unwrap().as_usize(), Some(2));
+        assert_eq!(start.get("character").unwrap().as_usize(), Some(7));
+    }
+
+    #[test]
+    fn finds_definitions_and_completions() {
+        let replies = session(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            OPEN,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///kb.pl"},"position":{"line":1,"character":12}}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/completion","params":{"textDocument":{"uri":"file:///kb.pl"},"position":{"line":0,"character":0}}}"#,
+            r#"{"jsonrpc":"2.0","id":4,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ]);
+        assert!(replies[0]
+            .at(&["result", "capabilities", "definitionProvider"])
+            .is_some());
+        let Some(Json::Array(locations)) = replies[2].get("result") else {
+            panic!("expected locations: {}", replies[2])
+        };
+        let lines: Vec<_> = locations
+            .iter()
+            .map(|l| {
+                l.at(&["range", "start", "line"])
+                    .unwrap()
+                    .as_usize()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(lines, vec![0, 3]);
+        let Some(Json::Array(items)) = replies[3].get("result") else {
+            panic!("expected completions: {}", replies[3])
+        };
+        let details: Vec<_> = items
+            .iter()
+            .map(|item| item.get("detail").unwrap().as_str().unwrap())
+            .collect();
+        assert_eq!(details, vec!["likes/2", "fan/1"]);
+        assert_eq!(replies[4].get("result"), Some(&Json::Null));
+    }
+
+    #[test]
+    fn positions_count_utf16_units() {
+        let text = "é😀x\nab";
+        assert_eq!(
+            position(text, 6).get("character").unwrap().as_usize(),
+            Some(3)
+        );
+        assert_eq!(offset(text, 0, 3), 6);
+        assert_eq!(offset(text, 1, 1), 9);
+        assert_eq!(name_at("fan(X) :- likes(X)", 12), Some("likes"));
+        assert_eq!(name_at("fan(X)", 4), None);
+        assert_eq!(name_at("好き(X) :- 日本(X)", 16), Some("日本"));
+    }
+}
@@ -1,24 +1,523 @@
 use amateurlog::*;
-use rand::thread_rng;
-use std::str::FromStr;
-fn main() {
-    let mut gen = thread_rng();
-    let r1 = Functor::new_fact(
-        Atom::from_str("likes").unwrap(),
-        vec![Term::atom_from_str("sam"), Term::atom_from_str("chocolate")],
-    );
-    let r2 = Functor::new_fact(
-        Atom::from_str("likes").unwrap(),
-        vec![Term::atom_from_str("popeye"), Term::atom_from_str("treats")],
-    );
-    let mut db = Database::from_rules(vec![r1, r2]);
-    let goal = Functor::new_fact(
-        Atom::from_str("likes").unwrap(),
-        vec![
-            Term::variable_from_str("X", &mut gen),
-            Term::atom_from_str("treats"),
-        ],
+use editor::Editor;
+use std::error::Error;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::{env, fs, io};
+
+mod editor;
+
+const USAGE: &str = "usage: amateurlog run <file>... [-q <query>]... [options]
+       amateurlog repl [<file>...] [options]
+
+options:
+    -q, --query <goal>   run a query after loading (repeatable)
+    --format <format>    print solutions as `bindings` (default), `goal`, `json` or `dot`
+    --json               same as `--format json`: one JSON object per solution
+    --proof-dot          same as `--format dot`: a Graphviz digraph of each proof
+    --limit <n>          stop after n solutions per query
+    --distinct           leave out solutions repeating an earlier one
+    --strategy <order>   search `depth-first` (default) or `breadth-first`
+    --trace              print each goal as it is called
+    --debug              step through each query's call, exit, redo and fail ports
+    --spy <name/arity>   with --debug, leap to the ports of this predicate (repeatable)
+
+debugger commands: c or enter to creep, l to leap, s to skip, a to abort
+
+the repl keeps its history in $AMATEURLOG_HISTORY, or ~/.amateurlog_history";
+
+const COMMANDS: &str = "repl commands:
+    :load <file>             consult a file
+    :listing [<name/arity>]  print the clauses loaded, or those of one predicate
+    :trace on|off            print each goal as it is called, or stop
+    :stats                   count what is loaded
+    :clear                   forget every clause loaded
+    :help                    show this";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Bindings,
+    Goal,
+    Json,
+    ProofDot,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    files: Vec<String>,
+    queries: Vec<String>,
+    format: Format,
+    limit: Option<usize>,
+    distinct: bool,
+    strategy: Strategy,
+    trace: bool,
+    debug: bool,
+    spy: Vec<(String, Arity)>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        files: Vec::new(),
+        queries: Vec::new(),
+        format: Format::Bindings,
+        limit: None,
+        distinct: false,
+        strategy: Strategy::DepthFirst,
+        trace: false,
+        debug: false,
+        spy: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "-q" | "--query" => options.queries.push(value()?),
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "bindings" => Format::Bindings,
+                    "goal" => Format::Goal,
+                    "json" => Format::Json,
+                    "dot" => Format::ProofDot,
+                    other => return Err(format!("unknown format `{}`", other)),
+                }
+            }
+            "--limit" => {
+                let limit = value()?;
+                let limit = limit
+                    .parse()
+                    .map_err(|_| format!("invalid limit `{}`", limit))?;
+                options.limit = Some(limit);
+            }
+            "--distinct" => options.distinct = true,
+            "--strategy" => {
+                options.strategy = match value()?.as_str() {
+                    "depth-first" => Strategy::DepthFirst,
+                    "breadth-first" => Strategy::BreadthFirst,
+                    other => return Err(format!("unknown strategy `{}`", other)),
+                }
+            }
+            "--json" => options.format = Format::Json,
+            "--proof-dot" => options.format = Format::ProofDot,
+            "--trace" => options.trace = true,
+            "--debug" => options.debug = true,
+            "--spy" => {
+                let spec = value()?;
+                let point = indicator(&spec).ok_or(format!("invalid spy point `{}`", spec))?;
+                options.spy.push(point);
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option `{}`", flag)),
+            _ => options.files.push(arg),
+        }
+    }
+    Ok(options)
+}
+
+// `name/arity`
+fn indicator(spec: &str) -> Option<(String, Arity)> {
+    let (name, arity) = spec.rsplit_once('/')?;
+    Some((name.to_string(), arity.parse().ok()?))
+}
+
+#[derive(Debug, PartialEq)]
+enum MetaCommand {
+    Load(String),
+    Listing(Option<(String, Arity)>),
+    Trace(bool),
+    Stats,
+    Clear,
+    Help,
+}
+
+// a repl line starting with `:`, handled by the repl itself
+fn parse_command(line: &str) -> Result<MetaCommand, String> {
+    let line = line.trim().trim_start_matches(':');
+    let (name, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let arg = arg.trim();
+    match (name, arg) {
+        ("load", "") => Err(String::from(":load needs a file")),
+        ("load", file) => Ok(MetaCommand::Load(file.to_string())),
+        ("listing", "") => Ok(MetaCommand::Listing(None)),
+        ("listing", spec) => indicator(spec)
+            .map(|spec| MetaCommand::Listing(Some(spec)))
+            .ok_or(format!("invalid predicate `{}`, expected name/arity", spec)),
+        ("trace", "on") => Ok(MetaCommand::Trace(true)),
+        ("trace", "off") => Ok(MetaCommand::Trace(false)),
+        ("trace", _) => Err(String::from(":trace takes on or off")),
+        ("stats", "") => Ok(MetaCommand::Stats),
+        ("clear", "") => Ok(MetaCommand::Clear),
+        ("help", "") => Ok(MetaCommand::Help),
+        ("stats" | "clear" | "help", _) => Err(format!(":{} takes no arguments", name)),
+        _ => Err(format!("unknown command `:{}`, see :help", name)),
+    }
+}
+
+// consults `file` into `db`, giving its warnings and then any errors
+fn load(db: &mut Database, file: &str) -> Result<(), String> {
+    let loaded = fs::read_to_string(file)
+        .map_err(|err| err.to_string())
+        .and_then(|source| {
+            db.consult_named(file, &source)
+                .map_err(|err| err.to_string())
+        });
+    for warning in db.take_warnings() {
+        eprintln!("{}: warning: {}", file, warning);
+    }
+    loaded.map_err(|err| format!("{}: {}", file, err))
+}
+
+fn run_command(db: &mut Arc<Database>, command: MetaCommand) {
+    match command {
+        MetaCommand::Load(file) => match load(Arc::make_mut(db), &file) {
+            Ok(()) => println!("loaded {}", file),
+            Err(err) => eprintln!("{}", err),
+        },
+        MetaCommand::Listing(None) => print!("{}", db.listing()),
+        MetaCommand::Listing(Some((name, arity))) => {
+            print!("{}", db.listing_predicate(&name, arity))
+        }
+        MetaCommand::Trace(on) => Arc::make_mut(db).trace(on),
+        MetaCommand::Stats => {
+            let stats = db.stats();
+            for p in &stats.predicates {
+                println!(
+                    "{}:{}/{}: {} clauses, {} terms",
+                    p.module, p.name, p.arity, p.clauses, p.terms
+                );
+            }
+            println!(
+                "{} predicates, {} clauses, {} terms, about {} bytes; {} atoms",
+                stats.predicates.len(),
+                stats.clauses,
+                stats.terms,
+                stats.memory,
+                stats.atoms
+            );
+        }
+        MetaCommand::Clear => Arc::make_mut(db).clear_clauses(),
+        MetaCommand::Help => println!("{}", COMMANDS),
+    }
+}
+
+// asks on stdin what to do at each port
+struct Terminal;
+
+impl Debugger for Terminal {
+    fn port(&self, port: Port, depth: usize, goal: &Term) -> Command {
+        loop {
+            let at = match goal {
+                Term::Functor(f) => f.span().map(|span| format!(" at {}", span)),
+                _ => None,
+            };
+            let at = at.unwrap_or_default();
+            eprint!("   {}: ({}) {}{} ? ", port, depth, goal.pretty(), at);
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                return Command::Abort;
+            }
+            match line.trim() {
+                "" | "c" => return Command::Creep,
+                "l" => return Command::Leap,
+                "s" => return Command::Skip,
+                "a" => return Command::Abort,
+                _ => eprintln!("c: creep, l: leap, s: skip, a: abort"),
+            }
+        }
+    }
+}
+
+fn render(query: &Query, bindings: &Bindings, proof: Option<&Proof>, format: Format) -> String {
+    match format {
+        Format::ProofDot => proof
+            .map(|p| String::from(p.to_dot().trim_end()))
+            .unwrap_or_default(),
+        Format::Goal => format!(
+            "{}.",
+            bindings
+                .resolve(&Term::Functor(Box::new(query.goal.clone())))
+                .pretty()
+        ),
+        Format::Json => query.solution(bindings).to_json(),
+        Format::Bindings => format!("{}.", answer(query, bindings)),
+    }
+}
+
+// the variables' values, or `true` if there are none to show
+fn answer(query: &Query, bindings: &Bindings) -> String {
+    query.solution(bindings).to_string()
+}
+
+fn run_query(db: &mut Database, source: &str, options: &Options) -> Result<(), Box<dyn Error>> {
+    let query = parse_query(source)?;
+    let mut found = 0;
+    let mut on_solution = |bindings: &Bindings, proof: Option<&Proof>| {
+        found += 1;
+        println!("{}", render(&query, bindings, proof, options.format));
+        if options.limit.is_some_and(|limit| found >= limit) {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    };
+    match options.format {
+        _ if options.limit == Some(0) => {}
+        Format::ProofDot => db.for_each_proof_mut(query.goal.clone(), |bindings, proof| {
+            on_solution(bindings, Some(proof))
+        })?,
+        _ => {
+            db.for_each_solution_mut(query.goal.clone(), |bindings| on_solution(bindings, None))?
+        }
+    }
+    if found == 0 && !matches!(options.format, Format::Json | Format::ProofDot) {
+        println!("false.");
+    }
+    Ok(())
+}
+
+// where the repl keeps the queries it was given
+fn history_file() -> Option<PathBuf> {
+    env::var_os("AMATEURLOG_HISTORY")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".amateurlog_history")))
+}
+
+// runs a query typed at the repl, keeping what it asserts and retracts
+fn ask(
+    db: &mut Arc<Database>,
+    source: &str,
+    editor: &mut Editor,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    if options.format != Format::Bindings {
+        return run_query(Arc::make_mut(db), source, options);
+    }
+    let query = parse_query(source)?;
+    let mut answers = db.clone().answers(query.goal.clone());
+    let shown = show_answers(&query, &mut answers, editor, options);
+    answers.keep_changes(db);
+    shown
+}
+
+// shows each answer in turn, asking after each whether to look for another:
+// `;` does, Enter (or anything else) stops
+fn show_answers(
+    query: &Query,
+    answers: &mut Answers,
+    editor: &mut Editor,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let mut found = 0;
+    loop {
+        let bindings = match answers.next() {
+            Some(answer) => answer?,
+            None => {
+                println!("false.");
+                return Ok(());
+            }
+        };
+        found += 1;
+        let shown = answer(query, &bindings);
+        if options.limit.is_some_and(|limit| found >= limit) {
+            println!("{}.", shown);
+            return Ok(());
+        }
+        let mut prompt = format!("{} ", shown);
+        loop {
+            match wants_next(editor.read_answer(&prompt, reply)?) {
+                Some(true) => break,
+                Some(false) => return Ok(()),
+                None => prompt = String::from("(; for the next answer, enter to stop) "),
+            }
+        }
+    }
+}
+
+// whether `key` asks for the next answer or to stop, if either
+fn wants_next(key: char) -> Option<bool> {
+    match key {
+        ';' | ' ' | 'n' | 'r' => Some(true),
+        '\n' | '.' | 'a' | 'c' => Some(false),
+        _ => None,
+    }
+}
+
+// shown after an answer for the key pressed
+fn reply(key: char) -> &'static str {
+    match wants_next(key) {
+        Some(true) => ";",
+        Some(false) => ".",
+        None => "",
+    }
+}
+
+// reads queries until the end of input, each up to the line ending in `.`,
+// and runs the commands given between them
+fn repl(mut db: Arc<Database>, options: &Options) -> io::Result<()> {
+    let mut editor = Editor::new(history_file())?;
+    loop {
+        let mut query = String::new();
+        while !query.trim_end().ends_with('.') {
+            let prompt = if query.is_empty() { "?- " } else { "|    " };
+            let Some(line) = editor.read_line(prompt)? else {
+                return Ok(());
+            };
+            if query.is_empty() && line.trim().starts_with(':') {
+                editor.add_history(line.trim());
+                match parse_command(&line) {
+                    Ok(command) => run_command(&mut db, command),
+                    Err(err) => eprintln!("{}", err),
+                }
+                continue;
+            }
+            if line.trim().is_empty() && query.is_empty() {
+                continue;
+            }
+            if !query.is_empty() {
+                query.push(' ');
+            }
+            query += line.trim();
+        }
+        editor.add_history(&query);
+        if let Err(err) = ask(&mut db, &query, &mut editor, options) {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let interactive = match args.next().as_deref() {
+        Some("run") => false,
+        Some("repl") => true,
+        Some("help" | "-h" | "--help") => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut db = Database::new();
+    db.trace(options.trace);
+    db.set_config(
+        SolverConfig::default()
+            .distinct(options.distinct)
+            .strategy(options.strategy),
     );
-    let answer = db.satisfy(goal).expect("answer");
-    println!("answer: {:?}", answer);
+    for file in &options.files {
+        if let Err(err) = load(&mut db, file) {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    }
+    if options.debug {
+        db.set_debugger(Terminal);
+    }
+    for (name, arity) in &options.spy {
+        db.spy(name, *arity);
+    }
+    for query in &options.queries {
+        if let Err(err) = run_query(&mut db, query, &options) {
+            eprintln!("query `{}`: {}", query, err);
+            return ExitCode::FAILURE;
+        }
+    }
+    if interactive {
+        if let Err(err) = repl(Arc::new(db), &options) {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|a| String::from(*a))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn parses_run_arguments() {
+        let options = parse_args(args(&[
+            "kb.pl",
+            "-q",
+            "likes(X, treats)",
+            "--limit",
+            "2",
+            "--distinct",
+            "--strategy",
+            "breadth-first",
+            "--format",
+            "goal",
+            "--trace",
+            "--spy",
+            "likes/2",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                files: vec![String::from("kb.pl")],
+                queries: vec![String::from("likes(X, treats)")],
+                format: Format::Goal,
+                limit: Some(2),
+                distinct: true,
+                strategy: Strategy::BreadthFirst,
+                trace: true,
+                debug: false,
+                spy: vec![(String::from("likes"), 2)],
+            }
+        );
+    }
+
+    #[test]
+    fn format_shorthand_flags() {
+        let options = parse_args(args(&["kb.pl", "--json"])).unwrap();
+        assert_eq!(options.format, Format::Json);
+        let options = parse_args(args(&["kb.pl", "--proof-dot"])).unwrap();
+        assert_eq!(options.format, Format::ProofDot);
+    }
+
+    #[test]
+    fn parses_repl_commands() {
+        assert_eq!(
+            parse_command(":load  kb.pl "),
+            Ok(MetaCommand::Load(String::from("kb.pl")))
+        );
+        assert_eq!(
+            parse_command(":listing likes/2"),
+            Ok(MetaCommand::Listing(Some((String::from("likes"), 2))))
+        );
+        assert_eq!(parse_command(":listing"), Ok(MetaCommand::Listing(None)));
+        assert_eq!(parse_command(":trace off"), Ok(MetaCommand::Trace(false)));
+        assert_eq!(parse_command(":stats"), Ok(MetaCommand::Stats));
+        assert!(parse_command(":load").is_err());
+        assert!(parse_command(":listing likes").is_err());
+        assert!(parse_command(":trace maybe").is_err());
+        assert!(parse_command(":clear all").is_err());
+        assert!(parse_command(":frobnicate").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse_args(args(&["--limit", "lots"])).is_err());
+        assert!(parse_args(args(&["-q"])).is_err());
+        assert!(parse_args(args(&["--frobnicate"])).is_err());
+        assert!(parse_args(args(&["--spy", "likes"])).is_err());
+        assert!(parse_args(args(&["--strategy", "sideways"])).is_err());
+    }
 }
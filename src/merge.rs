@@ -0,0 +1,126 @@
+use crate::builtins::variant;
+use crate::module::Module;
+use crate::{Database, Functor, Term};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+impl Database {
+    /// Adds every module, clause and foreign predicate of `other`. Clauses are
+    /// appended after those already in the same module, and export lists are
+    /// combined.
+    pub fn merge(&mut self, other: Database) {
+        self.merge_clauses(other, false);
+    }
+    /// Like `merge`, but skips clauses that are the same as one already in
+    /// the module up to variable names, returning the skipped clauses.
+    pub fn merge_unique(&mut self, other: Database) -> Vec<Functor> {
+        self.merge_clauses(other, true)
+    }
+    fn merge_clauses(&mut self, other: Database, unique: bool) -> Vec<Functor> {
+        let mut duplicates = Vec::new();
+        let mut modules: Vec<Module> = other.modules.into_values().collect();
+        modules.sort_by(|fst, snd| fst.name().as_str().cmp(snd.name().as_str()));
+        for other in modules {
+            let module = self
+                .modules
+                .entry(*other.name())
+                .or_insert_with(|| Module::new(*other.name(), Vec::new()));
+            for &export in &other.exports {
+                if !module.exports(&export.0, export.1) {
+                    module.exports.push(export);
+                }
+            }
+            for clause in other.clauses().cloned() {
+                let existing = module.predicate(&clause.name, clause.arity());
+                if unique && existing.iter().any(|c| same_clause(c, &clause)) {
+                    duplicates.push(clause);
+                    continue;
+                }
+                module.add(clause);
+            }
+        }
+        self.foreign.extend(other.foreign);
+        duplicates
+    }
+}
+
+/// Adds clauses to the module being loaded, as `add` does.
+impl Extend<Functor> for Database {
+    fn extend<I: IntoIterator<Item = Functor>>(&mut self, clauses: I) {
+        for clause in clauses {
+            self.add(clause);
+        }
+    }
+}
+
+fn same_clause(fst: &Functor, snd: &Functor) -> bool {
+    fst.body.len() == snd.body.len() && variant(&clause_term(fst), &clause_term(snd))
+}
+
+// the head and body goals as one list, so variables are renamed consistently
+fn clause_term(clause: &Functor) -> Term {
+    let goal = |f: &Functor| Term::Functor(Box::new(Functor::new_fact(f.name, f.args.clone())));
+    Term::list_from_vec(
+        core::iter::once(clause)
+            .chain(&clause.body)
+            .map(goal)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{answers, consulted};
+    use crate::*;
+
+    #[test]
+    fn merged_clauses_follow_existing_ones() {
+        let mut db = consulted("likes(sam, chocolate). fan(X) :- likes(X, treats).");
+        db.merge(consulted("likes(popeye, treats). likes(olive, spinach)."));
+        assert_eq!(
+            answers(&db, "likes(X, _)").unwrap(),
+            vec!["X = sam", "X = popeye", "X = olive"]
+        );
+        assert_eq!(answers(&db, "fan(X)").unwrap(), vec!["X = popeye"]);
+        let user = db.module("user").unwrap();
+        let ixs: Vec<u32> = user.clauses().map(|c| c.ix).collect();
+        assert_eq!(ixs, vec![0, 2, 3, 1]);
+    }
+
+    #[test]
+    fn merges_modules_and_exports() {
+        let mut db = consulted(":- module(a, [pa/1]). pa(X) :- helper(X). helper(a).");
+        db.merge(consulted(
+            ":- module(a, [pb/1]). pb(X) :- helper(X). helper(b).",
+        ));
+        assert_eq!(answers(&db, "pa(X)").unwrap(), vec!["X = a", "X = b"]);
+        assert_eq!(answers(&db, "pb(X)").unwrap(), vec!["X = a", "X = b"]);
+        assert!(answers(&db, "helper(X)").is_err());
+    }
+
+    #[test]
+    fn merge_unique_skips_variants() {
+        let mut db = consulted("p(a). q(X) :- p(X).");
+        let skipped = db.merge_unique(consulted("q(Y) :- p(Y). p(a). p(b). q(Y) :- p(a)."));
+        let skipped: Vec<String> = skipped.iter().map(|c| c.to_string()).collect();
+        assert_eq!(skipped, vec!["q(Y)", "p(a)"]);
+        assert_eq!(answers(&db, "p(X)").unwrap(), vec!["X = a", "X = b"]);
+    }
+
+    #[test]
+    fn extend_adds_clauses() {
+        let mut db = consulted("p(a).");
+        db.extend(
+            consulted("p(b). p(c).")
+                .module("user")
+                .unwrap()
+                .clauses()
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            answers(&db, "p(X)").unwrap(),
+            vec!["X = a", "X = b", "X = c"]
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use crate::compat::{HashMap, HashSet};
+use crate::{Arity, Atom, Bindings, Database, Functor, Term};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A namespace of clauses. Only exported predicates are visible from other modules.
+#[derive(Clone, Debug)]
+pub struct Module {
+    name: Atom,
+    pub(crate) exports: Vec<(Atom, Arity)>,
+    // the clauses of each predicate, in the order they were added
+    clauses: HashMap<(Atom, Arity), Vec<Functor>>,
+    // each predicate, in the order its first clause was added
+    predicates: Vec<(Atom, Arity)>,
+    // predicates declared `dynamic` or asserted to, which stay defined
+    // without clauses
+    dynamic: HashSet<(Atom, Arity)>,
+    // predicates whose clauses may be apart in the source
+    discontiguous: HashSet<(Atom, Arity)>,
+    len: usize,
+    // the `ix` of the next clause added
+    next_ix: u32,
+}
+
+impl Module {
+    pub fn new(name: Atom, exports: Vec<(Atom, Arity)>) -> Self {
+        Self {
+            name,
+            exports,
+            clauses: HashMap::new(),
+            predicates: Vec::new(),
+            dynamic: HashSet::new(),
+            discontiguous: HashSet::new(),
+            len: 0,
+            next_ix: 0,
+        }
+    }
+    /// Every clause, grouped by predicate, with predicates in the order they
+    /// were first defined.
+    pub fn clauses(&self) -> impl Iterator<Item = &Functor> {
+        self.predicates
+            .iter()
+            .flat_map(|predicate| &self.clauses[predicate])
+    }
+    /// How many clauses the module has.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Each predicate with its clauses, in the order it was first defined.
+    pub(crate) fn predicates(&self) -> impl Iterator<Item = (&(Atom, Arity), &[Functor])> {
+        self.predicates
+            .iter()
+            .map(|predicate| (predicate, self.clauses[predicate].as_slice()))
+    }
+    pub(crate) fn index_len(&self) -> usize {
+        self.clauses.len()
+    }
+    pub(crate) fn index_capacity(&self) -> usize {
+        self.clauses.capacity()
+    }
+    /// The clauses of `name/arity`, in order.
+    pub(crate) fn predicate(&self, name: &Atom, arity: Arity) -> &[Functor] {
+        self.clauses.get(&(*name, arity)).map_or(&[], Vec::as_slice)
+    }
+    /// Adds `clause` after the others of its predicate.
+    pub(crate) fn add(&mut self, clause: Functor) {
+        self.insert(clause, false);
+    }
+    /// Adds `clause` before the others of its predicate.
+    pub(crate) fn add_first(&mut self, clause: Functor) {
+        self.insert(clause, true);
+    }
+    fn insert(&mut self, mut clause: Functor, first: bool) {
+        clause.ix = self.next_ix;
+        self.next_ix += 1;
+        self.len += 1;
+        let predicate = (clause.name, clause.arity());
+        if !self.clauses.contains_key(&predicate) {
+            self.predicates.push(predicate);
+        }
+        let clauses = self.clauses.entry(predicate).or_default();
+        if first {
+            clauses.insert(0, clause);
+        } else {
+            clauses.push(clause);
+        }
+    }
+    /// Removes the clause of `name/arity` numbered `ix`.
+    pub(crate) fn remove(&mut self, name: Atom, arity: Arity, ix: u32) {
+        let predicate = (name, arity);
+        let Some(clauses) = self.clauses.get_mut(&predicate) else {
+            return;
+        };
+        let before = clauses.len();
+        clauses.retain(|c| c.ix != ix);
+        self.len -= before - clauses.len();
+        if clauses.is_empty() {
+            self.clauses.remove(&predicate);
+            self.predicates.retain(|p| *p != predicate);
+        }
+    }
+    pub fn name(&self) -> &Atom {
+        &self.name
+    }
+    pub fn exports(&self, name: &Atom, arity: Arity) -> bool {
+        self.exports.iter().any(|(n, a)| n == name && *a == arity)
+    }
+    /// Whether `name/arity` has clauses here or is dynamic.
+    pub fn defines(&self, name: &Atom, arity: Arity) -> bool {
+        self.clauses.contains_key(&(*name, arity)) || self.is_dynamic(name, arity)
+    }
+    pub fn is_dynamic(&self, name: &Atom, arity: Arity) -> bool {
+        self.dynamic.contains(&(*name, arity))
+    }
+    /// The dynamic predicates, by name and arity.
+    pub(crate) fn dynamic(&self) -> Vec<(Atom, Arity)> {
+        let mut dynamic: Vec<(Atom, Arity)> = self.dynamic.iter().copied().collect();
+        dynamic.sort_by_key(|(name, arity)| (name.as_str(), *arity));
+        dynamic
+    }
+    pub(crate) fn declare_dynamic(&mut self, name: Atom, arity: Arity) {
+        self.dynamic.insert((name, arity));
+    }
+    pub(crate) fn is_discontiguous(&self, name: &Atom, arity: Arity) -> bool {
+        self.discontiguous.contains(&(*name, arity))
+    }
+    pub(crate) fn declare_discontiguous(&mut self, name: Atom, arity: Arity) {
+        self.discontiguous.insert((name, arity));
+    }
+}
+
+pub(crate) fn user() -> Atom {
+    Atom::new("user")
+}
+
+pub(crate) fn user_modules() -> HashMap<Atom, Module> {
+    HashMap::from([(user(), Module::new(user(), Vec::new()))])
+}
+
+/// Wraps `goal` as `module:goal`, unless it already runs in `user`.
+pub(crate) fn qualify(module: &Atom, goal: Term) -> Term {
+    if *module == user() {
+        goal
+    } else {
+        Term::Functor(Box::new(Functor::new_fact(
+            Atom::new(":"),
+            vec![Term::Atom(*module), goal],
+        )))
+    }
+}
+
+impl Database {
+    /// Runs a directive (the body of `:- Goal`). `module(Name, Exports)` makes
+    /// subsequently added clauses belong to `Name`, `dynamic Name/Arity`
+    /// makes calls to a predicate without clauses fail rather than throw, and
+    /// `discontiguous Name/Arity` lets its clauses be apart in the source;
+    /// anything else is proved as a goal, once, keeping the clauses it
+    /// asserts and retracts.
+    pub fn directive(&mut self, directive: Functor) -> bool {
+        let declare: Option<fn(&mut Module, Atom, Arity)> =
+            match (directive.name.as_str(), directive.arity()) {
+                ("dynamic", 1) => Some(Module::declare_dynamic),
+                ("discontiguous", 1) => Some(Module::declare_discontiguous),
+                _ => None,
+            };
+        if let Some(declare) = declare {
+            let Some(predicates) = predicate_indicators(&directive.args[0]) else {
+                return false;
+            };
+            let loading = self.loading;
+            let module = self
+                .modules
+                .entry(loading)
+                .or_insert_with(|| Module::new(loading, Vec::new()));
+            for (name, arity) in predicates {
+                declare(module, name, arity);
+            }
+            return true;
+        }
+        if directive.name.as_str() == "module" && directive.arity() == 2 {
+            let (Term::Atom(name), Some(exports)) = (
+                &directive.args[0],
+                Bindings::new().list_to_vec(&directive.args[1]),
+            ) else {
+                return false;
+            };
+            let Some(exports) = exports
+                .iter()
+                .map(predicate_indicator)
+                .collect::<Option<Vec<_>>>()
+            else {
+                return false;
+            };
+            self.modules
+                .entry(*name)
+                .and_modify(|m| m.exports = exports.clone())
+                .or_insert_with(|| Module::new(*name, exports));
+            self.loading = *name;
+            true
+        } else {
+            self.run_directive(directive)
+        }
+    }
+    pub fn module(&self, name: &str) -> Option<&Module> {
+        self.modules.get(&Atom::new(name))
+    }
+    /// The clauses of `name/arity` that a query would use, in order.
+    pub fn clauses(&self, name: &str, arity: Arity) -> impl Iterator<Item = &Functor> {
+        let name = Atom::new(name);
+        self.visible_module(&user(), &name, arity)
+            .into_iter()
+            .flat_map(move |m| m.predicate(&name, arity))
+    }
+    /// The name and arity of each predicate a query can call: those defined
+    /// in `user`, then those exported by other modules, in order of definition.
+    pub fn predicates(&self) -> Vec<(Atom, Arity)> {
+        let mut modules: Vec<&Module> = self.modules.values().collect();
+        modules.sort_by_key(|m| (*m.name() != user(), m.name().as_str()));
+        let mut predicates = Vec::new();
+        for module in modules {
+            for &(name, arity) in &module.predicates {
+                let visible = *module.name() == user() || module.exports(&name, arity);
+                if visible && !predicates.contains(&(name, arity)) {
+                    predicates.push((name, arity));
+                }
+            }
+        }
+        predicates
+    }
+    /// Goes back to adding clauses to `user`, e.g. at the end of a module file.
+    pub fn end_module(&mut self) {
+        self.loading = user();
+    }
+    /// The module whose clauses answer `name/arity` when called from `context`:
+    /// the context itself, then `user`, then any module exporting the predicate.
+    pub(crate) fn visible_module(
+        &self,
+        context: &Atom,
+        name: &Atom,
+        arity: Arity,
+    ) -> Option<&Module> {
+        self.modules
+            .get(context)
+            .filter(|m| m.defines(name, arity))
+            .or_else(|| self.modules.get(&user()).filter(|m| m.defines(name, arity)))
+            .or_else(|| {
+                self.modules
+                    .values()
+                    .find(|m| m.exports(name, arity) && m.defines(name, arity))
+            })
+    }
+}
+
+// name/arity, a conjunction of them or a list of them
+fn predicate_indicators(term: &Term) -> Option<Vec<(Atom, Arity)>> {
+    match term {
+        Term::Functor(f) if f.name.as_str() == "," && f.arity() == 2 => {
+            let mut indicators = predicate_indicators(&f.args[0])?;
+            indicators.extend(predicate_indicators(&f.args[1])?);
+            Some(indicators)
+        }
+        _ => match Bindings::new().list_to_vec(term) {
+            Some(items) => items.iter().map(predicate_indicator).collect(),
+            None => Some(vec![predicate_indicator(term)?]),
+        },
+    }
+}
+
+// name/arity
+pub(crate) fn predicate_indicator(term: &Term) -> Option<(Atom, Arity)> {
+    match term {
+        Term::Functor(f) if f.name.as_str() == "/" && f.arity() == 2 => {
+            match (&f.args[0], &f.args[1]) {
+                (Term::Atom(name), Term::Integer(arity)) => {
+                    Some((*name, Arity::try_from(*arity).ok()?))
+                }
+                (_, _) => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compat::thread_rng;
+    use crate::*;
+    use std::str::FromStr;
+
+    fn module(name: &str, exports: &[(&str, i64)]) -> Functor {
+        Functor::new_fact(
+            Atom::from_str("module").unwrap(),
+            vec![
+                Term::atom_from_str(name),
+                Term::list_from_vec(
+                    exports
+                        .iter()
+                        .map(|(n, a)| {
+                            Term::functor_from_str(
+                                "/",
+                                vec![Term::atom_from_str(n), Term::Integer(*a)],
+                            )
+                        })
+                        .collect(),
+                ),
+            ],
+        )
+    }
+
+    // :- module(Name, [Exported/1]).
+    // helper(x, Value).
+    // Exported(X) :- helper(x, X).
+    fn load_module(db: &mut Database, name: &str, exported: &str, value: &str) {
+        let mut gen = thread_rng();
+        assert!(db.directive(module(name, &[(exported, 1)])));
+        db.add(Functor::new_fact(
+            Atom::from_str("helper").unwrap(),
+            vec![Term::atom_from_str("x"), Term::atom_from_str(value)],
+        ));
+        let x = Term::variable_from_str("X", &mut gen);
+        db.add(Functor::new_rule(
+            Atom::from_str(exported).unwrap(),
+            vec![x.clone()],
+            vec![Functor::new_fact(
+                Atom::from_str("helper").unwrap(),
+                vec![Term::atom_from_str("x"), x],
+            )],
+        ));
+        db.end_module();
+    }
+
+    fn answer(db: &Database, goal: Term, x: &Term) -> Vec<Term> {
+        let Term::Functor(goal) = goal else {
+            panic!("goal must be a functor")
+        };
+        db.satisfy_all(*goal).iter().map(|b| b.resolve(x)).collect()
+    }
+
+    #[test]
+    fn lists_visible_predicates_and_clauses() {
+        let mut db = Database::new();
+        db.consult(
+            "likes(sam, chocolate). likes(popeye, spinach). happy(X) :- likes(X, _).
+             :- module(a, [pa/1]). pa(X) :- helper(X). helper(a).",
+        )
+        .unwrap();
+        let predicates: Vec<String> = db
+            .predicates()
+            .iter()
+            .map(|(name, arity)| format!("{}/{}", name, arity))
+            .collect();
+        assert_eq!(predicates, vec!["likes/2", "happy/1", "pa/1"]);
+        let likes: Vec<String> = db.clauses("likes", 2).map(|c| c.to_string()).collect();
+        assert_eq!(
+            likes,
+            vec!["likes(sam, chocolate)", "likes(popeye, spinach)"]
+        );
+        let happy: Vec<&Functor> = db.clauses("happy", 1).collect();
+        assert_eq!(happy[0].body().len(), 1);
+        assert_eq!(db.clauses("pa", 1).count(), 1);
+        assert_eq!(db.clauses("helper", 1).count(), 0);
+        assert_eq!(db.clauses("likes", 3).count(), 0);
+    }
+
+    #[test]
+    fn modules_keep_helpers_apart() {
+        let mut gen = thread_rng();
+        let mut db = Database::new();
+        load_module(&mut db, "a", "pa", "from_a");
+        load_module(&mut db, "b", "pb", "from_b");
+        let x = Term::variable_from_str("X", &mut gen);
+        assert_eq!(
+            answer(&db, Term::functor_from_str("pa", vec![x.clone()]), &x),
+            vec![Term::atom_from_str("from_a")]
+        );
+        assert_eq!(
+            answer(&db, Term::functor_from_str("pb", vec![x.clone()]), &x),
+            vec![Term::atom_from_str("from_b")]
+        );
+    }
+
+    #[test]
+    fn unexported_predicates_need_qualification() {
+        let mut gen = thread_rng();
+        let mut db = Database::new();
+        load_module(&mut db, "a", "pa", "from_a");
+        let x = Term::variable_from_str("X", &mut gen);
+        let helper = Term::functor_from_str("helper", vec![Term::atom_from_str("x"), x.clone()]);
+        assert!(answer(&db, helper.clone(), &x).is_empty());
+        assert_eq!(
+            answer(
+                &db,
+                Term::functor_from_str(":", vec![Term::atom_from_str("a"), helper]),
+                &x
+            ),
+            vec![Term::atom_from_str("from_a")]
+        );
+    }
+
+    #[test]
+    fn clauses_are_grouped_by_predicate() {
+        let mut db = Database::new();
+        db.consult("p(1). q(a). p(2). p(3, x). q(b).").unwrap();
+        let user = db.module("user").unwrap();
+        let clauses: Vec<String> = user.clauses().map(|c| c.to_string()).collect();
+        assert_eq!(clauses, vec!["p(1)", "p(2)", "q(a)", "q(b)", "p(3, x)"]);
+        assert_eq!(user.len(), 5);
+        assert!(user.defines(&Atom::new("p"), 2));
+        assert!(!user.defines(&Atom::new("q"), 2));
+    }
+
+    #[test]
+    fn clauses_go_to_user_after_end_module() {
+        let mut db = Database::new();
+        load_module(&mut db, "a", "pa", "from_a");
+        db.add(Functor::new_fact(
+            Atom::from_str("top").unwrap(),
+            Vec::new(),
+        ));
+        let user = db.module("user").expect("user module");
+        assert!(user.defines(&Atom::from_str("top").unwrap(), 0));
+        assert!(!user.defines(&Atom::from_str("helper").unwrap(), 2));
+    }
+}
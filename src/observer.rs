@@ -0,0 +1,131 @@
+//! Watching a search from outside: an `Observer` set with
+//! `Database::set_observer` is told of each goal called, each clause tried
+//! and whether its head unified, and each time the search backtracks, so
+//! tools such as visualizers and profilers can follow a query as it runs.
+//! Unlike a `Debugger`, an observer can't change how the search goes on.
+
+use crate::{Arity, Atom, Database, Term};
+use alloc::sync::Arc;
+
+/// A clause, by its predicate and where it comes in the order a call tries
+/// the predicate's clauses, from 0. Clauses asserted with `asserta/1` come
+/// first, and retracted ones keep their place for the calls that see them.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ClauseId {
+    /// The module the predicate is defined in.
+    pub module: Atom,
+    pub name: Atom,
+    pub arity: Arity,
+    pub index: usize,
+}
+
+/// Something the search did. `depth` is how deeply the goal concerned is
+/// nested, as the debugger counts it: 1 for the query's own goals.
+#[derive(Clone, Copy, Debug)]
+pub enum Event<'e> {
+    /// A goal was called, other than a control construct.
+    Call { depth: usize, goal: &'e Term },
+    /// A clause's head is about to be unified with the goal called.
+    TryClause { depth: usize, clause: ClauseId },
+    /// The clause's head unified, and its body goes on.
+    Unified { depth: usize, clause: ClauseId },
+    /// The clause's head didn't unify.
+    NotUnified { depth: usize, clause: ClauseId },
+    /// The search went back to the goal's last choice point, to try its
+    /// next clause or branch. For a disjunction, an if-then-else or a
+    /// maplist/N or foldl/N, the depth is that of the goals it calls.
+    Backtrack { depth: usize },
+}
+
+/// Told of each `Event` as the search makes it.
+pub trait Observer: Send + Sync {
+    fn event(&self, event: &Event<'_>);
+}
+
+impl Database {
+    /// Tells `observer` of the events of every query from now on.
+    pub fn set_observer<O: Observer + 'static>(&mut self, observer: O) {
+        self.debugging.observer = Some(Arc::new(observer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Recorded(Arc<Mutex<Vec<String>>>);
+
+    impl Observer for Recorded {
+        fn event(&self, event: &Event<'_>) {
+            let shown = match event {
+                Event::Call { depth, goal } => format!("call ({}) {}", depth, goal.pretty()),
+                Event::TryClause { depth, clause } => format!(
+                    "try ({}) {}/{} #{}",
+                    depth, clause.name, clause.arity, clause.index
+                ),
+                Event::Unified { depth, clause } => {
+                    format!("unified ({}) #{}", depth, clause.index)
+                }
+                Event::NotUnified { depth, clause } => {
+                    format!("not unified ({}) #{}", depth, clause.index)
+                }
+                Event::Backtrack { depth } => format!("backtrack ({})", depth),
+            };
+            self.0.lock().unwrap().push(shown);
+        }
+    }
+
+    fn observed(program: &str, query: &str) -> Vec<String> {
+        let recorded = Recorded::default();
+        let mut db = Database::new();
+        db.consult(program).unwrap();
+        db.set_observer(recorded.clone());
+        db.satisfy_all(parse_query(query).unwrap().goal);
+        let events = recorded.0.lock().unwrap().clone();
+        events
+    }
+
+    #[test]
+    fn clauses_tried_and_backtracking_are_seen() {
+        let events = observed(
+            "parent(tom, bob). parent(bob, ann).
+             grandparent(X, Z) :- parent(X, Y), parent(Y, Z).",
+            "grandparent(tom, W)",
+        );
+        assert!(
+            events[3].starts_with("call (2) parent(tom, _"),
+            "{}",
+            events[3]
+        );
+        assert_eq!(
+            events,
+            vec![
+                "call (1) grandparent(tom, W)",
+                "try (1) grandparent/2 #0",
+                "unified (1) #0",
+                &events[3],
+                "try (2) parent/2 #0",
+                "unified (2) #0",
+                "call (2) parent(bob, W)",
+                "try (2) parent/2 #0",
+                "not unified (2) #0",
+                "try (2) parent/2 #1",
+                "unified (2) #1",
+                "backtrack (2)",
+                "try (2) parent/2 #1",
+                "not unified (2) #1",
+            ]
+        );
+    }
+
+    #[test]
+    fn branches_are_backtracked_into() {
+        let events = observed("", "(X = a ; X = b)");
+        assert_eq!(
+            events,
+            vec!["call (1) X = a", "backtrack (1)", "call (1) X = b"]
+        );
+    }
+}
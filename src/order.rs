@@ -0,0 +1,481 @@
+//! The standard order of terms, and the built-ins that compare and sort by it.
+//! Variables come first, then numbers by value, atoms alphabetically, and
+//! compound terms by arity, then name, then arguments left to right.
+//! Variables are ordered by their internal names, which is arbitrary but
+//! stays the same during a query.
+use crate::builtins::{self, Alternatives};
+use crate::compat::{thread_rng, HashSet};
+use crate::error;
+use crate::solver::{Bindings, Halt, Solver};
+use crate::{Atom, Functor, Term, Variable};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::iter;
+use core::ops::ControlFlow;
+use core::ptr;
+
+impl Ord for Atom {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialOrd for Atom {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Orders terms as they are, without following bindings. Only identical
+/// terms compare `Equal`, whereas `==` on functors only looks at the name and
+/// arity; see `Term::identical`.
+impl Ord for Term {
+    fn cmp(&self, other: &Self) -> Ordering {
+        fn rank(term: &Term) -> u8 {
+            match term {
+                Term::Variable(_) => 0,
+                Term::Integer(_) => 1,
+                Term::Atom(_) => 2,
+                Term::String(_) => 3,
+                Term::Functor(_) => 4,
+            }
+        }
+        // the pairs of arguments still to compare, the next last, so deep
+        // terms need no deep Rust stack
+        let mut pending = vec![(self, other)];
+        while let Some((fst, snd)) = pending.pop() {
+            let order = match (fst, snd) {
+                (Term::Variable(fst), Term::Variable(snd)) => fst.alias.cmp(&snd.alias),
+                (Term::Integer(fst), Term::Integer(snd)) => fst.cmp(snd),
+                (Term::Atom(fst), Term::Atom(snd)) => fst.cmp(snd),
+                (Term::String(fst), Term::String(snd)) => fst.cmp(snd),
+                (Term::Functor(fst), Term::Functor(snd)) => {
+                    let order = fst
+                        .arity()
+                        .cmp(&snd.arity())
+                        .then_with(|| fst.name.cmp(&snd.name));
+                    if order.is_eq() {
+                        pending.extend(fst.args.iter().zip(&snd.args).rev());
+                    }
+                    order
+                }
+                (_, _) => rank(fst).cmp(&rank(snd)),
+            };
+            if order.is_ne() {
+                return order;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Term {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Term {
+    /// Whether the terms are the same, argument for argument, as `==/2` has
+    /// it: a variable is only identical to itself. Unlike `==` on functors,
+    /// this looks at the arguments too. Bindings aren't followed, so resolve
+    /// both terms first.
+    pub fn identical(&self, other: &Term) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Bindings {
+    /// Whether the terms are identical once bindings are followed, as `==/2`
+    /// has it. Cyclic terms are identical if they unfold into the same
+    /// infinite tree: after `X = f(X), Y = f(f(Y))`, `X == Y` holds.
+    pub(crate) fn identical(&self, fst: &Term, snd: &Term) -> bool {
+        // the pairs still to compare, the next last; a pair of compound terms
+        // come back to through a binding is already being compared, and taken
+        // to be identical, which is what ends the comparison of cyclic terms
+        let mut pending = vec![(fst, snd)];
+        let mut comparing: HashSet<(*const Functor, *const Functor)> = HashSet::new();
+        while let Some((fst, snd)) = pending.pop() {
+            let (fst_bound, snd_bound) = (self.walk(fst), self.walk(snd));
+            let followed = !ptr::eq(fst, fst_bound) || !ptr::eq(snd, snd_bound);
+            match (fst_bound, snd_bound) {
+                (Term::Functor(f), Term::Functor(g)) => {
+                    if f.name != g.name || f.arity() != g.arity() {
+                        return false;
+                    }
+                    if followed && !comparing.insert((&**f, &**g)) {
+                        continue;
+                    }
+                    pending.extend(f.args.iter().zip(&g.args).rev());
+                }
+                (fst, snd) if fst.identical(snd) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+/// `compare(Order, X, Y)`: unifies `Order` with `<`, `=` or `>`.
+pub(crate) fn compare(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    match bindings.walk(&args[0]) {
+        Term::Variable(_) => {}
+        Term::Atom(a) if matches!(a.as_str(), "<" | "=" | ">") => {}
+        Term::Atom(_) => {
+            return builtins::raise(solver, error::domain_error("order", args[0].clone()))
+        }
+        order => return builtins::raise(solver, error::type_error("atom", order.clone())),
+    }
+    let order = match bindings.resolve(&args[1]).cmp(&bindings.resolve(&args[2])) {
+        Ordering::Less => "<",
+        Ordering::Equal => "=",
+        Ordering::Greater => ">",
+    };
+    Box::new(iter::once(builtins::unify(vec![(
+        args[0].clone(),
+        Term::atom_from_str(order),
+    )])))
+}
+
+fn ordered(args: &[Term], bindings: &Bindings, holds: fn(Ordering) -> bool) -> Alternatives {
+    if holds(bindings.resolve(&args[0]).cmp(&bindings.resolve(&args[1]))) {
+        builtins::success()
+    } else {
+        builtins::failure()
+    }
+}
+
+pub(crate) fn less(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    ordered(args, bindings, Ordering::is_lt)
+}
+
+pub(crate) fn greater(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    ordered(args, bindings, Ordering::is_gt)
+}
+
+pub(crate) fn less_or_equal(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    ordered(args, bindings, Ordering::is_le)
+}
+
+pub(crate) fn greater_or_equal(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    ordered(args, bindings, Ordering::is_ge)
+}
+
+/// `X == Y`: whether the terms are identical, without binding either.
+pub(crate) fn identical(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    if bindings.identical(&args[0], &args[1]) {
+        builtins::success()
+    } else {
+        builtins::failure()
+    }
+}
+
+/// `X \== Y`: whether the terms aren't identical, without binding either.
+pub(crate) fn not_identical(_: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    if bindings.identical(&args[0], &args[1]) {
+        builtins::failure()
+    } else {
+        builtins::success()
+    }
+}
+
+/// `sort(List, Sorted)`: sorts by the standard order, removing duplicates.
+pub(crate) fn sort(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    sorted(solver, args, bindings, true)
+}
+
+/// `msort(List, Sorted)`: like `sort/2`, but keeps duplicates.
+pub(crate) fn msort(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    sorted(solver, args, bindings, false)
+}
+
+fn sorted(solver: &mut Solver, args: &[Term], bindings: &Bindings, dedup: bool) -> Alternatives {
+    let Some(items) = builtins::list(solver, &args[0], bindings) else {
+        return builtins::failure();
+    };
+    let mut items: Vec<Term> = items.iter().map(|item| bindings.resolve(item)).collect();
+    items.sort();
+    if dedup {
+        items.dedup_by(|fst, snd| fst.cmp(&snd).is_eq());
+    }
+    Box::new(iter::once(builtins::unify(vec![(
+        args[1].clone(),
+        Term::list_from_vec(items),
+    )])))
+}
+
+/// `predsort(Pred, List, Sorted)`: merge sorts by calling `Pred(Order, A, B)`,
+/// dropping `B` whenever `Order` is `=`. Fails if a call fails or binds
+/// `Order` to anything else.
+pub(crate) fn predsort(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let Some(items) = builtins::list(solver, &args[1], bindings) else {
+        return builtins::failure();
+    };
+    let pred = bindings.resolve(&args[0]);
+    match merge_sort(solver, &pred, items, bindings) {
+        Some(items) => Box::new(iter::once(builtins::unify(vec![(
+            args[2].clone(),
+            Term::list_from_vec(items),
+        )]))),
+        None => builtins::failure(),
+    }
+}
+
+fn merge_sort(
+    solver: &mut Solver,
+    pred: &Term,
+    mut items: Vec<Term>,
+    bindings: &Bindings,
+) -> Option<Vec<Term>> {
+    if items.len() < 2 {
+        return Some(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(solver, pred, items, bindings)?;
+    let right = merge_sort(solver, pred, right, bindings)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(fst), Some(snd)) = (left.peek(), right.peek()) {
+        match call_order(solver, pred, fst, snd, bindings)? {
+            Ordering::Less => merged.extend(left.next()),
+            Ordering::Greater => merged.extend(right.next()),
+            Ordering::Equal => {
+                right.next();
+            }
+        }
+    }
+    merged.extend(left.chain(right));
+    Some(merged)
+}
+
+// the order `pred` gives `fst` and `snd` in its first solution
+fn call_order(
+    solver: &mut Solver,
+    pred: &Term,
+    fst: &Term,
+    snd: &Term,
+    bindings: &Bindings,
+) -> Option<Ordering> {
+    let order = Term::Variable(Variable::new_anonymous(&mut thread_rng()));
+    let Some(goal) = builtins::add_args(pred, vec![order.clone(), fst.clone(), snd.clone()]) else {
+        solver.raise(match pred {
+            Term::Variable(_) => error::instantiation_error(),
+            _ => error::type_error("callable", pred.clone()),
+        });
+        return None;
+    };
+    let goal = solver.in_context(goal);
+    let mut found = None;
+    let flow = solver.solve(&[goal], &mut bindings.clone(), &mut |b, _| {
+        found = Some(b.resolve(&order));
+        ControlFlow::Break(())
+    });
+    if let ControlFlow::Break(Halt::Thrown(ball)) = flow {
+        solver.raise(ball);
+        return None;
+    }
+    match found? {
+        Term::Atom(a) if a.as_str() == "<" => Some(Ordering::Less),
+        Term::Atom(a) if a.as_str() == "=" => Some(Ordering::Equal),
+        Term::Atom(a) if a.as_str() == ">" => Some(Ordering::Greater),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+
+    #[test]
+    fn orders_by_kind_then_value() {
+        let mut terms: Vec<Term> = ["f(b)", "g(a, a)", "b", "10", "a", "f(a)", "-(1)", "2", "X"]
+            .iter()
+            .map(|t| parse_term(t).unwrap())
+            .collect();
+        terms.sort();
+        let sorted: Vec<String> = terms.iter().map(|t| t.to_string()).collect();
+        assert_eq!(
+            sorted,
+            vec!["X", "2", "10", "a", "b", "-(1)", "f(a)", "f(b)", "g(a, a)"]
+        );
+    }
+
+    #[test]
+    fn compare_unifies_the_order() {
+        assert_eq!(
+            answers(&Database::new(), "compare(O, 1, a)").unwrap(),
+            vec!["O = <"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "X = f(a), compare(O, X, f(a))").unwrap(),
+            vec!["X = f(a), O = ="]
+        );
+        assert!(answers(&Database::new(), "compare(<, b, a)")
+            .unwrap()
+            .is_empty());
+        assert!(Database::new()
+            .try_satisfy_all(parse_query("compare(less, a, b)").unwrap().goal)
+            .is_err());
+    }
+
+    #[test]
+    fn sorts_lists() {
+        assert_eq!(
+            answers(&Database::new(), "sort([c, 1, f(x), a, c, X], L)").unwrap(),
+            vec!["X = X, L = .(X, .(1, .(a, .(c, .(f(x), [])))))"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "msort([b, a, b], L)").unwrap(),
+            vec!["L = .(a, .(b, .(b, [])))"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "sort([], L)").unwrap(),
+            vec!["L = []"]
+        );
+        let partial = parse_query("sort([a | _], L)").unwrap();
+        assert!(Database::new().try_satisfy_all(partial.goal).is_err());
+    }
+
+    #[test]
+    fn predsort_calls_the_predicate() {
+        let mut db = Database::new();
+        db.consult(
+            "by_age(O, A-_, B-_) :- compare(O, A, B).
+             by_age_desc(O, A, B) :- by_age(O, B, A).",
+        )
+        .unwrap();
+        let render = |query: &str| {
+            let query = parse_query(query).unwrap();
+            let answers = db.satisfy_all(query.goal.clone());
+            answers
+                .iter()
+                .map(|b| query.solution(b).values()[0].1.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            render("predsort(by_age, [3-c, 1-a, 2-b, 1-z], L)"),
+            vec![".(-(1, a), .(-(2, b), .(-(3, c), [])))"]
+        );
+        assert_eq!(
+            render("predsort(by_age_desc, [1-a, 3-c, 2-b], L)"),
+            vec![".(-(3, c), .(-(2, b), .(-(1, a), [])))"]
+        );
+        assert!(render("predsort(missing, [b, a], L)").is_empty());
+    }
+
+    #[test]
+    fn comparison_goals() {
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "a @< b, f(a) @> z, 1 @=< 1, g(x) @>= f(y)"
+            )
+            .unwrap()
+            .len(),
+            1
+        );
+        assert!(answers(&Database::new(), "b @< a").unwrap().is_empty());
+        assert!(answers(&Database::new(), "X = 1, X @> a")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn identity_goals_bind_nothing() {
+        assert_eq!(answers(&Database::new(), "X == X").unwrap(), vec!["X = X"]);
+        assert!(answers(&Database::new(), "X == Y").unwrap().is_empty());
+        assert_eq!(
+            answers(&Database::new(), "X \\== Y").unwrap(),
+            vec!["X = X, Y = Y"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "f(a, X) == f(a, X)").unwrap(),
+            vec!["X = X"]
+        );
+        assert!(answers(&Database::new(), "f(a) == f(b)")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            answers(&Database::new(), "f(a) \\== f(b)").unwrap().len(),
+            1
+        );
+        assert_eq!(
+            answers(&Database::new(), "X = a, X == a").unwrap(),
+            vec!["X = a"]
+        );
+        assert!(answers(&Database::new(), "X = a, X \\== a")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn identity_goals_take_cyclic_terms_as_rational_trees() {
+        assert_eq!(
+            answers(&Database::new(), "X = f(X), Y = f(Y), X == Y")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            answers(&Database::new(), "X = f(X), Y = f(f(Y)), X == Y")
+                .unwrap()
+                .len(),
+            1
+        );
+        // the two only come back to a bound variable at alternate depths
+        assert_eq!(
+            answers(&Database::new(), "X = f(f(X)), Y = f(f(Y)), f(X) == Y")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(
+            answers(&Database::new(), "X = f(X, a), Y = f(Y, b), X == Y")
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            answers(&Database::new(), "X = f(X, a), Y = f(Y, b), X \\== Y")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(answers(&Database::new(), "X = f(X), X == f(f(a))")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn deep_terms_compare_without_a_deep_stack() {
+        const DEPTH: usize = 100_000;
+        let nested = |leaf: &str| {
+            (0..DEPTH).fold(Term::atom_from_str(leaf), |term, _| {
+                Term::functor_from_str("f", vec![term])
+            })
+        };
+        let (a, b) = (nested("a"), nested("b"));
+        assert!(a.identical(&nested("a")));
+        assert!(!a.identical(&b));
+        assert_eq!(a.cmp(&b), core::cmp::Ordering::Less);
+        let mut bindings = Bindings::new();
+        let x = parse_term("X").unwrap();
+        assert!(bindings.unify(&x, &a));
+        assert!(bindings.identical(&x, &nested("a")));
+        assert!(!bindings.identical(&x, &b));
+    }
+
+    #[test]
+    fn identical_terms_have_the_same_arguments() {
+        let fst = parse_term("f(a, b)").unwrap();
+        let snd = parse_term("f(a, c)").unwrap();
+        let (Term::Functor(f), Term::Functor(g)) = (&fst, &snd) else {
+            unreachable!()
+        };
+        assert_eq!(f, g);
+        assert!(!fst.identical(&snd));
+        assert!(fst.identical(&parse_term("f(a, b)").unwrap()));
+    }
+}
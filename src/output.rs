@@ -0,0 +1,296 @@
+//! Printing built-ins: `write/1`, `writeln/1`, `nl/0` and `format/1,2`.
+//! They print to the database's output sink, stdout unless replaced with
+//! `Database::set_output`. Without the `std` feature the sink is a
+//! `fmt::Write`, and until one is set what's printed goes nowhere.
+
+use crate::atoms;
+use crate::builtins::{self, Alternatives};
+use crate::compat::Mutex;
+use crate::error;
+use crate::solver::{Bindings, Solver};
+use crate::{Database, Term};
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+#[cfg(feature = "wasm")]
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+#[cfg(feature = "std")]
+pub(crate) type Sink = dyn Write + Send;
+
+#[cfg(not(feature = "std"))]
+pub(crate) type Sink = dyn fmt::Write + Send;
+
+#[derive(Clone)]
+pub(crate) struct Output(Arc<Mutex<Box<Sink>>>);
+
+impl Default for Output {
+    fn default() -> Self {
+        #[cfg(feature = "std")]
+        return Self(Arc::new(Mutex::new(Box::new(io::stdout()))));
+        #[cfg(not(feature = "std"))]
+        return Self(Arc::new(Mutex::new(Box::new(Discard))));
+    }
+}
+
+#[cfg(not(feature = "std"))]
+struct Discard;
+
+#[cfg(not(feature = "std"))]
+impl fmt::Write for Discard {
+    fn write_str(&mut self, _: &str) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Output")
+    }
+}
+
+impl Output {
+    #[cfg(feature = "std")]
+    pub(crate) fn print(&self, text: &str) -> io::Result<()> {
+        let mut sink = self.0.lock();
+        sink.write_all(text.as_bytes())?;
+        sink.flush()
+    }
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn print(&self, text: &str) -> fmt::Result {
+        self.0.lock().write_str(text)
+    }
+}
+
+// a sink passing on what's printed to another, unless `muted` is set
+#[cfg(feature = "wasm")]
+struct Gated {
+    sink: Output,
+    muted: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "wasm")]
+impl Write for Gated {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.muted.load(Ordering::Relaxed) {
+            self.sink.0.lock().write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.0.lock().flush()
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl Output {
+    // the same sink, printing nothing while `muted` is set
+    pub(crate) fn gated(&self, muted: Arc<AtomicBool>) -> Self {
+        let gated = Gated {
+            sink: self.clone(),
+            muted,
+        };
+        Self(Arc::new(Mutex::new(Box::new(gated))))
+    }
+}
+
+impl Database {
+    /// Sends what `write/1`, `format/2` and friends print to `sink` instead
+    /// of stdout.
+    #[cfg(feature = "std")]
+    pub fn set_output<W: Write + Send + 'static>(&mut self, sink: W) {
+        self.output = Output(Arc::new(Mutex::new(Box::new(sink))));
+    }
+    /// Sends what `write/1`, `format/2` and friends print to `sink`.
+    #[cfg(not(feature = "std"))]
+    pub fn set_output<W: fmt::Write + Send + 'static>(&mut self, sink: W) {
+        self.output = Output(Arc::new(Mutex::new(Box::new(sink))));
+    }
+}
+
+pub(crate) fn print(solver: &mut Solver, text: &str) -> Alternatives {
+    match solver.output().print(text) {
+        Ok(()) => builtins::success(),
+        Err(err) => builtins::raise(solver, error::io_error(&err.to_string())),
+    }
+}
+
+// how `write/1` shows a term: like `Display`, but an atom or string on its
+// own is printed without quotes
+fn written(term: &Term, bindings: &Bindings) -> String {
+    match bindings.resolve(term) {
+        Term::Atom(a) => a.as_str().to_string(),
+        Term::String(text) => text.to_string(),
+        term => term.to_string(),
+    }
+}
+
+pub(crate) fn write(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    print(solver, &written(&args[0], bindings))
+}
+
+pub(crate) fn writeln(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    print(solver, &(written(&args[0], bindings) + "\n"))
+}
+
+pub(crate) fn nl(solver: &mut Solver, _: &[Term], _: &Bindings) -> Alternatives {
+    print(solver, "\n")
+}
+
+/// `format(Format, Args)`: prints `Format`, an atom, a string or a list of
+/// characters or codes, with each directive replaced: `~w` writes the next
+/// argument, `~a` an atomic one, `~d` an integer, `~n` is a newline and
+/// `~~` a tilde. `Args` is a list, or a single argument that isn't one.
+pub(crate) fn format(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let format = match bindings.walk(&args[0]) {
+        Term::Variable(_) => Err(error::instantiation_error()),
+        Term::Atom(a) if a.as_str() != "[]" => Ok(a.as_str().to_string()),
+        Term::String(text) => Ok(text.to_string()),
+        _ => match builtins::list(solver, &args[0], bindings) {
+            Some(items) => atoms::spelled(&items, bindings, |item| {
+                atoms::code_of(item).or_else(|_| atoms::char_of(item))
+            }),
+            None => return builtins::failure(),
+        },
+    };
+    let values = match args.get(1).map(|list| bindings.walk(list)) {
+        None => Vec::new(),
+        Some(Term::Variable(_)) => vec![args[1].clone()],
+        Some(_) => bindings
+            .list_to_vec(&args[1])
+            .unwrap_or_else(|| vec![args[1].clone()]),
+    };
+    match format.and_then(|format| render(&format, &values, bindings)) {
+        Ok(text) => print(solver, &text),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+fn render(format: &str, values: &[Term], bindings: &Bindings) -> Result<String, Term> {
+    let mut text = String::new();
+    let mut values = values.iter();
+    let mut next = || {
+        values
+            .next()
+            .map(|value| bindings.resolve(value))
+            .ok_or_else(|| error::format_error("not enough arguments"))
+    };
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('w') => text += &written(&next()?, bindings),
+            Some('a') => match next()? {
+                Term::Atom(a) => text += a.as_str(),
+                Term::String(s) => text += &s,
+                Term::Integer(n) => text += &n.to_string(),
+                Term::Variable(_) => return Err(error::instantiation_error()),
+                other => return Err(error::type_error("atomic", other)),
+            },
+            Some('d') => match next()? {
+                Term::Integer(n) => text += &n.to_string(),
+                Term::Variable(_) => return Err(error::instantiation_error()),
+                other => return Err(error::type_error("integer", other)),
+            },
+            Some('n') => text.push('\n'),
+            Some('~') => text.push('~'),
+            Some(other) => {
+                return Err(error::format_error(&format!(
+                    "unknown directive ~{}",
+                    other
+                )))
+            }
+            None => return Err(error::format_error("truncated directive")),
+        }
+    }
+    if values.next().is_some() {
+        return Err(error::format_error("too many arguments"));
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // without `std`, what's printed comes as text
+    #[cfg(not(feature = "std"))]
+    impl std::fmt::Write for Captured {
+        fn write_str(&mut self, text: &str) -> std::fmt::Result {
+            self.0.lock().unwrap().extend_from_slice(text.as_bytes());
+            Ok(())
+        }
+    }
+
+    fn printed(query: &str) -> Result<String, SolveError> {
+        let captured = Captured::default();
+        let mut db = Database::new();
+        db.set_output(captured.clone());
+        let query = parse_query(query).unwrap();
+        db.try_satisfy_all(query.goal.clone())?;
+        let bytes = captured.0.lock().unwrap().clone();
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn writes_terms() {
+        assert_eq!(
+            printed("X = likes(sam, 'ice cream'), write(X), nl").unwrap(),
+            "likes(sam, 'ice cream')\n"
+        );
+        assert_eq!(
+            printed("writeln('hello world'), write(42)").unwrap(),
+            "hello world\n42"
+        );
+    }
+
+    #[test]
+    fn formats_directives() {
+        assert_eq!(
+            printed("format('~w likes ~a: ~d~n', [sam, pizza, 3])").unwrap(),
+            "sam likes pizza: 3\n"
+        );
+        assert_eq!(printed("format('~~~w', oops)").unwrap(), "~oops");
+        assert_eq!(printed("format([a, '~', n, b], [])").unwrap(), "a\nb");
+        assert_eq!(printed("format(done)").unwrap(), "done");
+        assert_eq!(
+            printed("format(\"~a and ~w\", [\"a\", \"b\"])").unwrap(),
+            "a and b"
+        );
+    }
+
+    #[test]
+    fn format_errors_are_thrown() {
+        let thrown = |query| match printed(query) {
+            Err(SolveError::Uncaught(ball)) => ball.to_string(),
+            Ok(text) => panic!("{} printed {:?}", query, text),
+            Err(err) => panic!("{}: {}", query, err),
+        };
+        assert!(thrown("format('~d', [a])").contains("type_error(integer, a)"));
+        assert!(thrown("format('~w ~w', [a])").contains("not enough arguments"));
+        assert!(thrown("format('~w', [a, b])").contains("too many arguments"));
+        assert!(thrown("format('~z', [])").contains("unknown directive"));
+    }
+}
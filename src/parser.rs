@@ -0,0 +1,1304 @@
+use crate::compat::{thread_rng, HashSet, ThreadRng};
+use crate::flags;
+use crate::load;
+use crate::{Atom, Database, DoubleQuotes, Functor, Term, Variable};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+use core::iter::Peekable;
+use core::ops::{Deref, Range};
+use core::str::CharIndices;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    /// What was wanted where the error is, e.g. "`)`".
+    pub expected: Option<String>,
+    /// What was there instead, e.g. "`,`" or "end of input".
+    pub found: Option<String>,
+    /// Byte offsets into the source of the text at fault.
+    pub span: Range<usize>,
+    /// Where `span` starts, counting from 1, with columns in characters.
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ParseError {
+    fn new(source: &str, message: &str, span: Range<usize>) -> Self {
+        let (line, column) = line_column(source, span.start);
+        ParseError {
+            message: String::from(message),
+            expected: None,
+            found: None,
+            line,
+            column,
+            span,
+        }
+    }
+}
+
+// the line and column of a byte offset, counting from 1
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |ix| ix + 1);
+    let column = before[line_start..].chars().count() + 1;
+    (before.matches('\n').count() + 1, column)
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let (Some(_), Some(found)) = (&self.expected, &self.found) {
+            write!(f, ", found {}", found)?;
+        }
+        write!(f, " at line {}, column {}", self.line, self.column)
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// The syntax errors in a consulted source text, in order; the clauses
+/// between them were still loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl Deref for ParseErrors {
+    type Target = [ParseError];
+    fn deref(&self) -> &[ParseError] {
+        &self.0
+    }
+}
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (ix, err) in self.0.iter().enumerate() {
+            if ix > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", err)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for ParseErrors {}
+
+/// Where a consulted clause or goal starts: the file, `user` for source
+/// given to `Database::consult`, and the line and column, counting from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub file: Arc<str>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// A parsed top-level goal, along with the named variables it mentions.
+#[derive(Clone, Debug)]
+pub struct Query {
+    pub goal: Functor,
+    pub variables: Vec<(String, Term)>,
+}
+
+/// Parses a single goal. The terminating `.` is optional.
+pub fn parse_query(source: &str) -> Result<Query, ParseError> {
+    let mut parser = Parser::new(source);
+    let (term, _) = parser.parse(1200)?;
+    if parser.peek() == Some(&Token::End) {
+        parser.advance();
+    }
+    if parser.peek().is_some() {
+        return Err(parser.error("expected end of query"));
+    }
+    let goal = goal(term).ok_or_else(|| parser.error("query is not callable"))?;
+    Ok(Query {
+        goal,
+        variables: parser.variables,
+    })
+}
+
+/// Parses a single term, e.g. for building goals in code.
+pub fn parse_term(source: &str) -> Result<Term, ParseError> {
+    let mut parser = Parser::new(source);
+    let (term, _) = parser.parse(1200)?;
+    if parser.peek().is_some() {
+        return Err(parser.error("expected end of term"));
+    }
+    Ok(term)
+}
+
+/// A term read by `read_clause`, with its named variables.
+#[cfg(feature = "std")]
+pub(crate) type Read = (Term, Vec<(String, Term)>);
+
+/// Reads the first `.`-terminated clause of `source`, for `read_term/2`.
+/// Returns how many bytes it used along with the term and its named
+/// variables, or `None` if `source` stops before the clause does.
+#[cfg(feature = "std")]
+pub(crate) fn read_clause(
+    source: &str,
+    double_quotes: DoubleQuotes,
+) -> Option<(usize, Result<Read, ParseError>)> {
+    let mut tokens = lex(source);
+    let end = tokens
+        .iter()
+        .position(|(token, _)| matches!(token, Token::End | Token::Error(_)))?;
+    if let (Token::Error(err), _) = &tokens[end] {
+        if err.message == UNTERMINATED {
+            return None;
+        }
+        return Some((source.len(), Err(err.clone())));
+    }
+    let used = tokens[end].1.end;
+    tokens.truncate(end + 1);
+    let mut parser = Parser {
+        source,
+        file: None,
+        tokens,
+        pos: 0,
+        variables: Vec::new(),
+        double_quotes,
+        rng: thread_rng(),
+        depth: 0,
+    };
+    let read = parser.parse(1200).and_then(|(term, _)| {
+        parser.expect(Token::End, "expected `.` after clause")?;
+        Ok((term, parser.variables))
+    });
+    Some((used, read))
+}
+
+// a clause's predicate, unless it's a directive, and where the clause starts
+#[cfg(feature = "lsp")]
+pub(crate) type Outlined = (Option<(Atom, crate::Arity)>, usize);
+
+/// The clauses of a source text, for editor tooling: each clause's predicate
+/// (`None` for a directive) with the byte offset it starts at, and the
+/// syntax errors in between, each clause after an error parsed afresh.
+#[cfg(feature = "lsp")]
+pub(crate) fn outline(source: &str) -> (Vec<Outlined>, Vec<ParseError>) {
+    let mut parser = Parser::new(source);
+    let (mut clauses, mut errors) = (Vec::new(), Vec::new());
+    loop {
+        let start = parser
+            .tokens
+            .get(parser.pos)
+            .map_or(0, |(_, span)| span.start);
+        match parser.clause() {
+            Ok(Some(term)) => {
+                let head = match term {
+                    Term::Functor(f) if f.name.as_str() == ":-" && f.arity() == 1 => None,
+                    Term::Functor(f) if f.name.as_str() == ":-" && f.arity() == 2 => {
+                        f.into_args().into_iter().next()
+                    }
+                    term => Some(term),
+                };
+                let predicate = match head {
+                    Some(Term::Atom(name)) => Some((name, 0)),
+                    Some(Term::Functor(f)) => Some((f.name, f.arity())),
+                    _ => None,
+                };
+                clauses.push((predicate, start));
+            }
+            Ok(None) => break,
+            Err(err) => {
+                errors.push(err);
+                parser.recover(&mut errors);
+            }
+        }
+    }
+    (clauses, errors)
+}
+
+impl Database {
+    /// Loads clauses and runs directives from Prolog source text. A clause
+    /// with a syntax error is skipped, up to the `.` that ends it, and
+    /// loading goes on from there; the errors are returned at the end.
+    pub fn consult(&mut self, source: &str) -> Result<(), ParseErrors> {
+        self.consult_named("user", source)
+    }
+    /// What loading source since the last call found suspicious but loaded
+    /// anyway, such as the clauses of a predicate split up by others without
+    /// a `discontiguous` declaration.
+    pub fn take_warnings(&mut self) -> Vec<ParseError> {
+        core::mem::take(&mut self.warnings)
+    }
+    /// Like `consult`, with the clauses' spans naming `file` as their source.
+    /// `include/1` and `ensure_loaded/1` directives find files relative to
+    /// `file`'s directory.
+    pub fn consult_named(&mut self, file: &str, source: &str) -> Result<(), ParseErrors> {
+        let entered = self.enter_file(file);
+        let errors = self.consult_clauses(file, source);
+        if entered {
+            self.leave_file();
+        }
+        self.end_module();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ParseErrors(errors))
+        }
+    }
+    pub(crate) fn consult_clauses(&mut self, file: &str, source: &str) -> Vec<ParseError> {
+        let mut parser = Parser::new(source)
+            .locating(file)
+            .quoting(self.config.flags.double_quotes);
+        let mut errors = Vec::new();
+        // the predicate of the clause before, those whose clauses came earlier
+        // in the file, with the module they went into, and those warned of
+        let mut last = None;
+        let (mut seen, mut warned) = (HashSet::new(), HashSet::new());
+        loop {
+            let start = parser.pos;
+            let term = match parser.clause() {
+                Ok(Some(term)) => term,
+                Ok(None) => return errors,
+                Err(err) => {
+                    errors.push(err);
+                    parser.recover(&mut errors);
+                    continue;
+                }
+            };
+            match term {
+                Term::Functor(f) if f.name.as_str() == ":-" && f.arity() == 1 => {
+                    match f.into_args().into_iter().next().and_then(goal) {
+                        Some(directive) if is_double_quotes_flag(&directive) => {
+                            match flags::double_quotes(&directive.args[1]) {
+                                Some(double_quotes) => parser.double_quotes = double_quotes,
+                                None => errors.push(
+                                    parser.clause_error(start, "unknown double_quotes value"),
+                                ),
+                            }
+                        }
+                        Some(directive) if load::is_load_directive(&directive) => {
+                            match self.load_file(&directive, file) {
+                                Ok(loaded) => errors.extend(loaded),
+                                Err(message) => errors.push(parser.clause_error(start, &message)),
+                            }
+                        }
+                        Some(directive) => {
+                            self.directive(directive);
+                        }
+                        None => {
+                            errors.push(parser.clause_error(start, "directive is not callable"))
+                        }
+                    }
+                }
+                term => match clause(term) {
+                    Some(clause) => {
+                        let predicate = (self.loading, clause.name, clause.arity());
+                        if last != Some(predicate) && !seen.insert(predicate) {
+                            let (module, name, arity) = predicate;
+                            let declared = self.modules.get(&module).is_some_and(|m| {
+                                m.is_discontiguous(&name, arity) || m.is_dynamic(&name, arity)
+                            });
+                            if !declared && warned.insert(predicate) {
+                                let message =
+                                    format!("clauses of {}/{} are not together", name, arity);
+                                self.warnings.push(parser.clause_error(start, &message));
+                            }
+                        }
+                        last = Some(predicate);
+                        self.add(clause)
+                    }
+                    None => errors.push(parser.clause_error(start, "invalid clause")),
+                },
+            }
+        }
+    }
+}
+
+// `set_prolog_flag(double_quotes, Value)`, which applies to the rest of the
+// file being read
+fn is_double_quotes_flag(directive: &Functor) -> bool {
+    directive.name.as_str() == "set_prolog_flag"
+        && directive.arity() == 2
+        && matches!(&directive.args[0], Term::Atom(a) if a.as_str() == "double_quotes")
+}
+
+pub(crate) fn clause(term: Term) -> Option<Functor> {
+    match term {
+        Term::Functor(f) if f.name.as_str() == ":-" && f.arity() == 2 => {
+            let mut args = f.into_args().into_iter();
+            let (name, head, span) = goal(args.next()?)?.into_parts();
+            let mut body = Vec::new();
+            conjuncts(args.next()?, &mut body)?;
+            Some(Functor::new_rule(name, head, body).located(span))
+        }
+        Term::Variable(_) => None,
+        term => goal(term),
+    }
+}
+
+fn goal(term: Term) -> Option<Functor> {
+    match term {
+        Term::Atom(name) => Some(Functor::new_fact(name, Vec::new())),
+        Term::Functor(f) => Some(*f),
+        Term::Variable(_) => Some(Functor::new_fact(Atom::new("call"), vec![term])),
+        Term::Integer(_) | Term::String(_) => None,
+    }
+}
+
+fn conjuncts(term: Term, body: &mut Vec<Functor>) -> Option<()> {
+    match term {
+        Term::Functor(f) if f.name.as_str() == "," && f.arity() == 2 => {
+            let mut args = f.into_args().into_iter();
+            conjuncts(args.next()?, body)?;
+            conjuncts(args.next()?, body)
+        }
+        term => {
+            body.push(goal(term)?);
+            Some(())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Assoc {
+    Xfx,
+    Xfy,
+    Yfx,
+    Fy,
+    Fx,
+}
+
+pub(crate) fn infix_op(name: &str) -> Option<(u16, Assoc)> {
+    use Assoc::*;
+    Some(match name {
+        ":-" | "-->" => (1200, Xfx),
+        ";" | "|" => (1100, Xfy),
+        "->" => (1050, Xfy),
+        "," => (1000, Xfy),
+        "=" | "\\=" | "==" | "\\==" | "@<" | "@>" | "@=<" | "@>=" | "=.." | "is" | "=:="
+        | "=\\=" | "<" | ">" | "=<" | ">=" => (700, Xfx),
+        "#=" | "#\\=" | "#<" | "#>" | "#=<" | "#>=" | "in" | "ins" => (700, Xfx),
+        "+" | "-" | "/\\" | "\\/" | "xor" => (500, Yfx),
+        ".." => (450, Xfx),
+        "*" | "/" | "//" | "rem" | "mod" | "rdiv" | "<<" | ">>" => (400, Yfx),
+        "**" => (200, Xfx),
+        "^" | ":" => (200, Xfy),
+        _ => return None,
+    })
+}
+
+pub(crate) fn prefix_op(name: &str) -> Option<(u16, Assoc)> {
+    use Assoc::*;
+    Some(match name {
+        ":-" | "?-" => (1200, Fx),
+        "dynamic" | "discontiguous" => (1150, Fx),
+        "\\+" => (900, Fy),
+        "-" | "+" | "\\" => (200, Fy),
+        _ => return None,
+    })
+}
+
+/// A token of Prolog source, as `tokenize` reads it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    /// An atom: a name, a run of symbol characters, quoted text, `[]`, `!`
+    /// or `;`, with any quotes taken off and escapes read.
+    Name(String),
+    /// An integer's digits: 2^63, which fits only after a `-`, comes as
+    /// `i64::MIN`.
+    Int(i64),
+    Var(String),
+    /// Double-quoted text, with the quotes taken off and escapes read.
+    Str(String),
+    /// `(` immediately following a name, opening an argument list.
+    OpenCall,
+    Open,
+    Close,
+    OpenList,
+    CloseList,
+    Comma,
+    Bar,
+    /// The `.` ending a clause.
+    End,
+    /// Text that couldn't be read as a token.
+    Error(ParseError),
+}
+
+pub(crate) const SYMBOL_CHARS: &str = "+-*/\\^<>=~:.?@#&$";
+
+// Names and variables are Unicode identifiers: they start with a character
+// that can start one (a letter, of any script) and go on with those that can
+// continue one (letters, digits, combining marks and joiners like `_`).
+// Variables start with an uppercase or titlecase letter, or `_`; names with
+// any other letter, lowercase or of a script without case, as in `日本`.
+
+/// Whether `c` can start an unquoted name.
+pub(crate) fn is_name_start(c: char) -> bool {
+    unicode_ident::is_xid_start(c) && !is_upper(c)
+}
+
+/// Whether `c` can start a variable.
+pub(crate) fn is_var_start(c: char) -> bool {
+    c == '_' || unicode_ident::is_xid_start(c) && is_upper(c)
+}
+
+/// Whether `c` can go on a name or variable.
+pub(crate) fn is_name_char(c: char) -> bool {
+    unicode_ident::is_xid_continue(c)
+}
+
+// uppercase, or titlecase like `ǅ`: a letter that isn't lowercase but has a
+// lowercase form
+fn is_upper(c: char) -> bool {
+    c.is_uppercase() || !c.is_lowercase() && c.to_lowercase().ne([c])
+}
+
+/// Splits `source` into tokens, each with the byte offsets of its text,
+/// skipping layout and comments. Reading goes on past text that isn't a
+/// token, which comes out as `Token::Error`.
+pub fn tokenize(source: &str) -> impl Iterator<Item = (Token, Range<usize>)> {
+    lex(source).into_iter()
+}
+
+fn lex(source: &str) -> Vec<(Token, Range<usize>)> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    // the rest of a token whose first character (at `start`) was already consumed
+    let take_while = |start: usize, chars: &mut Peekable<CharIndices>, pred: fn(char) -> bool| {
+        let mut end = start + source[start..].chars().next().map_or(0, char::len_utf8);
+        while let Some(&(ix, c)) = chars.peek() {
+            if !pred(c) {
+                break;
+            }
+            end = ix + c.len_utf8();
+            chars.next();
+        }
+        String::from(&source[start..end])
+    };
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '%' => {
+                chars.find(|(_, c)| *c == '\n');
+                continue;
+            }
+            // block comments don't nest: the first `*/` ends one
+            '/' if chars.next_if(|(_, c)| *c == '*').is_some() => {
+                let mut star = false;
+                let closed = chars.by_ref().any(|(_, c)| {
+                    let closes = star && c == '/';
+                    star = c == '*';
+                    closes
+                });
+                if closed {
+                    continue;
+                }
+                Token::Error(ParseError::new(
+                    source,
+                    "unterminated block comment",
+                    start..source.len(),
+                ))
+            }
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '[' if chars.next_if(|(_, c)| *c == ']').is_some() => Token::Name(String::from("[]")),
+            '[' => Token::OpenList,
+            ']' => Token::CloseList,
+            ',' => Token::Comma,
+            '|' => Token::Bar,
+            '!' | ';' => Token::Name(String::from(c)),
+            '\'' => match quoted(source, start, &mut chars, '\'') {
+                Ok(name) => Token::Name(name),
+                Err(err) => Token::Error(err),
+            },
+            '"' => match quoted(source, start, &mut chars, '"') {
+                Ok(text) => Token::Str(text),
+                Err(err) => Token::Error(err),
+            },
+            '.' if chars
+                .peek()
+                .is_none_or(|(_, c)| c.is_whitespace() || *c == '%') =>
+            {
+                Token::End
+            }
+            c if c.is_ascii_digit() => {
+                let digits = take_while(start, &mut chars, |c| c.is_ascii_digit());
+                match digits.parse() {
+                    Ok(n) => Token::Int(n),
+                    // 2^63 only fits negated; the parser sees to that
+                    Err(_) if digits.parse() == Ok(1u64 << 63) => Token::Int(i64::MIN),
+                    Err(_) => {
+                        let message = format!("integer {} is too large", digits);
+                        Token::Error(ParseError::new(
+                            source,
+                            &message,
+                            start..start + digits.len(),
+                        ))
+                    }
+                }
+            }
+            c if is_var_start(c) => Token::Var(take_while(start, &mut chars, is_name_char)),
+            c if is_name_start(c) => Token::Name(take_while(start, &mut chars, is_name_char)),
+            c if SYMBOL_CHARS.contains(c) => {
+                Token::Name(take_while(start, &mut chars, |c| SYMBOL_CHARS.contains(c)))
+            }
+            c => {
+                let message = format!("unexpected character {:?}", c);
+                Token::Error(ParseError::new(
+                    source,
+                    &message,
+                    start..start + c.len_utf8(),
+                ))
+            }
+        };
+        let end = chars.peek().map_or(source.len(), |(ix, _)| *ix);
+        let call = matches!(token, Token::Name(_)) && chars.peek().is_some_and(|(_, c)| *c == '(');
+        tokens.push((token, start..end));
+        if call {
+            let (ix, _) = chars.next().expect("peeked `(`");
+            tokens.push((Token::OpenCall, ix..ix + 1));
+        }
+    }
+    tokens
+}
+
+const UNTERMINATED: &str = "unterminated quoted text";
+
+// the text of a quoted atom or string whose opening `quote` (at `start`)
+// was already consumed
+fn quoted(
+    source: &str,
+    start: usize,
+    chars: &mut Peekable<CharIndices>,
+    quote: char,
+) -> Result<String, ParseError> {
+    let unterminated = || ParseError::new(source, UNTERMINATED, start..source.len());
+    // skips the rest of the atom, so that reading goes on after it
+    let error = |message: &str, ix: usize, chars: &mut Peekable<CharIndices>| {
+        let end = chars.peek().map_or(source.len(), |(ix, _)| *ix);
+        for (_, c) in chars.by_ref() {
+            if c == quote {
+                break;
+            }
+        }
+        ParseError::new(source, message, ix..end)
+    };
+    let mut text = String::new();
+    loop {
+        let Some((ix, c)) = chars.next() else {
+            return Err(unterminated());
+        };
+        match c {
+            c if c == quote && chars.next_if(|(_, c)| *c == quote).is_some() => text.push(quote),
+            c if c == quote => return Ok(text),
+            '\\' => {
+                let Some((_, escape)) = chars.next() else {
+                    return Err(unterminated());
+                };
+                match escape {
+                    'n' => text.push('\n'),
+                    't' => text.push('\t'),
+                    'r' => text.push('\r'),
+                    'a' => text.push('\x07'),
+                    'b' => text.push('\x08'),
+                    'f' => text.push('\x0c'),
+                    'v' => text.push('\x0b'),
+                    'e' => text.push('\x1b'),
+                    's' => text.push(' '),
+                    '0'..='7' | 'x' => {
+                        // `\NNN\` in octal or `\xHH\` in hex
+                        let (radix, mut digits) = match escape {
+                            'x' => (16, String::new()),
+                            digit => (8, String::from(digit)),
+                        };
+                        while let Some((_, c)) = chars.next_if(|(_, c)| c.is_digit(radix)) {
+                            digits.push(c);
+                        }
+                        let code = u32::from_str_radix(&digits, radix)
+                            .ok()
+                            .and_then(char::from_u32);
+                        match (code, chars.next()) {
+                            (Some(c), Some((_, '\\'))) => text.push(c),
+                            // the atom ended with the escape
+                            (_, Some((end, c))) if c == quote => {
+                                let message = "invalid character code escape";
+                                return Err(ParseError::new(source, message, ix..end));
+                            }
+                            (_, _) => {
+                                return Err(error("invalid character code escape", ix, chars))
+                            }
+                        }
+                    }
+                    // a line continuation
+                    '\n' => {}
+                    '\\' | '\'' | '"' | '`' => text.push(escape),
+                    _ => return Err(error("unknown escape sequence", ix, chars)),
+                }
+            }
+            c => text.push(c),
+        }
+    }
+}
+
+/// Writes `name` so that it reads back as the same atom, quoting it unless
+/// it is a letter-digit name, a run of symbol characters or a solo atom.
+pub(crate) fn write_atom(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    let mut chars = name.chars();
+    let plain = match chars.next() {
+        Some(c) if is_name_start(c) => chars.all(is_name_char),
+        Some(c) if SYMBOL_CHARS.contains(c) => chars.all(|c| SYMBOL_CHARS.contains(c)),
+        _ => matches!(name, "[]" | "!" | ";" | "{}"),
+    };
+    if plain {
+        return f.write_str(name);
+    }
+    write_quoted(f, name, '\'')
+}
+
+/// Writes `text` as a double-quoted string.
+pub(crate) fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write_quoted(f, text, '"')
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, text: &str, quote: char) -> fmt::Result {
+    write!(f, "{}", quote)?;
+    for c in text.chars() {
+        match c {
+            c if c == quote => write!(f, "\\{}", quote)?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\x{:x}\\", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "{}", quote)
+}
+
+/// How deep terms may nest in source text, in arguments, brackets, lists and
+/// operands of prefix operators: each level is read by a Rust call of its
+/// own, so past this a term is an error rather than a stack overflow.
+const MAX_DEPTH: usize = 256;
+
+struct Parser<'s> {
+    source: &'s str,
+    // the file to give the spans of compound terms, if they're wanted
+    file: Option<Arc<str>>,
+    tokens: Vec<(Token, Range<usize>)>,
+    pos: usize,
+    // named variables of the clause being parsed, in order of appearance
+    variables: Vec<(String, Term)>,
+    double_quotes: DoubleQuotes,
+    rng: ThreadRng,
+    // how many terms the one being parsed is nested in
+    depth: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn new(source: &'s str) -> Self {
+        Self {
+            source,
+            file: None,
+            tokens: lex(source),
+            pos: 0,
+            variables: Vec::new(),
+            double_quotes: DoubleQuotes::default(),
+            rng: thread_rng(),
+            depth: 0,
+        }
+    }
+    fn quoting(mut self, double_quotes: DoubleQuotes) -> Self {
+        self.double_quotes = double_quotes;
+        self
+    }
+    fn locating(mut self, file: &str) -> Self {
+        self.file = Some(Arc::from(file));
+        self
+    }
+    // `name(args)`, located at token `start` if spans are wanted
+    fn compound(&self, start: usize, name: &str, args: Vec<Term>) -> Term {
+        let span = self.file.as_ref().map(|file| {
+            let offset = self.tokens[start].1.start;
+            let (line, column) = line_column(self.source, offset);
+            Arc::new(Span {
+                file: file.clone(),
+                line,
+                column,
+            })
+        });
+        let functor = Functor::new_fact(Atom::new(name), args).located(span);
+        Term::Functor(Box::new(functor))
+    }
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(t, _)| t.clone());
+        self.pos += 1;
+        token
+    }
+    /// An error at the next token, saying what it is; or if the tokenizer
+    /// couldn't read it, the tokenizer's error.
+    fn error(&self, message: &str) -> ParseError {
+        match self.tokens.get(self.pos) {
+            Some((Token::Error(err), _)) => err.clone(),
+            Some((_, span)) => ParseError {
+                found: Some(format!("`{}`", &self.source[span.clone()])),
+                ..ParseError::new(self.source, message, span.clone())
+            },
+            None => {
+                let end = self.source.len();
+                ParseError {
+                    found: Some(String::from("end of input")),
+                    ..ParseError::new(self.source, message, end..end)
+                }
+            }
+        }
+    }
+    fn expected(&self, message: &str, expected: &str) -> ParseError {
+        ParseError {
+            expected: Some(String::from(expected)),
+            ..self.error(message)
+        }
+    }
+    // an error spanning the clause from token `start` to the one just read
+    fn clause_error(&self, start: usize, message: &str) -> ParseError {
+        let span = self.tokens[start].1.start..self.tokens[self.pos - 1].1.end;
+        ParseError::new(self.source, message, span)
+    }
+    fn expect(&mut self, token: Token, message: &str) -> Result<(), ParseError> {
+        if self.peek() == Some(&token) {
+            self.advance();
+            return Ok(());
+        }
+        let expected = match token {
+            Token::End => "`.`",
+            Token::Close => "`)`",
+            Token::CloseList => "`]`",
+            _ => unreachable!("only closing tokens are expected"),
+        };
+        Err(self.expected(message, expected))
+    }
+    /// Skips past the end of a clause that failed to parse at the next
+    /// token, adding to `errors` what the tokenizer couldn't read on the way.
+    fn recover(&mut self, errors: &mut Vec<ParseError>) {
+        let failed_at = self.pos;
+        while let Some(token) = self.advance() {
+            match token {
+                Token::End => break,
+                Token::Error(err) if self.pos - 1 != failed_at => errors.push(err),
+                _ => {}
+            }
+        }
+    }
+    /// Parses the next `.`-terminated clause, or returns `None` at end of input.
+    fn clause(&mut self) -> Result<Option<Term>, ParseError> {
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+        self.variables.clear();
+        let (term, _) = self.parse(1200)?;
+        self.expect(Token::End, "expected `.` after clause")?;
+        Ok(Some(term))
+    }
+    /// Parses a term whose priority is at most `max`, returning it with its
+    /// priority, as long as it doesn't nest past `MAX_DEPTH`.
+    fn parse(&mut self, max: u16) -> Result<(Term, u16), ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("term nested too deeply"));
+        }
+        self.depth += 1;
+        let parsed = self.operators(max);
+        self.depth -= 1;
+        parsed
+    }
+    // a term whose priority is at most `max`, its operators and all
+    fn operators(&mut self, max: u16) -> Result<(Term, u16), ParseError> {
+        // left operands waiting on their right, each with where it starts,
+        // its operator and the operator's priority, and the most the term around
+        // them may take: a run like `a, b, c` needs no deep Rust stack
+        let mut pending: Vec<(usize, Term, String, u16, u16)> = Vec::new();
+        let mut max = max;
+        let mut start = self.pos;
+        let (mut left, mut left_prec) = self.primary(max)?;
+        loop {
+            if let Some((name, prec, right_max)) = self.infix(max, left_prec) {
+                self.advance();
+                pending.push((start, left, name, prec, max));
+                max = right_max;
+                start = self.pos;
+                (left, left_prec) = self.primary(max)?;
+                continue;
+            }
+            let Some((left_start, left_arg, name, prec, outer_max)) = pending.pop() else {
+                return Ok((left, left_prec));
+            };
+            let name = if name == "|" { String::from(";") } else { name };
+            left = self.compound(left_start, &name, vec![left_arg, left]);
+            left_prec = prec;
+            max = outer_max;
+            start = left_start;
+        }
+    }
+    // the next token as an infix operator that can take a left operand of
+    // priority `left_prec` in a term of at most `max`, with its priority and
+    // the most its right operand may take
+    fn infix(&self, max: u16, left_prec: u16) -> Option<(String, u16, u16)> {
+        let name = match self.peek() {
+            Some(Token::Name(name)) => name.clone(),
+            Some(Token::Comma) => String::from(","),
+            Some(Token::Bar) => String::from("|"),
+            _ => return None,
+        };
+        let (prec, assoc) = infix_op(&name)?;
+        let (left_max, right_max) = match assoc {
+            Assoc::Xfy => (prec - 1, prec),
+            Assoc::Yfx => (prec, prec - 1),
+            _ => (prec - 1, prec - 1),
+        };
+        (prec <= max && left_prec <= left_max).then_some((name, prec, right_max))
+    }
+    fn primary(&mut self, max: u16) -> Result<(Term, u16), ParseError> {
+        let start = self.pos;
+        let Some(token) = self.advance() else {
+            return Err(self.expected("unexpected end of input", "a term"));
+        };
+        // the arms that read operands of their own are kept out of line, so
+        // each level of nesting takes no more Rust stack than it needs
+        match token {
+            Token::Var(name) => Ok((self.variable(name), 0)),
+            // only `-` can take 2^63, which the tokenizer reads as `i64::MIN`
+            Token::Int(i64::MIN) => {
+                self.pos -= 1;
+                Err(self.error("integer 9223372036854775808 is too large"))
+            }
+            Token::Int(n) => Ok((Term::Integer(n), 0)),
+            Token::Str(text) => Ok((self.text(&text), 0)),
+            Token::Name(name) if self.peek() == Some(&Token::OpenCall) => {
+                self.arguments(start, &name)
+            }
+            // `-` right before a number makes it negative, wherever an
+            // operand may start
+            Token::Name(name) if name == "-" && self.number_follows() => {
+                let Some(Token::Int(n)) = self.advance() else {
+                    unreachable!("a number follows")
+                };
+                Ok((Term::Integer(n.wrapping_neg()), 0))
+            }
+            Token::Name(name) => match prefix_op(&name) {
+                Some((prec, assoc)) if prec <= max && self.starts_term() => {
+                    let arg_max = if assoc == Assoc::Fy { prec } else { prec - 1 };
+                    self.prefixed(start, &name, prec, arg_max)
+                }
+                _ => Ok((Term::Atom(Atom::new(&name)), 0)),
+            },
+            // an infix operator right before `(` reads as one, as in `X>>(Y)`
+            Token::Open | Token::OpenCall => self.bracketed(),
+            Token::OpenList => self.list(),
+            _ => {
+                self.pos -= 1;
+                Err(self.expected("unexpected token", "a term"))
+            }
+        }
+    }
+    // `name(args)`, with the `(` next
+    fn arguments(&mut self, start: usize, name: &str) -> Result<(Term, u16), ParseError> {
+        self.advance();
+        let mut args = vec![self.parse(999)?.0];
+        while self.peek() == Some(&Token::Comma) {
+            self.advance();
+            args.push(self.parse(999)?.0);
+        }
+        self.expect(Token::Close, "expected `)` after arguments")?;
+        Ok((self.compound(start, name, args), 0))
+    }
+    // prefix operator `name` of priority `prec`, with its operand next
+    fn prefixed(
+        &mut self,
+        start: usize,
+        name: &str,
+        prec: u16,
+        arg_max: u16,
+    ) -> Result<(Term, u16), ParseError> {
+        let (arg, _) = self.parse(arg_max)?;
+        Ok((self.compound(start, name, vec![arg]), prec))
+    }
+    // a term in parentheses, after the `(`
+    fn bracketed(&mut self) -> Result<(Term, u16), ParseError> {
+        let (term, _) = self.parse(1200)?;
+        self.expect(Token::Close, "expected `)`")?;
+        Ok((term, 0))
+    }
+    // a list, after the `[`
+    fn list(&mut self) -> Result<(Term, u16), ParseError> {
+        let mut items = vec![self.parse(999)?.0];
+        while self.peek() == Some(&Token::Comma) {
+            self.advance();
+            items.push(self.parse(999)?.0);
+        }
+        let tail = if self.peek() == Some(&Token::Bar) {
+            self.advance();
+            self.parse(999)?.0
+        } else {
+            Term::nil()
+        };
+        self.expect(Token::CloseList, "expected `]` after list")?;
+        let list = items
+            .into_iter()
+            .rev()
+            .fold(tail, |tail, head| Term::cons(head, tail));
+        Ok((list, 0))
+    }
+    // whether the next token is a number, with nothing between it and the
+    // token just read
+    fn number_follows(&self) -> bool {
+        match (self.tokens.get(self.pos - 1), self.tokens.get(self.pos)) {
+            (Some((_, before)), Some((Token::Int(_), number))) => before.end == number.start,
+            _ => false,
+        }
+    }
+    // whether the next token can begin an operand of a prefix operator
+    fn starts_term(&self) -> bool {
+        match self.peek() {
+            Some(Token::Var(_) | Token::Int(_) | Token::Str(_) | Token::Open | Token::OpenList) => {
+                true
+            }
+            Some(Token::Name(name)) => infix_op(name).is_none() || prefix_op(name).is_some(),
+            _ => false,
+        }
+    }
+    // double-quoted text, as the `double_quotes` flag has it read
+    fn text(&self, text: &str) -> Term {
+        match self.double_quotes {
+            DoubleQuotes::Codes => {
+                Term::list_from_vec(text.chars().map(|c| Term::Integer(c as i64)).collect())
+            }
+            DoubleQuotes::Chars => Term::list_from_vec(
+                text.chars()
+                    .map(|c| Term::Atom(Atom::new(c.encode_utf8(&mut [0; 4]))))
+                    .collect(),
+            ),
+            DoubleQuotes::Atom => Term::Atom(Atom::new(text)),
+            DoubleQuotes::String => Term::String(Arc::from(text)),
+        }
+    }
+    fn variable(&mut self, name: String) -> Term {
+        if name == "_" {
+            return Term::Variable(Variable::new_anonymous(&mut self.rng));
+        }
+        if let Some((_, var)) = self.variables.iter().find(|(n, _)| *n == name) {
+            return var.clone();
+        }
+        let var = Term::variable_from_str(&name, &mut self.rng);
+        self.variables.push((name, var.clone()));
+        var
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bindings;
+
+    #[test]
+    fn parses_operators_by_priority() {
+        let term = parse_term("a :- b, c ; d").unwrap();
+        assert_eq!(format!("{}", term), ":-(a, ;(','(b, c), d))");
+        let term = parse_term("x - y - z").unwrap();
+        assert_eq!(format!("{}", term), "-(-(x, y), z)");
+        let term = parse_term("\\+ a = b").unwrap();
+        assert_eq!(format!("{}", term), "\\+(=(a, b))");
+        let term = parse_term("f(-1, - 1, a-1, [-2|T])").unwrap();
+        assert_eq!(format!("{}", term), "f(-1, -(1), -(a, 1), .(-2, T))");
+        let term = parse_term("[X]>>(X>3)").unwrap();
+        assert_eq!(format!("{}", term), ">>(.(X, []), >(X, 3))");
+    }
+
+    #[test]
+    fn parses_the_least_integer() {
+        let term = parse_term("f(-9223372036854775808, - 9223372036854775807)").unwrap();
+        let Term::Functor(f) = &term else {
+            panic!("expected f/2")
+        };
+        assert_eq!(f.args[0], Term::Integer(i64::MIN));
+        assert_eq!(f.args[1].to_string(), "-(9223372036854775807)");
+        for source in [
+            "9223372036854775808",
+            "- 9223372036854775808",
+            "a-9223372036854775808",
+        ] {
+            let err = parse_term(source).unwrap_err();
+            assert_eq!(err.message, "integer 9223372036854775808 is too large");
+        }
+        assert!(parse_term("-9223372036854775809").is_err());
+    }
+
+    #[test]
+    fn nesting_past_the_limit_is_an_error() {
+        let nested = |open: &str, close: &str, depth: usize| {
+            format!("{}a{}", open.repeat(depth), close.repeat(depth))
+        };
+        assert!(parse_term(&nested("f(", ")", MAX_DEPTH - 1)).is_ok());
+        for source in [
+            nested("f(", ")", MAX_DEPTH),
+            nested("(", ")", MAX_DEPTH),
+            nested("[", "]", MAX_DEPTH),
+            nested("- ", "", MAX_DEPTH),
+            nested("g(x, [b|", "])", MAX_DEPTH),
+        ] {
+            let err = parse_term(&source).unwrap_err();
+            assert_eq!(err.message, "term nested too deeply");
+        }
+        // operators chain without nesting Rust calls
+        let goals: Vec<String> = (0..100_000).map(|i| format!("g({})", i)).collect();
+        assert!(parse_term(&goals.join(", ")).is_ok());
+    }
+
+    #[test]
+    fn parses_lists() {
+        let term = parse_term("[a, b | T]").unwrap();
+        assert_eq!(format!("{}", term), ".(a, .(b, T))");
+        let items = Bindings::new().list_to_vec(&parse_term("[a, b]").unwrap());
+        assert_eq!(
+            items,
+            Some(vec![Term::atom_from_str("a"), Term::atom_from_str("b")])
+        );
+    }
+
+    #[test]
+    fn tokens_come_with_their_offsets() {
+        let source = "likes(X, 'big cheese') :- [a|T], \"hi\". % done";
+        let tokens: Vec<(Token, &str)> = tokenize(source)
+            .map(|(token, span)| (token, &source[span]))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (Token::Name("likes".into()), "likes"),
+                (Token::OpenCall, "("),
+                (Token::Var("X".into()), "X"),
+                (Token::Comma, ","),
+                (Token::Name("big cheese".into()), "'big cheese'"),
+                (Token::Close, ")"),
+                (Token::Name(":-".into()), ":-"),
+                (Token::OpenList, "["),
+                (Token::Name("a".into()), "a"),
+                (Token::Bar, "|"),
+                (Token::Var("T".into()), "T"),
+                (Token::CloseList, "]"),
+                (Token::Comma, ","),
+                (Token::Str("hi".into()), "\"hi\""),
+                (Token::End, "."),
+            ]
+        );
+        let (token, span) = tokenize("ok £").nth(1).unwrap();
+        assert!(matches!(token, Token::Error(_)));
+        assert_eq!(span, 3..5);
+    }
+
+    #[test]
+    fn skips_comments() {
+        let mut db = Database::new();
+        db.consult(
+            "% facts
+            likes(sam, pizza). % sam's favourite
+            /* a block comment, with a `.` and a % in it
+               likes(sam, spinach). */
+            likes(sam, /* inline */ chocolate).%no space
+            likes(X, Y) :- /* /* doesn't nest */ fail.",
+        )
+        .unwrap();
+        let query = parse_query("likes(sam, What) % trailing").unwrap();
+        assert_eq!(db.satisfy_all(query.goal).len(), 2);
+        let err = parse_term("a /* never closed").unwrap_err();
+        assert_eq!(err.message, "unterminated block comment");
+    }
+
+    #[test]
+    fn query_shares_variables_by_name() {
+        let query = parse_query("likes(X, Y), likes(Y, X)").unwrap();
+        assert_eq!(query.variables.len(), 2);
+        let Term::Functor(first) = &query.goal.args[0] else {
+            panic!("expected likes/2")
+        };
+        let Term::Functor(second) = &query.goal.args[1] else {
+            panic!("expected likes/2")
+        };
+        assert_eq!(first.args[0], second.args[1]);
+    }
+
+    #[test]
+    fn parses_quoted_atoms() {
+        let term = parse_term("likes('New York', 'don''t', '\\x41\\\\n')").unwrap();
+        let Term::Functor(f) = &term else {
+            panic!("expected likes/3")
+        };
+        let names: Vec<&str> = f
+            .args
+            .iter()
+            .map(|arg| match arg {
+                Term::Atom(a) => a.as_str(),
+                _ => panic!("expected an atom"),
+            })
+            .collect();
+        assert_eq!(names, vec!["New York", "don't", "A\n"]);
+        assert_eq!(term.to_string(), "likes('New York', 'don\\'t', 'A\\n')");
+        assert_eq!(parse_term("'hello'(X)").unwrap().to_string(), "hello(X)");
+        assert!(parse_term("'open").is_err());
+        assert!(parse_term("'\\q'").is_err());
+    }
+
+    #[test]
+    fn quoted_atoms_round_trip() {
+        for name in [
+            "Sam",
+            "two words",
+            "it's",
+            "back\\slash",
+            "tab\there",
+            "_x",
+            "",
+            ",",
+            "9lives",
+            "Émile",
+            "ǅemal",
+            "½",
+        ] {
+            let text = Term::atom_from_str(name).to_string();
+            assert_eq!(
+                parse_term(&text).unwrap(),
+                Term::atom_from_str(name),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn unicode_names_and_variables() {
+        let query = parse_query("likes(café, Ñu), Ǆ = ǅ, 日本 = _ü, cafe\u{301} = x").unwrap();
+        let names: Vec<&str> = query
+            .variables
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["Ñu", "Ǆ", "ǅ", "_ü"]);
+        let term = parse_term("f(café, 日本, cafe\u{301}, 'Ñu', ǆ)").unwrap();
+        assert_eq!(term.to_string(), "f(café, 日本, cafe\u{301}, 'Ñu', ǆ)");
+        assert!(parse_term("x²").is_err());
+    }
+
+    #[test]
+    fn reports_error_position() {
+        let err = parse_term("likes(X, ").unwrap_err();
+        assert_eq!(err.span, 9..9);
+        let mut db = Database::new();
+        let err = db.consult("likes(sam, chocolate)").unwrap_err();
+        assert_eq!(err[0].message, "expected `.` after clause");
+    }
+
+    #[test]
+    fn describes_errors() {
+        let err = parse_term("likes(sam,\n  pizza, ]").unwrap_err();
+        assert_eq!(err.expected.as_deref(), Some("a term"));
+        assert_eq!(err.found.as_deref(), Some("`]`"));
+        assert_eq!(err.span, 20..21);
+        assert_eq!((err.line, err.column), (2, 10));
+        assert_eq!(
+            err.to_string(),
+            "unexpected token, found `]` at line 2, column 10"
+        );
+        let err = parse_term("f(x, 'a\\q')").unwrap_err();
+        assert_eq!(err.message, "unknown escape sequence");
+        assert_eq!(err.span, 7..9);
+    }
+
+    #[test]
+    fn consult_goes_on_after_errors() {
+        let mut db = Database::new();
+        let errors = db
+            .consult(
+                "likes(sam, pizza).
+                 likes(sam, ).
+                 likes(popeye, £).
+                 likes(X, treats) :- 3.
+                 likes(popeye, spinach).",
+            )
+            .unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|err| err.line).collect();
+        assert_eq!(lines, vec![2, 3, 4]);
+        assert_eq!(errors[1].message, "unexpected character '£'");
+        assert_eq!(errors[2].message, "invalid clause");
+        let query = parse_query("likes(Who, What)").unwrap();
+        assert_eq!(db.satisfy_all(query.goal).len(), 2);
+    }
+
+    #[test]
+    fn split_up_predicates_are_warned_of() {
+        let mut db = Database::new();
+        db.consult(
+            "a(1).
+            b(1).
+            a(2).
+            a(3).
+            b(2).
+            :- discontiguous c/1.
+            c(1).
+            b(3).
+            c(2).",
+        )
+        .unwrap();
+        let warnings = db.take_warnings();
+        let found: Vec<(usize, &str)> = warnings
+            .iter()
+            .map(|w| (w.line, w.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, "clauses of a/1 are not together"),
+                (5, "clauses of b/1 are not together")
+            ]
+        );
+        assert!(db.take_warnings().is_empty());
+        assert_eq!(db.satisfy_all(parse_query("a(X)").unwrap().goal).len(), 3);
+    }
+
+    #[test]
+    fn clauses_know_where_they_were_written() {
+        let mut db = Database::new();
+        db.consult_named(
+            "kb.pl",
+            "likes(sam, pizza).\n  fan(X) :-\n    likes(X, pizza).",
+        )
+        .unwrap();
+        let user = crate::module::user();
+        let module = &db.modules[&user];
+        let fact = &module.predicate(&Atom::new("likes"), 2)[0];
+        assert_eq!(fact.span().unwrap().to_string(), "kb.pl:1:1");
+        let rule = &module.predicate(&Atom::new("fan"), 1)[0];
+        assert_eq!(rule.span().unwrap().to_string(), "kb.pl:2:3");
+        assert_eq!(rule.body()[0].span().unwrap().to_string(), "kb.pl:3:5");
+        assert_eq!(parse_query("likes(X, Y)").unwrap().goal.span(), None);
+    }
+
+    #[test]
+    fn consult_and_query() {
+        let mut db = Database::new();
+        db.consult(
+            "likes(sam, chocolate).
+             likes(popeye, treats).
+             sweet_tooth(X) :- likes(X, chocolate).",
+        )
+        .unwrap();
+        let query = parse_query("sweet_tooth(Who).").unwrap();
+        let answers = db.satisfy_all(query.goal);
+        assert_eq!(answers.len(), 1);
+        assert_eq!(
+            answers[0].resolve(&query.variables[0].1),
+            Term::atom_from_str("sam")
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Writing terms the way they'd be typed: operators in operator notation,
+//! lists in brackets, and parentheses only where priorities call for them.
+//! `Display` stays canonical, e.g. `+(1, *(2, 3))`; `Term::pretty` gives
+//! `1 + 2*3`, which reads back as the same term.
+
+use crate::parser::{infix_op, prefix_op, Assoc, SYMBOL_CHARS};
+use crate::{Atom, Term};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A term shown in operator notation, from `Term::pretty`.
+pub struct Pretty<'a>(&'a Term);
+
+impl Term {
+    pub fn pretty(&self) -> Pretty<'_> {
+        Pretty(self)
+    }
+}
+
+impl fmt::Display for Pretty<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&render(self.0, 1200))
+    }
+}
+
+/// `term` as an operand whose priority may be at most `max`.
+pub(crate) fn render(term: &Term, max: u16) -> String {
+    // what's left to do, last first: a compound term's text is put together
+    // from those of its operands, waiting on `done`, and only written out in
+    // full at the end, so deep terms need neither a deep Rust stack nor
+    // copying their operands' text into each term around them
+    enum Task<'a> {
+        Render(&'a Term, u16),
+        Build(Form<'a>, usize),
+    }
+    let mut texts = Texts::default();
+    let mut tasks = vec![Task::Render(term, max)];
+    let mut done: Vec<usize> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Render(term, max) => match layout(term, max) {
+                Layout::Text(text) => done.push(texts.add(vec![Part::Str(text)])),
+                Layout::Form(form, operands) => {
+                    tasks.push(Task::Build(form, operands.len()));
+                    tasks.extend(
+                        operands
+                            .into_iter()
+                            .rev()
+                            .map(|(term, max)| Task::Render(term, max)),
+                    );
+                }
+            },
+            Task::Build(form, operands) => {
+                let operands = done.split_off(done.len() - operands);
+                let parts = build(&texts, form, &operands);
+                done.push(texts.add(parts));
+            }
+        }
+    }
+    texts.write(done.pop().expect("one term rendered"))
+}
+
+// how a term is written: as text, or in a form around its operands, each
+// with the highest priority it may have
+enum Layout<'a> {
+    Text(String),
+    Form(Form<'a>, Vec<(&'a Term, u16)>),
+}
+
+enum Form<'a> {
+    // whether the operator needs brackets
+    Infix(&'a str, bool),
+    Prefix(&'a Atom, bool),
+    Canonical(&'a Atom),
+    // whether the list has a tail other than `[]`, its last operand
+    List(bool),
+}
+
+fn layout(term: &Term, max: u16) -> Layout<'_> {
+    let f = match term {
+        Term::Atom(a) => {
+            let priority = operator_priority(a);
+            return Layout::Text(if priority > max {
+                format!("({})", a)
+            } else {
+                a.to_string()
+            });
+        }
+        Term::Integer(n) => return Layout::Text(n.to_string()),
+        Term::String(_) => return Layout::Text(term.to_string()),
+        Term::Variable(v) => return Layout::Text(v.to_string()),
+        Term::Functor(f) => f,
+    };
+    let name = f.name.as_str();
+    let canonical = || {
+        Layout::Form(
+            Form::Canonical(&f.name),
+            f.args.iter().map(|arg| (arg, 999)).collect(),
+        )
+    };
+    match f.args.as_slice() {
+        [_, _] if name == "." => list(term),
+        [left, right] => match infix_op(name) {
+            Some((priority, assoc)) => {
+                let (left_max, right_max) = match assoc {
+                    Assoc::Xfy => (priority - 1, priority),
+                    Assoc::Yfx => (priority, priority - 1),
+                    _ => (priority - 1, priority - 1),
+                };
+                let form = Form::Infix(name, priority > max);
+                Layout::Form(form, vec![(left, left_max), (right, right_max)])
+            }
+            None => canonical(),
+        },
+        // `-(1)` stays canonical, so as not to read back as a negative number
+        [Term::Integer(_)] if name == "-" || name == "+" => canonical(),
+        [arg] => match prefix_op(name) {
+            Some((priority, assoc)) => {
+                let arg_max = if assoc == Assoc::Fy {
+                    priority
+                } else {
+                    priority - 1
+                };
+                Layout::Form(Form::Prefix(&f.name, priority > max), vec![(arg, arg_max)])
+            }
+            None => canonical(),
+        },
+        _ => canonical(),
+    }
+}
+
+// `[a, b|Tail]`
+fn list(mut term: &Term) -> Layout<'_> {
+    let mut items = Vec::new();
+    loop {
+        match term {
+            Term::Functor(f) if f.name.as_str() == "." && f.args.len() == 2 => {
+                items.push((&f.args[0], 999));
+                term = &f.args[1];
+            }
+            Term::Atom(a) if a.as_str() == "[]" => return Layout::Form(Form::List(false), items),
+            tail => {
+                items.push((tail, 999));
+                return Layout::Form(Form::List(true), items);
+            }
+        }
+    }
+}
+
+// the parts of a term's text in `form`, around the texts of its operands
+fn build(texts: &Texts, form: Form, operands: &[usize]) -> Vec<Part> {
+    match form {
+        Form::Infix(name, bracketed) => {
+            let [left, right] = operands else {
+                unreachable!("an infix operator has two operands")
+            };
+            bracket(infix(texts, name, *left, *right), bracketed)
+        }
+        Form::Prefix(name, bracketed) => {
+            let operand = operands[0];
+            // `-1^2` would read back with a negative number
+            let spaced = !symbolic_name(name.as_str())
+                || texts.starts_with(operand, |c| c == '(')
+                || texts.starts_with(operand, symbolic)
+                || name.as_str() == "-" && texts.starts_with(operand, |c| c.is_ascii_digit());
+            let name = if spaced {
+                format!("{} ", name)
+            } else {
+                name.to_string()
+            };
+            bracket(vec![Part::Str(name), Part::Text(operand)], bracketed)
+        }
+        Form::Canonical(name) => {
+            let mut parts = vec![Part::Str(format!("{}(", name))];
+            parts.extend(separated(operands, ", "));
+            parts.push(Part::Str(")".to_string()));
+            parts
+        }
+        Form::List(tail) => {
+            let (items, tail) = match tail {
+                true => operands.split_at(operands.len() - 1),
+                false => (operands, &[][..]),
+            };
+            let mut parts = vec![Part::Str("[".to_string())];
+            parts.extend(separated(items, ", "));
+            if let [tail] = tail {
+                parts.extend([Part::Str("|".to_string()), Part::Text(*tail)]);
+            }
+            parts.push(Part::Str("]".to_string()));
+            parts
+        }
+    }
+}
+
+fn separated<'a>(operands: &'a [usize], separator: &'a str) -> impl Iterator<Item = Part> + 'a {
+    operands.iter().enumerate().flat_map(move |(ix, id)| {
+        let separator = (ix > 0).then(|| Part::Str(separator.to_string()));
+        separator.into_iter().chain([Part::Text(*id)])
+    })
+}
+
+// the highest priority `name` has as an operator, if it is one
+fn operator_priority(name: &Atom) -> u16 {
+    let infix = infix_op(name.as_str()).map_or(0, |(p, _)| p);
+    let prefix = prefix_op(name.as_str()).map_or(0, |(p, _)| p);
+    infix.max(prefix)
+}
+
+fn bracket(parts: Vec<Part>, needed: bool) -> Vec<Part> {
+    if !needed {
+        return parts;
+    }
+    let mut bracketed = vec![Part::Str("(".to_string())];
+    bracketed.extend(parts);
+    bracketed.push(Part::Str(")".to_string()));
+    bracketed
+}
+
+fn symbolic(c: char) -> bool {
+    SYMBOL_CHARS.contains(c)
+}
+
+fn symbolic_name(name: &str) -> bool {
+    name.chars().all(symbolic)
+}
+
+// `left op right`, spaced out for operators of priority 500 and up (and
+// alphabetic ones), and wherever symbol characters would run together
+fn infix(texts: &Texts, name: &str, left: usize, right: usize) -> Vec<Part> {
+    let op = Atom::new(name).to_string();
+    if name == "," {
+        return vec![
+            Part::Text(left),
+            Part::Str(", ".to_string()),
+            Part::Text(right),
+        ];
+    }
+    let (priority, _) = infix_op(name).expect("an infix operator");
+    let spaced = priority >= 500
+        || !symbolic_name(name)
+        || texts.ends_with(left, symbolic)
+        || texts.starts_with(right, symbolic);
+    let op = if spaced { format!(" {} ", op) } else { op };
+    vec![Part::Text(left), Part::Str(op), Part::Text(right)]
+}
+
+// the texts of the terms rendered so far, each a run of parts, with its
+// first and last characters
+#[derive(Default)]
+struct Texts {
+    parts: Vec<Vec<Part>>,
+    ends: Vec<(Option<char>, Option<char>)>,
+}
+
+enum Part {
+    Str(String),
+    // an earlier text
+    Text(usize),
+}
+
+impl Texts {
+    fn add(&mut self, parts: Vec<Part>) -> usize {
+        let first = parts.iter().find_map(|part| match part {
+            Part::Str(s) => s.chars().next(),
+            Part::Text(id) => self.ends[*id].0,
+        });
+        let last = parts.iter().rev().find_map(|part| match part {
+            Part::Str(s) => s.chars().next_back(),
+            Part::Text(id) => self.ends[*id].1,
+        });
+        self.parts.push(parts);
+        self.ends.push((first, last));
+        self.parts.len() - 1
+    }
+    fn starts_with(&self, id: usize, test: impl Fn(char) -> bool) -> bool {
+        self.ends[id].0.is_some_and(test)
+    }
+    fn ends_with(&self, id: usize, test: impl Fn(char) -> bool) -> bool {
+        self.ends[id].1.is_some_and(test)
+    }
+    // text `id` in full, its parts written off a work list
+    fn write(&self, id: usize) -> String {
+        let mut text = String::new();
+        let mut pending: Vec<&Part> = self.parts[id].iter().rev().collect();
+        while let Some(part) = pending.pop() {
+            match part {
+                Part::Str(s) => text.push_str(s),
+                Part::Text(id) => pending.extend(self.parts[*id].iter().rev()),
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn pretty(source: &str) -> String {
+        parse_term(source).unwrap().pretty().to_string()
+    }
+
+    #[test]
+    fn writes_operators_by_priority() {
+        assert_eq!(pretty("+(1, *(2, 3))"), "1 + 2*3");
+        assert_eq!(pretty("*(+(1, 2), 3)"), "(1 + 2)*3");
+        assert_eq!(pretty("-(-(a, b), c)"), "a - b - c");
+        assert_eq!(pretty("-(a, -(b, c))"), "a - (b - c)");
+        assert_eq!(pretty("(a :- b, c ; d)"), "a :- b, c ; d");
+        assert_eq!(pretty("f((a, b), (:-))"), "f((a, b), (:-))");
+        assert_eq!(pretty("X is Y mod 2"), "X is Y mod 2");
+        assert_eq!(pretty("\\+ f(x)"), "\\+f(x)");
+        assert_eq!(pretty("-(a)"), "-a");
+        assert_eq!(pretty("-(1)"), "-(1)");
+        assert_eq!(pretty("-(-(a))"), "- -a");
+        assert_eq!(pretty("*(2, -(a))"), "2 * -a");
+    }
+
+    #[test]
+    fn writes_lists_in_brackets() {
+        assert_eq!(pretty("[a, f(B), [c]]"), "[a, f(B), [c]]");
+        assert_eq!(pretty("[a|T]"), "[a|T]");
+        assert_eq!(pretty("[(a, b)]"), "[(a, b)]");
+        assert_eq!(pretty("'hello world'"), "'hello world'");
+    }
+
+    #[test]
+    fn reads_back_as_the_same_term() {
+        for source in [
+            "1 + 2*3 - 4",
+            "(a ; b) -> c ; d",
+            "f(- a, [1, 2|X], 'q q')",
+            "a = (b :- c)",
+            "- (1 + 2)",
+            "(2 ** 3) ^ 4 + m:g(x)",
+            "- (1 ^ 2) - -3 * (-4)",
+        ] {
+            let term = parse_term(source).expect(source);
+            let again = parse_term(&term.pretty().to_string()).unwrap();
+            assert_eq!(again.to_string(), term.to_string(), "{}", source);
+        }
+    }
+}
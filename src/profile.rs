@@ -0,0 +1,201 @@
+//! Profiling a query: `Database::profile` runs one to its last solution and
+//! reports how much work it took, overall and predicate by predicate.
+//!
+//! An inference is a call of anything that isn't a control construct: a
+//! built-in, a foreign predicate or a predicate with clauses. Unifications
+//! count the clause heads tried against calls. A predicate's time is spent
+//! between its call or redo port and the exit or fail port that follows, so
+//! it includes the time of the goals it calls.
+
+use crate::debugger::Port;
+use crate::solver::{self, Solver};
+use crate::{Arity, Atom, Database, Functor, SolveError, Term};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
+
+/// What running a query took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub solutions: usize,
+    pub inferences: u64,
+    pub unifications: u64,
+    pub time: Duration,
+    /// Every predicate called, the one taking longest first.
+    pub predicates: Vec<PredicateProfile>,
+}
+
+/// The ports one predicate's calls passed, and the time spent in them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PredicateProfile {
+    pub name: Atom,
+    pub arity: Arity,
+    pub calls: u64,
+    pub redos: u64,
+    pub exits: u64,
+    pub fails: u64,
+    pub time: Duration,
+}
+
+impl Profile {
+    /// Logical inferences per second.
+    pub fn lips(&self) -> f64 {
+        self.inferences as f64 / self.time.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} inferences, {} unifications in {:.3}s ({:.0} LIPS), {} solutions",
+            self.inferences,
+            self.unifications,
+            self.time.as_secs_f64(),
+            self.lips(),
+            self.solutions
+        )?;
+        writeln!(
+            f,
+            "{:<24} {:>9} {:>9} {:>9} {:>9} {:>10}",
+            "predicate", "calls", "redos", "exits", "fails", "time"
+        )?;
+        for p in &self.predicates {
+            writeln!(
+                f,
+                "{:<24} {:>9} {:>9} {:>9} {:>9} {:>9.3}s",
+                format!("{}/{}", p.name, p.arity),
+                p.calls,
+                p.redos,
+                p.exits,
+                p.fails,
+                p.time.as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Tallies the ports each predicate passes.
+#[derive(Default)]
+pub(crate) struct Profiler {
+    predicates: HashMap<(Atom, Arity), PredicateProfile>,
+}
+
+impl Profiler {
+    /// Records a port of `name/arity`, entered at `since` if it's an exit or
+    /// a fail.
+    pub(crate) fn port(&mut self, (name, arity): (Atom, Arity), port: Port, since: Instant) {
+        let predicate = self
+            .predicates
+            .entry((name, arity))
+            .or_insert_with(|| PredicateProfile {
+                name,
+                arity,
+                calls: 0,
+                redos: 0,
+                exits: 0,
+                fails: 0,
+                time: Duration::ZERO,
+            });
+        let count = match port {
+            Port::Call => &mut predicate.calls,
+            Port::Redo => &mut predicate.redos,
+            Port::Exit => &mut predicate.exits,
+            Port::Fail => &mut predicate.fails,
+        };
+        *count += 1;
+        if matches!(port, Port::Exit | Port::Fail) {
+            predicate.time += since.elapsed();
+        }
+    }
+}
+
+impl Database {
+    /// Runs `goal` through all its solutions, counting the work it takes.
+    /// Needs a clock, so it isn't supported on `wasm32-unknown-unknown`.
+    pub fn profile(&self, goal: Functor) -> Result<Profile, SolveError> {
+        let mut solutions = 0;
+        let mut allowance = self.config.allowance();
+        let mut solver = Solver::new(self).profiling();
+        let started = Instant::now();
+        if !allowance.exhausted() {
+            let goal = Term::Functor(Box::new(goal));
+            let mut filter = self.config.filter(&goal);
+            let flow = solver.solve(
+                std::slice::from_ref(&goal),
+                &mut self.bindings(),
+                &mut |bindings, _| {
+                    if !filter.wanted(bindings) {
+                        return ControlFlow::Continue(());
+                    }
+                    solutions += 1;
+                    allowance.spend()
+                },
+            );
+            solver::outcome(flow)?;
+        }
+        let time = started.elapsed();
+        let (inferences, unifications) = solver.counts();
+        let mut predicates: Vec<PredicateProfile> = solver
+            .into_profiler()
+            .map(|profiler| profiler.predicates.into_values().collect())
+            .unwrap_or_default();
+        predicates.sort_by(|a, b| {
+            b.time
+                .cmp(&a.time)
+                .then_with(|| (a.name.as_str(), a.arity).cmp(&(b.name.as_str(), b.arity)))
+        });
+        Ok(Profile {
+            solutions,
+            inferences,
+            unifications,
+            time,
+            predicates,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn counts_inferences_and_ports() {
+        let mut db = Database::new();
+        db.consult(
+            "len([], 0).
+            len([_|T], N) :- len(T, M), N is M + 1.
+            pick(X) :- member_(X, [a, b, c]).
+            member_(X, [X|_]).
+            member_(X, [_|T]) :- member_(X, T).",
+        )
+        .unwrap();
+        let profile = db
+            .profile(parse_query("len([a, b, c], N)").unwrap().goal)
+            .unwrap();
+        assert_eq!(profile.solutions, 1);
+        // four calls of len/2 and three of is/2
+        assert_eq!(profile.inferences, 7);
+        assert_eq!(profile.unifications, 8);
+        let len = profile
+            .predicates
+            .iter()
+            .find(|p| p.name.as_str() == "len")
+            .unwrap();
+        assert_eq!((len.calls, len.exits, len.fails), (4, 4, 4));
+        assert!(profile.to_string().contains("len/2"));
+        let profile = db.profile(parse_query("pick(X)").unwrap().goal).unwrap();
+        assert_eq!(profile.solutions, 3);
+        let pick = profile
+            .predicates
+            .iter()
+            .find(|p| p.name.as_str() == "pick")
+            .unwrap();
+        assert_eq!(
+            (pick.calls, pick.redos, pick.exits, pick.fails),
+            (1, 3, 3, 1)
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::solver::Step;
+use crate::{Bindings, Functor, Term};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::slice;
+
+/// How a goal was proved: the clause it was resolved against (`None` for
+/// built-ins), the values the proof gave the clause's variables, and the
+/// proofs of that clause's body goals.
+#[derive(Clone, Debug)]
+pub struct Proof {
+    pub goal: Term,
+    pub clause: Option<Functor>,
+    pub bindings: Vec<(String, Term)>,
+    pub subproofs: Vec<Proof>,
+}
+
+impl Proof {
+    pub(crate) fn from_steps(steps: &[Step], bindings: &Bindings) -> Self {
+        let mut steps = steps.iter();
+        match steps.next() {
+            Some(root) => Self {
+                goal: bindings.resolve(&root.goal),
+                clause: root.clause.clone(),
+                bindings: applied(root, bindings),
+                subproofs: subproofs(&mut steps, root.subgoals, bindings),
+            },
+            None => Self {
+                goal: Term::atom_from_str("true"),
+                clause: None,
+                bindings: Vec::new(),
+                subproofs: Vec::new(),
+            },
+        }
+    }
+    /// Renders the proof as a Graphviz digraph, with goals as boxes leading to
+    /// the clauses that proved them and on to their subgoals.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph proof {\n    node [shape=box];\n");
+        self.write_dot(&mut dot, &mut 0);
+        dot.push_str("}\n");
+        dot
+    }
+    fn write_dot(&self, dot: &mut String, next_id: &mut usize) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        let _ = writeln!(
+            dot,
+            "    g{} [label={}];",
+            id,
+            quote(&self.goal.to_string())
+        );
+        let parent = match self.clause {
+            Some(ref clause) => {
+                let _ = writeln!(
+                    dot,
+                    "    c{} [label={}, shape=ellipse];",
+                    id,
+                    quote(&clause_text(clause))
+                );
+                let _ = writeln!(dot, "    g{} -> c{};", id, id);
+                format!("c{}", id)
+            }
+            None => format!("g{}", id),
+        };
+        for subproof in &self.subproofs {
+            let child = subproof.write_dot(dot, next_id);
+            let _ = writeln!(dot, "    {} -> g{};", parent, child);
+        }
+        id
+    }
+}
+
+fn subproofs(steps: &mut slice::Iter<Step>, count: usize, bindings: &Bindings) -> Vec<Proof> {
+    let mut proofs = Vec::new();
+    for _ in 0..count {
+        let Some(step) = steps.next() else {
+            break;
+        };
+        let children = subproofs(steps, step.subgoals, bindings);
+        if step.conjunction {
+            proofs.extend(children);
+        } else {
+            proofs.push(Proof {
+                goal: bindings.resolve(&step.goal),
+                clause: step.clause.clone(),
+                bindings: applied(step, bindings),
+                subproofs: children,
+            });
+        }
+    }
+    proofs
+}
+
+fn applied(step: &Step, bindings: &Bindings) -> Vec<(String, Term)> {
+    step.variables
+        .iter()
+        .map(|(name, var)| (name.clone(), bindings.resolve(var)))
+        .collect()
+}
+
+fn clause_text(clause: &Functor) -> String {
+    if clause.body.is_empty() {
+        format!("{}.", clause)
+    } else {
+        let body: Vec<String> = clause.body.iter().map(Functor::to_string).collect();
+        format!("{} :- {}.", clause, body.join(", "))
+    }
+}
+
+fn quote(label: &str) -> String {
+    format!("\"{}\"", label.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::ops::ControlFlow;
+    use std::str::FromStr;
+
+    fn first_proof(program: &str, query: &str) -> Proof {
+        let mut db = Database::new();
+        db.consult(program).unwrap();
+        first_proof_in(&db, query)
+    }
+
+    fn first_proof_in(db: &Database, query: &str) -> Proof {
+        let mut found = None;
+        db.for_each_proof(parse_query(query).unwrap().goal, |_, proof| {
+            found = Some(proof.clone());
+            ControlFlow::Break(())
+        })
+        .unwrap();
+        found.expect("a proof")
+    }
+
+    #[test]
+    fn records_clauses_and_subgoals() {
+        let proof = first_proof(
+            "likes(sam, chocolate). fan(X) :- likes(X, chocolate), true.",
+            "fan(Who)",
+        );
+        assert_eq!(proof.goal.to_string(), "fan(sam)");
+        assert_eq!(proof.clause.as_ref().unwrap().body.len(), 2);
+        assert_eq!(
+            proof.bindings,
+            vec![("X".to_string(), Term::atom_from_str("sam"))]
+        );
+        assert_eq!(proof.subproofs.len(), 2);
+        assert_eq!(proof.subproofs[0].goal.to_string(), "likes(sam, chocolate)");
+        assert!(proof.subproofs[0].clause.is_some());
+        assert_eq!(proof.subproofs[1].goal.to_string(), "true");
+        assert!(proof.subproofs[1].clause.is_none());
+    }
+
+    #[test]
+    fn splices_conjunctions() {
+        let mut db = Database::new();
+        db.consult("a. b.").unwrap();
+        let Ok(Term::Functor(conjunction)) = parse_term("(a, b)") else {
+            panic!("expected ,/2")
+        };
+        db.add(Functor::new_rule(
+            Atom::from_str("c").unwrap(),
+            Vec::new(),
+            vec![*conjunction],
+        ));
+        let proof = first_proof_in(&db, "c");
+        let goals: Vec<String> = proof.subproofs.iter().map(|p| p.goal.to_string()).collect();
+        assert_eq!(goals, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn conjunctive_query_is_the_root() {
+        let proof = first_proof("a. b. c :- a, b.", "c, a");
+        assert_eq!(proof.goal.to_string(), "','(c, a)");
+        let goals: Vec<String> = proof.subproofs.iter().map(|p| p.goal.to_string()).collect();
+        assert_eq!(goals, vec!["c", "a"]);
+        assert_eq!(proof.subproofs[0].subproofs.len(), 2);
+    }
+
+    #[test]
+    fn exports_dot() {
+        let proof = first_proof(
+            "likes(sam, chocolate). fan(X) :- likes(X, chocolate).",
+            "fan(W)",
+        );
+        assert_eq!(
+            proof.to_dot(),
+            "digraph proof {
+    node [shape=box];
+    g0 [label=\"fan(sam)\"];
+    c0 [label=\"fan(X) :- likes(X, chocolate).\", shape=ellipse];
+    g0 -> c0;
+    g1 [label=\"likes(sam, chocolate)\"];
+    c1 [label=\"likes(sam, chocolate).\", shape=ellipse];
+    g1 -> c1;
+    c0 -> g1;
+}
+"
+        );
+    }
+}
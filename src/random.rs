@@ -0,0 +1,165 @@
+//! Random-number built-ins: `random/1`, `random_between/3`,
+//! `random_member/2` and `random_permutation/2`. They draw from the
+//! database's generator, seeded from the OS unless fixed with
+//! `Database::set_random_seed` for runs that repeat. Without the `std`
+//! feature there's no OS to ask, and the seed is 0.
+
+use crate::arith::Number;
+use crate::builtins::{self, Alternatives};
+use crate::compat::{Mutex, MutexGuard};
+use crate::error;
+use crate::solver::{Bindings, Solver};
+use crate::{Database, Term};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::fmt;
+use core::iter;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+#[derive(Clone)]
+pub(crate) struct Random(Arc<Mutex<StdRng>>);
+
+impl Default for Random {
+    fn default() -> Self {
+        #[cfg(feature = "std")]
+        let generator = StdRng::from_entropy();
+        #[cfg(not(feature = "std"))]
+        let generator = StdRng::seed_from_u64(0);
+        Self(Arc::new(Mutex::new(generator)))
+    }
+}
+
+impl fmt::Debug for Random {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Random")
+    }
+}
+
+impl Random {
+    fn generator(&self) -> MutexGuard<'_, StdRng> {
+        self.0.lock()
+    }
+}
+
+impl Database {
+    /// Restarts the generator behind `random/1` and friends from `seed`, so
+    /// the same queries draw the same numbers.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random = Random(Arc::new(Mutex::new(StdRng::seed_from_u64(seed))));
+    }
+}
+
+// what `random/1` divides by: its fractions are multiples of 1/2^32
+const DENOMINATOR: i128 = 1 << 32;
+
+/// `random(X)`: `X` is a fraction in `[0, 1)`, as `N rdiv 2^32` in lowest
+/// terms (or `0`), there being no floats.
+pub(crate) fn random(solver: &mut Solver, args: &[Term], _: &Bindings) -> Alternatives {
+    let n = solver.random().generator().gen_range(0..DENOMINATOR);
+    match Number::ratio(n, DENOMINATOR) {
+        Ok(x) => unified(&args[0], x.to_term()),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+/// `random_between(Low, High, X)`: `X` is an integer in `Low..=High`,
+/// failing if there is none.
+pub(crate) fn random_between(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    let bound = |term: &Term| match bindings.walk(term) {
+        Term::Integer(n) => Ok(*n),
+        Term::Variable(_) => Err(error::instantiation_error()),
+        other => Err(error::type_error("integer", other.clone())),
+    };
+    let (low, high) = match bound(&args[0]).and_then(|low| Ok((low, bound(&args[1])?))) {
+        Ok(bounds) => bounds,
+        Err(ball) => return builtins::raise(solver, ball),
+    };
+    if low > high {
+        return builtins::failure();
+    }
+    let x = solver.random().generator().gen_range(low..=high);
+    unified(&args[2], Term::Integer(x))
+}
+
+/// `random_member(X, List)`: `X` is an item of `List` picked at random,
+/// failing if it's empty.
+pub(crate) fn random_member(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    let items = match builtins::list(solver, &args[1], bindings) {
+        Some(items) => items,
+        None => return builtins::failure(),
+    };
+    let item = items.choose(&mut *solver.random().generator()).cloned();
+    match item {
+        Some(item) => unified(&args[0], item),
+        None => builtins::failure(),
+    }
+}
+
+/// `random_permutation(List, Permutation)`: `Permutation` has the items of
+/// `List` in a random order.
+pub(crate) fn random_permutation(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    let mut items = match builtins::list(solver, &args[0], bindings) {
+        Some(items) => items,
+        None => return builtins::failure(),
+    };
+    items.shuffle(&mut *solver.random().generator());
+    unified(&args[1], Term::list_from_vec(items))
+}
+
+fn unified(term: &Term, value: Term) -> Alternatives {
+    Box::new(iter::once(builtins::unify(vec![(term.clone(), value)])))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+
+    const DRAWS: &str = "random(F), random_between(1, 100, N), \
+        random_member(M, [a, b, c, d]), random_permutation([1, 2, 3, 4, 5], P)";
+
+    #[test]
+    fn seeded_runs_repeat() {
+        let mut db = Database::new();
+        db.set_random_seed(42);
+        let first = answers(&db, DRAWS).unwrap();
+        db.set_random_seed(42);
+        assert_eq!(answers(&db, DRAWS).unwrap(), first);
+        assert_eq!(first.len(), 1);
+        db.set_random_seed(43);
+        let draws: Vec<Vec<String>> = (0..5).map(|_| answers(&db, DRAWS).unwrap()).collect();
+        assert!(draws.iter().any(|draw| *draw != first));
+    }
+
+    #[test]
+    fn draws_stay_in_range() {
+        let db = Database::new();
+        for _ in 0..50 {
+            let query = "random_between(-2, 2, N), N >= -2, N =< 2, \
+                random_member(M, [x, x]), M = x, \
+                random_permutation([1, 2, 3], P), msort(P, [1, 2, 3]), \
+                random(F), F >= 0, F < 1";
+            assert_eq!(answers(&db, query).unwrap().len(), 1);
+        }
+        assert!(answers(&db, "random_between(3, 1, _)").unwrap().is_empty());
+        assert!(answers(&db, "random_member(_, [])").unwrap().is_empty());
+        assert_eq!(answers(&db, "random_permutation([], P)").unwrap().len(), 1);
+        let query = parse_query("random_between(a, 3, _)").unwrap();
+        assert!(db.try_satisfy_all(query.goal).is_err());
+    }
+}
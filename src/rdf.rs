@@ -0,0 +1,543 @@
+//! Loading RDF as facts: `Database::load_rdf` reads N-Triples or Turtle and
+//! adds `rdf(Subject, Predicate, Object)` for each triple, so a database can
+//! serve as a small triple store.
+//!
+//! IRIs are atoms, with prefixed names expanded and relative IRIs resolved
+//! against the base, and blank nodes are atoms like `'_:b1'`. A literal is
+//! `literal(Text)`, `literal(lang(Lang, Text))` or
+//! `literal(type(Type, Text))`, its text an atom. Turtle's numbers and
+//! booleans are typed literals: `42` is `literal(type(Integer, '42'))`, with
+//! `Integer` the IRI of `xsd:integer`.
+
+use crate::{Atom, Database, Functor, Term};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+impl Database {
+    /// Adds `rdf(S, P, O)` for each triple in the N-Triples or Turtle file at
+    /// `path`, giving how many were added. Text that can't be read is an
+    /// `InvalidData` error naming its line, once the triples before it are
+    /// added.
+    pub fn load_rdf<P: AsRef<Path>>(&mut self, path: P) -> io::Result<usize> {
+        self.read_rdf(&fs::read_to_string(path)?)
+    }
+    /// Like `load_rdf`, reading the triples in `text`.
+    pub fn read_rdf(&mut self, text: &str) -> io::Result<usize> {
+        let mut parser = Parser::new(text);
+        let result = parser.document();
+        let added = parser.triples.len();
+        let rdf = Atom::new("rdf");
+        for [s, p, o] in parser.triples {
+            self.add(Functor::new_fact(rdf, vec![s, p, o]));
+        }
+        result.map(|()| added)
+    }
+}
+
+struct Parser {
+    text: Vec<char>,
+    at: usize,
+    line: usize,
+    base: String,
+    prefixes: HashMap<String, String>,
+    // how many blank nodes were made up, for `[]` and collections
+    blanks: usize,
+    triples: Vec<[Term; 3]>,
+}
+
+type Parsed<T> = Result<T, io::Error>;
+
+impl Parser {
+    fn new(text: &str) -> Self {
+        Self {
+            text: text.chars().collect(),
+            at: 0,
+            line: 1,
+            base: String::new(),
+            prefixes: HashMap::new(),
+            blanks: 0,
+            triples: Vec::new(),
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Parsed<T> {
+        let message = format!("line {}: {}", self.line, message);
+        Err(io::Error::new(io::ErrorKind::InvalidData, message))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text.get(self.at).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.at += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    // skips whitespace and comments
+    fn skip(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                c if c.is_whitespace() => {
+                    self.bump();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip();
+        let found = self.peek() == Some(c);
+        if found {
+            self.bump();
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Parsed<()> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.error(&format!("expected `{}`", c))
+        }
+    }
+
+    fn document(&mut self) -> Parsed<()> {
+        loop {
+            self.skip();
+            if self.peek().is_none() {
+                return Ok(());
+            }
+            self.statement()?;
+        }
+    }
+
+    fn statement(&mut self) -> Parsed<()> {
+        let start = (self.at, self.line);
+        let word = self.word();
+        match word.as_str() {
+            "@prefix" | "PREFIX" | "prefix" => {
+                self.skip();
+                let name = self.word();
+                let Some(prefix) = name.strip_suffix(':') else {
+                    return self.error("expected a prefix ending in `:`");
+                };
+                self.expect('<')?;
+                let iri = self.iri_ref()?;
+                self.prefixes.insert(prefix.to_string(), iri);
+                if word.starts_with('@') {
+                    self.expect('.')?;
+                }
+                Ok(())
+            }
+            "@base" | "BASE" | "base" => {
+                self.expect('<')?;
+                self.base = self.iri_ref()?;
+                if word.starts_with('@') {
+                    self.expect('.')?;
+                }
+                Ok(())
+            }
+            _ => {
+                (self.at, self.line) = start;
+                self.triples_statement()?;
+                self.expect('.')
+            }
+        }
+    }
+
+    fn triples_statement(&mut self) -> Parsed<()> {
+        self.skip();
+        if self.peek() == Some('[') {
+            let subject = self.object()?;
+            self.skip();
+            // `[ ... ] .` says only what's inside the brackets
+            if self.peek() == Some('.') {
+                return Ok(());
+            }
+            return self.predicates(&subject);
+        }
+        let subject = self.subject()?;
+        self.predicates(&subject)
+    }
+
+    // `Verb Object, ...; ...` about `subject`
+    fn predicates(&mut self, subject: &Term) -> Parsed<()> {
+        loop {
+            let verb = self.verb()?;
+            loop {
+                let object = self.object()?;
+                self.triples.push([subject.clone(), verb.clone(), object]);
+                if !self.eat(',') {
+                    break;
+                }
+            }
+            if !self.eat(';') {
+                return Ok(());
+            }
+            // a `;` may end the list
+            while self.eat(';') {}
+            self.skip();
+            if matches!(self.peek(), Some('.' | ']') | None) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn verb(&mut self) -> Parsed<Term> {
+        self.skip();
+        let start = (self.at, self.line);
+        if self.word() == "a" {
+            return Ok(iri(&format!("{}type", RDF)));
+        }
+        (self.at, self.line) = start;
+        self.iri()
+    }
+
+    fn subject(&mut self) -> Parsed<Term> {
+        self.skip();
+        match self.peek() {
+            Some('(') => self.collection(),
+            Some('_') => self.blank_node(),
+            _ => self.iri(),
+        }
+    }
+
+    fn object(&mut self) -> Parsed<Term> {
+        self.skip();
+        match self.peek() {
+            Some('(') => self.collection(),
+            Some('_') => self.blank_node(),
+            Some('[') => {
+                self.bump();
+                let node = self.fresh_blank();
+                if !self.eat(']') {
+                    self.predicates(&node)?;
+                    self.expect(']')?;
+                }
+                Ok(node)
+            }
+            Some('"' | '\'') => self.literal(),
+            Some(c) if c.is_ascii_digit() || "+-.".contains(c) => self.number(),
+            _ => {
+                let start = (self.at, self.line);
+                match self.word().as_str() {
+                    word @ ("true" | "false") => {
+                        Ok(literal(word, Some(&format!("{}boolean", XSD))))
+                    }
+                    _ => {
+                        (self.at, self.line) = start;
+                        self.iri()
+                    }
+                }
+            }
+        }
+    }
+
+    fn fresh_blank(&mut self) -> Term {
+        self.blanks += 1;
+        Term::Atom(Atom::new(&format!("_:genid{}", self.blanks)))
+    }
+
+    fn blank_node(&mut self) -> Parsed<Term> {
+        let label = self.word();
+        if !label.starts_with("_:") || label.len() == 2 {
+            return self.error("expected a blank node");
+        }
+        Ok(Term::Atom(Atom::new(&label)))
+    }
+
+    // `( Object ... )`, as `rdf:first` and `rdf:rest` triples
+    fn collection(&mut self) -> Parsed<Term> {
+        self.expect('(')?;
+        let mut items = Vec::new();
+        while !self.eat(')') {
+            if self.peek().is_none() {
+                return self.error("expected `)`");
+            }
+            items.push(self.object()?);
+        }
+        let mut list = iri(&format!("{}nil", RDF));
+        for item in items.into_iter().rev() {
+            let node = self.fresh_blank();
+            let first = iri(&format!("{}first", RDF));
+            let rest = iri(&format!("{}rest", RDF));
+            self.triples.push([node.clone(), first, item]);
+            self.triples.push([node.clone(), rest, list]);
+            list = node;
+        }
+        Ok(list)
+    }
+
+    fn iri(&mut self) -> Parsed<Term> {
+        if self.eat('<') {
+            return Ok(iri(&self.iri_ref()?));
+        }
+        let name = self.word();
+        let Some((prefix, local)) = name.split_once(':') else {
+            return self.error("expected an IRI");
+        };
+        match self.prefixes.get(prefix) {
+            Some(namespace) => Ok(iri(&format!("{}{}", namespace, local.replace('\\', "")))),
+            None => self.error(&format!("unknown prefix `{}:`", prefix)),
+        }
+    }
+
+    // the rest of `<iri>`, resolved against the base
+    fn iri_ref(&mut self) -> Parsed<String> {
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some('>') => break,
+                Some('\\') => text.push(self.escape()?),
+                Some(c) if c != '\n' => text.push(c),
+                _ => return self.error("expected `>`"),
+            }
+        }
+        // absolute if it has a scheme
+        let scheme = text.split_once(':').is_some_and(|(scheme, _)| {
+            !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        });
+        Ok(if scheme {
+            text
+        } else if text.starts_with('#') || text.is_empty() {
+            format!("{}{}", self.base.split('#').next().unwrap_or(""), text)
+        } else {
+            let dir = self.base.rfind('/').map_or("", |end| &self.base[..=end]);
+            format!("{}{}", dir, text)
+        })
+    }
+
+    // prefixed names, keywords and blank node labels
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || "_-.:%@\\".contains(c) {
+                if c == '\\' {
+                    word.push(c);
+                    self.bump();
+                    let Some(escaped) = self.bump() else { break };
+                    word.push(escaped);
+                    continue;
+                }
+                word.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        // a name can't end with a `.`, which ends the statement instead
+        while word.ends_with('.') && !word.ends_with("\\.") {
+            word.pop();
+            self.at -= 1;
+        }
+        word
+    }
+
+    fn escape(&mut self) -> Parsed<char> {
+        let c = match self.bump() {
+            Some('t') => '\t',
+            Some('b') => '\u{8}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('f') => '\u{c}',
+            Some('u') => return self.hex(4),
+            Some('U') => return self.hex(8),
+            Some(c @ ('"' | '\'' | '\\')) => c,
+            _ => return self.error("unknown escape"),
+        };
+        Ok(c)
+    }
+
+    fn hex(&mut self, digits: usize) -> Parsed<char> {
+        let mut code = 0;
+        for _ in 0..digits {
+            match self.bump().and_then(|c| c.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return self.error("expected a hex digit"),
+            }
+        }
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => self.error("not a character"),
+        }
+    }
+
+    fn literal(&mut self) -> Parsed<Term> {
+        let quote = self.bump().expect("a quote");
+        let long = self.text[self.at..].starts_with(&[quote, quote]);
+        if long {
+            self.at += 2;
+        }
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some('\\') => text.push(self.escape()?),
+                Some(c) if c == quote => {
+                    if !long {
+                        break;
+                    }
+                    if self.text[self.at..].starts_with(&[quote, quote]) {
+                        self.at += 2;
+                        break;
+                    }
+                    text.push(c);
+                }
+                Some('\n') if !long => return self.error("unterminated string"),
+                Some(c) => text.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+        match self.peek() {
+            Some('@') => {
+                self.bump();
+                let mut lang = String::new();
+                while let Some(c) = self
+                    .peek()
+                    .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+                {
+                    lang.push(c);
+                    self.bump();
+                }
+                let lang = Term::Atom(Atom::new(&lang));
+                let text = Term::Atom(Atom::new(&text));
+                Ok(Term::functor_from_str(
+                    "literal",
+                    vec![Term::functor_from_str("lang", vec![lang, text])],
+                ))
+            }
+            Some('^') if self.text[self.at..].starts_with(&['^', '^']) => {
+                self.at += 2;
+                let Term::Atom(kind) = self.iri()? else {
+                    unreachable!("IRIs are atoms")
+                };
+                Ok(literal(&text, Some(kind.as_str())))
+            }
+            _ => Ok(literal(&text, None)),
+        }
+    }
+
+    fn number(&mut self) -> Parsed<Term> {
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            let exponent = text.ends_with(['e', 'E']);
+            if c.is_ascii_digit()
+                || "eE.".contains(c)
+                || ("+-".contains(c) && (text.is_empty() || exponent))
+            {
+                text.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        while text.ends_with('.') {
+            text.pop();
+            self.at -= 1;
+        }
+        let kind = if text.contains(['e', 'E']) {
+            "double"
+        } else if text.contains('.') {
+            "decimal"
+        } else {
+            "integer"
+        };
+        if !text.chars().any(|c| c.is_ascii_digit()) {
+            return self.error("expected a number");
+        }
+        Ok(literal(&text, Some(&format!("{}{}", XSD, kind))))
+    }
+}
+
+fn iri(text: &str) -> Term {
+    Term::Atom(Atom::new(text))
+}
+
+// `literal(Text)`, or `literal(type(Type, Text))`
+fn literal(text: &str, kind: Option<&str>) -> Term {
+    let text = Term::Atom(Atom::new(text));
+    let value = match kind {
+        Some(kind) => Term::functor_from_str("type", vec![iri(kind), text]),
+        None => text,
+    };
+    Term::functor_from_str("literal", vec![value])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+
+    #[test]
+    fn triples_become_facts() {
+        let mut db = Database::new();
+        let ntriples = r#"
+            # people
+            <http://example.org/sam> <http://xmlns.com/foaf/0.1/name> "Sam \"S\" Smith"@en .
+            <http://example.org/sam> <http://xmlns.com/foaf/0.1/knows> _:b1 .
+            _:b1 <http://xmlns.com/foaf/0.1/age> "30"^^<http://www.w3.org/2001/XMLSchema#integer> .
+        "#;
+        assert_eq!(db.read_rdf(ntriples).unwrap(), 3);
+        assert_eq!(
+            answers(&db, "rdf('http://example.org/sam', P, literal(lang(L, N)))").unwrap(),
+            [r#"P = 'http://xmlns.com/foaf/0.1/name', L = en, N = 'Sam "S" Smith'"#]
+        );
+        let turtle = r#"
+            @base <http://example.org/> .
+            @prefix foaf: <http://xmlns.com/foaf/0.1/> .
+            PREFIX ex: <http://example.org/>
+            <olive> a foaf:Person ;
+                foaf:name 'Olive', """Olive
+            Oyl""" ;
+                foaf:age 25 ;
+                ex:likes ( ex:spinach ex:sailing ) ;
+                foaf:knows [ foaf:name "Popeye" ; ex:strong true ] .
+        "#;
+        assert_eq!(db.read_rdf(turtle).unwrap(), 12);
+        assert_eq!(
+            answers(
+                &db,
+                "rdf(X, 'http://www.w3.org/1999/02/22-rdf-syntax-ns#type', _)"
+            )
+            .unwrap(),
+            ["X = 'http://example.org/olive'"]
+        );
+        assert_eq!(
+            answers(
+                &db,
+                "rdf(_, 'http://xmlns.com/foaf/0.1/age', literal(type(_, A)))"
+            )
+            .unwrap(),
+            ["A = '30'", "A = '25'"]
+        );
+        assert_eq!(
+            answers(
+                &db,
+                "rdf(_, 'http://xmlns.com/foaf/0.1/knows', K), \
+                 rdf(K, 'http://xmlns.com/foaf/0.1/name', literal(N))"
+            )
+            .unwrap(),
+            ["K = '_:genid3', N = 'Popeye'"]
+        );
+        let error = db.read_rdf("<a> <b> <c> .\n<a> nope:b <c> .").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: unknown prefix `nope:`");
+    }
+}
@@ -0,0 +1,240 @@
+use crate::{pretty, Atom, Bindings, FromTerm, Query, Term, VariableName};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+/// The values of a query's named variables in one solution.
+#[derive(Clone, Debug)]
+pub struct Solution {
+    values: Vec<(String, Term)>,
+}
+
+impl Query {
+    pub fn solution(&self, bindings: &Bindings) -> Solution {
+        Solution {
+            values: self
+                .variables
+                .iter()
+                .map(|(name, var)| (name.clone(), bindings.resolve(var)))
+                .collect(),
+        }
+    }
+}
+
+impl Solution {
+    pub fn values(&self) -> &[(String, Term)] {
+        &self.values
+    }
+    /// The value of the query's variable `name`, with every binding
+    /// substituted, or `None` if the query has no variable so named.
+    pub fn get(&self, name: &str) -> Option<&Term> {
+        let (_, value) = self.values.iter().find(|(n, _)| n == name)?;
+        Some(value)
+    }
+    /// The value of the variable `name` as a Rust value, if it has one of
+    /// type `T`.
+    pub fn get_as<T: FromTerm>(&self, name: &str) -> Option<T> {
+        T::from_term(self.get(name)?)
+    }
+    /// Renders the solution as a JSON object keyed by variable name. Atoms become
+    /// strings, lists become arrays, unbound variables become `null`, and other
+    /// compound terms become `{"functor": name, "args": [...]}`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        for (ix, (name, value)) in self.values.iter().enumerate() {
+            if ix > 0 {
+                json.push_str(", ");
+            }
+            write_string(&mut json, name);
+            json.push_str(": ");
+            write_term(&mut json, value);
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// The solution as a Prolog top level shows it: `X = popeye, Y = treats`,
+/// each value in operator notation. Variables whose names start with `_`
+/// are left out, as are those left unbound, and a solution with nothing
+/// else to show is `true`.
+impl fmt::Display for Solution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unbound = |name: &str, value: &Term| matches!(value, Term::Variable(v) if v.name == VariableName::Name(Atom::new(name)));
+        let mut shown = self
+            .values
+            .iter()
+            .filter(|(name, value)| !name.starts_with('_') && !unbound(name, value));
+        let Some((name, value)) = shown.next() else {
+            return f.write_str("true");
+        };
+        // each value stands as an argument of `,`, so it's bracketed at
+        // priority 700 and up
+        write!(f, "{} = {}", name, pretty::render(value, 699))?;
+        for (name, value) in shown {
+            write!(f, ", {} = {}", name, pretty::render(value, 699))?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders solutions as a JSON array of objects.
+pub fn solutions_to_json(solutions: &[Solution]) -> String {
+    let objects: Vec<String> = solutions.iter().map(Solution::to_json).collect();
+    format!("[{}]", objects.join(", "))
+}
+
+// writes `term` off a work list, last first, so deep terms need no deep Rust
+// stack
+fn write_term(json: &mut String, term: &Term) {
+    enum Task<'a> {
+        Write(&'a Term),
+        Text(&'static str),
+    }
+    // `items` as a JSON array, written in `brackets`
+    fn elements<'a>(items: &[&'a Term], brackets: [&'static str; 2], tasks: &mut Vec<Task<'a>>) {
+        tasks.push(Task::Text(brackets[1]));
+        for (ix, item) in items.iter().enumerate().rev() {
+            tasks.push(Task::Write(item));
+            if ix > 0 {
+                tasks.push(Task::Text(", "));
+            }
+        }
+        tasks.push(Task::Text(brackets[0]));
+    }
+    let mut tasks = vec![Task::Write(term)];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Text(text) => json.push_str(text),
+            Task::Write(term) => {
+                if let Some(items) = list_items(term) {
+                    elements(&items, ["[", "]"], &mut tasks);
+                    continue;
+                }
+                match term {
+                    Term::Atom(a) => write_string(json, a.as_str()),
+                    Term::String(text) => write_string(json, text),
+                    Term::Integer(n) => {
+                        let _ = write!(json, "{}", n);
+                    }
+                    Term::Variable(_) => json.push_str("null"),
+                    Term::Functor(f) => {
+                        json.push_str("{\"functor\": ");
+                        write_string(json, f.name.as_str());
+                        let args: Vec<&Term> = f.args.iter().collect();
+                        elements(&args, [", \"args\": [", "]}"], &mut tasks);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// the items of `term`, if it's a proper list
+fn list_items(mut term: &Term) -> Option<Vec<&Term>> {
+    let mut items = Vec::new();
+    loop {
+        match term {
+            Term::Functor(f) if f.name.as_str() == "." && f.args.len() == 2 => {
+                items.push(&f.args[0]);
+                term = &f.args[1];
+            }
+            Term::Atom(a) if a.as_str() == "[]" => return Some(items),
+            _ => return None,
+        }
+    }
+}
+
+pub(crate) fn write_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_query, Database};
+
+    fn solutions(program: &str, query: &str) -> Vec<Solution> {
+        let mut db = Database::new();
+        db.consult(program).unwrap();
+        let query = parse_query(query).unwrap();
+        db.satisfy_all(query.goal.clone())
+            .iter()
+            .map(|b| query.solution(b))
+            .collect()
+    }
+
+    #[test]
+    fn atoms_as_strings() {
+        let found = solutions(
+            "likes(sam, chocolate). likes(popeye, treats).",
+            "likes(X, treats)",
+        );
+        assert_eq!(found[0].to_json(), r#"{"X": "popeye"}"#);
+    }
+
+    #[test]
+    fn lists_structures_and_unbound() {
+        let found = solutions("p([a, b], f(c), _).", "p(L, S, U)");
+        assert_eq!(
+            found[0].to_json(),
+            r#"{"L": ["a", "b"], "S": {"functor": "f", "args": ["c"]}, "U": null}"#
+        );
+    }
+
+    #[test]
+    fn array_of_solutions() {
+        let found = solutions("q(a). q(b).", "q(X)");
+        assert_eq!(solutions_to_json(&found), r#"[{"X": "a"}, {"X": "b"}]"#);
+        assert_eq!(solutions_to_json(&[]), "[]");
+    }
+
+    #[test]
+    fn shown_as_a_top_level_would() {
+        let found = solutions(
+            "likes(popeye, treats, [a, b], 1 + 2).",
+            "likes(X, Y, L, _Sum)",
+        );
+        assert_eq!(found[0].to_string(), "X = popeye, Y = treats, L = [a, b]");
+        let found = solutions("p(1).", "p(_), p(_Hidden)");
+        assert_eq!(found[0].to_string(), "true");
+        let found = solutions("", "X = Y, Z = f(W)");
+        assert_eq!(found[0].to_string(), "X = Y, Z = f(W)");
+        let found = solutions("", "X = (a, b), Y = (a :- b), Z = (p = q), N = -(1)");
+        assert_eq!(
+            found[0].to_string(),
+            "X = (a, b), Y = (a :- b), Z = (p = q), N = -(1)"
+        );
+    }
+
+    #[test]
+    fn values_by_name() {
+        let found = solutions("pair(sam, f(X, 1)).", "pair(Who, What)");
+        assert_eq!(found[0].get("Who"), Some(&Term::atom_from_str("sam")));
+        assert!(matches!(found[0].get("What"), Some(Term::Functor(f)) if f.arity() == 2));
+        assert_eq!(found[0].get("Nobody"), None);
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let mut json = String::new();
+        write_string(&mut json, "say \"hi\"\\\n");
+        assert_eq!(json, r#""say \"hi\"\\\n""#);
+    }
+}
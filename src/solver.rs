@@ -0,0 +1,2493 @@
+use crate::arena::Arena;
+use crate::builtins;
+use crate::clpfd;
+use crate::compat::{thread_rng, HashMap, HashSet, ThreadRng};
+use crate::config::{CancellationToken, Strategy};
+use crate::debugger::{Command, Port};
+use crate::distinct::Seen;
+use crate::dynamic::{Changes, Key, Slots};
+use crate::error::{self, Resource, SolveError};
+use crate::flags::{Flags, Unknown};
+use crate::globals::Globals;
+#[cfg(feature = "std")]
+use crate::input::Input;
+use crate::module::{self, qualify};
+use crate::observer::{ClauseId, Event};
+use crate::output::Output;
+#[cfg(feature = "std")]
+use crate::profile::Profiler;
+use crate::random::Random;
+use crate::stats::{self, Clock};
+#[cfg(feature = "tracing")]
+use crate::telemetry;
+use crate::{
+    Alias, Arity, Atom, Database, Functor, OccursCheck, Span, Term, Variable, VariableName,
+};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter;
+use core::ops::{ControlFlow, RangeInclusive};
+use rand::Rng;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// A substitution from variables (by alias) to the terms they are bound to,
+/// along with the attributes and finite domain constraints of the unbound ones.
+#[derive(Clone, Debug, Default)]
+pub struct Bindings {
+    vars: HashMap<Alias, Term>,
+    // module-keyed attributes, by variable alias
+    pub(crate) attributes: HashMap<Alias, Vec<(Atom, Term)>>,
+    // attribute hooks to run after the current unification
+    pub(crate) wakeups: Vec<Term>,
+    pub(crate) constraints: clpfd::Store,
+    // every change since the bindings were created, oldest first
+    trail: Vec<Undo>,
+    pub(crate) occurs_check: OccursCheck,
+}
+
+/// How to take back one change to the bindings when backtracking.
+#[derive(Clone, Debug)]
+pub(crate) enum Undo {
+    Bind(Alias),
+    /// A bound variable's previous binding, before unification pointed it
+    /// at another.
+    Rebind(Alias, Term),
+    /// A variable's previous attributes.
+    Attributes(Alias, Option<Vec<(Atom, Term)>>),
+    /// A variable's previous domain.
+    Domain(Alias, Option<clpfd::Domain>),
+    Constraint,
+}
+
+/// A point in the trail to backtrack to.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Mark {
+    trail: usize,
+    wakeups: usize,
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self, var: &Variable) -> Option<&Term> {
+        self.vars.get(&var.alias)
+    }
+    /// Follows variable bindings until reaching an unbound variable or a non-variable term.
+    pub fn walk<'a>(&'a self, mut term: &'a Term) -> &'a Term {
+        while let Term::Variable(v) = term {
+            match self.vars.get(&v.alias) {
+                Some(bound) => term = bound,
+                None => break,
+            }
+        }
+        term
+    }
+    /// Follows variable bindings like `walk`, also giving the last variable
+    /// passed through, if any.
+    fn walk_via<'a>(&'a self, mut term: &'a Term) -> (Option<&'a Variable>, &'a Term) {
+        let mut via = None;
+        while let Term::Variable(v) = term {
+            match self.vars.get(&v.alias) {
+                Some(bound) => {
+                    via = Some(v);
+                    term = bound;
+                }
+                None => break,
+            }
+        }
+        (via, term)
+    }
+    /// Substitutes bindings throughout `term`. A variable bound to a term
+    /// containing itself, which unification without the occurs check can
+    /// make, is left as it is inside its own binding: `X = f(X)` resolves to
+    /// `f(X)`.
+    pub fn resolve(&self, term: &Term) -> Term {
+        // what's left to do, last first; terms are built on `done` as their
+        // arguments are finished, so deep terms need no deep Rust stack
+        enum Task<'a> {
+            Resolve(&'a Term),
+            Build(&'a Functor),
+            // done substituting the binding of a variable
+            Expanded(Alias),
+        }
+        let mut tasks = vec![Task::Resolve(term)];
+        let mut done: Vec<Term> = Vec::new();
+        // the variables whose bindings are being substituted already
+        let mut expanding = HashSet::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Resolve(Term::Variable(v)) => match self.vars.get(&v.alias) {
+                    Some(bound) if expanding.insert(v.alias) => {
+                        tasks.push(Task::Expanded(v.alias));
+                        tasks.push(Task::Resolve(bound));
+                    }
+                    _ => done.push(Term::Variable(v.clone())),
+                },
+                Task::Resolve(Term::Functor(f)) => {
+                    tasks.push(Task::Build(f));
+                    tasks.extend(f.args.iter().rev().map(Task::Resolve));
+                }
+                Task::Resolve(term) => done.push(term.clone()),
+                Task::Build(f) => {
+                    let args = done.split_off(done.len() - f.arity());
+                    done.push(Term::Functor(Box::new(Functor::new_fact(f.name, args))));
+                }
+                Task::Expanded(alias) => {
+                    expanding.remove(&alias);
+                }
+            }
+        }
+        done.pop().expect("one term resolved")
+    }
+    /// Substitutes bindings throughout `term`, then replaces the variables
+    /// left with fresh ones, as `copy_term/2` does.
+    pub fn copy_term(&self, term: &Term) -> Term {
+        self.resolve(term).copy_fresh(&mut thread_rng())
+    }
+    /// Returns the elements of a proper list, or `None` if `term` isn't one.
+    pub fn list_to_vec(&self, term: &Term) -> Option<Vec<Term>> {
+        let mut items = Vec::new();
+        match self.list_end(term, |item| items.push(item.clone())) {
+            Term::Atom(a) if a.as_str() == "[]" => Some(items),
+            _ => None,
+        }
+    }
+    /// What's left after following the list cells of `term`, passing each
+    /// item to `item`: `[]` for a proper list, a variable for a partial one.
+    /// A list whose tail leads back into itself ends at the cell it comes
+    /// back to.
+    pub(crate) fn list_end<'a>(&'a self, term: &'a Term, mut item: impl FnMut(&Term)) -> &'a Term {
+        let mut tails: Vec<Alias> = Vec::new();
+        let (_, mut term) = self.walk_via(term);
+        while let Term::Functor(f) = term {
+            if f.name.as_str() != "." || f.arity() != 2 {
+                break;
+            }
+            item(&f.args[0]);
+            let (via, tail) = self.walk_via(&f.args[1]);
+            if let Some(via) = via {
+                if tails.contains(&via.alias) {
+                    return tail;
+                }
+                tails.push(via.alias);
+            }
+            term = tail;
+        }
+        term
+    }
+    /// How many variables are bound.
+    pub(crate) fn bound(&self) -> usize {
+        self.vars.len()
+    }
+    /// How deeply `term` nests, following bindings, or `None` if it's deeper
+    /// than `max` (as a cyclic term is). `depths` keeps the depth of each
+    /// bound variable's term, so shared subterms are only measured once.
+    pub(crate) fn depth(
+        &self,
+        term: &Term,
+        max: usize,
+        depths: &mut HashMap<Alias, Option<usize>>,
+    ) -> Option<usize> {
+        // what's left to do, last first; the depths of a compound term's
+        // arguments wait on `done` for it, so deep terms need no deep Rust
+        // stack
+        enum Task<'a> {
+            Measure(&'a Term, usize),
+            Compound(usize),
+            // done measuring the binding of a variable
+            Measured(Alias),
+        }
+        let mut tasks = vec![Task::Measure(term, max)];
+        let mut done: Vec<usize> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Measure(_, 0) => return None,
+                Task::Measure(Term::Variable(v), max) => {
+                    match (depths.get(&v.alias), self.vars.get(&v.alias)) {
+                        (Some(depth), _) => done.push(depth.filter(|depth| *depth <= max)?),
+                        (None, Some(bound)) => {
+                            // a cycle back to `v` while measuring it is too deep
+                            depths.insert(v.alias, None);
+                            tasks.push(Task::Measured(v.alias));
+                            tasks.push(Task::Measure(bound, max));
+                        }
+                        (None, None) => done.push(1),
+                    }
+                }
+                Task::Measure(Term::Functor(f), max) => {
+                    tasks.push(Task::Compound(f.arity()));
+                    tasks.extend(f.args.iter().rev().map(|arg| Task::Measure(arg, max - 1)));
+                }
+                Task::Measure(_, _) => done.push(1),
+                Task::Compound(arity) => {
+                    let deepest = done.drain(done.len() - arity..).max().unwrap_or(0);
+                    done.push(deepest + 1);
+                }
+                Task::Measured(alias) => {
+                    depths.insert(alias, done.last().copied());
+                }
+            }
+        }
+        done.pop()
+    }
+    /// How many changes there are to undo.
+    pub(crate) fn trail_len(&self) -> usize {
+        self.trail.len()
+    }
+    pub(crate) fn bind(&mut self, var: &Variable, term: Term) {
+        self.vars.insert(var.alias, term);
+        self.trail.push(Undo::Bind(var.alias));
+    }
+    pub(crate) fn record(&mut self, undo: Undo) {
+        self.trail.push(undo);
+    }
+    pub(crate) fn mark(&self) -> Mark {
+        Mark {
+            trail: self.trail.len(),
+            wakeups: self.wakeups.len(),
+        }
+    }
+    /// Takes back every change made since `mark`, newest first.
+    pub(crate) fn undo_to(&mut self, mark: Mark) {
+        self.undo(mark, drop);
+    }
+    /// Like `undo_to`, keeping the nodes of the terms variables were bound
+    /// to in `arena`.
+    pub(crate) fn undo_into(&mut self, mark: Mark, arena: &mut Arena) {
+        self.undo(mark, |term| arena.reclaim(term));
+    }
+    // undoes what was done since `mark`, handing `spare` each binding let go
+    fn undo(&mut self, mark: Mark, mut spare: impl FnMut(Term)) {
+        while self.trail.len() > mark.trail {
+            match self.trail.pop().expect("trail is longer than the mark") {
+                Undo::Bind(alias) => {
+                    if let Some(term) = self.vars.remove(&alias) {
+                        spare(term);
+                    }
+                }
+                Undo::Rebind(alias, old) => {
+                    if let Some(term) = self.vars.insert(alias, old) {
+                        spare(term);
+                    }
+                }
+                Undo::Attributes(alias, Some(old)) => {
+                    self.attributes.insert(alias, old);
+                }
+                Undo::Attributes(alias, None) => {
+                    self.attributes.remove(&alias);
+                }
+                Undo::Domain(alias, old) => self.constraints.restore_domain(alias, old),
+                Undo::Constraint => self.constraints.pop_constraint(),
+            }
+        }
+        self.wakeups.truncate(mark.wakeups);
+    }
+    // binds `var`, then wakes its attributes and checks its domain. Under
+    // `OccursCheck::Error` a binding that would make a cyclic term is left
+    // undone and a `throw/1` is queued to run before anything else
+    fn bind_checked(&mut self, var: &Variable, term: Term) -> bool {
+        if self.occurs_check != OccursCheck::Off && self.occurs(var, &term) {
+            if self.occurs_check == OccursCheck::True {
+                return false;
+            }
+            let ball = error::occurs_check(Term::Variable(var.clone()), self.resolve(&term));
+            self.wakeups
+                .push(Term::functor_from_str("throw", vec![ball]));
+            return true;
+        }
+        self.wake(var, &term);
+        self.bind(var, term);
+        self.check_domain(var)
+    }
+    /// Unifies `fst` and `snd`, as rational trees: a compound term reached
+    /// through a variable has the variable pointed at the other side before
+    /// their arguments are unified, so a cycle of bindings comes back round
+    /// to terms already unified rather than going on forever.
+    pub(crate) fn unify(&mut self, fst: &Term, snd: &Term) -> bool {
+        self.unify_pairs(vec![(Side::Given(fst), Side::Given(snd))])
+    }
+    pub(crate) fn unify_all(&mut self, fst: &[Term], snd: &[Term]) -> bool {
+        let pairs = fst.iter().map(Side::Given).zip(snd.iter().map(Side::Given));
+        fst.len() == snd.len() && self.unify_pairs(pairs.rev().collect())
+    }
+    // unifies each pair, the last first, keeping the pairs of arguments
+    // still to unify on `pending` rather than on the Rust stack, so terms
+    // nest as deeply as memory allows
+    fn unify_pairs(&mut self, mut pending: Vec<(Side<'_>, Side<'_>)>) -> bool {
+        while let Some((fst, snd)) = pending.pop() {
+            let (fst_via, fst) = self.walked(fst);
+            let (snd_via, snd) = self.walked(snd);
+            // the variable to point at the other side, and what to point it at
+            let rebound = match (&fst_via, &snd_via) {
+                (Some(x), Some(y)) if x.alias == y.alias => continue,
+                (Some(x), Some(y)) => Some((x.alias, Term::Variable(y.clone()))),
+                (Some(x), None) => Some((x.alias, snd.term().clone())),
+                (None, Some(y)) => Some((y.alias, fst.term().clone())),
+                (None, None) => None,
+            };
+            let unified = match (fst.term(), snd.term()) {
+                (Term::Variable(fst_v), Term::Variable(snd_v)) if fst_v.alias == snd_v.alias => {
+                    true
+                }
+                (Term::Variable(fst_v), Term::Variable(snd_v))
+                    if self.has_attributes(fst_v) && !self.has_attributes(snd_v) =>
+                {
+                    self.bind_checked(snd_v, Term::Variable(fst_v.clone()))
+                }
+                (Term::Variable(v), _) => self.bind_checked(v, snd.into_term()),
+                (_, Term::Variable(v)) => self.bind_checked(v, fst.into_term()),
+                (Term::Atom(fst_atom), Term::Atom(snd_atom)) => fst_atom == snd_atom,
+                (Term::Integer(fst_n), Term::Integer(snd_n)) => fst_n == snd_n,
+                (Term::String(fst_s), Term::String(snd_s)) => fst_s == snd_s,
+                (Term::Functor(fst_f), Term::Functor(snd_f)) => {
+                    if fst_f.name != snd_f.name || fst_f.arity() != snd_f.arity() {
+                        return false;
+                    }
+                    if let Some((var, term)) = rebound {
+                        self.rebind(var, term);
+                    }
+                    let (mut fst_args, mut snd_args) = (fst.into_args(), snd.into_args());
+                    while let (Some(fst), Some(snd)) = (fst_args.pop(), snd_args.pop()) {
+                        pending.push((fst, snd));
+                    }
+                    true
+                }
+                (_, _) => false,
+            };
+            if !unified {
+                return false;
+            }
+        }
+        true
+    }
+    // `side` with its bindings followed, as `walk_via` does
+    fn walked<'a>(&self, side: Side<'a>) -> (Option<Variable>, Side<'a>) {
+        match self.walk_via(side.term()) {
+            (Some(via), bound) => (Some(via.clone()), Side::Copied(bound.clone())),
+            (None, _) => (None, side),
+        }
+    }
+    // points the bound variable `alias` somewhere else
+    fn rebind(&mut self, alias: Alias, term: Term) {
+        if let Some(old) = self.vars.insert(alias, term) {
+            self.trail.push(Undo::Rebind(alias, old));
+        }
+    }
+    // whether `var` appears in `term`, other than as `term` itself
+    fn occurs(&self, var: &Variable, term: &Term) -> bool {
+        let mut pending = vec![term];
+        while let Some(term) = pending.pop() {
+            if let Term::Functor(f) = term {
+                for arg in &f.args {
+                    match self.walk(arg) {
+                        Term::Variable(v) if v.alias == var.alias => return true,
+                        arg => pending.push(arg),
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+// one side of a pair to unify: a term the caller gave, or part of one, used
+// as it is; or a copy of one reached through the bindings, which unifying
+// may change
+enum Side<'a> {
+    Given(&'a Term),
+    Copied(Term),
+}
+
+impl<'a> Side<'a> {
+    fn term(&self) -> &Term {
+        match self {
+            Side::Given(term) => term,
+            Side::Copied(term) => term,
+        }
+    }
+    fn into_term(self) -> Term {
+        match self {
+            Side::Given(term) => term.clone(),
+            Side::Copied(term) => term,
+        }
+    }
+    fn into_args(self) -> Args<'a> {
+        match self {
+            Side::Given(Term::Functor(f)) => Args::Given(&f.args),
+            Side::Copied(Term::Functor(f)) => Args::Copied(f.into_args()),
+            _ => Args::Given(&[]),
+        }
+    }
+}
+
+// the arguments of a `Side`, taken last first
+enum Args<'a> {
+    Given(&'a [Term]),
+    Copied(Vec<Term>),
+}
+
+impl<'a> Args<'a> {
+    fn pop(&mut self) -> Option<Side<'a>> {
+        match self {
+            Args::Given(args) => {
+                let (last, rest) = args.split_last()?;
+                *args = rest;
+                Some(Side::Given(last))
+            }
+            Args::Copied(args) => args.pop().map(Side::Copied),
+        }
+    }
+}
+
+/// One resolution step, recorded in the order a proof visits it.
+#[derive(Clone, Debug)]
+pub(crate) struct Step {
+    pub(crate) goal: Term,
+    pub(crate) clause: Option<Functor>,
+    pub(crate) subgoals: usize,
+    // `,` steps are spliced into their parent rather than shown
+    pub(crate) conjunction: bool,
+    // the clause's named variables and the fresh ones they were renamed to
+    pub(crate) variables: Vec<(String, Term)>,
+}
+
+impl Step {
+    // the step of a control construct, proved by proving `subgoals` goals
+    fn control(goal: Term, subgoals: usize) -> Self {
+        Step {
+            goal,
+            clause: None,
+            subgoals,
+            conjunction: false,
+            variables: Vec::new(),
+        }
+    }
+}
+
+pub(crate) type OnSolution<'s> = dyn FnMut(&Bindings, &[Step]) -> ControlFlow<()> + 's;
+
+// the goals to prove next, or `None` to backtrack
+type Next = ControlFlow<Halt, Option<Goals>>;
+
+// the goals left to prove, first first: a list each step adds its own goals
+// to the front of, sharing the rest with the choice points that go back to it
+#[derive(Clone, Default)]
+struct Goals(Option<Rc<GoalNode>>);
+
+struct GoalNode {
+    goal: Goal,
+    rest: Goals,
+}
+
+// a goal to call, or a mark the solver leaves itself among the goals, which
+// no term a query builds can stand for
+enum Goal {
+    Call(Term),
+    // Cond of the if-then-else whose Else is at this depth of the choice
+    // points has succeeded
+    Commit(usize),
+    // a solution of distinct/1,2's goal, giving its witness this value
+    Distinct(i64, Term),
+    // the goal catch/3 called has succeeded
+    ExitCatch(i64),
+    // the traced goal of this frame has succeeded
+    ExitPort(i64),
+}
+
+impl Goals {
+    fn new(goals: &[Term]) -> Self {
+        Self::default().prepend(goals.iter().cloned())
+    }
+    fn first(&self) -> Option<&Term> {
+        match &self.0.as_ref()?.goal {
+            Goal::Call(goal) => Some(goal),
+            _ => None,
+        }
+    }
+    fn split_first(&self) -> Option<(&Goal, Goals)> {
+        self.0.as_ref().map(|node| (&node.goal, node.rest.clone()))
+    }
+    fn push(self, goal: Term) -> Self {
+        self.mark(Goal::Call(goal))
+    }
+    fn mark(self, goal: Goal) -> Self {
+        Goals(Some(Rc::new(GoalNode { goal, rest: self })))
+    }
+    // `goals`, in order, then these
+    fn prepend<I>(self, goals: I) -> Self
+    where
+        I: IntoIterator<Item = Term>,
+        I::IntoIter: DoubleEndedIterator,
+    {
+        goals.into_iter().rev().fold(self, Goals::push)
+    }
+    // the goals as terms, or `None` if a mark among them ties them to this
+    // solver's choice points
+    #[cfg(feature = "parallel")]
+    fn to_vec(&self) -> Option<Vec<Term>> {
+        let mut goals = Vec::new();
+        let mut node = &self.0;
+        while let Some(next) = node {
+            match &next.goal {
+                Goal::Call(goal) => goals.push(goal.clone()),
+                _ => return None,
+            }
+            node = &next.rest.0;
+        }
+        Some(goals)
+    }
+}
+
+// a long list is let go of a node at a time, not a Rust stack frame each
+impl Drop for GoalNode {
+    fn drop(&mut self) {
+        let mut rest = self.rest.0.take();
+        while let Some(node) = rest {
+            rest = Rc::into_inner(node).and_then(|mut node| node.rest.0.take());
+        }
+    }
+}
+
+// how many calls go by between looks at the clock when there's a deadline
+#[cfg(feature = "std")]
+const DEADLINE_CHECK_INTERVAL: u32 = 256;
+
+// how many solutions a branch explored in parallel may find before the
+// consumer takes them
+#[cfg(feature = "parallel")]
+const PARALLEL_BUFFER: usize = 64;
+
+/// Why a search stopped before trying every alternative.
+#[derive(Debug)]
+pub(crate) enum Halt {
+    /// `on_solution` wanted no more solutions.
+    Done,
+    /// An exception no `catch/3` caught, with its ball.
+    Thrown(Term),
+    /// The deadline passed. Not an exception, so `catch/3` lets it through.
+    TimedOut,
+    /// A limit was hit; like `TimedOut`, not an exception.
+    ResourceExceeded(Resource),
+    /// The query's `CancellationToken` was cancelled; nor is this.
+    Cancelled,
+}
+
+/// The result of a whole query run by `flow`.
+pub(crate) fn outcome(flow: ControlFlow<Halt>) -> Result<(), SolveError> {
+    match flow {
+        ControlFlow::Break(Halt::Thrown(ball)) => Err(SolveError::Uncaught(ball)),
+        ControlFlow::Break(Halt::TimedOut) => Err(SolveError::TimedOut),
+        ControlFlow::Break(Halt::ResourceExceeded(resource)) => {
+            Err(SolveError::ResourceExceeded(resource))
+        }
+        ControlFlow::Break(Halt::Cancelled) => Err(SolveError::Cancelled),
+        _ => Ok(()),
+    }
+}
+
+// a goal the debugger has seen called that hasn't yet exited or failed
+struct Frame {
+    id: i64,
+    // the goal as it was called
+    goal: Term,
+    predicate: (Atom, Arity),
+    // where the goal was written
+    span: Option<Arc<Span>>,
+    // when it was last called or redone, if profiling
+    #[cfg(feature = "std")]
+    since: Option<Instant>,
+}
+
+// which ports the debugger is shown
+#[derive(Clone, Copy)]
+enum Mode {
+    Creep,
+    Leap,
+    // until the frame with this id exits or fails
+    Skip(i64),
+}
+
+// what backtracking goes back to: the bindings, and the steps recorded
+struct Checkpoint {
+    mark: Mark,
+    steps: usize,
+    length: usize,
+}
+
+// how long the derivations of a search under `Strategy::BreadthFirst` may
+// be, and how long those an earlier search found could be
+#[derive(Clone, Copy)]
+struct Bound {
+    limit: usize,
+    reported: usize,
+}
+
+// the length the first search under `Strategy::BreadthFirst` cuts off at
+const FIRST_LIMIT: usize = 16;
+
+// somewhere backtracking comes back to, newest on top of the stack: another
+// way for a goal to succeed, or something to undo on the way past
+enum Choice<'a> {
+    // the second branch of a disjunction
+    Branch {
+        goals: Goals,
+        step: Option<Step>,
+        at: Checkpoint,
+    },
+    Builtin(BuiltinCall),
+    Clauses(ClauseCall<'a>),
+    Iteration(Iteration),
+    // the Else of an if-then-else whose condition is running
+    Else(IfThenElse),
+    // a catch/3 call, which an exception unwinds to
+    Catch(Catch),
+    // a catch/3 whose goal succeeded, by id
+    ExitedCatch(i64),
+    // a distinct/1,2 call, by id
+    Distinct(i64),
+    // a traced goal that exited, to redo
+    Exited(Frame),
+    // a traced goal that was called, which fails once it's out of choices
+    Called,
+}
+
+// a call of a built-in or foreign predicate, with the branches it has left
+struct BuiltinCall {
+    name: Atom,
+    args: Vec<Term>,
+    alternatives: builtins::Alternatives,
+    rest: Goals,
+    at: Checkpoint,
+}
+
+// a call of a predicate's clauses, with those it has left to try
+struct ClauseCall<'a> {
+    key: Key,
+    stored: &'a [Functor],
+    slots: Slots,
+    // how many slots there were to begin with
+    clauses: usize,
+    generation: u64,
+    args: Vec<Term>,
+    rest: Goals,
+    at: Checkpoint,
+}
+
+// a maplist/N or foldl/N call, with the lengths of lists it has left to try
+struct Iteration {
+    context: Atom,
+    name: Atom,
+    args: Vec<Term>,
+    lengths: RangeInclusive<usize>,
+    rest: Goals,
+    at: Checkpoint,
+}
+
+// an if-then-else's context, condition, then and else branches
+struct IfThenElse {
+    context: Atom,
+    branches: [Term; 3],
+    rest: Goals,
+    at: Checkpoint,
+}
+
+struct Catch {
+    id: i64,
+    context: Atom,
+    // the goal, catcher and recovery
+    args: Vec<Term>,
+    rest: Goals,
+    at: Checkpoint,
+}
+
+pub(crate) struct Solver<'a> {
+    db: &'a Database,
+    rng: ThreadRng,
+    // module a built-in was called from, for qualifying the goals it runs
+    context: Atom,
+    recording: bool,
+    steps: Vec<Step>,
+    // how many goals the current derivation has proved
+    length: usize,
+    // how many searches are running: the outermost, and any started by
+    // built-ins inside it
+    searches: usize,
+    // under `Strategy::BreadthFirst`, the bound on the outermost search
+    bound: Option<Bound>,
+    // whether the search cut a derivation off at its bound
+    cut_off: bool,
+    // the solutions the bounded search has found, with their lengths
+    found: Vec<(usize, Bindings, Vec<Step>)>,
+    // the if-then-else conditions running, which aren't cut off
+    conditions: usize,
+    // set by a built-in to throw instead of failing
+    thrown: Option<Term>,
+    // where the goal about to be called was written, if it was consulted
+    called_at: Option<Arc<Span>>,
+    // ids of the catch/3 calls whose goal is running, innermost last
+    catching: Vec<i64>,
+    next_catch: i64,
+    // the answers seen by each distinct/1,2 call running
+    distinct: HashMap<i64, Seen>,
+    next_distinct: i64,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>,
+    // calls made, for checking the deadline every so often
+    #[cfg(feature = "std")]
+    calls: u32,
+    #[cfg(feature = "std")]
+    timed_out: bool,
+    // terms copied out of clauses, against `SolverConfig::max_terms`
+    terms: usize,
+    // the limit hit, if any; kept so that later calls stop too
+    exceeded: Option<Resource>,
+    // as set_prolog_flag/2 leaves them
+    flags: Flags,
+    // any of them cancelled from another thread stops the search at the
+    // next call: the query's own, and that of whoever takes its answers
+    cancellations: Vec<CancellationToken>,
+    // clauses asserted and retracted so far
+    changes: Changes,
+    // the nodes of terms the query has let go of, to build new ones out of
+    arena: Arena,
+    // calls of anything but control constructs, and clause heads tried
+    inferences: u64,
+    unifications: u64,
+    #[cfg(feature = "std")]
+    profiler: Option<Profiler>,
+    // when the query started, for statistics/2
+    clock: Clock,
+    // the goals being debugged or profiled, innermost last
+    frames: Vec<Frame>,
+    next_frame: i64,
+    mode: Mode,
+    // how many more choice points may fan out across threads
+    #[cfg(feature = "parallel")]
+    split_depth: usize,
+}
+
+impl<'a> Solver<'a> {
+    pub(crate) fn new(db: &'a Database) -> Self {
+        Self {
+            db,
+            rng: thread_rng(),
+            context: module::user(),
+            recording: false,
+            steps: Vec::new(),
+            length: 0,
+            searches: 0,
+            bound: None,
+            cut_off: false,
+            found: Vec::new(),
+            conditions: 0,
+            thrown: None,
+            called_at: None,
+            catching: Vec::new(),
+            next_catch: 0,
+            distinct: HashMap::new(),
+            next_distinct: 0,
+            #[cfg(feature = "std")]
+            deadline: db.config.timeout.map(|timeout| Instant::now() + timeout),
+            #[cfg(feature = "std")]
+            calls: 0,
+            #[cfg(feature = "std")]
+            timed_out: false,
+            terms: 0,
+            exceeded: None,
+            flags: db.config.flags,
+            cancellations: db.config.cancellation.iter().cloned().collect(),
+            changes: Changes::default(),
+            arena: Arena::default(),
+            inferences: 0,
+            unifications: 0,
+            #[cfg(feature = "std")]
+            profiler: None,
+            clock: Clock::start(),
+            frames: Vec::new(),
+            next_frame: 0,
+            mode: if db.debugging.leaping() {
+                Mode::Leap
+            } else {
+                Mode::Creep
+            },
+            #[cfg(feature = "parallel")]
+            split_depth: 0,
+        }
+    }
+    /// Explores alternative clauses on threads of their own at the first
+    /// `split_depth` choice points.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn parallel(mut self, split_depth: usize) -> Self {
+        self.split_depth = split_depth;
+        self
+    }
+    /// Stops the search with `SolveError::Cancelled` once `token` is
+    /// cancelled, as well as the query's own.
+    #[cfg(feature = "std")]
+    pub(crate) fn cancellable(mut self, token: CancellationToken) -> Self {
+        self.cancellations.push(token);
+        self
+    }
+    /// Tallies the ports each predicate passes, for `into_profiler`.
+    #[cfg(feature = "std")]
+    pub(crate) fn profiling(mut self) -> Self {
+        self.profiler = Some(Profiler::default());
+        self
+    }
+    /// The inferences and unifications made so far.
+    pub(crate) fn counts(&self) -> (u64, u64) {
+        (self.inferences, self.unifications)
+    }
+    #[cfg(feature = "std")]
+    pub(crate) fn into_profiler(self) -> Option<Profiler> {
+        self.profiler
+    }
+    pub(crate) fn clock(&mut self) -> &mut Clock {
+        &mut self.clock
+    }
+    /// Records the steps of each derivation, for building proofs.
+    pub(crate) fn recording(mut self) -> Self {
+        self.recording = true;
+        self
+    }
+    /// Proves `goals` left to right, calling `on_solution` with the bindings of
+    /// each proof. Every binding made along the way is undone before returning.
+    ///
+    /// The search keeps its goals and choice points on stacks of its own, on
+    /// the heap, so a long derivation needs no deeper Rust stack than a short
+    /// one. Only a built-in proving a goal of its own, as `findall/3` does,
+    /// starts another search inside this one.
+    pub(crate) fn solve(
+        &mut self,
+        goals: &[Term],
+        bindings: &mut Bindings,
+        on_solution: &mut OnSolution,
+    ) -> ControlFlow<Halt> {
+        #[cfg(feature = "tracing")]
+        let query = (self.searches == 0).then(|| telemetry::query(goals).entered());
+        let flow = if self.db.config.strategy == Strategy::BreadthFirst && self.searches == 0 {
+            self.deepen(goals, bindings, on_solution)
+        } else {
+            self.search(goals, bindings, on_solution, None)
+        };
+        #[cfg(feature = "tracing")]
+        if let Some(query) = query {
+            telemetry::finished(&flow, self.counts());
+            query.exit();
+        }
+        flow
+    }
+    // searches depth-first, time and again, each time cutting derivations
+    // off at twice the length, until a search cuts nothing off. Each search
+    // passes on the solutions longer than the last one's limit, shortest
+    // first, once it's done; what it asserts and retracts is forgotten
+    // before the next
+    fn deepen(
+        &mut self,
+        goals: &[Term],
+        bindings: &mut Bindings,
+        on_solution: &mut OnSolution,
+    ) -> ControlFlow<Halt> {
+        let changes = self.changes.clone();
+        let mut bound = Bound {
+            limit: FIRST_LIMIT,
+            reported: 0,
+        };
+        loop {
+            self.cut_off = false;
+            let flow = self.search(goals, bindings, on_solution, Some(bound));
+            let mut found = core::mem::take(&mut self.found);
+            found.sort_by_key(|(length, _, _)| *length);
+            for (_, solution, steps) in &found {
+                on_solution(solution, steps).map_break(|()| Halt::Done)?;
+            }
+            flow?;
+            if !self.cut_off {
+                return ControlFlow::Continue(());
+            }
+            self.changes = changes.clone();
+            bound = Bound {
+                limit: bound.limit.saturating_mul(2),
+                reported: bound.limit,
+            };
+        }
+    }
+    // the search itself, within `bound` if it's given
+    fn search(
+        &mut self,
+        goals: &[Term],
+        bindings: &mut Bindings,
+        on_solution: &mut OnSolution,
+        bound: Option<Bound>,
+    ) -> ControlFlow<Halt> {
+        self.searches += 1;
+        let outer = core::mem::replace(&mut self.bound, bound);
+        let start = self.checkpoint(bindings);
+        let mut choices = Vec::new();
+        let mut next = ControlFlow::Continue(Some(Goals::new(goals)));
+        let flow = loop {
+            next = match next {
+                ControlFlow::Continue(Some(goals)) => {
+                    self.prove(goals, &mut choices, bindings, on_solution)
+                }
+                ControlFlow::Continue(None) => match choices.pop() {
+                    Some(choice) => self.retry(choice, &mut choices, bindings),
+                    None => break ControlFlow::Continue(()),
+                },
+                ControlFlow::Break(Halt::Thrown(ball)) => {
+                    match self.catch_thrown(ball, &mut choices, bindings) {
+                        Ok(goals) => ControlFlow::Continue(Some(goals)),
+                        Err(ball) => break ControlFlow::Break(Halt::Thrown(ball)),
+                    }
+                }
+                ControlFlow::Break(halt) => break ControlFlow::Break(halt),
+            };
+        };
+        while let Some(choice) = choices.pop() {
+            self.discard(choice);
+        }
+        self.back_to(&start, bindings);
+        self.bound = outer;
+        self.searches -= 1;
+        flow
+    }
+    // proves the first of `goals`, giving the goals to prove next, or `None`
+    // if it fails; its other ways to succeed go on `choices`
+    fn prove(
+        &mut self,
+        goals: Goals,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+        on_solution: &mut OnSolution,
+    ) -> Next {
+        if self.out_of_time() {
+            return ControlFlow::Break(Halt::TimedOut);
+        }
+        if let Some(resource) = self.over_limit(goals.first(), bindings) {
+            return ControlFlow::Break(Halt::ResourceExceeded(resource));
+        }
+        if self
+            .cancellations
+            .iter()
+            .any(CancellationToken::is_cancelled)
+        {
+            return ControlFlow::Break(Halt::Cancelled);
+        }
+        let Some((goal, rest)) = goals.split_first() else {
+            match self.bound {
+                None => on_solution(bindings, &self.steps).map_break(|()| Halt::Done)?,
+                // an earlier search found those no longer than its limit
+                Some(bound) if self.length > bound.reported => {
+                    let solution = (self.length, bindings.clone(), self.steps.clone());
+                    self.found.push(solution);
+                }
+                Some(_) => {}
+            }
+            return ControlFlow::Continue(None);
+        };
+        if self.conditions == 0 && self.bound.is_some_and(|bound| self.length >= bound.limit) {
+            self.cut_off = true;
+            return ControlFlow::Continue(None);
+        }
+        self.length += 1;
+        let goal = match goal {
+            Goal::Call(goal) => goal,
+            Goal::Commit(depth) => return self.commit(*depth, rest, choices, bindings),
+            Goal::Distinct(id, answer) => {
+                let answer = bindings.resolve(answer);
+                let unseen = self
+                    .distinct
+                    .get_mut(id)
+                    .is_some_and(|seen| seen.insert(answer));
+                return ControlFlow::Continue(unseen.then_some(rest));
+            }
+            Goal::ExitCatch(id) => {
+                // the caught goal succeeded, so exceptions from here on aren't
+                // its own, until backtracking goes back into it
+                self.catching.retain(|c| c != id);
+                choices.push(Choice::ExitedCatch(*id));
+                return ControlFlow::Continue(Some(rest));
+            }
+            Goal::ExitPort(id) => return self.exit_port(*id, rest, choices, bindings),
+        };
+        let (context, goal) = unqualify(bindings, goal);
+        if self.db.tracing {
+            let call = bindings.resolve(&qualify(&context, goal.clone()));
+            #[cfg(feature = "std")]
+            eprintln!("call: {}", call);
+            // without stderr, the trace goes where the query prints
+            #[cfg(not(feature = "std"))]
+            let _ = self.output().print(&alloc::format!("call: {}\n", call));
+        }
+        let (name, args, span) = match goal {
+            Term::Atom(a) => (a, Vec::new(), None),
+            Term::Functor(f) => self.arena.take_apart(f),
+            Term::Variable(_) => {
+                return ControlFlow::Break(Halt::Thrown(error::instantiation_error()))
+            }
+            Term::Integer(_) | Term::String(_) => {
+                return ControlFlow::Break(Halt::Thrown(error::type_error("callable", goal)))
+            }
+        };
+        if name.as_str() == "," && args.len() == 2 {
+            let step = self.record(|| Step {
+                conjunction: true,
+                ..Step::control(goal_term(&name, &args), 2)
+            });
+            let goals = rest.prepend(args.into_iter().map(|g| qualify(&context, g)));
+            return ControlFlow::Continue(Some(self.descend(step, goals, bindings)));
+        }
+        if name.as_str() == ";" && args.len() == 2 {
+            if let Term::Functor(f) = bindings.walk(&args[0]) {
+                if f.name.as_str() == "->" && f.arity() == 2 {
+                    let branches = [f.args[0].clone(), f.args[1].clone(), args[1].clone()];
+                    let goals = self.if_then_else(context, branches, rest, choices, bindings);
+                    return ControlFlow::Continue(Some(goals));
+                }
+            }
+            let step = self.record(|| Step::control(goal_term(&name, &args), 1));
+            let [first, second]: [Term; 2] = args.try_into().expect("two branches");
+            choices.push(Choice::Branch {
+                goals: rest.clone().push(qualify(&context, second)),
+                step: step.clone(),
+                at: self.checkpoint(bindings),
+            });
+            let goals = rest.push(qualify(&context, first));
+            return ControlFlow::Continue(Some(self.descend(step, goals, bindings)));
+        }
+        if name.as_str() == "->" && args.len() == 2 {
+            let [cond, then]: [Term; 2] = args.try_into().expect("two branches");
+            let branches = [cond, then, Term::atom_from_str("fail")];
+            let goals = self.if_then_else(context, branches, rest, choices, bindings);
+            return ControlFlow::Continue(Some(goals));
+        }
+        if name.as_str() == "call" && !args.is_empty() {
+            let called = match with_args(bindings, &args[0], args[1..].to_vec()) {
+                Ok(called) => called,
+                Err(ball) => return ControlFlow::Break(Halt::Thrown(ball)),
+            };
+            let step = self.record(|| Step::control(goal_term(&name, &args), 1));
+            let goals = rest.push(qualify(&context, called));
+            return ControlFlow::Continue(Some(self.descend(step, goals, bindings)));
+        }
+        if name.as_str() == ">>" && args.len() >= 2 {
+            return self.lambda(&context, &args, rest, bindings);
+        }
+        if matches!(
+            (name.as_str(), args.len()),
+            ("maplist", 2..=5) | ("foldl", 4..=6)
+        ) {
+            return self.iterate(context, name, args, rest, choices, bindings);
+        }
+        if name.as_str() == "distinct" && matches!(args.len(), 1 | 2) {
+            return self.distinct(&context, &args, rest, choices, bindings);
+        }
+        if name.as_str() == "catch" && args.len() == 3 {
+            return self.catch(context, args, rest, choices, bindings);
+        }
+        self.called_at = span;
+        if self.watching_ports() {
+            return self.traced(context, name, args, rest, choices, bindings, on_solution);
+        }
+        self.call(context, name, args, rest, choices, bindings, on_solution)
+    }
+    // runs a goal that isn't a control construct: a built-in, a foreign
+    // predicate or the clauses of a predicate; `on_solution` takes the
+    // solutions of a call explored in parallel
+    #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+    fn call(
+        &mut self,
+        context: Atom,
+        name: Atom,
+        args: Vec<Term>,
+        rest: Goals,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+        on_solution: &mut OnSolution,
+    ) -> Next {
+        let span = self.called_at.take();
+        self.inferences += 1;
+        let alternatives = if let Some(builtin) = builtins::lookup(&name, args.len()) {
+            let caller = core::mem::replace(&mut self.context, context);
+            let alternatives = builtin(self, &args, bindings);
+            self.context = caller;
+            if let Some(ball) = self.thrown.take() {
+                let ball = error::in_context(ball, &name, args.len(), span.as_deref());
+                return ControlFlow::Break(Halt::Thrown(ball));
+            }
+            Some(alternatives)
+        } else {
+            self.db.foreign(&name, args.len()).map(|predicate| {
+                let rows = predicate(&args, bindings);
+                let args = args.clone();
+                Box::new(rows.filter_map(move |row| {
+                    (row.len() == args.len())
+                        .then(|| builtins::unify(args.iter().cloned().zip(row).collect()))
+                })) as builtins::Alternatives
+            })
+        };
+        if let Some(alternatives) = alternatives {
+            let call = BuiltinCall {
+                name,
+                args,
+                alternatives,
+                rest,
+                at: self.checkpoint(bindings),
+            };
+            return self.next_branch(call, choices, bindings);
+        }
+        let db = self.db;
+        let arity = args.len();
+        let Some(home) = self.changes.home(db, &context, &name, arity) else {
+            if self.flags.unknown == Unknown::Error {
+                let ball =
+                    error::existence_error("procedure", error::indicator(name.as_str(), arity));
+                return ControlFlow::Break(Halt::Thrown(ball));
+            }
+            return ControlFlow::Continue(None);
+        };
+        let stored = db
+            .modules
+            .get(&home)
+            .map_or(&[][..], |m| m.predicate(&name, arity));
+        #[cfg(feature = "parallel")]
+        if let Some(rest) = (self.split_depth > 0
+            && self.bound.is_none()
+            && self.changes.is_empty()
+            && self.distinct.is_empty()
+            && stored.len() > 1)
+            .then(|| rest.to_vec())
+            .flatten()
+        {
+            let clauses: Vec<&Functor> = stored.iter().collect();
+            return self
+                .solve_parallel(&clauses, &home, &args, &rest, bindings, on_solution)
+                .map_continue(|()| None);
+        }
+        // clauses asserted or retracted from here on don't change which
+        // clauses this call tries
+        let key = (home, name, arity);
+        let slots = self.changes.slots(&key, stored.len());
+        let call = ClauseCall {
+            clauses: slots.len(),
+            slots,
+            generation: self.changes.generation(),
+            key,
+            stored,
+            args,
+            rest,
+            at: self.checkpoint(bindings),
+        };
+        self.next_clause(call, choices, bindings)
+    }
+    // goes on after the next of a built-in's branches that succeeds, if any
+    fn next_branch(
+        &mut self,
+        mut call: BuiltinCall,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+    ) -> Next {
+        self.back_to(&call.at, bindings);
+        while let Some(branch) = call.alternatives.next() {
+            if !branch(bindings) {
+                bindings.undo_into(call.at.mark, &mut self.arena);
+                continue;
+            }
+            let step = self.record(|| Step::control(goal_term(&call.name, &call.args), 0));
+            let goals = self.descend(step, call.rest.clone(), bindings);
+            // a built-in that succeeds once leaves no choice point
+            if call.alternatives.size_hint().1 != Some(0) {
+                choices.push(Choice::Builtin(call));
+            } else {
+                self.arena.give(call.args);
+            }
+            return ControlFlow::Continue(Some(goals));
+        }
+        self.arena.give(call.args);
+        ControlFlow::Continue(None)
+    }
+    // goes on with the body of the next clause whose head unifies, if any
+    fn next_clause(
+        &mut self,
+        mut call: ClauseCall<'a>,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+    ) -> Next {
+        self.back_to(&call.at, bindings);
+        let depth = self.frames.len();
+        while let Some(slot) = call.slots.next() {
+            let visible = self
+                .changes
+                .visible(&call.key, slot, call.stored, call.generation);
+            let Some(clause) = visible else {
+                continue;
+            };
+            let (module, name, arity) = call.key;
+            let id = ClauseId {
+                module,
+                name,
+                arity,
+                index: call.clauses - call.slots.len() - 1,
+            };
+            self.observe(Event::TryClause { depth, clause: id });
+            if self.db.config.max_terms.is_some() {
+                self.terms += stats::functor_terms(&clause);
+            }
+            // the body is only copied once the head unifies; the bindings
+            // keep copies of what they need of the head
+            let mut renamed = HashMap::new();
+            let head = self.arena.copy(&clause, &mut self.rng, &mut renamed);
+            self.unifications += 1;
+            let unified = bindings.unify_all(&head.args, &call.args);
+            self.arena.reclaim(Term::Functor(head));
+            if !unified {
+                bindings.undo_into(call.at.mark, &mut self.arena);
+                self.observe(Event::NotUnified { depth, clause: id });
+                continue;
+            }
+            self.observe(Event::Unified { depth, clause: id });
+            let body = fresh_body(
+                &call.key.0,
+                &clause,
+                &mut self.rng,
+                &mut renamed,
+                &mut self.arena,
+            );
+            let step = self.record(|| Step {
+                clause: Some(clause.clone().into_owned()),
+                variables: clause_variables(&clause, &renamed),
+                ..Step::control(goal_term(&call.key.1, &call.args), clause.body.len())
+            });
+            let goals = call.rest.clone().prepend(body);
+            let goals = self.descend(step, goals, bindings);
+            // the last clause leaves no choice point, so a deterministic
+            // recursion keeps none for each level
+            if call.slots.len() > 0 {
+                choices.push(Choice::Clauses(call));
+            } else {
+                self.arena.give(call.args);
+            }
+            return ControlFlow::Continue(Some(goals));
+        }
+        self.arena.give(call.args);
+        ControlFlow::Continue(None)
+    }
+    // backtracks into `choice`: tries its next alternative, or undoes what it
+    // stands for and goes on failing
+    fn retry(
+        &mut self,
+        choice: Choice<'a>,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+    ) -> Next {
+        let depth = match choice {
+            Choice::Builtin(_) | Choice::Clauses(_) => Some(self.frames.len()),
+            Choice::Branch { .. } | Choice::Iteration(_) | Choice::Else(_) => {
+                Some(self.frames.len() + 1)
+            }
+            _ => None,
+        };
+        if let Some(depth) = depth {
+            self.observe(Event::Backtrack { depth });
+        }
+        match choice {
+            Choice::Branch { goals, step, at } => {
+                self.back_to(&at, bindings);
+                ControlFlow::Continue(Some(self.descend(step, goals, bindings)))
+            }
+            Choice::Builtin(call) => self.next_branch(call, choices, bindings),
+            Choice::Clauses(call) => self.next_clause(call, choices, bindings),
+            Choice::Iteration(iteration) => self.next_length(iteration, choices, bindings),
+            Choice::Else(ite) => {
+                self.conditions -= 1;
+                self.back_to(&ite.at, bindings);
+                let step = self.record(|| ite_step(&ite.branches));
+                let [_, _, otherwise] = ite.branches;
+                let goals = ite.rest.push(qualify(&ite.context, otherwise));
+                ControlFlow::Continue(Some(self.descend(step, goals, bindings)))
+            }
+            Choice::Catch(catch) => {
+                self.catching.retain(|c| *c != catch.id);
+                ControlFlow::Continue(None)
+            }
+            Choice::ExitedCatch(id) => {
+                self.catching.push(id);
+                ControlFlow::Continue(None)
+            }
+            Choice::Distinct(id) => {
+                self.distinct.remove(&id);
+                ControlFlow::Continue(None)
+            }
+            Choice::Exited(frame) => {
+                self.frames.push(frame);
+                self.port(Port::Redo, bindings)?;
+                ControlFlow::Continue(None)
+            }
+            Choice::Called => {
+                let flow = self.port(Port::Fail, bindings);
+                self.frames.pop();
+                flow?;
+                ControlFlow::Continue(None)
+            }
+        }
+    }
+    // drops `choice` without trying its alternatives, as when the search
+    // halts or commits to a condition's first solution
+    fn discard(&mut self, choice: Choice<'a>) {
+        match choice {
+            Choice::Catch(catch) => self.catching.retain(|c| *c != catch.id),
+            Choice::Distinct(id) => {
+                self.distinct.remove(&id);
+            }
+            Choice::Exited(frame) => self.frames.push(frame),
+            Choice::Called => {
+                self.frames.pop();
+            }
+            Choice::Else(_) => self.conditions -= 1,
+            _ => {}
+        }
+    }
+    // unwinds to the innermost catch/3 whose goal is running and whose
+    // catcher unifies with `ball`, giving its recovery and the goals after
+    // it; or, if there's none, gives back the ball
+    fn catch_thrown(
+        &mut self,
+        ball: Term,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+    ) -> Result<Goals, Term> {
+        while let Some(choice) = choices.pop() {
+            let Choice::Catch(catch) = choice else {
+                self.discard(choice);
+                continue;
+            };
+            let running = self.catching.contains(&catch.id);
+            self.catching.retain(|c| *c != catch.id);
+            if !running {
+                continue;
+            }
+            self.back_to(&catch.at, bindings);
+            if !bindings.unify(&catch.args[1], &ball) {
+                bindings.undo_into(catch.at.mark, &mut self.arena);
+                continue;
+            }
+            let step =
+                self.record(|| Step::control(goal_term(&Atom::new("catch"), &catch.args), 1));
+            let recovery = qualify(&catch.context, catch.args[2].clone());
+            return Ok(self.descend(step, catch.rest.push(recovery), bindings));
+        }
+        Err(ball)
+    }
+    // each clause's branch runs on a thread and solver of its own, as many
+    // at once as there are cores, and sends its solutions down a bounded
+    // channel; they're passed on in clause order, as the sequential search
+    // would find them, while the branches after get only so far ahead. The
+    // branches watch the query's cancellation tokens and one of their own,
+    // cancelled once the consumer wants no more or a branch halts. Only taken
+    // while the query hasn't changed the database and isn't inside
+    // distinct/1,2, and changes a branch makes don't outlive it
+    #[cfg(feature = "parallel")]
+    fn solve_parallel(
+        &mut self,
+        clauses: &[&'a Functor],
+        module: &Atom,
+        args: &[Term],
+        rest: &[Term],
+        bindings: &Bindings,
+        on_solution: &mut OnSolution,
+    ) -> ControlFlow<Halt> {
+        use std::collections::VecDeque;
+        use std::sync::mpsc;
+        let stop = CancellationToken::new();
+        let mut cancellations = self.cancellations.clone();
+        cancellations.push(stop.clone());
+        let (db, split_depth, deadline, terms, exceeded, flags) = (
+            self.db,
+            self.split_depth - 1,
+            self.deadline,
+            self.terms,
+            self.exceeded,
+            self.flags,
+        );
+        let width = std::thread::available_parallelism().map_or(1, usize::from);
+        std::thread::scope(|scope| {
+            let start = |clause: &'a Functor| {
+                let (sender, solutions) = mpsc::sync_channel(PARALLEL_BUFFER);
+                let cancellations = cancellations.clone();
+                let branch = scope.spawn(move || {
+                    // trying the head counts as `next_clause` counts it
+                    let copied = match db.config.max_terms {
+                        Some(_) => stats::functor_terms(clause),
+                        None => 0,
+                    };
+                    let mut next = bindings.clone();
+                    let mut solver = Solver::new(db).parallel(split_depth);
+                    let mut renamed = HashMap::new();
+                    let head = solver.arena.copy(clause, &mut solver.rng, &mut renamed);
+                    if !next.unify_all(&head.args, args) {
+                        return (None, (0, 1), copied);
+                    }
+                    let mut goals = next.take_wakeups();
+                    goals.extend(fresh_body(
+                        module,
+                        clause,
+                        &mut solver.rng,
+                        &mut renamed,
+                        &mut solver.arena,
+                    ));
+                    goals.extend_from_slice(rest);
+                    solver.deadline = deadline;
+                    solver.terms = terms + copied;
+                    solver.exceeded = exceeded;
+                    solver.flags = flags;
+                    solver.cancellations = cancellations;
+                    // the consumer has gone once the receiving end is dropped
+                    let flow = solver.solve(&goals, &mut next, &mut |solution, _| {
+                        match sender.send(solution.clone()) {
+                            Ok(()) => ControlFlow::Continue(()),
+                            Err(_) => ControlFlow::Break(()),
+                        }
+                    });
+                    let (inferences, unifications) = solver.counts();
+                    (
+                        flow.break_value(),
+                        (inferences, unifications + 1),
+                        solver.terms - terms,
+                    )
+                });
+                (solutions, branch)
+            };
+            let mut clauses = clauses.iter().copied();
+            let mut running: VecDeque<_> = clauses.by_ref().take(width).map(start).collect();
+            let mut flow = ControlFlow::Continue(());
+            while let Some((solutions, branch)) = running.pop_front() {
+                if flow.is_continue() {
+                    flow = solutions
+                        .iter()
+                        .try_for_each(|solution| on_solution(&solution, &self.steps))
+                        .map_break(|()| Halt::Done);
+                }
+                // a branch blocked on a full channel sees it closed, and a
+                // running one the token, before it's waited for
+                if flow.is_break() {
+                    stop.cancel();
+                }
+                drop(solutions);
+                let (halt, (inferences, unifications), copied) = branch
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                self.inferences += inferences;
+                self.unifications += unifications;
+                self.terms += copied;
+                if let (ControlFlow::Continue(()), Some(halt)) = (&flow, halt) {
+                    if let Halt::ResourceExceeded(resource) = halt {
+                        self.exceeded = Some(resource);
+                    }
+                    stop.cancel();
+                    flow = ControlFlow::Break(halt);
+                }
+                if flow.is_continue() {
+                    running.extend(clauses.next().map(start));
+                }
+            }
+            flow
+        })
+    }
+    // `goals`, after any attribute hooks woken by the step's unifications,
+    // recording `step` on the way
+    fn descend(&mut self, step: Option<Step>, goals: Goals, bindings: &mut Bindings) -> Goals {
+        let woken = bindings.take_wakeups();
+        if let Some(mut step) = step {
+            step.subgoals += woken.len();
+            self.steps.push(step);
+        }
+        goals.prepend(woken)
+    }
+    // the step made, if proofs are wanted
+    fn record(&self, step: impl FnOnce() -> Step) -> Option<Step> {
+        self.recording.then(step)
+    }
+    fn checkpoint(&self, bindings: &Bindings) -> Checkpoint {
+        Checkpoint {
+            mark: bindings.mark(),
+            steps: self.steps.len(),
+            length: self.length,
+        }
+    }
+    fn back_to(&mut self, at: &Checkpoint, bindings: &mut Bindings) {
+        bindings.undo_into(at.mark, &mut self.arena);
+        self.steps.truncate(at.steps);
+        self.length = at.length;
+    }
+    // `call` with the debugger shown the goal's ports: `$exit_port` after
+    // the goal marks where each solution leaves it
+    #[allow(clippy::too_many_arguments)]
+    fn traced(
+        &mut self,
+        context: Atom,
+        name: Atom,
+        args: Vec<Term>,
+        rest: Goals,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+        on_solution: &mut OnSolution,
+    ) -> Next {
+        let frame = Frame {
+            id: self.next_frame,
+            goal: bindings.resolve(&qualify(&context, goal_term(&name, &args))),
+            predicate: (name, args.len()),
+            span: self.called_at.clone(),
+            #[cfg(feature = "std")]
+            since: None,
+        };
+        self.next_frame += 1;
+        let exit = Goal::ExitPort(frame.id);
+        self.frames.push(frame);
+        choices.push(Choice::Called);
+        if let (Some(observer), Some(frame)) = (&self.db.debugging.observer, self.frames.last()) {
+            let depth = self.frames.len();
+            observer.event(&Event::Call {
+                depth,
+                goal: &frame.goal,
+            });
+        }
+        self.port(Port::Call, bindings)?;
+        self.call(
+            context,
+            name,
+            args,
+            rest.mark(exit),
+            choices,
+            bindings,
+            on_solution,
+        )
+    }
+    // a solution leaves the innermost goal; backtracking goes back into it
+    fn exit_port(
+        &mut self,
+        id: i64,
+        rest: Goals,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &Bindings,
+    ) -> Next {
+        debug_assert_eq!(self.frames.last().map(|f| f.id), Some(id));
+        self.port(Port::Exit, bindings)?;
+        let frame = self.frames.pop().expect("exit of a traced goal");
+        choices.push(Choice::Exited(frame));
+        ControlFlow::Continue(Some(rest))
+    }
+    // whether calls go through `traced`, for the debugger, the observer or
+    // the profiler
+    fn watching_ports(&self) -> bool {
+        #[cfg(feature = "std")]
+        if self.profiler.is_some() {
+            return true;
+        }
+        let debugging = &self.db.debugging;
+        debugging.debugger.is_some() || debugging.observer.is_some()
+    }
+    // tells the observer, if there is one, of `event`, and traces it
+    fn observe(&self, event: Event) {
+        #[cfg(feature = "tracing")]
+        telemetry::event(&event);
+        if let Some(observer) = &self.db.debugging.observer {
+            observer.event(&event);
+        }
+    }
+    // shows the innermost goal's port, unless leaping or skipping past it,
+    // and tallies it if profiling
+    fn port(&mut self, port: Port, bindings: &Bindings) -> ControlFlow<Halt> {
+        #[cfg(feature = "std")]
+        if let (Some(profiler), Some(frame)) = (&mut self.profiler, self.frames.last_mut()) {
+            let now = Instant::now();
+            profiler.port(frame.predicate, port, *frame.since.get_or_insert(now));
+            if matches!(port, Port::Call | Port::Redo) {
+                frame.since = Some(now);
+            }
+        }
+        let debugging = &self.db.debugging;
+        let (Some(debugger), Some(frame)) = (&debugging.debugger, self.frames.last()) else {
+            return ControlFlow::Continue(());
+        };
+        let shown = match self.mode {
+            Mode::Creep => true,
+            Mode::Leap => debugging.spied(&frame.predicate.0, frame.predicate.1),
+            Mode::Skip(id) => id == frame.id && matches!(port, Port::Exit | Port::Fail),
+        };
+        if !shown {
+            return ControlFlow::Continue(());
+        }
+        // the goal as called, except at exit, where it's as proved
+        let goal = match port {
+            Port::Exit => bindings.resolve(&frame.goal),
+            _ => frame.goal.clone(),
+        };
+        let goal = match goal {
+            Term::Functor(f) => Term::Functor(Box::new(f.located(frame.span.clone()))),
+            goal => goal,
+        };
+        self.mode = match debugger.port(port, self.frames.len(), &goal) {
+            Command::Creep => Mode::Creep,
+            Command::Leap => Mode::Leap,
+            Command::Skip if matches!(port, Port::Call | Port::Redo) => Mode::Skip(frame.id),
+            Command::Skip => Mode::Creep,
+            Command::Abort => return ControlFlow::Break(Halt::Done),
+        };
+        ControlFlow::Continue(())
+    }
+    // catch(Goal, Catcher, Recovery): an exception thrown while Goal is
+    // running unwinds to the bindings catch/3 was called with, and if it
+    // unifies with Catcher, Recovery runs in Goal's place
+    fn catch(
+        &mut self,
+        context: Atom,
+        args: Vec<Term>,
+        rest: Goals,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+    ) -> Next {
+        let id = self.next_catch;
+        self.next_catch += 1;
+        let step = self.record(|| Step::control(goal_term(&Atom::new("catch"), &args), 1));
+        let goals = rest
+            .clone()
+            .mark(Goal::ExitCatch(id))
+            .push(qualify(&context, args[0].clone()));
+        self.catching.push(id);
+        choices.push(Choice::Catch(Catch {
+            id,
+            context,
+            args,
+            rest,
+            at: self.checkpoint(bindings),
+        }));
+        ControlFlow::Continue(Some(self.descend(step, goals, bindings)))
+    }
+    // distinct(Goal) and distinct(Witness, Goal): the solutions of Goal,
+    // leaving out those giving Witness (or Goal) a value it's had already
+    fn distinct(
+        &mut self,
+        context: &Atom,
+        args: &[Term],
+        rest: Goals,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+    ) -> Next {
+        let (witness, goal) = match args {
+            [goal] => (goal, goal),
+            [witness, goal, ..] => (witness, goal),
+            [] => unreachable!("distinct/1,2 has arguments"),
+        };
+        let id = self.next_distinct;
+        self.next_distinct += 1;
+        self.distinct.insert(id, Seen::default());
+        choices.push(Choice::Distinct(id));
+        let step = self.record(|| Step::control(goal_term(&Atom::new("distinct"), args), 1));
+        let check = Goal::Distinct(id, witness.clone());
+        let goals = rest.mark(check).push(qualify(context, goal.clone()));
+        ControlFlow::Continue(Some(self.descend(step, goals, bindings)))
+    }
+    // Params>>Body, called with the arguments call/N added after the first
+    // two: a copy of the lambda, sharing only the variables of Free if it's
+    // written Free/Params>>Body, has its parameters unified with the
+    // arguments, and any arguments left over are added to its body
+    fn lambda(
+        &mut self,
+        context: &Atom,
+        args: &[Term],
+        rest: Goals,
+        bindings: &mut Bindings,
+    ) -> Next {
+        let mut renamed = HashMap::new();
+        let params = match bindings.walk(&args[0]) {
+            Term::Functor(f) if f.name.as_str() == "/" && f.arity() == 2 => {
+                let mut free = Vec::new();
+                builtins::term_variables(&bindings.resolve(&f.args[0]), &mut free);
+                for var in free {
+                    renamed.insert(var.alias, Term::Variable(var));
+                }
+                &f.args[1]
+            }
+            params => params,
+        };
+        let params = self.rename(&bindings.resolve(params), &mut renamed);
+        let body = self.rename(&bindings.resolve(&args[1]), &mut renamed);
+        let Some(params) = bindings.list_to_vec(&params) else {
+            let ball = error::type_error("list", params);
+            return ControlFlow::Break(Halt::Thrown(ball));
+        };
+        let actual = &args[2..];
+        let bound = params.len().min(actual.len());
+        if !bindings.unify_all(&params[..bound], &actual[..bound]) {
+            return ControlFlow::Continue(None);
+        }
+        let body = match with_args(bindings, &body, actual[bound..].to_vec()) {
+            Ok(body) => body,
+            Err(ball) => return ControlFlow::Break(Halt::Thrown(ball)),
+        };
+        let step = self.record(|| Step::control(goal_term(&Atom::new(">>"), args), 1));
+        let goals = rest.push(qualify(context, body));
+        ControlFlow::Continue(Some(self.descend(step, goals, bindings)))
+    }
+    // maplist(G, L1, ..., Ln) and foldl(G, L1, ..., Ln, V0, V): calls G on
+    // the items at each position of the lists, in order, threading the
+    // accumulator through for foldl. Once one list's length is known the
+    // others are unified with lists of that many fresh items and the calls
+    // are made as one conjunction; while none is, every length from the
+    // longest partial list up is tried in turn
+    fn iterate(
+        &mut self,
+        context: Atom,
+        name: Atom,
+        args: Vec<Term>,
+        rest: Goals,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+    ) -> Next {
+        let (mut shortest, mut known) = (0, None);
+        for list in lists(&name, &args) {
+            let mut items = 0;
+            match bindings.list_end(list, |_| items += 1) {
+                Term::Variable(_) => shortest = shortest.max(items),
+                Term::Atom(a) if a.as_str() == "[]" && known.is_none_or(|n| n == items) => {
+                    known = Some(items)
+                }
+                _ => return ControlFlow::Continue(None),
+            }
+        }
+        let lengths = match known {
+            Some(length) if length < shortest => return ControlFlow::Continue(None),
+            Some(length) => length..=length,
+            None => shortest..=usize::MAX,
+        };
+        let iteration = Iteration {
+            context,
+            name,
+            args,
+            lengths,
+            rest,
+            at: self.checkpoint(bindings),
+        };
+        self.next_length(iteration, choices, bindings)
+    }
+    // goes on with maplist/N or foldl/N's calls for the next length of lists
+    fn next_length(
+        &mut self,
+        mut iteration: Iteration,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+    ) -> Next {
+        self.back_to(&iteration.at, bindings);
+        let Some(length) = iteration.lengths.next() else {
+            return ControlFlow::Continue(None);
+        };
+        let (name, args) = (&iteration.name, &iteration.args);
+        let lists = lists(name, args);
+        let mut columns = Vec::with_capacity(lists.len());
+        for list in lists {
+            let items: Vec<Term> = (0..length)
+                .map(|_| Term::Variable(Variable::new_anonymous(&mut self.rng)))
+                .collect();
+            if !bindings.unify(list, &Term::list_from_vec(items.clone())) {
+                break;
+            }
+            columns.push(items);
+        }
+        let mut accumulators = Vec::new();
+        if name.as_str() == "foldl" {
+            accumulators.push(args[args.len() - 2].clone());
+            accumulators.extend(
+                (1..length).map(|_| Term::Variable(Variable::new_anonymous(&mut self.rng))),
+            );
+            accumulators.push(args[args.len() - 1].clone());
+            if length == 0 && !bindings.unify(&accumulators[0], &accumulators[1]) {
+                columns.clear();
+            }
+        }
+        if columns.len() < lists.len() {
+            return ControlFlow::Continue(None);
+        }
+        let mut goals = Vec::with_capacity(length);
+        for i in 0..length {
+            let mut extra: Vec<Term> = columns.iter().map(|items| items[i].clone()).collect();
+            extra.extend(accumulators.get(i..i + 2).into_iter().flatten().cloned());
+            match with_args(bindings, &args[0], extra) {
+                Ok(goal) => goals.push(qualify(&iteration.context, goal)),
+                Err(ball) => return ControlFlow::Break(Halt::Thrown(ball)),
+            }
+        }
+        let step = self.record(|| Step {
+            conjunction: true,
+            ..Step::control(goal_term(name, args), length)
+        });
+        let goals = iteration.rest.clone().prepend(goals);
+        let goals = self.descend(step, goals, bindings);
+        if !iteration.lengths.is_empty() {
+            choices.push(Choice::Iteration(iteration));
+        }
+        ControlFlow::Continue(Some(goals))
+    }
+    // (Cond -> Then ; Else): Cond runs with Else as a choice point under it;
+    // the commit mark after Cond takes its first solution and goes on with Then,
+    // while if it has none, backtracking comes to Else
+    fn if_then_else(
+        &mut self,
+        context: Atom,
+        branches: [Term; 3],
+        rest: Goals,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &Bindings,
+    ) -> Goals {
+        let goals = rest
+            .clone()
+            .push(qualify(&context, branches[1].clone()))
+            .mark(Goal::Commit(choices.len()))
+            .push(qualify(&context, branches[0].clone()));
+        self.conditions += 1;
+        choices.push(Choice::Else(IfThenElse {
+            context,
+            branches,
+            rest,
+            at: self.checkpoint(bindings),
+        }));
+        goals
+    }
+    // drops the choice points left by an if-then-else's condition, the Else
+    // among them, and the steps proving it
+    fn commit(
+        &mut self,
+        depth: usize,
+        rest: Goals,
+        choices: &mut Vec<Choice<'a>>,
+        bindings: &mut Bindings,
+    ) -> Next {
+        for choice in choices.drain(depth + 1..).rev() {
+            self.discard(choice);
+        }
+        let Some(Choice::Else(ite)) = choices.pop() else {
+            unreachable!("a commit mark under its if-then-else's choice point")
+        };
+        self.conditions -= 1;
+        self.steps.truncate(ite.at.steps);
+        let step = self.record(|| ite_step(&ite.branches));
+        ControlFlow::Continue(Some(self.descend(step, rest, bindings)))
+    }
+    // whether the deadline has passed, looking at the clock only every so
+    // often; once it has, every later call sees it too, so a built-in that
+    // drops the `Halt` of a goal it ran can't carry on past it
+    #[cfg(feature = "std")]
+    fn out_of_time(&mut self) -> bool {
+        let Some(deadline) = self.deadline else {
+            return false;
+        };
+        self.calls = self.calls.wrapping_add(1);
+        if !self.timed_out && self.calls.is_multiple_of(DEADLINE_CHECK_INTERVAL) {
+            self.timed_out = Instant::now() >= deadline;
+        }
+        self.timed_out
+    }
+    // there's no clock to keep a deadline by
+    #[cfg(not(feature = "std"))]
+    fn out_of_time(&mut self) -> bool {
+        false
+    }
+    // the first limit the query has gone past, checking `goal` against the
+    // depth limit; once one is hit, every later call sees it too
+    fn over_limit(&mut self, goal: Option<&Term>, bindings: &Bindings) -> Option<Resource> {
+        let config = &self.db.config;
+        let too_deep = |max: usize| {
+            let Some(Term::Functor(goal)) = goal.map(|goal| bindings.walk(goal)) else {
+                return false;
+            };
+            let mut depths = HashMap::new();
+            goal.args
+                .iter()
+                .any(|arg| bindings.depth(arg, max, &mut depths).is_none())
+        };
+        if self.exceeded.is_none() {
+            self.exceeded = if config
+                .max_bindings
+                .is_some_and(|max| bindings.bound() > max)
+            {
+                Some(Resource::Bindings)
+            } else if config.max_terms.is_some_and(|max| self.terms > max) {
+                Some(Resource::Terms)
+            } else if self.flags.max_term_depth.is_some_and(too_deep) {
+                Some(Resource::TermDepth)
+            } else {
+                None
+            };
+        }
+        self.exceeded
+    }
+    /// Where reading built-ins read from.
+    #[cfg(feature = "std")]
+    pub(crate) fn input(&self) -> &Input {
+        &self.db.input
+    }
+    /// Where printing built-ins write to.
+    pub(crate) fn output(&self) -> &Output {
+        &self.db.output
+    }
+    pub(crate) fn random(&self) -> &Random {
+        &self.db.random
+    }
+    pub(crate) fn globals(&self) -> &Globals {
+        &self.db.globals
+    }
+    pub(crate) fn db(&self) -> &'a Database {
+        self.db
+    }
+    /// The module a built-in was called from.
+    pub(crate) fn context(&self) -> Atom {
+        self.context
+    }
+    pub(crate) fn changes(&mut self) -> &mut Changes {
+        &mut self.changes
+    }
+    pub(crate) fn flags(&self) -> &Flags {
+        &self.flags
+    }
+    pub(crate) fn flags_mut(&mut self) -> &mut Flags {
+        &mut self.flags
+    }
+    /// The clauses asserted and retracted by the query, for the database to keep.
+    pub(crate) fn into_changes(self) -> Changes {
+        self.changes
+    }
+    /// Makes the built-in being called throw `ball` rather than fail.
+    pub(crate) fn raise(&mut self, ball: Term) {
+        self.thrown = Some(ball);
+    }
+    /// Qualifies a goal run by a built-in with the module the built-in was called from.
+    pub(crate) fn in_context(&self, goal: Term) -> Term {
+        qualify(&self.context, goal)
+    }
+    /// Copies `term`, replacing each distinct variable with a fresh one.
+    pub(crate) fn rename(&mut self, term: &Term, renamed: &mut HashMap<Alias, Term>) -> Term {
+        term.rename(&mut self.rng, renamed)
+    }
+}
+
+
+// the named variables of `clause`, in order of appearance, with their fresh
+// counterparts
+fn clause_variables(clause: &Functor, renamed: &HashMap<Alias, Term>) -> Vec<(String, Term)> {
+    let mut vars = Vec::new();
+    let goals = iter::once(clause).chain(&clause.body);
+    for arg in goals.flat_map(|goal| &goal.args) {
+        builtins::term_variables(arg, &mut vars);
+    }
+    vars.into_iter()
+        .filter_map(|var| match (&var.name, renamed.get(&var.alias)) {
+            (VariableName::Name(name), Some(fresh)) => {
+                Some((String::from(name.as_str()), fresh.clone()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// the goals of `clause`'s body, qualified with its module, copied out of
+// `arena` with the variables its head's copy was given in `renamed` and fresh
+// ones for the rest, so that their bindings are apart from those of every
+// other use of the clause
+fn fresh_body<Generator: Rng>(
+    module: &Atom,
+    clause: &Functor,
+    rng: &mut Generator,
+    renamed: &mut HashMap<Alias, Term>,
+    arena: &mut Arena,
+) -> Vec<Term> {
+    clause
+        .body
+        .iter()
+        .map(|goal| qualify(module, Term::Functor(arena.copy(goal, rng, renamed))))
+        .collect()
+}
+
+// the lists of a maplist/N or foldl/N call's arguments
+fn lists<'t>(name: &Atom, args: &'t [Term]) -> &'t [Term] {
+    if name.as_str() == "foldl" {
+        &args[1..args.len() - 2]
+    } else {
+        &args[1..]
+    }
+}
+
+// the step of an if-then-else, with its condition, then and else branches
+fn ite_step(branches: &[Term; 3]) -> Step {
+    let [cond, then, otherwise] = branches;
+    let cond_then = Term::functor_from_str("->", vec![cond.clone(), then.clone()]);
+    Step::control(
+        Term::functor_from_str(";", vec![cond_then, otherwise.clone()]),
+        1,
+    )
+}
+
+fn goal_term(name: &Atom, args: &[Term]) -> Term {
+    if args.is_empty() {
+        Term::Atom(*name)
+    } else {
+        Term::Functor(Box::new(Functor::new_fact(*name, args.to_vec())))
+    }
+}
+
+/// Strips `Module:` prefixes from a goal, returning the innermost module (`user` by default).
+fn unqualify(bindings: &Bindings, goal: &Term) -> (Atom, Term) {
+    let mut context = module::user();
+    let mut goal = bindings.walk(goal);
+    while let Term::Functor(f) = goal {
+        if f.name.as_str() != ":" || f.arity() != 2 {
+            break;
+        }
+        match bindings.walk(&f.args[0]) {
+            Term::Atom(m) => {
+                context = *m;
+                goal = bindings.walk(&f.args[1]);
+            }
+            _ => break,
+        }
+    }
+    (context, goal.clone())
+}
+
+/// The goal `call(Goal, Extra...)` calls, inside any `Module:` qualification.
+fn with_args(bindings: &Bindings, goal: &Term, extra: Vec<Term>) -> Result<Term, Term> {
+    match bindings.walk(goal) {
+        Term::Functor(f) if f.name.as_str() == ":" && f.arity() == 2 => {
+            let inner = with_args(bindings, &f.args[1], extra)?;
+            Ok(Term::Functor(Box::new(Functor::new_fact(
+                f.name,
+                vec![f.args[0].clone(), inner],
+            ))))
+        }
+        Term::Variable(_) => Err(error::instantiation_error()),
+        goal => builtins::add_args(goal, extra)
+            .ok_or_else(|| error::type_error("callable", goal.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{answers, consulted};
+    use crate::{parse_query, parse_term, VariableBinding};
+
+    #[test]
+    fn deep_terms_need_no_deep_stack() {
+        const DEPTH: usize = 100_000;
+        let mut rng = thread_rng();
+        let mut var = || Term::Variable(Variable::new_anonymous(&mut rng));
+        let f = |arg: Term| Term::functor_from_str("f", vec![arg]);
+        // f(f(...f(X)...)), nested in one piece
+        let nested = |leaf: &Term| (0..DEPTH).fold(leaf.clone(), |term, _| f(term));
+        // and V0 = f(V1), V1 = f(V2), ..., as building one a step at a time
+        // binds, ending in `leaf`
+        let chain = |bindings: &mut Bindings, var: &mut dyn FnMut() -> Term, leaf: Term| {
+            let first = var();
+            let mut link = first.clone();
+            for _ in 0..DEPTH {
+                let next = var();
+                let Term::Variable(v) = &link else {
+                    unreachable!()
+                };
+                bindings.bind(v, f(next.clone()));
+                link = next;
+            }
+            let Term::Variable(v) = &link else {
+                unreachable!()
+            };
+            bindings.bind(v, leaf);
+            first
+        };
+        let mut bindings = Bindings::new();
+        let (x, y) = (var(), var());
+        assert!(bindings.unify(&nested(&x), &nested(&y)));
+        assert_eq!(bindings.walk(&x), &y);
+        let fst = chain(&mut bindings, &mut var, Term::atom_from_str("end"));
+        let snd = chain(&mut bindings, &mut var, x.clone());
+        assert!(bindings.unify(&fst, &snd));
+        assert_eq!(bindings.walk(&y), &Term::atom_from_str("end"));
+        let mut resolved = bindings.resolve(&snd);
+        let mut depth = 0;
+        while let Term::Functor(f) = resolved {
+            resolved = f.into_args().pop().unwrap();
+            depth += 1;
+        }
+        assert_eq!((depth, resolved), (DEPTH, Term::atom_from_str("end")));
+        let measure = |term: &Term, max| bindings.depth(term, max, &mut HashMap::new());
+        assert_eq!(measure(&snd, DEPTH + 1), Some(DEPTH + 1));
+        assert_eq!(measure(&snd, DEPTH), None);
+        let copy = nested(&x).copy_fresh(&mut rng);
+        assert_eq!(
+            Bindings::new().depth(&copy, DEPTH + 1, &mut HashMap::new()),
+            Some(DEPTH + 1)
+        );
+        let shown = copy.to_string();
+        assert_eq!(shown.matches("f(").count(), DEPTH);
+        assert_eq!(copy.pretty().to_string(), shown);
+        let mut checked = Bindings::new();
+        checked.occurs_check = OccursCheck::True;
+        assert!(!checked.unify(&x, &nested(&x)));
+        assert!(checked.unify(&x, &nested(&y)));
+        // a chain of `Variable::bind`s
+        let mut rng = thread_rng();
+        let mut bound = Variable::new_anonymous(&mut rng);
+        bound.bind(VariableBinding::Atom(Atom::new("end")));
+        for _ in 0..DEPTH {
+            let mut outer = Variable::new_anonymous(&mut rng);
+            outer.bind(VariableBinding::Variable(Box::new(bound)));
+            bound = outer;
+        }
+        assert_eq!(bound.resolve(), Some(Atom::new("end")));
+        // and the built-ins that copy one, their answers written out; a
+        // search this deep takes a while, but overflowed as well
+        const BUILT: usize = 20_000;
+        let mut db = Database::new();
+        db.consult("mk(0, a). mk(N, f(T)) :- N > 0, N1 is N - 1, mk(N1, T).")
+            .unwrap();
+        for query in [
+            "copy_term(T, U)",
+            "assertz(big(T)), big(U)",
+            "nb_setval(k, T), nb_getval(k, U)",
+        ] {
+            let query = parse_query(&format!("mk({}, T), {}", BUILT, query)).unwrap();
+            let solutions = db.satisfy_all(query.goal.clone());
+            let solution = query.solution(&solutions[0]);
+            assert_eq!(solution.to_string().matches("f(").count(), 2 * BUILT);
+            assert_eq!(solution.to_json().matches("\"f\"").count(), 2 * BUILT);
+        }
+    }
+
+    #[test]
+    fn long_derivations_need_no_deep_stack() {
+        let program = "down(0).
+            down(N) :- N > 0, N1 is N - 1, down(N1).
+            up(0, []).
+            up(N, [N|T]) :- N > 0, N1 is N - 1, up(N1, T).
+            len([], 0).
+            len([_|T], N) :- len(T, N0), N is N0 + 1.";
+        assert_eq!(
+            answers(&consulted(program), "down(10000)").unwrap(),
+            vec![""]
+        );
+        let query = "up(10000, L), len(L, N), (N > 1 -> X = long ; X = short)";
+        let query = parse_query(query).unwrap();
+        let mut db = Database::new();
+        db.consult(program).unwrap();
+        let solutions = db.satisfy_all(query.goal.clone());
+        let solution = query.solution(&solutions[0]);
+        assert_eq!(solution.get_as::<i64>("N"), Some(10000));
+        assert_eq!(solution.get_as::<String>("X"), Some("long".into()));
+    }
+
+    #[test]
+    fn clauses_are_standardized_apart() {
+        let program = "parent(tom, bob). parent(bob, ann). parent(ann, joe).
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Y) :- parent(X, Z), ancestor(Z, Y).
+            append([], L, L).
+            append([H | T], L, [H | R]) :- append(T, L, R).";
+        assert_eq!(
+            answers(&consulted(program), "ancestor(tom, Who)").unwrap(),
+            vec!["Who = bob", "Who = ann", "Who = joe"]
+        );
+        assert_eq!(
+            answers(&consulted(program), "append(X, Y, [a, b])").unwrap(),
+            vec![
+                "X = [], Y = .(a, .(b, []))",
+                "X = .(a, []), Y = .(b, [])",
+                "X = .(a, .(b, [])), Y = []"
+            ]
+        );
+        assert_eq!(
+            answers(&consulted(program), "parent(X, bob), parent(bob, Y)").unwrap(),
+            vec!["X = tom, Y = ann"]
+        );
+    }
+
+    #[test]
+    fn disjunction_tries_both_branches() {
+        assert_eq!(
+            answers(&Database::new(), "(X = a ; X = b)").unwrap(),
+            vec!["X = a", "X = b"]
+        );
+        assert_eq!(
+            answers(&consulted("p(1). p(2). q(X) :- (p(X) | X = 3)."), "q(X)").unwrap(),
+            vec!["X = 1", "X = 2", "X = 3"]
+        );
+    }
+
+    #[test]
+    fn if_then_else_commits_to_the_first_condition_solution() {
+        let program = "p(1). p(2).
+            first(X) :- (p(Y) -> X = Y ; X = none).
+            check(X, R) :- (X > 1 -> R = big ; R = small).";
+        assert_eq!(
+            answers(&consulted(program), "first(X)").unwrap(),
+            vec!["X = 1"]
+        );
+        assert_eq!(
+            answers(&consulted(program), "check(1, R)").unwrap(),
+            vec!["R = small"]
+        );
+        assert_eq!(
+            answers(&consulted(program), "check(5, R)").unwrap(),
+            vec!["R = big"]
+        );
+        assert_eq!(
+            answers(&consulted(program), "(fail -> X = a ; X = b)").unwrap(),
+            vec!["X = b"]
+        );
+        assert!(answers(&consulted(program), "(fail -> true)")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            answers(&consulted(program), "(p(X) -> true), Y = X").unwrap(),
+            vec!["X = 1, Y = 1"]
+        );
+    }
+
+    #[test]
+    fn queries_cannot_call_the_solvers_own_marks() {
+        // tracing, so traced goals leave marks too
+        struct Creep;
+        impl crate::Debugger for Creep {
+            fn port(&self, _: crate::Port, _: usize, _: &Term) -> crate::Command {
+                crate::Command::Creep
+            }
+        }
+        let marks = [
+            "'$commit'(3)",
+            "'$exit_port'(0)",
+            "'$exit_catch'(0)",
+            "'$distinct'(0, a)",
+        ];
+        for mark in marks {
+            for query in [
+                mark.to_string(),
+                format!("(true -> {} ; true)", mark),
+                format!("catch({}, _, true), {}", mark, mark),
+                format!("distinct(X, (X = 1 ; X = 1)), {}", mark),
+                format!("G =.. ['$commit', 0], call(G), {}", mark),
+            ] {
+                let mut db = Database::new();
+                db.set_debugger(Creep);
+                match db.try_satisfy_all(parse_query(&query).unwrap().goal) {
+                    Err(SolveError::Uncaught(ball)) => {
+                        assert!(ball.to_string().contains("existence_error"), "{}", ball)
+                    }
+                    other => panic!("{}: {:?}", query, other),
+                }
+            }
+            let program = format!("{} :- true.", mark);
+            assert_eq!(answers(&consulted(&program), mark).unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn call_adds_arguments_to_its_goal() {
+        let program = "parent(tom, bob). parent(bob, ann).
+            twice(G, X, Z) :- call(G, X, Y), call(G, Y, Z).";
+        assert_eq!(
+            answers(&consulted(program), "G = parent(tom), call(G, X)").unwrap(),
+            vec!["G = parent(tom), X = bob"]
+        );
+        assert_eq!(
+            answers(&consulted(program), "twice(parent, tom, Z)").unwrap(),
+            vec!["Z = ann"]
+        );
+        assert_eq!(
+            answers(&consulted(program), "G = true, G").unwrap(),
+            vec!["G = true"]
+        );
+        assert_eq!(
+            answers(&consulted(program), "call(user:parent(bob), W)").unwrap(),
+            vec!["W = ann"]
+        );
+        assert_eq!(
+            answers(&consulted(program), "call((X = 1 ; X = 2))")
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            answers(&consulted(program), "catch(call(G, x), error(E, _), true)").unwrap(),
+            vec!["G = G, E = instantiation_error"]
+        );
+        assert_eq!(
+            answers(&consulted(program), "catch(call(1, x), error(E, _), true)").unwrap(),
+            vec!["E = type_error(callable, 1)"]
+        );
+    }
+
+    #[test]
+    fn maplist_and_foldl_call_their_goal_on_each_item() {
+        let program = "double(X, Y) :- Y is X * 2.
+            add(X, Acc0, Acc) :- Acc is Acc0 + X.
+            pair(X, Y, X-Y).";
+        assert_eq!(
+            answers(&consulted(program), "maplist(double, [1, 2, 3], L)").unwrap(),
+            vec!["L = .(2, .(4, .(6, [])))"]
+        );
+        assert_eq!(
+            answers(
+                &consulted(program),
+                "maplist(pair, [a, b], [1, 2], Ps), maplist(atom, [a, b])"
+            )
+            .unwrap(),
+            vec!["Ps = .(-(a, 1), .(-(b, 2), []))"]
+        );
+        assert!(answers(&consulted(program), "maplist(atom, [a, 1])")
+            .unwrap()
+            .is_empty());
+        assert!(answers(&consulted(program), "maplist(double, [1, 2], [_])")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            answers(&consulted(program), "foldl(add, [1, 2, 3], 0, S)").unwrap(),
+            vec!["S = 6"]
+        );
+        assert_eq!(
+            answers(&consulted(program), "foldl(add, [], 0, S)").unwrap(),
+            vec!["S = 0"]
+        );
+        let query = parse_query("maplist(=(x), L)").unwrap();
+        let lengths: Vec<usize> = Database::new()
+            .satisfy_n(query.goal.clone(), 3)
+            .iter()
+            .map(|b| {
+                b.list_to_vec(&query.solution(b).values()[0].1)
+                    .unwrap()
+                    .len()
+            })
+            .collect();
+        assert_eq!(lengths, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn lambdas_copy_all_but_their_free_variables() {
+        // the value of `var` in each solution of `query`
+        let values = |query: &str, var: &str| -> Vec<String> {
+            let query = parse_query(query).unwrap();
+            Database::new()
+                .satisfy_all(query.goal.clone())
+                .iter()
+                .map(|b| {
+                    let solution = query.solution(b);
+                    let values = solution.values();
+                    let (_, value) = values.iter().find(|(name, _)| name == var).unwrap();
+                    value.pretty().to_string()
+                })
+                .collect()
+        };
+        assert_eq!(
+            values("maplist([X, Y]>>(Y is X * 10), [1, 2], L)", "L"),
+            vec!["[10, 20]"]
+        );
+        assert_eq!(
+            values("N = 3, maplist([X]>>(X > N), [4, 5])", "N"),
+            vec!["3"]
+        );
+        assert!(values("maplist([X]>>(X > 3), [4, 2])", "X").is_empty());
+        assert_eq!(
+            values("foldl([X, A0, A]>>(A is A0 + X), [1, 2, 3], 0, S)", "S"),
+            vec!["6"]
+        );
+        assert_eq!(values("maplist([X]>>(Y = X), [a, b])", "Y"), vec!["Y"]);
+        assert!(values("maplist(Y/[X]>>(Y = X), [a, b])", "Y").is_empty());
+        assert_eq!(values("maplist(Y/[X]>>(Y = X), [a, a])", "Y"), vec!["a"]);
+        assert_eq!(values("call([X]>>atom_length(X), abc, N)", "N"), vec!["3"]);
+        assert_eq!(
+            values("G = [X]>>(X = 1), call(G, A), call(G, B), B = 1", "X"),
+            vec!["X"]
+        );
+    }
+
+    #[test]
+    fn undo_restores_the_marked_state() {
+        let term = parse_term("f(X, g(Y))").unwrap();
+        let Term::Functor(f) = &term else {
+            panic!("expected f/2")
+        };
+        let mut bindings = Bindings::new();
+        assert!(bindings.unify(&f.args[0], &Term::atom_from_str("a")));
+        let mark = bindings.mark();
+        let other = parse_term("f(a, g(b))").unwrap();
+        assert!(bindings.unify(&term, &other));
+        assert_eq!(bindings.resolve(&term).to_string(), "f(a, g(b))");
+        bindings.undo_to(mark);
+        assert_eq!(bindings.resolve(&f.args[0]), Term::atom_from_str("a"));
+        let Term::Functor(g) = &f.args[1] else {
+            panic!("expected g/1")
+        };
+        assert!(matches!(bindings.walk(&g.args[0]), Term::Variable(_)));
+    }
+
+    #[test]
+    fn failed_unifications_leave_partial_bindings_to_undo() {
+        let mut bindings = Bindings::new();
+        let mark = bindings.mark();
+        let fst = parse_term("f(X, b)").unwrap();
+        assert!(!bindings.unify(&fst, &parse_term("f(a, c)").unwrap()));
+        bindings.undo_to(mark);
+        assert!(bindings.vars.is_empty());
+    }
+
+    #[test]
+    fn cyclic_terms_unify_as_rational_trees() {
+        assert_eq!(
+            answers(&Database::new(), "X = f(X)").unwrap(),
+            vec!["X = f(X)"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "X = f(X), Y = f(Y), X = Y")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            answers(&Database::new(), "X = f(X), Y = f(f(Y)), X = Y")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(answers(&Database::new(), "X = f(X, a), Y = f(Y, b), X = Y")
+            .unwrap()
+            .is_empty());
+        let program = "abc(A, B, C) :- X = [a|X], X = [A, B|_], [_, _, C|_] = X.";
+        assert_eq!(
+            answers(&consulted(program), "abc(A, B, C)").unwrap(),
+            vec!["A = a, B = a, C = a"]
+        );
+        let query = parse_query("L = [a|L], msort(L, _)").unwrap();
+        let db = Database::new();
+        match db.try_satisfy_all(query.goal) {
+            Err(SolveError::Uncaught(ball)) => {
+                assert!(ball.to_string().contains("type_error(list"), "{}", ball)
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn undo_restores_rebound_variables() {
+        let mut bindings = Bindings::new();
+        let (x, y) = (parse_term("X").unwrap(), parse_term("Y").unwrap());
+        assert!(bindings.unify(&x, &parse_term("f(A)").unwrap()));
+        assert!(bindings.unify(&y, &parse_term("f(b)").unwrap()));
+        let mark = bindings.mark();
+        assert!(bindings.unify(&x, &y));
+        assert_eq!(bindings.resolve(&x).to_string(), "f(b)");
+        bindings.undo_to(mark);
+        assert_eq!(bindings.resolve(&x).to_string(), "f(A)");
+    }
+}
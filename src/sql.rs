@@ -0,0 +1,180 @@
+//! Predicates answered by SQLite, with the `sqlite` feature:
+//! `Database::register_sql` makes each row of a query a fact. A call's bound
+//! arguments narrow the query, and its rows are read a page at a time as the
+//! goal backtracks into them, so a table needn't fit in memory.
+
+use crate::foreign::Rows;
+use crate::{Atom, Database, Term};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params_from_iter, Connection};
+use std::sync::{Arc, Mutex};
+
+// how many rows are read at once
+const PAGE: usize = 256;
+
+impl Database {
+    /// Answers `name/N` with the rows of `query`, which has N columns. An
+    /// integer is an integer, text an atom and NULL the atom `null`; other
+    /// values are strings. Give the query an `ORDER BY` if the order of its
+    /// rows matters.
+    pub fn register_sql(
+        &mut self,
+        name: &str,
+        connection: Arc<Mutex<Connection>>,
+        query: &str,
+    ) -> rusqlite::Result<()> {
+        let arity = connection.lock().unwrap().prepare(query)?.column_count();
+        // the columns are named by position, as query's own names may clash
+        let columns: Vec<String> = (0..arity).map(|n| format!("c{}", n)).collect();
+        let from = format!(
+            "WITH q({}) AS ({}) SELECT * FROM q",
+            columns.join(", "),
+            query
+        );
+        self.register_rows(name, arity, move |args, bindings| {
+            let mut conditions = Vec::new();
+            let mut params = Vec::new();
+            for (column, arg) in columns.iter().zip(args) {
+                let value = match bindings.walk(arg) {
+                    Term::Variable(_) => continue,
+                    Term::Atom(a) if a.as_str() == "null" => {
+                        conditions.push(format!("{} IS NULL", column));
+                        continue;
+                    }
+                    Term::Atom(a) => Value::Text(a.as_str().to_string()),
+                    Term::Integer(n) => Value::Integer(*n),
+                    Term::String(text) => Value::Text(text.to_string()),
+                    // no row holds a compound term
+                    Term::Functor(_) => return Box::new(std::iter::empty()) as Rows,
+                };
+                conditions.push(format!("{} = ?", column));
+                params.push(value);
+            }
+            let mut sql = from.clone();
+            if !conditions.is_empty() {
+                sql = format!("{} WHERE {}", sql, conditions.join(" AND "));
+            }
+            Box::new(Pages {
+                connection: connection.clone(),
+                sql: format!("{} LIMIT ? OFFSET ?", sql),
+                params,
+                arity,
+                read: 0,
+                page: Vec::new().into_iter(),
+                last: false,
+            })
+        });
+        Ok(())
+    }
+}
+
+// the rows of a query, read a page at a time
+struct Pages {
+    connection: Arc<Mutex<Connection>>,
+    sql: String,
+    params: Vec<Value>,
+    arity: usize,
+    // how many rows have been read
+    read: usize,
+    page: std::vec::IntoIter<Vec<Term>>,
+    // whether the page is the last
+    last: bool,
+}
+
+impl Pages {
+    fn next_page(&self) -> rusqlite::Result<Vec<Vec<Term>>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(&self.sql)?;
+        let window = [
+            Value::Integer(PAGE as i64),
+            Value::Integer(self.read as i64),
+        ];
+        let params = params_from_iter(self.params.iter().chain(&window));
+        let rows = statement.query_map(params, |row| {
+            (0..self.arity)
+                .map(|n| row.get_ref(n).map(term))
+                .collect::<rusqlite::Result<Vec<Term>>>()
+        })?;
+        rows.collect()
+    }
+}
+
+impl Iterator for Pages {
+    type Item = Vec<Term>;
+
+    fn next(&mut self) -> Option<Vec<Term>> {
+        loop {
+            if let Some(row) = self.page.next() {
+                return Some(row);
+            }
+            if self.last {
+                return None;
+            }
+            // an error reading ends the rows
+            let page = self.next_page().unwrap_or_default();
+            self.read += page.len();
+            self.last = page.len() < PAGE;
+            self.page = page.into_iter();
+        }
+    }
+}
+
+fn term(value: ValueRef<'_>) -> Term {
+    match value {
+        ValueRef::Null => Term::Atom(Atom::new("null")),
+        ValueRef::Integer(n) => Term::Integer(n),
+        ValueRef::Real(x) => Term::String(x.to_string().into()),
+        ValueRef::Text(text) => Term::Atom(Atom::new(&String::from_utf8_lossy(text))),
+        ValueRef::Blob(bytes) => Term::String(String::from_utf8_lossy(bytes).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+    use rusqlite::Connection;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn goals_read_rows_from_the_database() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE person (name TEXT, age INTEGER, boss TEXT);
+                INSERT INTO person VALUES ('sam', 30, NULL), ('olive', 25, 'sam'),
+                    ('popeye', 40, 'sam');
+                CREATE TABLE number (n INTEGER);
+                WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1000)
+                INSERT INTO number SELECT i FROM n;",
+            )
+            .unwrap();
+        let connection = Arc::new(Mutex::new(connection));
+        let mut db = Database::new();
+        db.register_sql(
+            "person",
+            connection.clone(),
+            "SELECT * FROM person ORDER BY age",
+        )
+        .unwrap();
+        db.register_sql("serial", connection.clone(), "SELECT n FROM number")
+            .unwrap();
+        db.consult("reports(X, Y) :- person(X, _, Y), person(Y, _, _).")
+            .unwrap();
+        assert_eq!(
+            answers(&db, "person(X, A, sam)").unwrap(),
+            ["X = olive, A = 25", "X = popeye, A = 40"]
+        );
+        assert_eq!(answers(&db, "person(X, _, null)").unwrap(), ["X = sam"]);
+        assert_eq!(answers(&db, "reports(X, sam)").unwrap().len(), 2);
+        // a thousand rows, read in pages
+        assert_eq!(
+            answers(&db, "aggregate_all(count, serial(_), N)").unwrap(),
+            ["N = 1000"]
+        );
+        assert_eq!(answers(&db, "serial(1000)").unwrap().len(), 1);
+        assert!(db
+            .register_sql("broken", connection, "SELECT * FROM nowhere")
+            .is_err());
+    }
+}
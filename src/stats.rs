@@ -0,0 +1,336 @@
+//! Figures on what a `Database` holds, for keeping an eye on large
+//! knowledge bases, and on the running query, for `statistics/2`.
+
+use crate::builtins::{self, Alternatives};
+use crate::error;
+use crate::solver::{Bindings, Solver, Undo};
+use crate::{symbol, Arity, Atom, Database, Functor, Span, Term};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// What a `Database` held when `Database::stats` was called.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stats {
+    /// Every predicate with clauses, by module then in the order it was
+    /// first defined.
+    pub predicates: Vec<PredicateStats>,
+    /// Clauses across all predicates.
+    pub clauses: usize,
+    /// Terms across all clauses, counting every atom, integer, variable and
+    /// compound term of heads and bodies.
+    pub terms: usize,
+    /// Roughly how many bytes the clauses and the index over them take up.
+    pub memory: usize,
+    /// Entries in the index calls find a predicate's clauses by.
+    pub index_entries: usize,
+    /// Distinct atom names, which every database in the process shares.
+    pub atoms: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PredicateStats {
+    pub module: Atom,
+    pub name: Atom,
+    pub arity: Arity,
+    pub clauses: usize,
+    pub terms: usize,
+}
+
+impl Database {
+    pub fn stats(&self) -> Stats {
+        let mut modules: Vec<_> = self.modules.values().collect();
+        modules.sort_by_key(|module| module.name().as_str());
+        let mut stats = Stats {
+            predicates: Vec::new(),
+            clauses: 0,
+            terms: 0,
+            memory: 0,
+            index_entries: 0,
+            atoms: symbol::count(),
+        };
+        for module in modules {
+            stats.index_entries += module.index_len();
+            stats.memory += module.index_capacity() * size_of::<((Atom, Arity), Vec<Functor>)>();
+            for ((name, arity), clauses) in module.predicates() {
+                let terms = clauses.iter().map(functor_terms).sum();
+                stats.clauses += clauses.len();
+                stats.terms += terms;
+                stats.memory += clauses.iter().map(functor_bytes).sum::<usize>();
+                stats.predicates.push(PredicateStats {
+                    module: *module.name(),
+                    name: *name,
+                    arity: *arity,
+                    clauses: clauses.len(),
+                    terms,
+                });
+            }
+        }
+        stats
+    }
+}
+
+// the functor itself, its arguments and its body goals, counted off a work
+// list, so deep terms need no deep Rust stack
+pub(crate) fn functor_terms(functor: &Functor) -> usize {
+    let mut pending = vec![functor];
+    let mut terms = 0;
+    while let Some(functor) = pending.pop() {
+        terms += 1;
+        pending.extend(&functor.body);
+        for arg in &functor.args {
+            match arg {
+                Term::Functor(f) => pending.push(f),
+                _ => terms += 1,
+            }
+        }
+    }
+    terms
+}
+
+// a clause's size where it's stored, plus what it owns on the heap
+fn functor_bytes(functor: &Functor) -> usize {
+    size_of::<Functor>() + owned_bytes(functor)
+}
+
+fn owned_bytes(functor: &Functor) -> usize {
+    let mut pending = vec![functor];
+    let mut bytes = 0;
+    while let Some(functor) = pending.pop() {
+        bytes += functor.args.capacity() * size_of::<Term>();
+        bytes += functor.body.capacity() * size_of::<Functor>();
+        bytes += functor.span.as_ref().map_or(0, |_| size_of::<Span>());
+        pending.extend(&functor.body);
+        for arg in &functor.args {
+            match arg {
+                Term::Functor(f) => {
+                    bytes += size_of::<Functor>();
+                    pending.push(f);
+                }
+                Term::String(text) => bytes += text.len(),
+                // variables' names are interned, like atoms
+                Term::Atom(_) | Term::Integer(_) | Term::Variable(_) => {}
+            }
+        }
+    }
+    bytes
+}
+
+/// When a query started, by the clock on the wall and the process's CPU
+/// time, and when `statistics/2` last read each.
+pub(crate) struct Clock {
+    // not kept on wasm32-unknown-unknown, which has no clock, nor without
+    // the `std` feature
+    #[cfg(feature = "std")]
+    started: Option<Instant>,
+    cpu_started: Option<Duration>,
+    last_runtime: u64,
+    last_walltime: u64,
+}
+
+impl Clock {
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(feature = "std")]
+            started: (!cfg!(all(target_arch = "wasm32", target_os = "unknown"))).then(Instant::now),
+            cpu_started: cpu_time(),
+            last_runtime: 0,
+            last_walltime: 0,
+        }
+    }
+    // milliseconds of CPU time since the query started, falling back on
+    // time on the wall where there's no measure of CPU time
+    fn runtime(&self) -> u64 {
+        match (self.cpu_started, cpu_time()) {
+            (Some(started), Some(now)) => millis(now.saturating_sub(started)),
+            _ => self.walltime(),
+        }
+    }
+    fn walltime(&self) -> u64 {
+        #[cfg(feature = "std")]
+        return self.started.map_or(0, |started| millis(started.elapsed()));
+        #[cfg(not(feature = "std"))]
+        return 0;
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+// the CPU time the process has used
+fn cpu_time() -> Option<Duration> {
+    #[cfg(unix)]
+    {
+        let mut time = core::mem::MaybeUninit::<libc::timespec>::uninit();
+        // SAFETY: clock_gettime fills in the timespec, which is only read if
+        // it succeeds
+        unsafe {
+            if libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, time.as_mut_ptr()) != 0 {
+                return None;
+            }
+            let time = time.assume_init();
+            Some(Duration::new(
+                u64::try_from(time.tv_sec).ok()?,
+                u32::try_from(time.tv_nsec).ok()?,
+            ))
+        }
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+// the keys statistics/2 knows, in the order it gives them
+const KEYS: [&str; 7] = [
+    "atoms",
+    "clauses",
+    "inferences",
+    "predicates",
+    "runtime",
+    "trailused",
+    "walltime",
+];
+
+/// `statistics(Key, Value)`: `inferences`, the calls the query has made;
+/// `runtime` and `walltime`, `[Total, SinceLast]` milliseconds of CPU and
+/// wall time since the query started and since last asked; the `clauses`
+/// and `predicates` the query sees; `atoms`; and `trailused`, the bytes of
+/// bindings kept for backtracking. Enumerates the keys when `Key` is unbound.
+pub(crate) fn statistics(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let keys: Vec<&str> = match bindings.walk(&args[0]) {
+        Term::Variable(_) => KEYS.to_vec(),
+        Term::Atom(key) if KEYS.contains(&key.as_str()) => vec![key.as_str()],
+        Term::Atom(key) => {
+            let culprit = Term::Atom(*key);
+            return builtins::raise(solver, error::domain_error("statistics_key", culprit));
+        }
+        other => return builtins::raise(solver, error::type_error("atom", other.clone())),
+    };
+    let integer = |n: u64| Term::Integer(i64::try_from(n).unwrap_or(i64::MAX));
+    let (key, value) = (args[0].clone(), args[1].clone());
+    let rows: Vec<(Term, Term)> = keys
+        .into_iter()
+        .map(|name| {
+            let found = match name {
+                "atoms" => integer(symbol::count() as u64),
+                "clauses" | "predicates" => {
+                    let db = solver.db();
+                    let changes = solver.changes();
+                    let predicates = changes.predicates(db);
+                    let count = if name == "clauses" {
+                        predicates
+                            .iter()
+                            .map(|key| changes.current(db, key).len())
+                            .sum()
+                    } else {
+                        predicates
+                            .iter()
+                            .filter(|key| !changes.current(db, key).is_empty())
+                            .count()
+                    };
+                    integer(count as u64)
+                }
+                "inferences" => integer(solver.counts().0),
+                "trailused" => integer((bindings.trail_len() * size_of::<Undo>()) as u64),
+                _ => {
+                    let clock = solver.clock();
+                    let (total, last) = if name == "runtime" {
+                        (clock.runtime(), &mut clock.last_runtime)
+                    } else {
+                        (clock.walltime(), &mut clock.last_walltime)
+                    };
+                    let since = total.saturating_sub(*last);
+                    *last = total;
+                    Term::list_from_vec(vec![integer(total), integer(since)])
+                }
+            };
+            (Term::atom_from_str(name), found)
+        })
+        .collect();
+    Box::new(rows.into_iter().map(move |(name, found)| {
+        builtins::unify(vec![(key.clone(), name), (value.clone(), found)])
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn counts_what_is_loaded() {
+        let mut db = Database::new();
+        db.consult(
+            "likes(sam, pizza). likes(sam, X) :- sweet(X).
+             :- module(m, [sweet/1]).
+             sweet(cake).",
+        )
+        .unwrap();
+        let stats = db.stats();
+        let summary: Vec<(String, usize, usize)> = stats
+            .predicates
+            .iter()
+            .map(|p| {
+                (
+                    format!("{}:{}/{}", p.module, p.name, p.arity),
+                    p.clauses,
+                    p.terms,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("m:sweet/1".to_string(), 1, 2),
+                ("user:likes/2".to_string(), 2, 8),
+            ]
+        );
+        assert_eq!(stats.clauses, 3);
+        assert_eq!(stats.terms, 10);
+        assert_eq!(stats.index_entries, 2);
+        assert!(stats.memory > 3 * std::mem::size_of::<Functor>());
+        assert!(stats.atoms >= 4);
+        let empty = Database::new().stats();
+        assert_eq!((empty.clauses, empty.terms), (0, 0));
+    }
+
+    #[test]
+    fn statistics_reports_the_running_query() {
+        let mut db = Database::new();
+        db.consult("n(1). n(2). m(a).").unwrap();
+        let one = |query: &str| {
+            let query = parse_query(query).unwrap();
+            let answers = db.try_satisfy_all(query.goal.clone()).unwrap();
+            assert_eq!(answers.len(), 1, "{:?}", query.goal);
+            query.solution(&answers[0]).values()[0]
+                .1
+                .pretty()
+                .to_string()
+        };
+        assert_eq!(one("statistics(clauses, N)"), "3");
+        assert_eq!(one("assertz(n(3)), statistics(clauses, N)"), "4");
+        assert_eq!(one("statistics(predicates, N)"), "2");
+        assert_eq!(one("m(_), statistics(inferences, N)"), "2");
+        let walltime = one("statistics(walltime, [T, S])");
+        assert!(walltime.parse::<u64>().is_ok(), "{}", walltime);
+        let count = |query: &str| db.try_satisfy_all(parse_query(query).unwrap().goal);
+        assert_eq!(count("statistics(K, _)").unwrap().len(), 7);
+        assert_eq!(
+            count("statistics(runtime, [A, _]), statistics(runtime, [B, S]), A =< B, S =< B")
+                .unwrap()
+                .len(),
+            1
+        );
+        match count("statistics(nope, _)") {
+            Err(SolveError::Uncaught(ball)) => {
+                assert!(ball
+                    .to_string()
+                    .contains("domain_error(statistics_key, nope)"))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Queries searched on a thread of their own, one solution at a time: the
+//! producer `Database::answers` and, with the `async` feature,
+//! `Database::solutions_async` are built on. The search waits for each
+//! solution to be asked for before looking for it, so a query can be left
+//! part-way through and resumed, and it stops at its next call once the
+//! consumer is dropped. Awaiting it never blocks an executor's worker.
+
+use crate::dynamic::Changes;
+use crate::solver::{self, Bindings, Solver};
+use crate::{CancellationToken, Database, Flags, Functor, SolveError, Term};
+#[cfg(feature = "async")]
+use futures_core::Stream;
+#[cfg(feature = "async")]
+use std::future::{self, Future};
+use std::ops::ControlFlow;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+/// The solutions of a query, as they are found. An uncaught exception or a
+/// timeout ends the stream with an error.
+#[cfg(feature = "async")]
+pub struct Solutions {
+    producer: Producer,
+}
+
+// the consumer's end of a search on another thread; dropping it stops the
+// search
+pub(crate) struct Producer {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // signalled when the consumer wants another solution, or is dropped
+    wanted: Condvar,
+    cancelled: CancellationToken,
+}
+
+#[derive(Default)]
+struct State {
+    wanted: bool,
+    found: Option<Result<Bindings, SolveError>>,
+    finished: bool,
+    // what the query asserted and retracted, and the flags it set
+    changed: Option<(Changes, Flags)>,
+    waker: Option<Waker>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    // waits for the consumer to want a solution, returning false if it was
+    // dropped instead
+    fn asked(&self) -> bool {
+        let mut state = self.lock();
+        while !state.wanted && !self.cancelled.is_cancelled() {
+            state = self
+                .wanted
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        !self.cancelled.is_cancelled()
+    }
+    // hands `found` to the consumer, which has asked for it
+    fn give(&self, found: Result<Bindings, SolveError>) {
+        let mut state = self.lock();
+        state.wanted = false;
+        state.found = Some(found);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+    fn finish(&self) {
+        let mut state = self.lock();
+        state.finished = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Producer {
+    // searches for the solutions of `goal` on another thread, each once it's
+    // asked for
+    pub(crate) fn start(db: Arc<Database>, goal: Functor) -> Self {
+        let cancelled = CancellationToken::new();
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            wanted: Condvar::new(),
+            cancelled: cancelled.clone(),
+        });
+        let producer = shared.clone();
+        thread::spawn(move || {
+            let mut allowance = db.config.allowance();
+            if !allowance.exhausted() && producer.asked() {
+                let goal = Term::Functor(Box::new(goal));
+                let mut filter = db.config.filter(&goal);
+                let mut solver = Solver::new(&db).cancellable(cancelled);
+                let flow = solver.solve(
+                    std::slice::from_ref(&goal),
+                    &mut db.bindings(),
+                    &mut |bindings, _| {
+                        if !filter.wanted(bindings) {
+                            return ControlFlow::Continue(());
+                        }
+                        producer.give(Ok(bindings.clone()));
+                        if !producer.asked() {
+                            return ControlFlow::Break(());
+                        }
+                        allowance.spend()
+                    },
+                );
+                let flags = *solver.flags();
+                producer.lock().changed = Some((solver.into_changes(), flags));
+                if let Err(err) = solver::outcome(flow) {
+                    producer.give(Err(err));
+                }
+            }
+            // let go of the database first, so the consumer can change it
+            // without copying it
+            drop(db);
+            producer.finish();
+        });
+        Producer { shared }
+    }
+    // the next solution if it's been found, asking for it if not
+    pub(crate) fn poll_next(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bindings, SolveError>>> {
+        let mut state = self.shared.lock();
+        if let Some(found) = state.found.take() {
+            return Poll::Ready(Some(found));
+        }
+        if state.finished {
+            return Poll::Ready(None);
+        }
+        state.wanted = true;
+        state.waker = Some(cx.waker().clone());
+        self.shared.wanted.notify_one();
+        Poll::Pending
+    }
+    // stops the search at its next call
+    pub(crate) fn cancel(&self) {
+        self.shared.cancelled.cancel();
+        self.shared.wanted.notify_one();
+    }
+    // what the query changed, once the search is over
+    pub(crate) fn changed(&self) -> Option<(Changes, Flags)> {
+        self.shared.lock().changed.take()
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(feature = "async")]
+impl Stream for Solutions {
+    type Item = Result<Bindings, SolveError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.producer.poll_next(cx)
+    }
+}
+
+#[cfg(feature = "async")]
+impl Database {
+    /// The solutions of `goal` as a `Stream`, searched for on another thread.
+    pub fn solutions_async(self: Arc<Self>, goal: Functor) -> Solutions {
+        Solutions {
+            producer: Producer::start(self, goal),
+        }
+    }
+    /// Like `satisfy`, but searches on another thread: `goal` with its
+    /// variables bound as in its first solution, if any.
+    pub fn satisfy_async(self: Arc<Self>, goal: Functor) -> impl Future<Output = Option<Functor>> {
+        let mut solutions = self.solutions_async(goal.clone());
+        async move {
+            let first = future::poll_fn(|cx| Pin::new(&mut solutions).poll_next(cx)).await;
+            let bindings = first?.ok()?;
+            let mut goal = goal;
+            goal.args
+                .iter_mut()
+                .for_each(|arg| crate::bind_values(arg, &bindings));
+            Some(goal)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use crate::*;
+    use futures_core::Stream;
+    use std::future::Future;
+    use std::pin::{pin, Pin};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = Arc::new(Unpark(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    fn next(solutions: &mut Solutions) -> Option<Result<Bindings, SolveError>> {
+        block_on(std::future::poll_fn(|cx| {
+            Pin::new(&mut *solutions).poll_next(cx)
+        }))
+    }
+
+    fn program(text: &str) -> Arc<Database> {
+        let mut db = Database::new();
+        db.consult(text).unwrap();
+        Arc::new(db)
+    }
+
+    #[test]
+    fn streams_solutions_in_order() {
+        let db = program("n(1). n(2). n(3).");
+        let query = parse_query("n(X)").unwrap();
+        let mut solutions = db.solutions_async(query.goal.clone());
+        let mut found = Vec::new();
+        while let Some(solution) = next(&mut solutions) {
+            found.push(query.solution(&solution.unwrap()).to_json());
+        }
+        assert_eq!(found.len(), 3);
+        assert!(found[2].contains('3'));
+    }
+
+    #[test]
+    fn errors_end_the_stream() {
+        let db = program("");
+        let query = parse_query("X is foo + 1").unwrap();
+        let mut solutions = db.solutions_async(query.goal);
+        assert!(matches!(next(&mut solutions), Some(Err(_))));
+        assert!(next(&mut solutions).is_none());
+    }
+
+    #[test]
+    fn dropping_the_stream_stops_an_endless_search() {
+        let db = program("nat(0).\nnat(N) :- nat(M), N is M + 1.");
+        let query = parse_query("nat(N)").unwrap();
+        let mut solutions = db.clone().solutions_async(query.goal);
+        assert!(next(&mut solutions).unwrap().is_ok());
+        drop(solutions);
+        let query = parse_query("nat(2)").unwrap();
+        assert!(block_on(db.satisfy_async(query.goal)).is_some());
+    }
+}
@@ -0,0 +1,216 @@
+//! Built-ins for strings, the text written in double quotes:
+//! `string_concat/3`, `string_chars/2`, `string_to_atom/2` and
+//! `split_string/4`. Like their atom counterparts, they take any atomic
+//! term as text, and count in characters.
+
+use crate::atoms;
+use crate::builtins::{self, Alternatives};
+use crate::error;
+use crate::solver::{Bindings, Solver};
+use crate::Term;
+use alloc::boxed::Box;
+use alloc::vec;
+use core::iter;
+
+fn string(text: &str) -> Term {
+    Term::String(text.into())
+}
+
+fn equals(term: &Term, value: Term) -> Alternatives {
+    Box::new(iter::once(builtins::unify(vec![(term.clone(), value)])))
+}
+
+/// `string_concat(A, B, AB)`: like `atom_concat/3`, giving strings.
+pub(crate) fn string_concat(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    atoms::concat(solver, args, bindings, string)
+}
+
+/// `string_chars(String, Chars)`: like `atom_chars/2`, giving a string.
+pub(crate) fn string_chars(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    atoms::convert(
+        solver,
+        args,
+        bindings,
+        string,
+        atoms::char_item,
+        atoms::char_of,
+    )
+}
+
+/// `string_to_atom(String, Atom)`: converts whichever is given.
+pub(crate) fn string_to_atom(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    let converted = match atoms::text(&args[0], bindings) {
+        Ok(Some(text)) => Ok((&args[1], atoms::atom(&text))),
+        Ok(None) => match atoms::text(&args[1], bindings) {
+            Ok(Some(text)) => Ok((&args[0], string(&text))),
+            Ok(None) => Err(error::instantiation_error()),
+            Err(ball) => Err(ball),
+        },
+        Err(ball) => Err(ball),
+    };
+    match converted {
+        Ok((term, value)) => equals(term, value),
+        Err(ball) => builtins::raise(solver, ball),
+    }
+}
+
+/// `split_string(String, SepChars, PadChars, SubStrings)`: cuts `String` at
+/// each of the characters in `SepChars`, then trims those in `PadChars` off
+/// both ends of every piece. With no separators, it only trims.
+pub(crate) fn split_string(
+    solver: &mut Solver,
+    args: &[Term],
+    bindings: &Bindings,
+) -> Alternatives {
+    let given = |term: &Term| match atoms::text(term, bindings) {
+        Ok(Some(text)) => Ok(text),
+        Ok(None) => Err(error::instantiation_error()),
+        Err(ball) => Err(ball),
+    };
+    let texts = given(&args[0]).and_then(|text| Ok((text, given(&args[1])?, given(&args[2])?)));
+    let (text, separators, padding) = match texts {
+        Ok(texts) => texts,
+        Err(ball) => return builtins::raise(solver, ball),
+    };
+    let pieces = text
+        .split(|c| separators.contains(c))
+        .map(|piece| string(piece.trim_matches(|c| padding.contains(c))))
+        .collect();
+    equals(&args[3], Term::list_from_vec(pieces))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{answers, consulted};
+    use crate::*;
+
+    #[test]
+    fn strings_are_their_own_type() {
+        assert_eq!(
+            answers(&Database::new(), "X = \"hi there\"").unwrap(),
+            vec!["X = \"hi there\""]
+        );
+        assert_eq!(
+            answers(&Database::new(), "X = \"say \\\"hi\\\"\"").unwrap(),
+            vec!["X = \"say \\\"hi\\\"\""]
+        );
+        assert!(answers(&Database::new(), "\"abc\" = abc")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            answers(&Database::new(), "f(\"abc\") = f(\"abc\")").unwrap(),
+            vec![""]
+        );
+        assert_eq!(
+            answers(&Database::new(), "string(\"abc\"), atomic(\"abc\")").unwrap(),
+            vec![""]
+        );
+        assert!(answers(&Database::new(), "string(abc)").unwrap().is_empty());
+        assert_eq!(
+            answers(&Database::new(), "msort([\"b\", f(x), a, 1, \"a\"], L)").unwrap(),
+            vec!["L = .(1, .(a, .(\"a\", .(\"b\", .(f(x), [])))))"]
+        );
+    }
+
+    #[test]
+    fn concatenates_and_converts() {
+        assert_eq!(
+            answers(&Database::new(), "string_concat(abc, \"def\", S)").unwrap(),
+            vec!["S = \"abcdef\""]
+        );
+        assert_eq!(
+            answers(&Database::new(), "string_concat(A, B, \"ab\")").unwrap(),
+            vec![
+                "A = \"\", B = \"ab\"",
+                "A = \"a\", B = \"b\"",
+                "A = \"ab\", B = \"\""
+            ]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "string_chars(\"hi\", Cs), string_chars(S, [o, k])"
+            )
+            .unwrap(),
+            vec!["Cs = .(h, .(i, [])), S = \"ok\""]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "string_to_atom(\"x y\", A), string_to_atom(S, b)"
+            )
+            .unwrap(),
+            vec!["A = 'x y', S = \"b\""]
+        );
+        assert_eq!(
+            answers(&Database::new(), "atom_length(\"four\", N)").unwrap(),
+            vec!["N = 4"]
+        );
+    }
+
+    #[test]
+    fn splits_and_trims() {
+        assert_eq!(
+            answers(&Database::new(), "split_string(\"a,b,,c\", \",\", \"\", L)").unwrap(),
+            vec!["L = .(\"a\", .(\"b\", .(\"\", .(\"c\", []))))"]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "split_string(\"/home//jan///nice/path\", \"/\", \"\", L)"
+            )
+            .unwrap(),
+            vec!["L = .(\"\", .(\"home\", .(\"\", .(\"jan\", .(\"\", .(\"\", .(\"nice\", .(\"path\", []))))))))"]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "split_string(\"  a word \", \"\", \" \", L)"
+            )
+            .unwrap(),
+            vec!["L = .(\"a word\", [])"]
+        );
+        assert_eq!(
+            answers(
+                &Database::new(),
+                "split_string(\"SWI-Prolog, 7.0\", \",\", \" \", L)"
+            )
+            .unwrap(),
+            vec!["L = .(\"SWI-Prolog\", .(\"7.0\", []))"]
+        );
+    }
+
+    #[test]
+    fn double_quotes_flag_changes_what_text_reads_as() {
+        let program = ":- set_prolog_flag(double_quotes, codes).
+            in_codes(\"ab\").
+            :- set_prolog_flag(double_quotes, chars).
+            in_chars(\"ab\").
+            :- set_prolog_flag(double_quotes, atom).
+            in_atom(\"a b\").";
+        assert_eq!(
+            answers(&consulted(program), "in_codes(X), in_chars(Y), in_atom(Z)").unwrap(),
+            vec!["X = .(97, .(98, [])), Y = .(a, .(b, [])), Z = 'a b'"]
+        );
+        let mut db = Database::new();
+        db.set_config(SolverConfig::default().double_quotes(DoubleQuotes::Chars));
+        db.consult("word(\"hi\").").unwrap();
+        assert_eq!(db.listing_predicate("word", 1), "word([h, i]).\n\n");
+        let err = Database::new()
+            .consult(":- set_prolog_flag(double_quotes, nope).")
+            .unwrap_err();
+        assert_eq!(err[0].message, "unknown double_quotes value");
+    }
+}
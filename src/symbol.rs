@@ -0,0 +1,57 @@
+//! The table of names that atoms index into. Each name is stored once and
+//! leaked, so an atom's text can be borrowed for the rest of the program.
+use crate::compat::{HashMap, OnceLock, RwLock};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+#[derive(Default)]
+struct Table {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, u32>,
+}
+
+fn table() -> &'static RwLock<Table> {
+    static TABLE: OnceLock<RwLock<Table>> = OnceLock::new();
+    TABLE.get_or_init(RwLock::default)
+}
+
+/// The id of `name`, adding it to the table if it is new.
+pub(crate) fn intern(name: &str) -> u32 {
+    let read = table().read();
+    if let Some(id) = read.ids.get(name) {
+        return *id;
+    }
+    drop(read);
+    let mut table = table().write();
+    // another thread may have added it in between
+    if let Some(id) = table.ids.get(name) {
+        return *id;
+    }
+    let id = u32::try_from(table.names.len()).expect("fewer than 2^32 distinct atoms");
+    let name: &'static str = Box::leak(Box::from(name));
+    table.names.push(name);
+    table.ids.insert(name, id);
+    id
+}
+
+pub(crate) fn name(id: u32) -> &'static str {
+    table().read().names[id as usize]
+}
+
+/// How many names have been interned.
+pub(crate) fn count() -> usize {
+    table().read().names.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interns_each_name_once() {
+        let id = intern("symbol-test");
+        assert_eq!(intern(&String::from("symbol-test")), id);
+        assert_ne!(intern("symbol-test-2"), id);
+        assert_eq!(name(id), "symbol-test");
+    }
+}
@@ -0,0 +1,127 @@
+//! What a query does, as `tracing` spans and events, with the `tracing`
+//! feature: a `query` span at the `debug` level around each query, with
+//! an event as it finishes, and at the `trace` level an event for each
+//! clause selected and each time the search backtracks. They all have the
+//! target `amateurlog`, to filter them by.
+
+use crate::observer::Event;
+use crate::solver::Halt;
+use crate::Term;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::ControlFlow;
+use tracing::field;
+
+/// The span a query runs in, `goals` being what it proves.
+pub(crate) fn query(goals: &[Term]) -> tracing::Span {
+    let goal: Vec<String> = goals.iter().map(|g| g.pretty().to_string()).collect();
+    tracing::debug_span!(target: "amateurlog", "query", goal = %goal.join(", "))
+}
+
+/// The query ended as `flow` says, after `inferences` and `unifications`.
+pub(crate) fn finished(flow: &ControlFlow<Halt>, (inferences, unifications): (u64, u64)) {
+    let outcome = match flow {
+        ControlFlow::Continue(()) => "exhausted",
+        ControlFlow::Break(Halt::Done) => "stopped",
+        ControlFlow::Break(Halt::Thrown(_)) => "uncaught",
+        ControlFlow::Break(Halt::TimedOut) => "timed_out",
+        ControlFlow::Break(Halt::ResourceExceeded(_)) => "resource_exceeded",
+        ControlFlow::Break(Halt::Cancelled) => "cancelled",
+    };
+    let ball = match flow {
+        ControlFlow::Break(Halt::Thrown(ball)) => Some(field::display(ball.pretty())),
+        _ => None,
+    };
+    tracing::debug!(
+        target: "amateurlog",
+        outcome,
+        ball,
+        inferences,
+        unifications,
+        "query finished"
+    );
+}
+
+/// The clauses selected and the backtracking among the observer's events.
+pub(crate) fn event(event: &Event<'_>) {
+    match event {
+        Event::Unified { clause, .. } => tracing::trace!(
+            target: "amateurlog",
+            module = %clause.module,
+            predicate = %format_args!("{}/{}", clause.name, clause.arity),
+            clause = clause.index,
+            "clause selected"
+        ),
+        Event::Backtrack { .. } => tracing::trace!(target: "amateurlog", "backtrack"),
+        Event::Call { .. } | Event::TryClause { .. } | Event::NotUnified { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Metadata, Subscriber};
+
+    // keeps the spans opened and the events recorded, as their names
+    // followed by their fields
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn core::fmt::Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push_str(&format!(" {}={}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields(span.metadata().name().to_string());
+            span.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0.trim().to_string());
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn queries_clauses_and_backtracking_are_traced() {
+        let mut db = Database::new();
+        db.consult("p(1). p(2). q(2).").unwrap();
+        let query = parse_query("p(X), q(X)").unwrap();
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            db.satisfy_all(query.goal);
+        });
+        let recorded = recorder.0.lock().unwrap().clone();
+        assert_eq!(
+            recorded,
+            vec![
+                "query goal=p(X), q(X)",
+                "message=clause selected module=user predicate=p/1 clause=0",
+                "message=backtrack",
+                "message=clause selected module=user predicate=p/1 clause=1",
+                "message=clause selected module=user predicate=q/1 clause=0",
+                "message=query finished outcome=exhausted inferences=3 unifications=4",
+            ]
+        );
+    }
+}
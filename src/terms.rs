@@ -0,0 +1,210 @@
+//! Built-ins that take terms apart and build them: `functor/3`, `arg/3`
+//! and `=../2`.
+
+use crate::builtins::{self, Alternatives, Branch};
+use crate::compat::thread_rng;
+use crate::error;
+use crate::solver::{Bindings, Solver};
+use crate::{Atom, Functor, Term, Variable};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter;
+
+fn equals(term: &Term, value: Term) -> Alternatives {
+    Box::new(iter::once(builtins::unify(vec![(term.clone(), value)])))
+}
+
+// `name(Args...)`, or just the name when there are no arguments
+fn build(name: Atom, args: Vec<Term>) -> Term {
+    if args.is_empty() {
+        Term::Atom(name)
+    } else {
+        Term::Functor(Box::new(Functor::new_fact(name, args)))
+    }
+}
+
+/// `functor(Term, Name, Arity)`: reads the name and arity of a term, or
+/// builds the most general term with them if `Term` is unbound.
+pub(crate) fn functor(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let (name, arity) = match bindings.walk(&args[0]) {
+        Term::Functor(f) => (Term::Atom(f.name), f.arity()),
+        Term::Variable(_) => return construct(solver, args, bindings),
+        atomic => (atomic.clone(), 0),
+    };
+    Box::new(iter::once(builtins::unify(vec![
+        (args[1].clone(), name),
+        (args[2].clone(), Term::Integer(arity as i64)),
+    ])))
+}
+
+fn construct(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let arity = match bindings.walk(&args[2]) {
+        Term::Variable(_) => return builtins::raise(solver, error::instantiation_error()),
+        Term::Integer(n) if *n < 0 => {
+            let ball = error::domain_error("not_less_than_zero", Term::Integer(*n));
+            return builtins::raise(solver, ball);
+        }
+        Term::Integer(n) => *n as usize,
+        other => return builtins::raise(solver, error::type_error("integer", other.clone())),
+    };
+    let ball = match bindings.walk(&args[1]) {
+        Term::Variable(_) => error::instantiation_error(),
+        Term::Functor(_) => error::type_error("atomic", bindings.resolve(&args[1])),
+        atomic if arity == 0 => return equals(&args[0], atomic.clone()),
+        Term::Atom(name) => {
+            let fresh = (0..arity)
+                .map(|_| Term::Variable(Variable::new_anonymous(&mut thread_rng())))
+                .collect();
+            return equals(&args[0], build(*name, fresh));
+        }
+        other => error::type_error("atom", other.clone()),
+    };
+    builtins::raise(solver, ball)
+}
+
+/// `arg(N, Term, Arg)`: `Arg` is the `N`th argument of `Term`, counting
+/// from 1. Enumerates the arguments if `N` is unbound.
+pub(crate) fn arg(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let f = match bindings.walk(&args[1]) {
+        Term::Functor(f) => f.clone(),
+        Term::Variable(_) => return builtins::raise(solver, error::instantiation_error()),
+        other => return builtins::raise(solver, error::type_error("compound", other.clone())),
+    };
+    match bindings.walk(&args[0]) {
+        Term::Integer(n) if *n >= 1 && *n as usize <= f.arity() => {
+            equals(&args[2], f.args[*n as usize - 1].clone())
+        }
+        Term::Integer(_) => builtins::failure(),
+        Term::Variable(_) => {
+            let (n, arg) = (args[0].clone(), args[2].clone());
+            Box::new(
+                f.into_args()
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(i, value)| -> Branch {
+                        builtins::unify(vec![
+                            (n.clone(), Term::Integer(i as i64 + 1)),
+                            (arg.clone(), value),
+                        ])
+                    }),
+            )
+        }
+        other => builtins::raise(solver, error::type_error("integer", other.clone())),
+    }
+}
+
+/// `Term =.. List`: `List` is `[Name|Args]` for a compound term, or `[Term]`
+/// for an atomic one.
+pub(crate) fn univ(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let items = match bindings.walk(&args[0]) {
+        Term::Functor(f) => iter::once(Term::Atom(f.name))
+            .chain(f.args.iter().cloned())
+            .collect(),
+        Term::Variable(_) => return compose(solver, args, bindings),
+        atomic => vec![atomic.clone()],
+    };
+    equals(&args[1], Term::list_from_vec(items))
+}
+
+fn compose(solver: &mut Solver, args: &[Term], bindings: &Bindings) -> Alternatives {
+    let Some(mut items) = builtins::list(solver, &args[1], bindings) else {
+        return builtins::failure();
+    };
+    if items.is_empty() {
+        let ball = error::domain_error("non_empty_list", Term::nil());
+        return builtins::raise(solver, ball);
+    }
+    let rest = items.split_off(1);
+    let ball = match bindings.walk(&items[0]) {
+        Term::Variable(_) => error::instantiation_error(),
+        Term::Functor(_) => error::type_error("atomic", bindings.resolve(&items[0])),
+        atomic if rest.is_empty() => return equals(&args[0], atomic.clone()),
+        Term::Atom(name) => return equals(&args[0], build(*name, rest)),
+        other => error::type_error("atom", other.clone()),
+    };
+    builtins::raise(solver, ball)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::answers;
+    use crate::*;
+
+    fn thrown(query: &str) -> String {
+        match answers(&Database::new(), query) {
+            Err(SolveError::Uncaught(ball)) => ball.to_string(),
+            Ok(answers) => panic!("{} succeeded with {:?}", query, answers),
+            Err(err) => panic!("{}: {}", query, err),
+        }
+    }
+
+    #[test]
+    fn functor_reads_and_builds() {
+        assert_eq!(
+            answers(&Database::new(), "functor(likes(sam, _), N, A)").unwrap(),
+            vec!["N = likes, A = 2"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "functor(sam, N, A)").unwrap(),
+            vec!["N = sam, A = 0"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "functor(3, N, A)").unwrap(),
+            vec!["N = 3, A = 0"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "functor(T, point, 3), T = point(1, 2, 3)").unwrap(),
+            vec!["T = point(1, 2, 3)"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "functor(T, foo, 0)").unwrap(),
+            vec!["T = foo"]
+        );
+        assert!(thrown("functor(_, foo, _)").contains("instantiation_error"));
+        assert!(thrown("functor(_, foo(a), 1)").contains("type_error(atomic"));
+        assert!(
+            thrown("N is 0 - 1, functor(_, foo, N)").contains("domain_error(not_less_than_zero")
+        );
+    }
+
+    #[test]
+    fn arg_selects_and_enumerates() {
+        assert_eq!(
+            answers(&Database::new(), "arg(2, f(a, b, c), X)").unwrap(),
+            vec!["X = b"]
+        );
+        assert!(answers(&Database::new(), "arg(4, f(a, b, c), _)")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            answers(&Database::new(), "arg(N, f(a, b), X)").unwrap(),
+            vec!["N = 1, X = a", "N = 2, X = b"]
+        );
+        assert!(thrown("arg(1, foo, _)").contains("type_error(compound, foo)"));
+        assert!(thrown("arg(a, f(x), _)").contains("type_error(integer, a)"));
+    }
+
+    #[test]
+    fn univ_runs_both_ways() {
+        assert_eq!(
+            answers(&Database::new(), "likes(sam, X) =.. L, X = pizza").unwrap(),
+            vec!["X = pizza, L = .(likes, .(sam, .(pizza, [])))"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "sam =.. L").unwrap(),
+            vec!["L = .(sam, [])"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "T =.. [likes, sam, X], X = pizza").unwrap(),
+            vec!["T = likes(sam, pizza), X = pizza"]
+        );
+        assert_eq!(
+            answers(&Database::new(), "T =.. [7]").unwrap(),
+            vec!["T = 7"]
+        );
+        assert!(thrown("_ =.. [foo|_]").contains("instantiation_error"));
+        assert!(thrown("_ =.. []").contains("domain_error(non_empty_list"));
+        assert!(thrown("_ =.. [1, a]").contains("type_error(atom, 1)"));
+    }
+}
@@ -0,0 +1,33 @@
+//! What the tests of every module share.
+
+use crate::{parse_query, Database, SolveError};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// A database of `program`'s clauses.
+pub(crate) fn consulted(program: &str) -> Database {
+    let mut db = Database::new();
+    db.consult(program).unwrap();
+    db
+}
+
+/// The answers to `query` in `db`, each as the values of its named
+/// variables written canonically, like `X = f(a), Y = 1`; or the error the
+/// search ended in.
+pub(crate) fn answers(db: &Database, query: &str) -> Result<Vec<String>, SolveError> {
+    let query = parse_query(query).unwrap();
+    let answers = db.try_satisfy_all(query.goal.clone())?;
+    Ok(answers
+        .iter()
+        .map(|b| {
+            let values: Vec<String> = query
+                .solution(b)
+                .values()
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect();
+            values.join(", ")
+        })
+        .collect())
+}
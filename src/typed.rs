@@ -0,0 +1,170 @@
+//! Typed queries: a `Signature` names a predicate along with the Rust types
+//! of its arguments, so a query of it that gives the wrong number of
+//! arguments, or an argument of the wrong kind, doesn't compile. Each
+//! solution comes back as a tuple of those types.
+
+use crate::compat::thread_rng;
+use crate::{Atom, Database, FromTerm, Functor, SolveError, Term, ToTerm, Variable};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+/// A predicate, `name/N`, whose arguments are the `N` types of the tuple
+/// `Args`.
+pub struct Signature<Args> {
+    name: &'static str,
+    args: PhantomData<fn() -> Args>,
+}
+
+impl<Args> Signature<Args> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            args: PhantomData,
+        }
+    }
+    /// A call of the predicate. Each of `given` is a value of its argument's
+    /// type, or `Var` for one to be found.
+    pub fn query<Given: Arguments<Args>>(&self, given: Given) -> TypedQuery<Args> {
+        TypedQuery {
+            goal: Functor::new_fact(Atom::new(self.name), given.into_terms()),
+            args: PhantomData,
+        }
+    }
+}
+
+/// An argument left for the solver to find.
+#[derive(Clone, Copy, Debug)]
+pub struct Var;
+
+/// What may be given for an argument of type `T`: a `T`, `Var`, or a `&str`
+/// for a `String`.
+pub trait IntoArg<T> {
+    fn into_arg(self) -> Term;
+}
+
+impl<T: ToTerm> IntoArg<T> for T {
+    fn into_arg(self) -> Term {
+        self.to_term()
+    }
+}
+
+impl<T> IntoArg<T> for Var {
+    fn into_arg(self) -> Term {
+        Term::Variable(Variable::new_anonymous(&mut thread_rng()))
+    }
+}
+
+impl IntoArg<String> for &str {
+    fn into_arg(self) -> Term {
+        self.to_term()
+    }
+}
+
+/// A tuple of arguments for a predicate whose arguments are `Args`.
+pub trait Arguments<Args> {
+    fn into_terms(self) -> Vec<Term>;
+}
+
+/// A tuple read from the arguments of a solved call.
+pub trait FromArgs: Sized {
+    fn from_args(args: &[Term]) -> Option<Self>;
+}
+
+macro_rules! typed_args {
+    ($($t:ident $g:ident $v:ident),+) => {
+        impl<$($t, $g: IntoArg<$t>),+> Arguments<($($t,)+)> for ($($g,)+) {
+            fn into_terms(self) -> Vec<Term> {
+                let ($($v,)+) = self;
+                vec![$($v.into_arg()),+]
+            }
+        }
+
+        impl<$($t: FromTerm),+> FromArgs for ($($t,)+) {
+            fn from_args(args: &[Term]) -> Option<Self> {
+                let [$($v),+] = args else {
+                    return None;
+                };
+                Some(($($t::from_term($v)?,)+))
+            }
+        }
+    };
+}
+
+typed_args!(A Ga a);
+typed_args!(A Ga a, B Gb b);
+typed_args!(A Ga a, B Gb b, C Gc c);
+typed_args!(A Ga a, B Gb b, C Gc c, D Gd d);
+typed_args!(A Ga a, B Gb b, C Gc c, D Gd d, E Ge e);
+typed_args!(A Ga a, B Gb b, C Gc c, D Gd d, E Ge e, F Gf f);
+
+/// A call built from a `Signature`.
+pub struct TypedQuery<Args> {
+    goal: Functor,
+    args: PhantomData<fn() -> Args>,
+}
+
+impl<Args: FromArgs> TypedQuery<Args> {
+    pub fn goal(&self) -> &Functor {
+        &self.goal
+    }
+    /// The call's arguments in each solution. A solution whose arguments
+    /// aren't of the signature's types, such as one leaving a `String`
+    /// unbound, is left out.
+    pub fn solutions(&self, db: &Database) -> Result<Vec<Args>, SolveError> {
+        Ok(db
+            .try_satisfy_all(self.goal.clone())?
+            .iter()
+            .filter_map(|bindings| {
+                let args: Vec<Term> = self
+                    .goal
+                    .args
+                    .iter()
+                    .map(|arg| bindings.resolve(arg))
+                    .collect();
+                Args::from_args(&args)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    const AGE: Signature<(String, i64)> = Signature::new("age");
+    const OWNS: Signature<(String, Vec<String>, Option<i64>)> = Signature::new("owns");
+
+    #[test]
+    fn typed_queries() {
+        let mut db = Database::new();
+        db.consult(
+            "age(sam, 30). age(olive, 25). age(popeye, old).
+            owns(sam, [spinach, pipe], _).",
+        )
+        .unwrap();
+        assert_eq!(
+            AGE.query((Var, Var)).solutions(&db).unwrap(),
+            [("sam".to_string(), 30), ("olive".to_string(), 25)]
+        );
+        assert_eq!(
+            AGE.query(("olive", Var)).solutions(&db).unwrap(),
+            [("olive".to_string(), 25)]
+        );
+        assert_eq!(AGE.query((Var, 30)).goal().arity(), 2);
+        assert!(AGE.query(("sam", 31)).solutions(&db).unwrap().is_empty());
+        let owns = OWNS
+            .query(("sam".to_string(), Var, Var))
+            .solutions(&db)
+            .unwrap();
+        assert_eq!(
+            owns,
+            [(
+                "sam".to_string(),
+                vec!["spinach".to_string(), "pipe".to_string()],
+                None
+            )]
+        );
+    }
+}
@@ -0,0 +1,319 @@
+//! Bindings for running amateurlog in a browser, e.g. built with
+//! `wasm-pack build --target web -- --features wasm`.
+//!
+//! `Engine` holds a program, and `engine.query(text)` gives its solutions as
+//! an async iterator of plain objects. Errors are `Error`s with a `kind`:
+//! `"syntax"`, with the `errors` found; `"exception"`, with the uncaught
+//! `ball`; `"timeout"`; or `"resource"`, naming the `resource` run out of.
+use crate::{
+    parse_query, solutions_to_json, Database, ParseError, Query, Resource, Solution, SolveError,
+};
+use js_sys::{Array, Function, Object, Promise, Reflect, Symbol, JSON};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ops::ControlFlow;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    static DATABASE: RefCell<Database> = RefCell::new(Database::new());
+}
+
+/// Replaces the loaded program with the clauses in `source`.
+#[wasm_bindgen(js_name = loadProgram)]
+pub fn load_program(source: &str) -> Result<(), JsValue> {
+    let engine = Engine::load_program(source)?;
+    DATABASE.with(|loaded| loaded.replace(engine.db));
+    Ok(())
+}
+
+/// Runs `goal` against the loaded program, returning an array with one object
+/// of variable bindings per solution, or an error for an uncaught exception.
+#[wasm_bindgen]
+pub fn query(goal: &str) -> Result<JsValue, JsValue> {
+    let query = parse_query(goal).map_err(|err| syntax_error(slice::from_ref(&err)))?;
+    let solutions: Vec<_> = DATABASE
+        .with(|db| {
+            db.borrow()
+                .try_satisfy_all(query.goal.clone())
+                .map(|answers| answers.iter().map(|b| query.solution(b)).collect())
+        })
+        .map_err(|err| solve_error(&err))?;
+    JSON::parse(&solutions_to_json(&solutions))
+}
+
+/// A program to query.
+#[wasm_bindgen]
+pub struct Engine {
+    db: Database,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Engine {
+    /// An engine with no clauses.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Engine {
+        Engine {
+            db: Database::new(),
+        }
+    }
+    /// An engine with the clauses in `source`.
+    #[wasm_bindgen(js_name = loadProgram)]
+    pub fn load_program(source: &str) -> Result<Engine, JsValue> {
+        let mut engine = Engine::new();
+        engine.consult(source)?;
+        Ok(engine)
+    }
+    /// Adds the clauses in `source`.
+    pub fn consult(&mut self, source: &str) -> Result<(), JsValue> {
+        self.db
+            .consult(source)
+            .map_err(|errors| syntax_error(&errors))
+    }
+    /// The solutions of `text`, to be read with
+    /// `for await (const { X } of engine.query("between(1, 3, X)"))`. The
+    /// query sees the program as it is now, even if more is consulted while
+    /// it runs.
+    pub fn query(&self, text: &str) -> Result<JsValue, JsValue> {
+        let query = parse_query(text).map_err(|err| syntax_error(slice::from_ref(&err)))?;
+        let solutions = JsValue::from(Solutions {
+            batches: Batches::new(self.db.clone(), query, rand::random()),
+        });
+        let itself = Function::new_no_args("return this");
+        Reflect::set(&solutions, &Symbol::async_iterator(), &itself)?;
+        Ok(solutions)
+    }
+}
+
+/// The solutions of a query, found as they're asked for, by JavaScript's
+/// async iterator protocol.
+#[wasm_bindgen]
+pub struct Solutions {
+    batches: Batches,
+}
+
+#[wasm_bindgen]
+impl Solutions {
+    /// A promise of `{ value, done }`, where `value` binds the query's
+    /// variables, or of an error.
+    pub fn next(&mut self) -> Promise {
+        match self.batches.next() {
+            None => Promise::resolve(&object(&[("done", true.into())])),
+            Some(Ok(solution)) => match JSON::parse(&solution.to_json()) {
+                Ok(value) => Promise::resolve(&object(&[("value", value), ("done", false.into())])),
+                Err(err) => Promise::reject(&err),
+            },
+            Some(Err(err)) => Promise::reject(&solve_error(&err)),
+        }
+    }
+}
+
+// a query's solutions, found a batch at a time so that the page gets
+// control back between them and a query with endless solutions can still be
+// stepped through. A search can't be suspended on wasm's one thread, so each
+// batch runs the query again on a fresh copy of the database, with the same
+// random seed, and skips what earlier batches found, printing nothing until
+// it's past them; batches double in size, so running again at most doubles
+// the work
+struct Batches {
+    db: Database,
+    query: Query,
+    seed: u64,
+    found: usize,
+    size: usize,
+    pending: VecDeque<Solution>,
+    error: Option<SolveError>,
+    finished: bool,
+}
+
+impl Batches {
+    fn new(db: Database, query: Query, seed: u64) -> Self {
+        Self {
+            db,
+            query,
+            seed,
+            found: 0,
+            size: 1,
+            pending: VecDeque::new(),
+            error: None,
+            finished: false,
+        }
+    }
+    // the next solution, or the error that stopped the search once the
+    // solutions before it are taken
+    fn next(&mut self) -> Option<Result<Solution, SolveError>> {
+        if self.pending.is_empty() && self.error.is_none() && !self.finished {
+            if let Err(err) = self.run() {
+                self.error = Some(err);
+            }
+        }
+        if let Some(solution) = self.pending.pop_front() {
+            return Some(Ok(solution));
+        }
+        self.finished = true;
+        self.error.take().map(Err)
+    }
+    fn run(&mut self) -> Result<(), SolveError> {
+        let mut db = self.db.clone();
+        db.set_random_seed(self.seed);
+        let (skip, wanted) = (self.found, self.found + self.size);
+        // what's printed on the way to the solutions skipped was printed
+        // the first time round
+        let muted = Arc::new(AtomicBool::new(skip > 0));
+        db.output = db.output.gated(muted.clone());
+        let (query, pending) = (&self.query, &mut self.pending);
+        let mut seen = 0;
+        let result = db.for_each_solution(query.goal.clone(), |bindings| {
+            seen += 1;
+            if seen == skip {
+                muted.store(false, Ordering::Relaxed);
+            }
+            if seen > skip {
+                pending.push_back(query.solution(bindings));
+            }
+            if seen == wanted {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        self.finished = seen < wanted;
+        self.found = seen;
+        self.size *= 2;
+        result
+    }
+}
+
+fn object(fields: &[(&str, JsValue)]) -> JsValue {
+    let object = Object::new();
+    for (key, value) in fields {
+        let _ = Reflect::set(&object, &(*key).into(), value);
+    }
+    object.into()
+}
+
+// an `Error` saying `message`, with `kind` and `fields` set on it
+fn error(message: &str, kind: &str, fields: &[(&str, JsValue)]) -> JsValue {
+    let error = js_sys::Error::new(message);
+    let _ = Reflect::set(&error, &"kind".into(), &kind.into());
+    for (key, value) in fields {
+        let _ = Reflect::set(&error, &(*key).into(), value);
+    }
+    error.into()
+}
+
+fn syntax_error(errors: &[ParseError]) -> JsValue {
+    let text = |text: &Option<String>| text.as_deref().map_or(JsValue::NULL, JsValue::from);
+    let list: Array = errors
+        .iter()
+        .map(|err| {
+            object(&[
+                ("message", err.message.as_str().into()),
+                ("expected", text(&err.expected)),
+                ("found", text(&err.found)),
+                ("line", err.line.into()),
+                ("column", err.column.into()),
+                ("start", err.span.start.into()),
+                ("end", err.span.end.into()),
+            ])
+        })
+        .collect();
+    let message: Vec<String> = errors.iter().map(ToString::to_string).collect();
+    error(&message.join("\n"), "syntax", &[("errors", list.into())])
+}
+
+fn solve_error(err: &SolveError) -> JsValue {
+    let message = err.to_string();
+    match err {
+        SolveError::Uncaught(ball) => error(
+            &message,
+            "exception",
+            &[("ball", ball.pretty().to_string().into())],
+        ),
+        SolveError::TimedOut => error(&message, "timeout", &[]),
+        SolveError::Cancelled => error(&message, "cancelled", &[]),
+        SolveError::ResourceExceeded(resource) => {
+            let resource = match resource {
+                Resource::TermDepth => "term_depth",
+                Resource::Bindings => "bindings",
+                Resource::Terms => "terms",
+            };
+            error(&message, "resource", &[("resource", resource.into())])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Batches;
+    use crate::*;
+    use std::io::{self, Write};
+    use std::iter;
+    use std::sync::{Arc, Mutex};
+
+    fn batches(db: &Database, query: &str) -> Batches {
+        Batches::new(db.clone(), parse_query(query).unwrap(), 7)
+    }
+
+    #[test]
+    fn batches_step_through_endless_queries() {
+        let mut db = Database::new();
+        db.consult("roll(X) :- between(1, inf, _), random_between(1, 6, X).")
+            .unwrap();
+        let mut rolls = batches(&db, "roll(X)");
+        let first: Vec<String> = (0..20)
+            .map(|_| rolls.next().unwrap().unwrap().to_json())
+            .collect();
+        // the same as one search going on to the 20th
+        db.set_random_seed(7);
+        let query = parse_query("roll(X)").unwrap();
+        let once: Vec<String> = db
+            .satisfy_n(query.goal.clone(), 20)
+            .iter()
+            .map(|bindings| query.solution(bindings).to_json())
+            .collect();
+        assert_eq!(first, once);
+        let mut few = batches(&db, "between(1, 3, X)");
+        let found: Vec<_> = iter::from_fn(|| few.next()).collect();
+        assert_eq!(found.len(), 3);
+        let mut thrown = batches(&db, "between(1, 2, X) ; throw(oops)");
+        assert!(thrown.next().unwrap().is_ok());
+        assert!(thrown.next().unwrap().is_ok());
+        assert!(matches!(thrown.next(), Some(Err(SolveError::Uncaught(_)))));
+        assert!(thrown.next().is_none());
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn batches_print_once() {
+        let mut db = Database::new();
+        db.consult("p(X) :- write(start), nl, between(1, 9, X), write(X), nl.")
+            .unwrap();
+        let captured = Captured::default();
+        db.set_output(captured.clone());
+        let mut ps = batches(&db, "p(X)");
+        assert_eq!(iter::from_fn(|| ps.next()).count(), 9);
+        let printed = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(printed, "start\n1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+    }
+}